@@ -76,7 +76,11 @@ fn compile_risc0_contracts() {
         let pkg = get_package(manifest_dir.join(name));
         let mut guest_opts = GuestOptionsBuilder::default();
 
-        guest_opts.features(vec!["risc0".into()]);
+        let mut features = vec!["risc0".into()];
+        if cfg!(feature = "rounding-audit") {
+            features.push("rounding-audit".into());
+        }
+        guest_opts.features(features);
 
         if reproducible {
             guest_opts.use_docker(
@@ -0,0 +1,278 @@
+//! Uniform-price batch auction of the swaps sequenced in one block.
+//!
+//! Under `OrderingPolicy::BatchAuction`, or in a pool created as a batch auction pool whatever
+//! the policy, a swap does not execute when sequenced: its input is escrowed in the batch of its
+//! pool for the current block. The batch clears once the block is
+//! over, when a later transaction touches the pool or the keeper's `ExecuteDue` runs. Every order
+//! selling the same token then receives the same price, whatever its position in the block, so
+//! nothing is gained by placing a swap right before or after another one.
+//!
+//! Orders selling opposite tokens are matched against each other and only the net flow goes
+//! through the pool curve. The net amount is chosen so both directions trade at one price that
+//! leaves the pool on its curve, which is exact for equal weights; weighted pools still clear at
+//! one price per direction. Orders whose share falls below their minimum are refunded and the
+//! batch is cleared again without them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crate::{AmmContract, AmmError, LiquidityPool, BPS_DENOMINATOR};
+
+/// How swaps sequenced in the same block execute against each other, set at registration
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingPolicy {
+    /// Each swap executes as sequenced, against the reserves the previous one left
+    #[default]
+    Arrival,
+    /// Swaps of a pool within one block clear together, one price per direction
+    BatchAuction,
+}
+
+/// Swap waiting for its pool's batch to clear, its input escrowed
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchedSwap {
+    pub user: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: u128,
+    /// Applies to what the user keeps, after the relayer fee of a relayed swap
+    pub min_amount_out: u128,
+    /// Relayer of a relayed swap and its fee in basis points of the output
+    pub relayer: Option<(String, u128)>,
+}
+
+/// Swaps of a pool sequenced in one block
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SwapBatch {
+    pub block: u64,
+    pub orders: Vec<BatchedSwap>,
+}
+
+impl AmmContract {
+    /// Order swaps by the policy given
+    pub fn with_ordering_policy(mut self, policy: OrderingPolicy) -> Self {
+        self.ordering_policy = policy;
+        self
+    }
+
+    pub fn ordering_policy(&self) -> OrderingPolicy {
+        self.ordering_policy
+    }
+
+    /// Batches not cleared yet, by pair key
+    pub fn swap_batches(&self) -> &BTreeMap<String, SwapBatch> {
+        &self.swap_batches
+    }
+
+    /// Pools created as batch auction pools, by pair key
+    pub fn batch_auction_pools(&self) -> &BTreeSet<String> {
+        &self.batch_pools
+    }
+
+    /// Whether the swaps of a pool clear in batches
+    pub fn is_batch_auction(&self, pair_key: &str) -> bool {
+        self.ordering_policy == OrderingPolicy::BatchAuction || self.batch_pools.contains(pair_key)
+    }
+
+    /// Create a pool whose swaps always clear in a batch auction, whatever the ordering policy
+    pub fn create_batch_auction_pool(
+        &mut self,
+        user: String,
+        token_a: String,
+        token_b: String,
+        amount_a: u128,
+        amount_b: u128
+    ) -> Result<Vec<u8>, AmmError> {
+        if self.pool(&token_a, &token_b).is_some_and(|p| p.total_liquidity > 0) {
+            return Err(AmmError::PoolExists);
+        }
+        self.add_liquidity(user, token_a.clone(), token_b.clone(), amount_a, amount_b)?;
+        let pair_key = self.get_pair_key(&token_a, &token_b);
+        self.batch_pools.insert(pair_key.clone());
+
+        Ok(format!("Created {} batch auction pool with {} {} and {} {}",
+            pair_key, amount_a, token_a, amount_b, token_b).into_bytes())
+    }
+
+    /// Escrow a swap's input in its pool's batch for the current block
+    pub(crate) fn queue_swap(&mut self, order: BatchedSwap) -> Result<Vec<u8>, AmmError> {
+        let pair_key = self.get_pair_key(&order.token_in, &order.token_out);
+        let pool = self.pools.get(&pair_key).ok_or(AmmError::PoolNotFound)?;
+        if pool.reserve_a == 0 || pool.reserve_b == 0 {
+            return Err(AmmError::InsufficientLiquidity);
+        }
+        if order.amount_in == 0 {
+            return Err(AmmError::InvalidInput("Cannot swap nothing".to_string()));
+        }
+        self.clear_due_batch(&pair_key);
+
+        let balance_in_key = self.user_key(&order.user, &order.token_in);
+        let user_balance_in = *self.user_balances.get(&balance_in_key).unwrap_or(&0);
+        if user_balance_in < order.amount_in {
+            return Err(AmmError::InsufficientBalance { token: order.token_in.clone() });
+        }
+        self.user_balances.insert(balance_in_key, user_balance_in - order.amount_in);
+
+        let output = format!("Queued swap of {} {} for {} in the batch of block {}",
+            order.amount_in, order.token_in, order.token_out, self.block_height).into_bytes();
+        let block = self.block_height;
+        self.swap_batches
+            .entry(pair_key)
+            .or_insert_with(|| SwapBatch { block, orders: vec![] })
+            .orders
+            .push(order);
+        Ok(output)
+    }
+
+    /// Clear the batch of a pool if its block is over, returning whether there was one
+    pub(crate) fn clear_due_batch(&mut self, pair_key: &str) -> bool {
+        match self.swap_batches.get(pair_key) {
+            Some(batch) if batch.block < self.block_height => {},
+            _ => return false,
+        }
+        if let Some(batch) = self.swap_batches.remove(pair_key) {
+            self.clear_batch(pair_key, batch);
+        }
+        true
+    }
+
+    /// Clear every batch whose block is over, returning how many were
+    pub(crate) fn clear_due_batches(&mut self) -> usize {
+        let due: Vec<String> = self.swap_batches.iter()
+            .filter(|(_, batch)| batch.block < self.block_height)
+            .map(|(pair_key, _)| pair_key.clone())
+            .collect();
+        for pair_key in &due {
+            self.clear_due_batch(pair_key);
+        }
+        due.len()
+    }
+
+    fn clear_batch(&mut self, pair_key: &str, batch: SwapBatch) {
+        let pool = match self.pools.get(pair_key) {
+            Some(pool) if pool.reserve_a > 0 && pool.reserve_b > 0 => pool.clone(),
+            _ => return self.refund(batch.orders),
+        };
+        // Fees are taken from the inputs as for any swap, only the rest is traded
+        let mut orders: Vec<(BatchedSwap, u128)> = batch.orders.into_iter()
+            .map(|order| {
+                let fee = self.swap_fee(pair_key, order.amount_in);
+                (order, fee)
+            })
+            .collect();
+
+        // Each refund changes the price the others clear at, so clear again until all fill
+        let outputs = loop {
+            let outputs = clearing_outputs(&pool, batch.block, &orders);
+            let (filled, refunded): (Vec<_>, Vec<_>) = orders.into_iter()
+                .zip(outputs)
+                .partition(|((order, _), amount_out)| self.fills(order, *amount_out));
+            let outputs;
+            (orders, outputs) = filled.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+            if refunded.is_empty() {
+                break outputs;
+            }
+            self.refund(refunded.into_iter().map(|((order, _), _)| order).collect());
+        };
+
+        // The pool takes in every input and pays out every output: the net swap, the liquidity
+        // providers' share of the fees and the rounding dust of the pro rata shares
+        let (mut in_a, mut in_b, mut out_a, mut out_b) = (0, 0, 0, 0);
+        for ((order, fee), amount_out) in orders.into_iter().zip(outputs) {
+            let sells_a = order.token_in == pool.token_a;
+            let (fund_cut, referrer_cut) = self.fee_cuts(pair_key, &order.user, fee);
+            let fees_out = fund_cut + referrer_cut.as_ref().map_or(0, |(_, cut)| *cut);
+            let protocol_cut = self.protocol_cut(fee - fees_out);
+            let fees_out = fees_out + protocol_cut;
+            if sells_a {
+                in_a += order.amount_in - fees_out;
+                out_b += amount_out;
+            } else {
+                in_b += order.amount_in - fees_out;
+                out_a += amount_out;
+            }
+            if let Some(fund) = self.insurance.as_mut() {
+                *fund.balances.entry(order.token_in.clone()).or_insert(0) += fund_cut;
+            }
+            self.accrue_protocol_fee(&order.token_in, protocol_cut);
+            if let Some((referrer, cut)) = referrer_cut {
+                self.credit(&referrer, &order.token_in, cut);
+            }
+
+            let traded = if sells_a {
+                (order.amount_in, amount_out)
+            } else {
+                (amount_out, order.amount_in)
+            };
+            self.pool_volumes.entry(pair_key.to_string()).or_default().record(traded);
+            let volume_key = self.user_key(&order.user, pair_key);
+            self.user_volumes.entry(volume_key).or_default().record(traded);
+
+            let relayer_fee = match &order.relayer {
+                Some((relayer, fee_bps)) => {
+                    let relayer_fee = amount_out * fee_bps / BPS_DENOMINATOR;
+                    self.credit(relayer, &order.token_out, relayer_fee);
+                    relayer_fee
+                },
+                None => 0,
+            };
+            self.credit(&order.user, &order.token_out, amount_out - relayer_fee);
+        }
+
+        let pool = self.pools.get_mut(pair_key).expect("Pool of a cleared batch");
+        pool.reserve_a = pool.reserve_a + in_a - out_a;
+        pool.reserve_b = pool.reserve_b + in_b - out_b;
+    }
+
+    /// Whether an order's share of the batch meets its minimum and the contract's
+    fn fills(&self, order: &BatchedSwap, amount_out: u128) -> bool {
+        let relayer_fee = order.relayer.as_ref().map_or(0, |(_, fee_bps)| amount_out * fee_bps / BPS_DENOMINATOR);
+        amount_out > 0 && amount_out >= self.min_swap_output && amount_out - relayer_fee >= order.min_amount_out
+    }
+
+    /// Give orders that did not clear their escrowed input back
+    pub(crate) fn refund(&mut self, orders: Vec<BatchedSwap>) {
+        for order in orders {
+            self.credit(&order.user, &order.token_in, order.amount_in);
+        }
+    }
+
+    fn credit(&mut self, user: &str, token: &str, amount: u128) {
+        let key = self.user_key(user, token);
+        *self.user_balances.entry(key).or_insert(0) += amount;
+    }
+}
+
+/// What each order of a batch receives when cleared against the pool, in order.
+///
+/// With `x` of token A and `y` of token B sold into reserves `ra` and `rb`, the side in excess
+/// swaps the net amount `d` through the pool and both sides trade at the price it leaves: for A
+/// in excess, `rb / (ra + d) = y / (x - d)`, so `d = (x * rb - y * ra) / (rb + y)`. Each side then
+/// shares what the other brought and the pool paid, pro rata to the inputs.
+fn clearing_outputs(pool: &LiquidityPool, block: u64, orders: &[(BatchedSwap, u128)]) -> Vec<u128> {
+    let sold = |token: &str| -> u128 {
+        orders.iter()
+            .filter(|(order, _)| order.token_in == token)
+            .map(|(order, fee)| order.amount_in - fee)
+            .sum()
+    };
+    let (x, y) = (sold(&pool.token_a), sold(&pool.token_b));
+    let (ra, rb) = (pool.reserve_a, pool.reserve_b);
+    let (to_a_sellers, to_b_sellers) = if x * rb >= y * ra {
+        let a_to_pool = (x * rb - y * ra) / (rb + y);
+        (y + pool.amount_out(&pool.token_a, a_to_pool, block), x - a_to_pool)
+    } else {
+        let b_to_pool = (y * ra - x * rb) / (ra + x);
+        (y - b_to_pool, x + pool.amount_out(&pool.token_b, b_to_pool, block))
+    };
+
+    orders.iter()
+        .map(|(order, fee)| match order.token_in == pool.token_a {
+            true => to_a_sellers * (order.amount_in - fee) / x,
+            false => to_b_sellers * (order.amount_in - fee) / y,
+        })
+        .collect()
+}
@@ -10,7 +10,13 @@ pub mod metadata {
 }
 
 impl TxExecutorHandler for Contract1 {
-    fn build_commitment_metadata(&self, _blob: &Blob) -> anyhow::Result<Vec<u8>> {
+    fn build_commitment_metadata(&self, blob: &Blob) -> anyhow::Result<Vec<u8>> {
+        #[cfg(feature = "segmented-state")]
+        if let Ok(action) = borsh::from_slice::<crate::AmmAction>(&blob.data.0) {
+            return borsh::to_vec(&self.witness(&action)).context("Failed to encode Contract1 witness");
+        }
+        #[cfg(not(feature = "segmented-state"))]
+        let _ = blob;
         borsh::to_vec(self).context("Failed to encode Contract1")
     }
 
@@ -26,11 +32,27 @@ impl TxExecutorHandler for Contract1 {
         ))
     }
 
+    /// The registered commitment is a hash that cannot be decoded, so start from the state in the
+    /// metadata, or the default one, only once it commits to what was registered
     fn construct_state(
-        _register_blob: &RegisterContractEffect,
-        _metadata: &Option<Vec<u8>>,
+        register_blob: &RegisterContractEffect,
+        metadata: &Option<Vec<u8>>,
     ) -> anyhow::Result<Self> {
-        Ok(Self::default())
+        let (state, source) = match metadata {
+            Some(bytes) => (
+                borsh::from_slice::<Self>(bytes).context("Could not decode Contract1 from the registration metadata")?,
+                "the registration metadata",
+            ),
+            None => (Self::default(), "a default state"),
+        };
+        if state.commit() != register_blob.state_commitment {
+            anyhow::bail!(
+                "Could not construct {} state: the registered commitment is not the one of {}",
+                register_blob.contract_name.0,
+                source
+            );
+        }
+        Ok(state)
     }
 
     fn get_state_commitment(&self) -> sdk::StateCommitment {
@@ -0,0 +1,141 @@
+//! Garbage collection of state entries that no longer hold anything.
+//!
+//! Balances spent down to zero and pools whose liquidity was all withdrawn stay in the state,
+//! which every proof carries and commits, so long-running deployments would prove ever larger
+//! states. `Compact` drops them. Reading a missing entry gives what the dropped one held, so
+//! compaction changes no outcome, but only the keeper and the admin council run it. The keeper
+//! does when enough entries add up, signing each compaction with its secp256k1 key.
+
+use std::collections::BTreeSet;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{AmmContract, AmmError, Secp256k1Blob, SECP256K1_CONTRACT};
+
+/// Identity allowed to compact the state besides the admin council, with the key it signs with
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Keeper {
+    pub identity: String,
+    /// Compressed secp256k1 public key
+    pub public_key: Vec<u8>,
+    /// Compactions the keeper ran, signed along so that no signature is accepted twice
+    pub nonce: u64,
+}
+
+impl Keeper {
+    /// Message the keeper signs for its next compaction of the AMM contract
+    pub fn digest(&self, contract_name: &sdk::ContractName) -> [u8; 32] {
+        let message = borsh::to_vec(&("Compact", &contract_name.0, self.nonce)).expect("Failed to encode compaction");
+        Sha256::digest(message).into()
+    }
+}
+
+/// Entries `Compact` would drop
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Compactable {
+    pub balances: usize,
+    /// Liquidity balances and liquidity mining positions
+    pub liquidity: usize,
+    /// Pair keys of drained pools
+    pub pools: BTreeSet<String>,
+}
+
+impl Compactable {
+    pub fn entries(&self) -> usize {
+        self.balances + self.liquidity + self.pools.len()
+    }
+}
+
+impl AmmContract {
+    /// Let `identity` submit `Compact` signed with `public_key`, besides the admins of the council
+    pub fn with_keeper(mut self, identity: String, public_key: Vec<u8>) -> Self {
+        self.keeper = Some(Keeper { identity, public_key, nonce: 0 });
+        self
+    }
+
+    pub fn keeper(&self) -> Option<&Keeper> {
+        self.keeper.as_ref()
+    }
+
+    /// Admins of the council compact as they are. The keeper's transaction must carry its
+    /// signature of the next compaction, which the native secp256k1 verifier checks.
+    pub(crate) fn authorize_compaction(&mut self, calldata: &sdk::Calldata) -> Result<(), AmmError> {
+        let identity = &calldata.identity.0;
+        if self.admin_council.as_ref().is_some_and(|council| council.admins.iter().any(|admin| admin == identity)) {
+            return Ok(());
+        }
+        let contract_name = calldata.blobs.get(&calldata.index)
+            .map(|blob| blob.contract_name.clone())
+            .ok_or_else(|| AmmError::InvalidBlob("Missing AMM blob".to_string()))?;
+        let Some(keeper) = self.keeper.as_mut().filter(|keeper| &keeper.identity == identity) else {
+            return Err(AmmError::Unauthorized(format!("The state cannot be compacted by {}", identity)));
+        };
+
+        let digest = keeper.digest(&contract_name);
+        let signed = calldata
+            .blobs
+            .iter()
+            .map(|(_, blob)| blob)
+            .filter(|blob| blob.contract_name.0 == SECP256K1_CONTRACT)
+            .filter_map(|blob| borsh::from_slice::<Secp256k1Blob>(&blob.data.0).ok())
+            .any(|blob| blob.data == digest && blob.public_key.as_slice() == keeper.public_key);
+        if !signed {
+            return Err(AmmError::Unauthorized(format!("Compaction is not signed by the keeper {}", identity)));
+        }
+        keeper.nonce += 1;
+        Ok(())
+    }
+
+    /// What `Compact` would drop from the state as it is
+    pub fn compactable(&self) -> Compactable {
+        let liquidity = self.liquidity_balances.values().filter(|liquidity| **liquidity == 0).count()
+            + self.lp_positions.keys().filter(|key| self.is_spent_position(key)).count();
+        Compactable {
+            balances: self.user_balances.values().filter(|balance| **balance == 0).count(),
+            liquidity,
+            pools: self.drained_pools(),
+        }
+    }
+
+    /// Drop zero balances, spent liquidity mining positions and drained pools
+    pub fn compact(&mut self) -> Result<Vec<u8>, AmmError> {
+        let compacted = self.compactable();
+        self.user_balances.retain(|_, balance| *balance > 0);
+        let spent: Vec<_> = self.lp_positions.keys().filter(|key| self.is_spent_position(key)).copied().collect();
+        for key in spent {
+            self.lp_positions.remove(&key);
+        }
+        self.liquidity_balances.retain(|_, liquidity| *liquidity > 0);
+        for pair_key in &compacted.pools {
+            self.pools.remove(pair_key);
+        }
+
+        Ok(format!("Compacted {} balances, {} liquidity entries and {} pools ({})",
+            compacted.balances, compacted.liquidity, compacted.pools.len(),
+            compacted.pools.into_iter().collect::<Vec<_>>().join(", ")).into_bytes())
+    }
+
+    /// Positions without liquidity whose reward points are all spent or were never earned
+    fn is_spent_position(&self, key: &(u32, u32)) -> bool {
+        self.lp_positions.get(key).is_some_and(|position| position.reward_points == 0)
+            && self.liquidity_balances.get(key).copied().unwrap_or(0) == 0
+    }
+
+    /// Pools without liquidity nor reserves, which the next deposit would create again as they are.
+    ///
+    /// Pools keeping something a new pool would not be created with, i.e. an LBP schedule, batch
+    /// clearing, a swap fee, a fee distribution or decimals of their own, stay. A `segmented-state`
+    /// witness of `Compact` carries each of these pools.
+    pub(crate) fn drained_pools(&self) -> BTreeSet<String> {
+        self.pools.iter()
+            .filter(|(_, pool)| pool.total_liquidity == 0 && pool.reserve_a == 0 && pool.reserve_b == 0)
+            .filter(|(_, pool)| pool.lbp.is_none() && pool.fee_distribution == self.fee_distribution)
+            .filter(|(_, pool)| pool.swap_fee_bps == self.pool_swap_fee_bps)
+            .filter(|(_, pool)| (pool.scale_a, pool.scale_b) == self.pool_scales(&pool.token_a, &pool.token_b))
+            .filter(|(pair_key, _)| !self.batch_pools.contains(*pair_key) && !self.swap_batches.contains_key(*pair_key))
+            .map(|(pair_key, _)| pair_key.clone())
+            .collect()
+    }
+}
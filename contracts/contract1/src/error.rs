@@ -0,0 +1,127 @@
+//! Errors of the AMM actions.
+//!
+//! Contract functions return `AmmError`; `execute` turns it into the `code: message` string
+//! the sdk carries in failed transactions, which [`AmmError::code_of`] reads back.
+
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmmError {
+    /// The admin council paused the AMM
+    Paused,
+    /// Only withdrawals are open in emergency mode
+    EmergencyMode,
+    InsufficientBalance { token: String },
+    /// The pool cannot pay out the requested amount
+    InsufficientLiquidity,
+    InsufficientLpTokens,
+    InvalidLiquidityRatio,
+    /// The output is under the minimum the caller accepted
+    SlippageExceeded,
+    /// The output is under the minimum trade size of the contract
+    BelowMinimumOutput { amount_out: u128, token: String },
+    PoolNotFound,
+    PoolExists,
+    /// Amounts too large to compute with
+    Overflow,
+    /// A feature this deployment was not registered with
+    NotEnabled(String),
+    /// The transaction identity may not perform the action
+    Unauthorized(String),
+    /// A blob the action relies on is missing or invalid
+    InvalidBlob(String),
+    NotFound(String),
+    /// The action was already performed or conflicts with what was
+    Conflict(String),
+    LimitExceeded(String),
+    InvalidInput(String),
+}
+
+impl AmmError {
+    /// Every code, as prefixed to failed transaction errors
+    pub const CODES: [&'static str; 18] = [
+        "paused",
+        "emergency_mode",
+        "insufficient_balance",
+        "insufficient_liquidity",
+        "insufficient_lp_tokens",
+        "invalid_liquidity_ratio",
+        "slippage_exceeded",
+        "below_minimum_output",
+        "pool_not_found",
+        "pool_exists",
+        "overflow",
+        "not_enabled",
+        "unauthorized",
+        "invalid_blob",
+        "not_found",
+        "conflict",
+        "limit_exceeded",
+        "invalid_input",
+    ];
+
+    /// Stable identifier of the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            AmmError::Paused => "paused",
+            AmmError::EmergencyMode => "emergency_mode",
+            AmmError::InsufficientBalance { .. } => "insufficient_balance",
+            AmmError::InsufficientLiquidity => "insufficient_liquidity",
+            AmmError::InsufficientLpTokens => "insufficient_lp_tokens",
+            AmmError::InvalidLiquidityRatio => "invalid_liquidity_ratio",
+            AmmError::SlippageExceeded => "slippage_exceeded",
+            AmmError::BelowMinimumOutput { .. } => "below_minimum_output",
+            AmmError::PoolNotFound => "pool_not_found",
+            AmmError::PoolExists => "pool_exists",
+            AmmError::Overflow => "overflow",
+            AmmError::NotEnabled(_) => "not_enabled",
+            AmmError::Unauthorized(_) => "unauthorized",
+            AmmError::InvalidBlob(_) => "invalid_blob",
+            AmmError::NotFound(_) => "not_found",
+            AmmError::Conflict(_) => "conflict",
+            AmmError::LimitExceeded(_) => "limit_exceeded",
+            AmmError::InvalidInput(_) => "invalid_input",
+        }
+    }
+
+    /// Code of a failed transaction error produced by `execute`, if it came from an action
+    pub fn code_of(error: &str) -> Option<&'static str> {
+        let (code, _) = error.split_once(": ")?;
+        Self::CODES.into_iter().find(|known| *known == code)
+    }
+}
+
+impl fmt::Display for AmmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmmError::Paused => write!(f, "The AMM is paused"),
+            AmmError::EmergencyMode => write!(f, "Swaps are disabled in emergency mode, only withdrawals are open"),
+            AmmError::InsufficientBalance { token } => write!(f, "Insufficient {} balance", token),
+            AmmError::InsufficientLiquidity => write!(f, "Insufficient liquidity"),
+            AmmError::InsufficientLpTokens => write!(f, "Insufficient liquidity tokens"),
+            AmmError::InvalidLiquidityRatio => write!(f, "Invalid liquidity ratio"),
+            AmmError::SlippageExceeded => write!(f, "Insufficient output amount"),
+            AmmError::BelowMinimumOutput { amount_out, token } => {
+                write!(f, "Swap output of {} {} is below the minimum trade size", amount_out, token)
+            }
+            AmmError::PoolNotFound => write!(f, "Pool does not exist"),
+            AmmError::PoolExists => write!(f, "Pool already exists"),
+            AmmError::Overflow => write!(f, "Amounts are too large"),
+            AmmError::NotEnabled(message)
+            | AmmError::Unauthorized(message)
+            | AmmError::InvalidBlob(message)
+            | AmmError::NotFound(message)
+            | AmmError::Conflict(message)
+            | AmmError::LimitExceeded(message)
+            | AmmError::InvalidInput(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for AmmError {}
+
+impl From<AmmError> for String {
+    fn from(error: AmmError) -> Self {
+        format!("{}: {}", error.code(), error)
+    }
+}
@@ -0,0 +1,114 @@
+//! Faucet handing out test tokens, kept apart from the AMM actions.
+//!
+//! Minting is what a separate faucet contract would do in production; here it has its own action
+//! namespace within contract1, so its limits are enforced and proven with the AMM state they
+//! credit. A user waits `cooldown_blocks` between two mints of a token, and the faucet never
+//! mints more of a token than its lifetime cap.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{AmmContract, AmmError, UserKey};
+
+/// Actions of the faucet, wrapped in `AmmAction::Faucet`
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum FaucetAction {
+    /// Mint test tokens to a user, within the faucet's cooldown and lifetime caps
+    Mint {
+        user: String,
+        token: String,
+        amount: u128,
+    },
+}
+
+impl FaucetAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaucetAction::Mint { .. } => "mint_tokens",
+        }
+    }
+
+    pub fn tokens(&self) -> Vec<&str> {
+        match self {
+            FaucetAction::Mint { token, .. } => vec![token],
+        }
+    }
+}
+
+/// Limits of the faucet and what it handed out so far. The default has no limits.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Faucet {
+    /// Blocks a user waits between two mints of the same token, 0 for none
+    pub cooldown_blocks: u64,
+    /// Most the faucet ever mints of each token, tokens without a cap are unlimited
+    pub lifetime_caps: BTreeMap<String, u128>,
+    /// Minted so far, by token
+    pub minted: BTreeMap<String, u128>,
+    /// Block of each user's last mint of a token, kept while there is a cooldown
+    #[serde(with = "crate::entries")]
+    last_mints: BTreeMap<UserKey, u64>,
+}
+
+impl Faucet {
+    pub fn new(cooldown_blocks: u64, lifetime_caps: BTreeMap<String, u128>) -> Self {
+        Faucet { cooldown_blocks, lifetime_caps, ..Default::default() }
+    }
+
+    /// What the faucet may still mint of a token, `None` when uncapped
+    pub fn remaining(&self, token: &str) -> Option<u128> {
+        let minted = self.minted.get(token).copied().unwrap_or(0);
+        self.lifetime_caps.get(token).map(|cap| cap.saturating_sub(minted))
+    }
+}
+
+impl AmmContract {
+    /// Limit what the faucet mints
+    pub fn with_faucet(mut self, faucet: Faucet) -> Self {
+        self.faucet = faucet;
+        self
+    }
+
+    pub fn faucet(&self) -> &Faucet {
+        &self.faucet
+    }
+
+    pub(crate) fn apply_faucet(&mut self, action: FaucetAction) -> Result<Vec<u8>, AmmError> {
+        match action {
+            FaucetAction::Mint { user, token, amount } => self.mint_tokens(user, token, amount),
+        }
+    }
+
+    /// Mint test tokens through the faucet
+    pub fn mint_tokens(&mut self, user: String, token: String, amount: u128) -> Result<Vec<u8>, AmmError> {
+        if self.external_tokens.contains_key(&token) {
+            return Err(AmmError::InvalidInput(format!("{} is minted by its own contract, deposit it instead", token)));
+        }
+        if let Some(mint_cap) = self.admin_council.as_ref().and_then(|council| council.mint_cap) {
+            if amount > mint_cap {
+                return Err(AmmError::LimitExceeded(format!("Minting more than {} needs admin approval", mint_cap)));
+            }
+        }
+        if let Some(remaining) = self.faucet.remaining(&token) {
+            if amount > remaining {
+                return Err(AmmError::LimitExceeded(format!("The faucet can only mint {} more {}", remaining, token)));
+            }
+        }
+        let balance_key = self.user_key(&user, &token);
+        if self.faucet.cooldown_blocks > 0 {
+            if let Some(last_mint) = self.faucet.last_mints.get(&balance_key) {
+                let next_mint = last_mint + self.faucet.cooldown_blocks;
+                if self.block_height < next_mint {
+                    return Err(AmmError::LimitExceeded(format!("{} can mint {} again from block {}", user, token, next_mint)));
+                }
+            }
+            self.faucet.last_mints.insert(balance_key, self.block_height);
+        }
+        *self.faucet.minted.entry(token.clone()).or_insert(0) += amount;
+
+        let current_balance = *self.user_balances.get(&balance_key).unwrap_or(&0);
+        self.user_balances.insert(balance_key, current_balance + amount);
+
+        Ok(format!("Minted {} {} tokens for user {}", amount, token, user).into_bytes())
+    }
+}
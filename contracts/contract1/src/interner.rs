@@ -0,0 +1,75 @@
+//! Interning of the user identities and asset names keying per-user state.
+//!
+//! Each name is stored once and numbered in the order it was first seen, so maps with an entry per
+//! user and token hold two `u32`s per key instead of two strings, in memory, in `commit()` and in
+//! the hashing of every access.
+
+use std::collections::HashMap;
+
+use borsh::{io, BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Interner {
+    names: Vec<String>,
+    /// Index of `names`, rebuilt rather than stored
+    ids: HashMap<String, u32>,
+}
+
+impl Interner {
+    /// Id of a name, numbering it if it is new
+    pub fn intern(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// Id of a name, if it was ever interned
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Name numbered `id`
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(id as usize).map(String::as_str)
+    }
+}
+
+impl TryFrom<Vec<String>> for Interner {
+    type Error = String;
+
+    fn try_from(names: Vec<String>) -> Result<Self, String> {
+        let mut interner = Interner::default();
+        for name in names {
+            if interner.id(&name).is_some() {
+                return Err(format!("{} is interned twice", name));
+            }
+            interner.intern(&name);
+        }
+        Ok(interner)
+    }
+}
+
+impl From<Interner> for Vec<String> {
+    fn from(interner: Interner) -> Self {
+        interner.names
+    }
+}
+
+impl BorshSerialize for Interner {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        BorshSerialize::serialize(&self.names, writer)
+    }
+}
+
+impl BorshDeserialize for Interner {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let names = Vec::<String>::deserialize_reader(reader)?;
+        Interner::try_from(names).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
@@ -0,0 +1,391 @@
+//! Migration of states exported before the current schema version.
+//!
+//! Schema 1 keyed balances, liquidity tokens, volumes and liquidity mining positions by strings
+//! such as `"user_token"` or `"user_liquidity_pair"`, which `commit()` encoded and the contract
+//! formatted and hashed on every access. Schema 2 keys them by `(user, asset)` and keeps
+//! liquidity tokens apart from token balances. Schema 3 interns the user and asset names of
+//! those keys, which become pairs of ids. Schema 4 adds the swap ordering policy, batch auction
+//! pools and the swap batches waiting to clear, which older states have neither set nor any of.
+//! Schema 5 adds the caps on pools and balance entries, which older states have none of.
+//! Schema 6 keeps the layout of schema 5 but commits to a hash of it instead of the encoded
+//! state itself, so only the commitments of its dumps differ. Schema 7 gives pools a swap fee of
+//! their own, which older pools take from the insurance fund like before.
+//! Schema 8 adds the nonces of nonced actions, which no older state has consumed any of.
+//! Schema 9 adds the protocol fee and the fees it accrued, which older states have neither.
+//! Schema 10 adds the faucet's limits and what it minted, which older states start without.
+//! Schema 11 adds the keeper allowed to compact the state, which older states have none of.
+//!
+//! Only schemas 1 and 2 have a layout of their own. States of schema 3 and later decode into the
+//! current one with the fields added since at their defaults, and [`AmmContract::encode_as`]
+//! encodes them as their schema laid them out, to check the commitment they were exported with.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use borsh::BorshSerialize;
+use serde::Deserialize;
+
+use crate::batch_auction::OrderingPolicy;
+use crate::interner::Interner;
+use crate::oracle::OracleGuard;
+use crate::{
+    entries, AdminCouncil, AmmContract, AmmError, Bridge, Faucet, FeeDistribution, IdentityPolicy, InsuranceFund, LbpSchedule, LiquidityPool,
+    LpPosition, OtcOffer, StateLimits, StreamingSwap, TradeVolume, UserKey, Vesting, VoteCheckpoint,
+};
+#[cfg(feature = "rounding-audit")]
+use crate::RoundingReserve;
+
+/// `(user, asset)` key of schema 2, before names were interned
+type NamedKey = (String, String);
+
+/// `LiquidityPool` as laid out up to schema version 6, before pools had a swap fee of their own
+#[derive(BorshSerialize, Deserialize, Debug, Clone)]
+pub struct LiquidityPoolV6 {
+    pub token_a: String,
+    pub token_b: String,
+    pub reserve_a: u128,
+    pub reserve_b: u128,
+    pub total_liquidity: u128,
+    pub lbp: Option<LbpSchedule>,
+    pub fee_distribution: Option<FeeDistribution>,
+    pub scale_a: u128,
+    pub scale_b: u128,
+}
+
+impl From<LiquidityPoolV6> for LiquidityPool {
+    fn from(v6: LiquidityPoolV6) -> Self {
+        LiquidityPool {
+            token_a: v6.token_a,
+            token_b: v6.token_b,
+            reserve_a: v6.reserve_a,
+            reserve_b: v6.reserve_b,
+            total_liquidity: v6.total_liquidity,
+            lbp: v6.lbp,
+            fee_distribution: v6.fee_distribution,
+            swap_fee_bps: None,
+            scale_a: v6.scale_a,
+            scale_b: v6.scale_b,
+        }
+    }
+}
+
+impl From<&LiquidityPool> for LiquidityPoolV6 {
+    fn from(pool: &LiquidityPool) -> Self {
+        LiquidityPoolV6 {
+            token_a: pool.token_a.clone(),
+            token_b: pool.token_b.clone(),
+            reserve_a: pool.reserve_a,
+            reserve_b: pool.reserve_b,
+            total_liquidity: pool.total_liquidity,
+            lbp: pool.lbp.clone(),
+            fee_distribution: pool.fee_distribution.clone(),
+            scale_a: pool.scale_a,
+            scale_b: pool.scale_b,
+        }
+    }
+}
+
+/// `AmmContract` as laid out in schema version 1, in the same field order for borsh
+#[derive(BorshSerialize, Deserialize, Debug, Clone, Default)]
+pub struct StateV1 {
+    pub pools: BTreeMap<String, LiquidityPoolV6>,
+    /// `"user_token"` balances and `"user_liquidity_pair"` liquidity tokens
+    pub user_balances: BTreeMap<String, u128>,
+    pub identity_policy: Option<IdentityPolicy>,
+    pub swap_volume: BTreeMap<String, (u64, u128)>,
+    pub insurance: Option<InsuranceFund>,
+    pub block_height: u64,
+    pub pool_volumes: BTreeMap<String, TradeVolume>,
+    /// Keyed by `"user_volume_pair"`
+    pub user_volumes: BTreeMap<String, TradeVolume>,
+    /// Keyed by `"user_position_pair"`
+    pub lp_positions: BTreeMap<String, LpPosition>,
+    pub streaming_swaps: BTreeMap<u64, StreamingSwap>,
+    pub next_streaming_swap_id: u64,
+    pub otc_offers: BTreeMap<u64, OtcOffer>,
+    pub next_otc_offer_id: u64,
+    pub vestings: BTreeMap<u64, Vesting>,
+    pub next_vesting_id: u64,
+    pub admin_council: Option<AdminCouncil>,
+    pub paused: bool,
+    pub emergency: bool,
+    pub min_swap_output: u128,
+    #[cfg(feature = "rounding-audit")]
+    pub rounding_reserve: BTreeMap<String, RoundingReserve>,
+    pub vote_checkpoints: BTreeMap<String, Vec<VoteCheckpoint>>,
+    pub signing_keys: BTreeMap<String, Vec<u8>>,
+    pub intent_nonces: BTreeMap<String, u64>,
+    pub external_tokens: BTreeMap<String, sdk::ContractName>,
+    pub oracle_guard: Option<OracleGuard>,
+    pub bridge: Option<Bridge>,
+    pub fee_distribution: Option<FeeDistribution>,
+    pub referrers: BTreeMap<String, String>,
+    pub token_decimals: BTreeMap<String, u8>,
+    #[cfg(feature = "segmented-state")]
+    #[serde(default)]
+    pub elided_pools: BTreeMap<String, [u8; 32]>,
+}
+
+/// `AmmContract` as laid out in schema version 2, in the same field order for borsh
+#[derive(BorshSerialize, Deserialize, Debug, Clone, Default)]
+pub struct StateV2 {
+    pub pools: BTreeMap<String, LiquidityPoolV6>,
+    #[serde(deserialize_with = "entries::deserialize")]
+    pub user_balances: BTreeMap<NamedKey, u128>,
+    #[serde(deserialize_with = "entries::deserialize")]
+    pub liquidity_balances: BTreeMap<NamedKey, u128>,
+    pub identity_policy: Option<IdentityPolicy>,
+    pub swap_volume: BTreeMap<String, (u64, u128)>,
+    pub insurance: Option<InsuranceFund>,
+    pub block_height: u64,
+    pub pool_volumes: BTreeMap<String, TradeVolume>,
+    #[serde(deserialize_with = "entries::deserialize")]
+    pub user_volumes: BTreeMap<NamedKey, TradeVolume>,
+    #[serde(deserialize_with = "entries::deserialize")]
+    pub lp_positions: BTreeMap<NamedKey, LpPosition>,
+    pub streaming_swaps: BTreeMap<u64, StreamingSwap>,
+    pub next_streaming_swap_id: u64,
+    pub otc_offers: BTreeMap<u64, OtcOffer>,
+    pub next_otc_offer_id: u64,
+    pub vestings: BTreeMap<u64, Vesting>,
+    pub next_vesting_id: u64,
+    pub admin_council: Option<AdminCouncil>,
+    pub paused: bool,
+    pub emergency: bool,
+    pub min_swap_output: u128,
+    #[cfg(feature = "rounding-audit")]
+    pub rounding_reserve: BTreeMap<String, RoundingReserve>,
+    pub vote_checkpoints: BTreeMap<String, Vec<VoteCheckpoint>>,
+    pub signing_keys: BTreeMap<String, Vec<u8>>,
+    pub intent_nonces: BTreeMap<String, u64>,
+    pub external_tokens: BTreeMap<String, sdk::ContractName>,
+    pub oracle_guard: Option<OracleGuard>,
+    pub bridge: Option<Bridge>,
+    pub fee_distribution: Option<FeeDistribution>,
+    pub referrers: BTreeMap<String, String>,
+    pub token_decimals: BTreeMap<String, u8>,
+    #[cfg(feature = "segmented-state")]
+    #[serde(default)]
+    pub elided_pools: BTreeMap<String, [u8; 32]>,
+}
+
+impl TryFrom<StateV1> for StateV2 {
+    type Error = AmmError;
+
+    fn try_from(v1: StateV1) -> Result<Self, AmmError> {
+        let mut user_balances = BTreeMap::new();
+        let mut liquidity_balances = BTreeMap::new();
+        for (key, amount) in v1.user_balances {
+            // Token symbols hold no underscore, pair keys join two of them with one
+            if let Some((user, pair_key)) = key.split_once("_liquidity_") {
+                liquidity_balances.insert(named_key(user, pair_key), amount);
+            } else {
+                let (user, token) = key.rsplit_once('_').ok_or_else(|| unsplittable(&key))?;
+                user_balances.insert(named_key(user, token), amount);
+            }
+        }
+
+        Ok(StateV2 {
+            pools: v1.pools,
+            user_balances,
+            liquidity_balances,
+            identity_policy: v1.identity_policy,
+            swap_volume: v1.swap_volume,
+            insurance: v1.insurance,
+            block_height: v1.block_height,
+            pool_volumes: v1.pool_volumes,
+            user_volumes: rekey(v1.user_volumes, "_volume_")?,
+            lp_positions: rekey(v1.lp_positions, "_position_")?,
+            streaming_swaps: v1.streaming_swaps,
+            next_streaming_swap_id: v1.next_streaming_swap_id,
+            otc_offers: v1.otc_offers,
+            next_otc_offer_id: v1.next_otc_offer_id,
+            vestings: v1.vestings,
+            next_vesting_id: v1.next_vesting_id,
+            admin_council: v1.admin_council,
+            paused: v1.paused,
+            emergency: v1.emergency,
+            min_swap_output: v1.min_swap_output,
+            #[cfg(feature = "rounding-audit")]
+            rounding_reserve: v1.rounding_reserve,
+            vote_checkpoints: v1.vote_checkpoints,
+            signing_keys: v1.signing_keys,
+            intent_nonces: v1.intent_nonces,
+            external_tokens: v1.external_tokens,
+            oracle_guard: v1.oracle_guard,
+            bridge: v1.bridge,
+            fee_distribution: v1.fee_distribution,
+            referrers: v1.referrers,
+            token_decimals: v1.token_decimals,
+            #[cfg(feature = "segmented-state")]
+            elided_pools: v1.elided_pools,
+        })
+    }
+}
+
+impl From<StateV2> for AmmContract {
+    fn from(v2: StateV2) -> Self {
+        let mut identities = Interner::default();
+        let mut symbols = Interner::default();
+        let user_balances = intern_keys(v2.user_balances, &mut identities, &mut symbols);
+        let liquidity_balances = intern_keys(v2.liquidity_balances, &mut identities, &mut symbols);
+        let user_volumes = intern_keys(v2.user_volumes, &mut identities, &mut symbols);
+        let lp_positions = intern_keys(v2.lp_positions, &mut identities, &mut symbols);
+
+        AmmContract {
+            pools: upgrade_pools(v2.pools),
+            identities,
+            symbols,
+            user_balances,
+            liquidity_balances,
+            identity_policy: v2.identity_policy,
+            swap_volume: v2.swap_volume,
+            insurance: v2.insurance,
+            block_height: v2.block_height,
+            pool_volumes: v2.pool_volumes,
+            user_volumes,
+            lp_positions,
+            streaming_swaps: v2.streaming_swaps,
+            next_streaming_swap_id: v2.next_streaming_swap_id,
+            otc_offers: v2.otc_offers,
+            next_otc_offer_id: v2.next_otc_offer_id,
+            vestings: v2.vestings,
+            next_vesting_id: v2.next_vesting_id,
+            admin_council: v2.admin_council,
+            paused: v2.paused,
+            emergency: v2.emergency,
+            min_swap_output: v2.min_swap_output,
+            #[cfg(feature = "rounding-audit")]
+            rounding_reserve: v2.rounding_reserve,
+            vote_checkpoints: v2.vote_checkpoints,
+            signing_keys: v2.signing_keys,
+            intent_nonces: v2.intent_nonces,
+            action_nonces: BTreeMap::new(),
+            protocol_fee: None,
+            protocol_fees: BTreeMap::new(),
+            faucet: Faucet::default(),
+            external_tokens: v2.external_tokens,
+            oracle_guard: v2.oracle_guard,
+            bridge: v2.bridge,
+            fee_distribution: v2.fee_distribution,
+            pool_swap_fee_bps: None,
+            referrers: v2.referrers,
+            token_decimals: v2.token_decimals,
+            ordering_policy: OrderingPolicy::Arrival,
+            batch_pools: BTreeSet::new(),
+            swap_batches: BTreeMap::new(),
+            state_limits: StateLimits::default(),
+            keeper: None,
+            #[cfg(feature = "segmented-state")]
+            elided_pools: v2.elided_pools,
+        }
+    }
+}
+
+impl TryFrom<StateV1> for AmmContract {
+    type Error = AmmError;
+
+    fn try_from(v1: StateV1) -> Result<Self, AmmError> {
+        StateV2::try_from(v1).map(AmmContract::from)
+    }
+}
+
+impl AmmContract {
+    /// Borsh encoding of the state as laid out in a schema from 3 on, which dumps of that schema committed to
+    ///
+    /// Schemas after 3 only added fields and changed pools, so the fields a schema did not have yet are
+    /// left out and pools are encoded as they were.
+    pub fn encode_as(&self, schema_version: u32) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut field = |added_in: u32, bytes: Vec<u8>| {
+            if schema_version >= added_in {
+                encoded.extend(bytes);
+            }
+        };
+        if schema_version < 7 {
+            let pools: BTreeMap<&String, LiquidityPoolV6> = self.pools.iter().map(|(pair_key, pool)| (pair_key, pool.into())).collect();
+            field(3, encode(&pools));
+        } else {
+            field(7, encode(&self.pools));
+        }
+        field(3, encode(&self.identities));
+        field(3, encode(&self.symbols));
+        field(3, encode(&self.user_balances));
+        field(3, encode(&self.liquidity_balances));
+        field(3, encode(&self.identity_policy));
+        field(3, encode(&self.swap_volume));
+        field(3, encode(&self.insurance));
+        field(3, encode(&self.block_height));
+        field(3, encode(&self.pool_volumes));
+        field(3, encode(&self.user_volumes));
+        field(3, encode(&self.lp_positions));
+        field(3, encode(&self.streaming_swaps));
+        field(3, encode(&self.next_streaming_swap_id));
+        field(3, encode(&self.otc_offers));
+        field(3, encode(&self.next_otc_offer_id));
+        field(3, encode(&self.vestings));
+        field(3, encode(&self.next_vesting_id));
+        field(3, encode(&self.admin_council));
+        field(3, encode(&self.paused));
+        field(3, encode(&self.emergency));
+        field(3, encode(&self.min_swap_output));
+        #[cfg(feature = "rounding-audit")]
+        field(3, encode(&self.rounding_reserve));
+        field(3, encode(&self.vote_checkpoints));
+        field(3, encode(&self.signing_keys));
+        field(3, encode(&self.intent_nonces));
+        field(8, encode(&self.action_nonces));
+        field(3, encode(&self.external_tokens));
+        field(3, encode(&self.oracle_guard));
+        field(3, encode(&self.bridge));
+        field(3, encode(&self.fee_distribution));
+        field(7, encode(&self.pool_swap_fee_bps));
+        field(9, encode(&self.protocol_fee));
+        field(9, encode(&self.protocol_fees));
+        field(10, encode(&self.faucet));
+        field(3, encode(&self.referrers));
+        field(3, encode(&self.token_decimals));
+        field(4, encode(&self.ordering_policy));
+        field(4, encode(&self.batch_pools));
+        field(4, encode(&self.swap_batches));
+        field(5, encode(&self.state_limits));
+        field(11, encode(&self.keeper));
+        #[cfg(feature = "segmented-state")]
+        field(3, encode(&self.elided_pools));
+        encoded
+    }
+}
+
+fn encode<T: BorshSerialize>(value: &T) -> Vec<u8> {
+    borsh::to_vec(value).expect("Failed to encode state field")
+}
+
+fn upgrade_pools(pools: BTreeMap<String, LiquidityPoolV6>) -> BTreeMap<String, LiquidityPool> {
+    pools.into_iter().map(|(pair_key, pool)| (pair_key, pool.into())).collect()
+}
+
+/// Re-key a `"user{separator}pair"` map by `(user, pair)`
+fn rekey<V>(map: BTreeMap<String, V>, separator: &str) -> Result<BTreeMap<NamedKey, V>, AmmError> {
+    map.into_iter()
+        .map(|(key, value)| {
+            let (user, pair_key) = key.split_once(separator).ok_or_else(|| unsplittable(&key))?;
+            Ok((named_key(user, pair_key), value))
+        })
+        .collect()
+}
+
+/// Re-key a `(user, asset)` map by interned ids, numbering names in key order
+fn intern_keys<V>(map: BTreeMap<NamedKey, V>, identities: &mut Interner, symbols: &mut Interner) -> BTreeMap<UserKey, V> {
+    map.into_iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|((user, asset), value)| ((identities.intern(&user), symbols.intern(&asset)), value))
+        .collect()
+}
+
+fn named_key(user: &str, asset: &str) -> NamedKey {
+    (user.to_string(), asset.to_string())
+}
+
+fn unsplittable(key: &str) -> AmmError {
+    AmmError::InvalidInput(format!("Schema 1 key {} names no user and asset", key))
+}
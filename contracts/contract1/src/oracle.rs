@@ -0,0 +1,92 @@
+//! Prices attested by a separate oracle contract.
+//!
+//! The oracle contract settles its own blob, so contract1 only reads the price it carries
+//! from the same transaction and trusts it as far as it trusts that contract.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crate::{AmmError, BPS_DENOMINATOR};
+
+/// Fixed-point scale of oracle prices
+pub const ORACLE_PRICE_SCALE: u128 = 1_000_000_000_000_000_000;
+
+/// Price blob of the oracle contract: `price` units of `quote` per unit of `base`,
+/// scaled by `ORACLE_PRICE_SCALE`
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OraclePrice {
+    pub base: String,
+    pub quote: String,
+    pub price: u128,
+}
+
+impl OraclePrice {
+    pub fn as_blob(&self, oracle_contract: sdk::ContractName) -> sdk::Blob {
+        sdk::Blob {
+            contract_name: oracle_contract,
+            data: sdk::BlobData(borsh::to_vec(self).expect("Failed to encode OraclePrice")),
+        }
+    }
+
+    /// Output `amount_in` of `token_in` is worth in `token_out`, if this prices the pair
+    fn amount_out(&self, token_in: &str, token_out: &str, amount_in: u128) -> Option<u128> {
+        if self.price == 0 {
+            return None;
+        }
+        if self.base == token_in && self.quote == token_out {
+            amount_in.checked_mul(self.price).map(|v| v / ORACLE_PRICE_SCALE)
+        } else if self.base == token_out && self.quote == token_in {
+            amount_in.checked_mul(ORACLE_PRICE_SCALE).map(|v| v / self.price)
+        } else {
+            None
+        }
+    }
+}
+
+/// Keeps swaps of at least `min_amount_in` within `max_deviation_bps` of the oracle price
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OracleGuard {
+    pub oracle_contract: sdk::ContractName,
+    pub min_amount_in: u128,
+    pub max_deviation_bps: u128,
+}
+
+impl OracleGuard {
+    pub fn new(oracle_contract: sdk::ContractName, min_amount_in: u128, max_deviation_bps: u128) -> Result<Self, AmmError> {
+        if max_deviation_bps > BPS_DENOMINATOR {
+            return Err(AmmError::InvalidInput("Basis points must be at most 10000".to_string()));
+        }
+        Ok(OracleGuard { oracle_contract, min_amount_in, max_deviation_bps })
+    }
+
+    /// Require a swap paying `amount_out` to be priced within the allowed deviation by an
+    /// oracle blob of the transaction, when it is large enough to be guarded
+    pub fn check_swap(
+        &self,
+        calldata: &sdk::Calldata,
+        token_in: &str,
+        token_out: &str,
+        amount_in: u128,
+        amount_out: u128,
+    ) -> Result<(), AmmError> {
+        if amount_in < self.min_amount_in {
+            return Ok(());
+        }
+        let fair_out = calldata
+            .blobs
+            .iter()
+            .map(|(_, blob)| blob)
+            .filter(|blob| blob.contract_name == self.oracle_contract)
+            .filter_map(|blob| borsh::from_slice::<OraclePrice>(&blob.data.0).ok())
+            .find_map(|price| price.amount_out(token_in, token_out, amount_in))
+            .ok_or_else(|| AmmError::InvalidBlob(format!("Swaps of {} {} or more need a {} price for {}/{}",
+                self.min_amount_in, token_in, self.oracle_contract, token_in, token_out)))?;
+
+        let deviation = fair_out.abs_diff(amount_out);
+        if deviation.saturating_mul(BPS_DENOMINATOR) > fair_out.saturating_mul(self.max_deviation_bps) {
+            return Err(AmmError::LimitExceeded(format!("Swap output {} {} is more than {} bps away from the oracle price ({} {})",
+                amount_out, token_out, self.max_deviation_bps, fair_out, token_out)));
+        }
+        Ok(())
+    }
+}
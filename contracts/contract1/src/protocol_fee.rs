@@ -0,0 +1,74 @@
+//! Protocol fee: a share of swap fees kept for the protocol rather than liquidity providers.
+//!
+//! The share is taken from what a swap fee leaves liquidity providers once the insurance fund and
+//! referrers had their cuts, so it never takes from those. It accrues per token until the
+//! protocol fee admin collects it into their balances.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{AmmContract, AmmError, BPS_DENOMINATOR};
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProtocolFee {
+    /// Identity allowed to collect the accrued fees
+    pub admin: String,
+    /// Share of the liquidity providers' part of each swap fee, in basis points
+    pub share_bps: u128,
+}
+
+impl ProtocolFee {
+    pub fn new(admin: String, share_bps: u128) -> Result<Self, AmmError> {
+        if share_bps > BPS_DENOMINATOR {
+            return Err(AmmError::InvalidInput("Basis points must be at most 10000".to_string()));
+        }
+        Ok(ProtocolFee { admin, share_bps })
+    }
+}
+
+impl AmmContract {
+    /// Keep a share of swap fees for the protocol, collected by its admin
+    pub fn with_protocol_fee(mut self, protocol_fee: ProtocolFee) -> Self {
+        self.protocol_fee = Some(protocol_fee);
+        self
+    }
+
+    pub fn protocol_fee(&self) -> Option<&ProtocolFee> {
+        self.protocol_fee.as_ref()
+    }
+
+    /// Protocol fees accrued and not collected yet, by token
+    pub fn protocol_fees(&self) -> &BTreeMap<String, u128> {
+        &self.protocol_fees
+    }
+
+    /// Protocol's cut of the part of a swap fee left to liquidity providers
+    pub(crate) fn protocol_cut(&self, lp_fee: u128) -> u128 {
+        self.protocol_fee.as_ref().map_or(0, |protocol_fee| lp_fee * protocol_fee.share_bps / BPS_DENOMINATOR)
+    }
+
+    pub(crate) fn accrue_protocol_fee(&mut self, token: &str, cut: u128) {
+        if cut > 0 {
+            *self.protocol_fees.entry(token.to_string()).or_insert(0) += cut;
+        }
+    }
+
+    /// Move every accrued protocol fee to the admin's balances
+    pub fn collect_protocol_fees(&mut self, admin: String) -> Result<Vec<u8>, AmmError> {
+        let protocol_fee = self.protocol_fee.as_ref()
+            .ok_or_else(|| AmmError::InvalidInput("No protocol fee is set".to_string()))?;
+        if admin != protocol_fee.admin {
+            return Err(AmmError::Unauthorized(format!("{} is not the protocol fee admin", admin)));
+        }
+
+        let collected = std::mem::take(&mut self.protocol_fees);
+        for (token, amount) in &collected {
+            let key = self.user_key(&admin, token);
+            *self.user_balances.entry(key).or_insert(0) += amount;
+        }
+        let amounts: Vec<_> = collected.iter().map(|(token, amount)| format!("{} {}", amount, token)).collect();
+        Ok(format!("Collected protocol fees to {}: {}", admin,
+            if amounts.is_empty() { "nothing".to_string() } else { amounts.join(", ") }).into_bytes())
+    }
+}
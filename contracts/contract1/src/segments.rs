@@ -0,0 +1,228 @@
+//! Segmented state commitment, only compiled with the `segmented-state` feature.
+//!
+//! The state commits to a root over a global segment and one segment per pool, holding the pool
+//! with its volume and rounding reserve. Proofs are given a witness carrying only the pools their
+//! action touches, every other pool being replaced by the digest of its segment, so the guest no
+//! longer decodes, encodes and hashes every pool of the deployment on each transaction.
+
+use std::collections::BTreeSet;
+
+use borsh::BorshSerialize;
+use sha2::{Digest, Sha256};
+
+use crate::{AmmAction, AmmContract, AmmError, LiquidityPool, TradeVolume};
+#[cfg(feature = "rounding-audit")]
+use crate::RoundingReserve;
+
+/// Domain separator of the state root, bumped with the segment layout
+const ROOT_DOMAIN: &[u8] = b"hyli-amm-state-root-v1";
+
+#[derive(BorshSerialize)]
+struct PoolSegment<'a> {
+    pool: Option<&'a LiquidityPool>,
+    volume: Option<&'a TradeVolume>,
+    #[cfg(feature = "rounding-audit")]
+    rounding: Option<&'a RoundingReserve>,
+}
+
+impl AmmContract {
+    /// Root the state commits to: the global segment, then every pool segment by pair key
+    pub fn state_root(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(ROOT_DOMAIN);
+        hasher.update(self.global_digest());
+        for pair_key in self.segment_keys() {
+            hasher.update((pair_key.len() as u32).to_le_bytes());
+            hasher.update(pair_key.as_bytes());
+            hasher.update(self.pool_digest(pair_key));
+        }
+        hasher.finalize().into()
+    }
+
+    /// The state trimmed to the pools `action` touches, committing to the same root.
+    ///
+    /// Actions that may touch any pool get the full state.
+    pub fn witness(&self, action: &AmmAction) -> AmmContract {
+        let mut witness = self.clone();
+        let Some(touched) = self.footprint(action) else {
+            return witness;
+        };
+        for pair_key in self.segment_keys() {
+            if touched.contains(pair_key) || self.elided_pools.contains_key(pair_key) {
+                continue;
+            }
+            let digest = self.pool_digest(pair_key);
+            witness.pools.remove(pair_key);
+            witness.pool_volumes.remove(pair_key);
+            #[cfg(feature = "rounding-audit")]
+            witness.rounding_reserve.remove(pair_key);
+            witness.elided_pools.insert(pair_key.clone(), digest);
+        }
+        witness
+    }
+
+    /// Refuse an action needing a pool the witness left out, which would read as missing
+    pub(crate) fn require_witnessed(&self, action: &AmmAction) -> Result<(), AmmError> {
+        if self.elided_pools.is_empty() {
+            return Ok(());
+        }
+        let elided = match self.footprint(action) {
+            Some(touched) => touched.into_iter().find(|pair_key| self.elided_pools.contains_key(pair_key)),
+            None => self.elided_pools.keys().next().cloned(),
+        };
+        match elided {
+            Some(pair_key) => Err(AmmError::InvalidInput(format!("State witness lacks the {} pool", pair_key))),
+            None => Ok(()),
+        }
+    }
+
+    /// Pools an action reads or writes, `None` when it may touch any
+    fn footprint(&self, action: &AmmAction) -> Option<BTreeSet<String>> {
+        let pair = |token_a: &str, token_b: &str| Some(BTreeSet::from([self.get_pair_key(token_a, token_b)]));
+        match action {
+            AmmAction::AddLiquidity { token_a, token_b, .. }
+            | AmmAction::CreateLbpPool { token_a, token_b, .. }
+            | AmmAction::CreateBatchAuctionPool { token_a, token_b, .. }
+            | AmmAction::RemoveLiquidity { token_a, token_b, .. }
+            | AmmAction::GetReserves { token_a, token_b }
+            | AmmAction::GetVolume { token_a, token_b, .. }
+            | AmmAction::GetRoundingReserve { token_a, token_b } => pair(token_a, token_b),
+            AmmAction::SwapExactTokensForTokens { token_in, token_out, .. }
+            | AmmAction::CreateStreamingSwap { token_in, token_out, .. } => pair(token_in, token_out),
+            AmmAction::RelaySwap { intent, .. } => pair(&intent.token_in, &intent.token_out),
+            // Due chunks and batches depend on the block of the transaction, so every one is included
+            AmmAction::ExecuteDue {} => Some(
+                self.streaming_swaps
+                    .values()
+                    .map(|order| self.get_pair_key(&order.token_in, &order.token_out))
+                    .chain(self.swap_batches.keys().cloned())
+                    .collect(),
+            ),
+            // Drained pools are dropped, so the witness needs each of them to find it drained
+            AmmAction::Compact {} => Some(self.drained_pools()),
+            AmmAction::ProposeAdminAction { .. } | AmmAction::ApproveAdminAction { .. } => None,
+            _ => Some(BTreeSet::new()),
+        }
+    }
+
+    /// Pair keys with a segment, loaded or elided
+    fn segment_keys(&self) -> BTreeSet<&String> {
+        let keys = self.pools.keys().chain(self.pool_volumes.keys()).chain(self.elided_pools.keys());
+        #[cfg(feature = "rounding-audit")]
+        let keys = keys.chain(self.rounding_reserve.keys());
+        keys.collect()
+    }
+
+    fn pool_digest(&self, pair_key: &str) -> [u8; 32] {
+        if let Some(digest) = self.elided_pools.get(pair_key) {
+            return *digest;
+        }
+        let segment = PoolSegment {
+            pool: self.pools.get(pair_key),
+            volume: self.pool_volumes.get(pair_key),
+            #[cfg(feature = "rounding-audit")]
+            rounding: self.rounding_reserve.get(pair_key),
+        };
+        Sha256::digest(borsh::to_vec(&segment).expect("Failed to encode pool segment")).into()
+    }
+
+    /// Digest of everything outside the pool segments
+    fn global_digest(&self) -> [u8; 32] {
+        // Listing every field makes a new one fail to compile until it is placed in a segment
+        let AmmContract {
+            pools: _,
+            pool_volumes: _,
+            #[cfg(feature = "rounding-audit")]
+            rounding_reserve: _,
+            elided_pools: _,
+            identities,
+            symbols,
+            user_balances,
+            liquidity_balances,
+            identity_policy,
+            swap_volume,
+            insurance,
+            block_height,
+            user_volumes,
+            lp_positions,
+            streaming_swaps,
+            next_streaming_swap_id,
+            otc_offers,
+            next_otc_offer_id,
+            vestings,
+            next_vesting_id,
+            admin_council,
+            paused,
+            emergency,
+            min_swap_output,
+            vote_checkpoints,
+            signing_keys,
+            intent_nonces,
+            action_nonces,
+            external_tokens,
+            oracle_guard,
+            bridge,
+            fee_distribution,
+            pool_swap_fee_bps,
+            protocol_fee,
+            protocol_fees,
+            faucet,
+            referrers,
+            token_decimals,
+            ordering_policy,
+            batch_pools,
+            swap_batches,
+            state_limits,
+            keeper,
+        } = self;
+
+        let mut bytes = Vec::new();
+        macro_rules! encode {
+            ($($field:expr),*) => {
+                $(BorshSerialize::serialize($field, &mut bytes).expect("Failed to encode AMM state");)*
+            };
+        }
+        encode!(
+            identities,
+            symbols,
+            user_balances,
+            liquidity_balances,
+            identity_policy,
+            swap_volume,
+            insurance,
+            block_height,
+            user_volumes,
+            lp_positions,
+            streaming_swaps,
+            next_streaming_swap_id,
+            otc_offers,
+            next_otc_offer_id,
+            vestings,
+            next_vesting_id,
+            admin_council,
+            paused,
+            emergency,
+            min_swap_output,
+            vote_checkpoints,
+            signing_keys,
+            intent_nonces,
+            action_nonces,
+            external_tokens,
+            oracle_guard,
+            bridge,
+            fee_distribution,
+            pool_swap_fee_bps,
+            protocol_fee,
+            protocol_fees,
+            faucet,
+            referrers,
+            token_decimals,
+            ordering_policy,
+            batch_pools,
+            swap_batches,
+            state_limits,
+            keeper
+        );
+        Sha256::digest(bytes).into()
+    }
+}
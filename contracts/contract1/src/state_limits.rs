@@ -0,0 +1,100 @@
+//! Caps on the size of the state.
+//!
+//! Every proof carries and commits the whole state, so pools and balance entries created at no
+//! cost would make proving slower for everyone until it is impractical. Entries spent down to
+//! zero stay until a `Compact` drops them, so the caps bound what is held or was held since the
+//! last compaction.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crate::{AmmAction, AmmContract, AmmError};
+
+/// Unset caps do not apply
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StateLimits {
+    pub max_pools: Option<u64>,
+    /// Token and liquidity balance entries a transaction may bring its own identity to
+    pub max_entries_per_user: Option<u64>,
+    /// Token and liquidity balance entries of all users
+    pub max_total_entries: Option<u64>,
+}
+
+/// Sizes of the capped state before an action, to tell what the action added
+pub(crate) struct StateSize {
+    pools: usize,
+    entries: usize,
+    user_entries: usize,
+}
+
+impl AmmContract {
+    /// Cap the pools and balance entries transactions may create
+    pub fn with_state_limits(mut self, limits: StateLimits) -> Self {
+        self.state_limits = limits;
+        self
+    }
+
+    pub fn state_limits(&self) -> &StateLimits {
+        &self.state_limits
+    }
+
+    /// Pools, and token and liquidity balance entries in total
+    pub fn state_size(&self) -> (usize, usize) {
+        let pools = self.pools.len();
+        // Pools a proof witness left out still count
+        #[cfg(feature = "segmented-state")]
+        let pools = pools + self.elided_pools.len();
+        (pools, self.user_balances.len() + self.liquidity_balances.len())
+    }
+
+    /// Size of the state before an action a user submits, unless the action is not capped.
+    ///
+    /// The keeper's due chunks and batches, the admin council's operations and protocol fee
+    /// collection only settle what was already committed to, or are how the caps get raised, so
+    /// they are not capped.
+    pub(crate) fn measure(&self, action: &AmmAction, user: &str) -> Option<StateSize> {
+        if matches!(action, AmmAction::ExecuteDue {} | AmmAction::ProposeAdminAction { .. } | AmmAction::ApproveAdminAction { .. }
+            | AmmAction::CollectProtocolFees { .. }) {
+            return None;
+        }
+        let (pools, entries) = self.state_size();
+        Some(StateSize { pools, entries, user_entries: self.user_entries(user) })
+    }
+
+    /// Refuse an action that grew the state beyond a cap
+    pub(crate) fn check_state_limits(&self, user: &str, before: &StateSize) -> Result<(), AmmError> {
+        let limits = &self.state_limits;
+        let (pools, entries) = self.state_size();
+        if let Some(max) = limits.max_pools.filter(|max| pools > before.pools && pools as u64 > *max) {
+            return Err(AmmError::LimitExceeded(format!(
+                "The AMM holds at most {} pools until the admin council raises the limit", max
+            )));
+        }
+        if entries <= before.entries {
+            return Ok(());
+        }
+        if let Some(max) = limits.max_total_entries.filter(|max| entries as u64 > *max) {
+            return Err(AmmError::LimitExceeded(format!(
+                "The AMM holds at most {} balance entries until the admin council raises the limit", max
+            )));
+        }
+        let user_entries = self.user_entries(user);
+        if let Some(max) = limits.max_entries_per_user.filter(|max| user_entries > before.user_entries && user_entries as u64 > *max) {
+            return Err(AmmError::LimitExceeded(format!(
+                "{} would hold {} token and pool balances, above the limit of {}", user, user_entries, max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Token and liquidity balance entries of a user, only counted when capped
+    fn user_entries(&self, user: &str) -> usize {
+        let Some(id) = self.state_limits.max_entries_per_user.and(self.identities.id(user)) else {
+            return 0;
+        };
+        self.user_balances.keys()
+            .chain(self.liquidity_balances.keys())
+            .filter(|(holder, _)| *holder == id)
+            .count()
+    }
+}
@@ -0,0 +1,135 @@
+//! Canonical token symbols.
+//!
+//! Symbols are plain strings keying balances and pools, so "usdc" and "USDC" used to hold
+//! parallel balances and pools. The contract now only accepts uppercase symbols, and the
+//! `MergeTokenAliases` admin operation folds what older states hold under other spellings into
+//! the uppercase ones.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{AmmContract, AmmError};
+
+/// Form of a token symbol the contract accepts
+pub fn canonical_token(token: &str) -> String {
+    token.to_ascii_uppercase()
+}
+
+fn is_canonical(token: &str) -> bool {
+    !token.bytes().any(|byte| byte.is_ascii_lowercase())
+}
+
+impl AmmContract {
+    /// Refuse actions naming a token by anything but its canonical symbol
+    pub(crate) fn require_canonical_tokens(&self, tokens: &[&str]) -> Result<(), AmmError> {
+        match tokens.iter().find(|token| !is_canonical(token)) {
+            Some(token) => Err(AmmError::InvalidInput(format!(
+                "Token symbols are uppercase, use {} instead of {}", canonical_token(token), token
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fold every symbol that is not canonical into its canonical form.
+    ///
+    /// Pools trading such a symbol are dissolved, pending batch swaps refunded and liquidity paid
+    /// back pro-rata, since they cannot be merged into a pool at another price. Balances, the
+    /// tokens escrowed by streaming swaps, OTC offers, vestings, claims and withdrawals, insurance
+    /// fund holdings and the token registry then move to the canonical symbol.
+    pub(crate) fn merge_token_aliases(&mut self) -> Result<String, AmmError> {
+        let aliased_pools: BTreeSet<String> = self.pools.iter()
+            .filter(|(_, pool)| !is_canonical(&pool.token_a) || !is_canonical(&pool.token_b))
+            .map(|(pair_key, _)| pair_key.clone())
+            .collect();
+        for pair_key in &aliased_pools {
+            self.dissolve_pool(pair_key)?;
+        }
+
+        // Sorted so canonical symbols seen for the first time are interned in the same order
+        // by every prover
+        let aliased_balances: BTreeMap<(u32, u32), u128> = self.user_balances.iter()
+            .filter(|((_, token), _)| self.symbols.name(*token).is_some_and(|name| !is_canonical(name)))
+            .map(|(key, amount)| (*key, *amount))
+            .collect();
+        let mut aliases = BTreeSet::new();
+        for ((user, token), amount) in &aliased_balances {
+            self.user_balances.remove(&(*user, *token));
+            let alias = self.symbols.name(*token).unwrap_or_default().to_string();
+            let canonical = self.symbols.intern(&canonical_token(&alias));
+            *self.user_balances.entry((*user, canonical)).or_insert(0) += amount;
+            aliases.insert(alias);
+        }
+
+        let canonicalize = |token: &mut String| {
+            if !is_canonical(token) {
+                *token = canonical_token(token);
+            }
+        };
+        for order in self.streaming_swaps.values_mut() {
+            canonicalize(&mut order.token_in);
+            canonicalize(&mut order.token_out);
+        }
+        for offer in self.otc_offers.values_mut() {
+            canonicalize(&mut offer.give.token);
+            canonicalize(&mut offer.want.token);
+        }
+        for vesting in self.vestings.values_mut() {
+            canonicalize(&mut vesting.token);
+        }
+        if let Some(fund) = self.insurance.as_mut() {
+            let aliased: Vec<String> = fund.balances.keys().filter(|token| !is_canonical(token)).cloned().collect();
+            for alias in aliased {
+                let amount = fund.balances.remove(&alias).unwrap_or_default();
+                *fund.balances.entry(canonical_token(&alias)).or_insert(0) += amount;
+            }
+            for claim in fund.claims.values_mut() {
+                canonicalize(&mut claim.token);
+            }
+        }
+        if let Some(bridge) = self.bridge.as_mut() {
+            for deposit in bridge.deposits.values_mut() {
+                canonicalize(&mut deposit.token);
+            }
+            for withdrawal in bridge.withdrawals.values_mut() {
+                canonicalize(&mut withdrawal.token);
+            }
+        }
+        // Registered symbols win over their aliases
+        let aliased: Vec<String> = self.external_tokens.keys().filter(|token| !is_canonical(token)).cloned().collect();
+        for alias in aliased {
+            if let Some(contract) = self.external_tokens.remove(&alias) {
+                self.external_tokens.entry(canonical_token(&alias)).or_insert(contract);
+            }
+        }
+        let aliased: Vec<String> = self.token_decimals.keys().filter(|token| !is_canonical(token)).cloned().collect();
+        for alias in aliased {
+            if let Some(decimals) = self.token_decimals.remove(&alias) {
+                self.token_decimals.entry(canonical_token(&alias)).or_insert(decimals);
+            }
+        }
+
+        Ok(format!("Merged the balances of {} token aliases ({}) and dissolved {} pools ({})",
+            aliases.len(), aliases.into_iter().collect::<Vec<_>>().join(", "),
+            aliased_pools.len(), aliased_pools.into_iter().collect::<Vec<_>>().join(", ")))
+    }
+
+    /// Pay a pool back to its liquidity providers and remove it
+    fn dissolve_pool(&mut self, pair_key: &str) -> Result<(), AmmError> {
+        if let Some(batch) = self.swap_batches.remove(pair_key) {
+            self.refund(batch.orders);
+        }
+        let pool = self.pools.get(pair_key).cloned().ok_or(AmmError::PoolNotFound)?;
+        let providers: BTreeMap<String, u128> = self.liquidity_balances()
+            .filter(|(_, pool_key, liquidity)| *pool_key == pair_key && *liquidity > 0)
+            .map(|(user, _, liquidity)| (user.to_string(), liquidity))
+            .collect();
+        for (user, liquidity) in providers {
+            self.remove_liquidity(user, pool.token_a.clone(), pool.token_b.clone(), liquidity)?;
+        }
+        // Reserves no provider holds a share of are left for `SweepDust`
+        if self.pools.get(pair_key).is_some_and(|pool| pool.reserve_a == 0 && pool.reserve_b == 0) {
+            self.pools.remove(pair_key);
+            self.batch_pools.remove(pair_key);
+        }
+        Ok(())
+    }
+}
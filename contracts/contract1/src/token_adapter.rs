@@ -0,0 +1,49 @@
+//! Adapter to tokens held in external Hyli token contracts.
+//!
+//! The AMM keeps trading on its own ledger; for external tokens that ledger is the custody
+//! of tokens transferred to the AMM. Deposits and withdrawals are only credited or debited
+//! when the same transaction carries the matching transfer blob of the token contract.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+use crate::AmmError;
+
+/// Transfer blob an external token contract is expected to settle
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenTransfer {
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u128,
+}
+
+impl TokenTransfer {
+    pub fn as_blob(&self, token_contract: sdk::ContractName) -> sdk::Blob {
+        sdk::Blob {
+            contract_name: token_contract,
+            data: sdk::BlobData(borsh::to_vec(self).expect("Failed to encode TokenTransfer")),
+        }
+    }
+}
+
+/// Require a transfer blob of `token_contract` in the transaction matching `expected`
+pub fn require_transfer(
+    calldata: &sdk::Calldata,
+    token_contract: &sdk::ContractName,
+    expected: &TokenTransfer,
+) -> Result<(), AmmError> {
+    let found = calldata
+        .blobs
+        .iter()
+        .map(|(_, blob)| blob)
+        .filter(|blob| &blob.contract_name == token_contract)
+        .filter_map(|blob| borsh::from_slice::<TokenTransfer>(&blob.data.0).ok())
+        .any(|transfer| &transfer == expected);
+    if !found {
+        return Err(AmmError::InvalidBlob(format!(
+            "Missing {} transfer of {} from {} to {}",
+            token_contract, expected.amount, expected.sender, expected.recipient
+        )));
+    }
+    Ok(())
+}
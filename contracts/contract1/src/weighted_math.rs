@@ -0,0 +1,110 @@
+//! Weighted pool math in 18-decimal fixed point.
+//!
+//! Integer only, so the prover and the host executing the contract always agree.
+
+const ONE: i128 = 1_000_000_000_000_000_000;
+const LN_2: i128 = 693_147_180_559_945_309;
+
+/// Output of a swap against a weighted pool (no fees):
+/// Δy = y * (1 - (x / (x + Δx)) ^ (w_in / w_out))
+pub fn weighted_amount_out(
+    amount_in: u128,
+    reserve_in: u128,
+    reserve_out: u128,
+    weight_in: u128,
+    weight_out: u128,
+) -> u128 {
+    if weight_in == weight_out {
+        return crate::get_amount_out(amount_in, reserve_in, reserve_out);
+    }
+    if amount_in == 0 {
+        return 0;
+    }
+
+    let ratio = (reserve_in * ONE as u128 / (reserve_in + amount_in)) as i128;
+    if ratio == 0 {
+        return reserve_out;
+    }
+    let exponent = ln(ratio) * weight_in as i128 / weight_out as i128;
+    let remaining = exp(exponent).clamp(0, ONE);
+
+    reserve_out * (ONE - remaining) as u128 / ONE as u128
+}
+
+/// Natural logarithm of 0 < x <= 1
+fn ln(mut x: i128) -> i128 {
+    // Bring x into [0.5, 1], where the series below converges fast
+    let mut halvings = 0;
+    while x < ONE / 2 {
+        x *= 2;
+        halvings += 1;
+    }
+
+    // ln(x) = 2 * atanh(z) = 2 * (z + z^3/3 + z^5/5 + ...), z = (x - 1) / (x + 1)
+    let z = (x - ONE) * ONE / (x + ONE);
+    let z_squared = z * z / ONE;
+    let (mut sum, mut term, mut n) = (0, z, 1);
+    while term != 0 {
+        sum += term / n;
+        term = term * z_squared / ONE;
+        n += 2;
+    }
+
+    2 * sum - halvings * LN_2
+}
+
+/// Exponential of y <= 0
+fn exp(y: i128) -> i128 {
+    // e^y = e^f / 2^k with f in (-ln 2, 0]
+    let k = -y / LN_2;
+    if k >= 127 {
+        return 0;
+    }
+    let f = y + k * LN_2;
+
+    let (mut sum, mut term, mut n) = (ONE, ONE, 1);
+    loop {
+        term = term * f / ONE / n;
+        if term == 0 {
+            break;
+        }
+        sum += term;
+        n += 1;
+    }
+
+    sum >> k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_float_reference() {
+        // y * (1 - (x / (x + dx)) ^ (w_in / w_out)) computed in f64
+        let cases = [
+            (100_000, 8_000, 2_000, 316_986),
+            (100_000, 2_000, 8_000, 23_545),
+            (500_000, 9_000, 1_000, 973_987),
+        ];
+        for (amount_in, weight_in, weight_out, expected) in cases {
+            let out = weighted_amount_out(amount_in, 1_000_000, 1_000_000, weight_in, weight_out);
+            assert!(out.abs_diff(expected) <= 1, "{} vs {}", out, expected);
+        }
+    }
+
+    #[test]
+    fn test_equal_weights_are_constant_product() {
+        assert_eq!(
+            weighted_amount_out(1_000, 1_000_000, 1_000_000, 3_000, 3_000),
+            crate::get_amount_out(1_000, 1_000_000, 1_000_000)
+        );
+    }
+
+    #[test]
+    fn test_ln_and_exp_round_trip() {
+        for x in [ONE, ONE / 2, ONE / 3, ONE / 1_000_000] {
+            assert!(exp(ln(x)).abs_diff(x) < 1_000_000, "{}", x);
+        }
+    }
+}
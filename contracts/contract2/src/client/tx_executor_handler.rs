@@ -26,11 +26,14 @@ impl TxExecutorHandler for Contract2 {
         ))
     }
 
+    /// Start from the state the contract was registered with, rather than assuming it was empty
     fn construct_state(
-        _register_blob: &RegisterContractEffect,
+        register_blob: &RegisterContractEffect,
         _metadata: &Option<Vec<u8>>,
     ) -> anyhow::Result<Self> {
-        Ok(Self::default())
+        Self::try_from(register_blob.state_commitment.clone())
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Could not construct {} state", register_blob.contract_name.0))
     }
 
     fn get_state_commitment(&self) -> sdk::StateCommitment {
@@ -1,6 +1,6 @@
 use borsh::{io::Error, BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use sdk::RunResult;
 
@@ -18,8 +18,14 @@ impl sdk::ZkContract for IdentityContract {
 
         // Execute the given action
         let res = match action {
-            IdentityAction::VerifyIdentity { user, country_code, proof_data } => {
-                self.verify_identity(user, country_code, proof_data)?
+            IdentityAction::VerifyIdentity { user, country_code, proof_data, circuit_version } => {
+                self.verify_identity_with(user, country_code, proof_data, circuit_version)?
+            },
+            IdentityAction::RegisterCircuitVersion { vk_hash } => {
+                self.register_circuit_version(&calldata.identity.0, vk_hash)?
+            },
+            IdentityAction::DeprecateCircuitVersion { vk_hash } => {
+                self.deprecate_circuit_version(&calldata.identity.0, vk_hash)?
             },
             IdentityAction::GetVerificationStatus { user } => {
                 self.get_verification_status(user)?
@@ -32,22 +38,43 @@ impl sdk::ZkContract for IdentityContract {
         Ok((res, ctx, vec![]))
     }
 
-    /// Serialize the full identity state on-chain
+    /// Serialize the full identity state on-chain, maps in key order, as the server decodes it back
     fn commit(&self) -> sdk::StateCommitment {
         sdk::StateCommitment(self.as_bytes().expect("Failed to encode Identity state"))
     }
 }
 
 impl IdentityContract {
-    /// Verify user identity and check they are NOT from US
+    /// Let `registrar` register and deprecate circuit versions
+    pub fn with_registrar(mut self, registrar: impl Into<String>) -> Self {
+        self.registrar = Some(registrar.into());
+        self
+    }
+
+    /// Verify user identity against the unversioned circuit, and check they are NOT from US
     pub fn verify_identity(&mut self, user: String, country_code: String, proof_data: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.verify_identity_with(user, country_code, proof_data, String::new())
+    }
+
+    /// Verify user identity with a proof of the given circuit version, and check they are NOT from US.
+    ///
+    /// Once any version is registered, the proof must come from an active one; until then only
+    /// unversioned proofs are accepted, as before the registry existed.
+    pub fn verify_identity_with(
+        &mut self,
+        user: String,
+        country_code: String,
+        proof_data: Vec<u8>,
+        circuit_version: String,
+    ) -> Result<Vec<u8>, String> {
         // Basic proof validation (in real implementation, this would verify ZKPassport SNARK proof)
         if proof_data.len() < 32 {
             return Err("Invalid proof data - too short".to_string());
         }
+        self.check_circuit_version(&circuit_version)?;
         
         // Check if country code indicates US citizenship/residency
-        let is_us_related = country_code == "USA" || country_code == "US" || country_code == "840"; // ISO country codes
+        let is_us_related = is_restricted_country(&country_code);
         
         let verification_result = IdentityVerification {
             user: user.clone(),
@@ -55,6 +82,7 @@ impl IdentityContract {
             is_allowed: !is_us_related, // Allow if NOT US-related
             verified_at: self.get_current_timestamp(),
             proof_hash: self.hash_proof(&proof_data),
+            circuit_version,
         };
         
         // Store verification result
@@ -91,6 +119,74 @@ impl IdentityContract {
         Ok(format!("User {} is {}", user, if is_allowed { "ALLOWED" } else { "NOT ALLOWED" }).into_bytes())
     }
     
+    /// Accept proofs of a new circuit version, identified by the hex SHA-256 of its verification key
+    pub fn register_circuit_version(&mut self, caller: &str, vk_hash: String) -> Result<Vec<u8>, String> {
+        self.check_registrar(caller)?;
+        if !is_vk_hash(&vk_hash) {
+            return Err(format!("Invalid circuit version {} - expected a hex SHA-256", vk_hash));
+        }
+        match self.circuit_versions.get(&vk_hash) {
+            Some(CircuitStatus::Active) => return Err(format!("Circuit version {} is already registered", vk_hash)),
+            Some(CircuitStatus::Deprecated) => return Err(format!("Circuit version {} was deprecated", vk_hash)),
+            None => {},
+        }
+        self.circuit_versions.insert(vk_hash.clone(), CircuitStatus::Active);
+        Ok(format!("Circuit version {} registered", vk_hash).into_bytes())
+    }
+
+    /// Refuse new proofs of a circuit version; verifications already made with it stand
+    pub fn deprecate_circuit_version(&mut self, caller: &str, vk_hash: String) -> Result<Vec<u8>, String> {
+        self.check_registrar(caller)?;
+        match self.circuit_versions.get_mut(&vk_hash) {
+            Some(status @ CircuitStatus::Active) => *status = CircuitStatus::Deprecated,
+            Some(CircuitStatus::Deprecated) => return Err(format!("Circuit version {} is already deprecated", vk_hash)),
+            None => return Err(format!("Unknown circuit version {}", vk_hash)),
+        }
+        Ok(format!("Circuit version {} deprecated", vk_hash).into_bytes())
+    }
+
+    /// Status of a registered circuit version
+    pub fn circuit_version(&self, vk_hash: &str) -> Option<CircuitStatus> {
+        self.circuit_versions.get(vk_hash).copied()
+    }
+
+    fn check_registrar(&self, caller: &str) -> Result<(), String> {
+        match &self.registrar {
+            Some(registrar) if registrar == caller => Ok(()),
+            Some(_) => Err(format!("Circuit versions cannot be managed by {}", caller)),
+            None => Err("Circuit versions cannot be managed: no registrar".to_string()),
+        }
+    }
+
+    fn check_circuit_version(&self, circuit_version: &str) -> Result<(), String> {
+        if self.circuit_versions.is_empty() {
+            return match circuit_version.is_empty() {
+                true => Ok(()),
+                false => Err(format!("Unknown circuit version {}", circuit_version)),
+            };
+        }
+        match self.circuit_versions.get(circuit_version) {
+            Some(CircuitStatus::Active) => Ok(()),
+            Some(CircuitStatus::Deprecated) => Err(format!("Circuit version {} is deprecated", circuit_version)),
+            None => Err(format!("Unknown circuit version {}", circuit_version)),
+        }
+    }
+
+    /// Whether a user passed identity verification and is allowed to trade
+    pub fn allows(&self, user: &str) -> bool {
+        self.allowed_users.contains(user)
+    }
+
+    /// Users currently allowed to trade
+    pub fn allowed_users(&self) -> impl Iterator<Item = &String> {
+        self.allowed_users.iter()
+    }
+
+    /// Latest verification recorded for a user
+    pub fn verification(&self, user: &str) -> Option<&IdentityVerification> {
+        self.verifications.get(user)
+    }
+
     /// Simple timestamp simulation (in real implementation would use block timestamp)
     fn get_current_timestamp(&self) -> u64 {
         // In a real implementation, this would come from block metadata
@@ -104,12 +200,34 @@ impl IdentityContract {
     }
 }
 
+/// Whether a country code indicates US citizenship/residency (ISO alpha-2, alpha-3 or numeric)
+pub fn is_restricted_country(country_code: &str) -> bool {
+    country_code == "USA" || country_code == "US" || country_code == "840"
+}
+
+/// Whether a circuit version is a lowercase hex SHA-256, as the server hashes verification keys
+fn is_vk_hash(vk_hash: &str) -> bool {
+    vk_hash.len() == 64 && vk_hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Default)]
 pub struct IdentityContract {
     /// Map of user -> their identity verification
-    verifications: HashMap<String, IdentityVerification>,
+    verifications: BTreeMap<String, IdentityVerification>,
     /// Set of users who are allowed (not US citizens/residents)
-    allowed_users: std::collections::HashSet<String>,
+    allowed_users: BTreeSet<String>,
+    /// Accepted circuit versions, by verification key hash
+    circuit_versions: BTreeMap<String, CircuitStatus>,
+    /// Identity allowed to register and deprecate circuit versions
+    registrar: Option<String>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitStatus {
+    /// New proofs of this version are accepted
+    Active,
+    /// Only verifications already made with this version stand
+    Deprecated,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
@@ -119,6 +237,8 @@ pub struct IdentityVerification {
     pub is_allowed: bool,
     pub verified_at: u64,
     pub proof_hash: String,
+    /// Circuit version the proof was made with, empty for unversioned proofs
+    pub circuit_version: String,
 }
 
 /// Enum representing possible calls to the identity contract
@@ -129,6 +249,16 @@ pub enum IdentityAction {
         user: String,
         country_code: String,
         proof_data: Vec<u8>,
+        /// Verification key hash of the circuit the proof was made with
+        circuit_version: String,
+    },
+    /// Accept proofs of a circuit version, sent by the registrar
+    RegisterCircuitVersion {
+        vk_hash: String,
+    },
+    /// Refuse new proofs of a circuit version, sent by the registrar
+    DeprecateCircuitVersion {
+        vk_hash: String,
     },
     /// Get verification status for a user
     GetVerificationStatus {
@@ -155,11 +285,13 @@ impl IdentityContract {
     }
 }
 
-impl From<sdk::StateCommitment> for IdentityContract {
-    fn from(state: sdk::StateCommitment) -> Self {
+/// Decode the state the identity contract committed to, failing on commitments of another layout
+impl TryFrom<sdk::StateCommitment> for IdentityContract {
+    type Error = String;
+
+    fn try_from(state: sdk::StateCommitment) -> Result<Self, Self::Error> {
         borsh::from_slice(&state.0)
-            .map_err(|_| "Could not decode identity state".to_string())
-            .unwrap()
+            .map_err(|e| format!("Could not decode identity state from a {}-byte commitment: {}", state.0.len(), e))
     }
 }
 
@@ -177,11 +309,17 @@ mod tests {
 
     fn create_test_contract() -> IdentityContract {
         IdentityContract {
-            verifications: HashMap::new(),
-            allowed_users: std::collections::HashSet::new(),
+            verifications: BTreeMap::new(),
+            allowed_users: BTreeSet::new(),
+            circuit_versions: BTreeMap::new(),
+            registrar: None,
         }
     }
 
+    fn vk_hash(byte: u8) -> String {
+        format!("{:02x}", byte).repeat(32)
+    }
+
     fn create_test_proof_data() -> Vec<u8> {
         // Simulate valid proof data (32+ bytes)
         (0..64).collect::<Vec<u8>>()
@@ -348,6 +486,24 @@ mod tests {
         assert!(result_str.contains("NOT ALLOWED"));
     }
 
+    #[test]
+    fn test_allows_and_verification_accessors() {
+        let mut contract = create_test_contract();
+        let proof_data = create_test_proof_data();
+
+        assert!(!contract.allows("alice"));
+        assert!(contract.verification("alice").is_none());
+
+        contract.verify_identity("alice".to_string(), "CAN".to_string(), proof_data.clone()).unwrap();
+        contract.verify_identity("bob".to_string(), "USA".to_string(), proof_data).unwrap();
+
+        assert!(contract.allows("alice"));
+        assert!(!contract.allows("bob"));
+        let verification = contract.verification("bob").unwrap();
+        assert_eq!(verification.country_code, "USA");
+        assert!(!verification.is_allowed);
+    }
+
     #[test]
     fn test_multiple_verifications_same_user() {
         let mut contract = create_test_contract();
@@ -438,4 +594,88 @@ mod tests {
         let result_str = String::from_utf8_lossy(&binding);
         assert!(result_str.contains("ALLOWED")); // Should be allowed since it's not exact "USA"
     }
+
+    #[test]
+    fn test_register_circuit_version_requires_registrar() {
+        let mut contract = create_test_contract();
+        assert!(contract.register_circuit_version("admin", vk_hash(1)).is_err());
+
+        let mut contract = contract.with_registrar("admin");
+        let result = contract.register_circuit_version("mallory", vk_hash(1));
+        assert!(result.unwrap_err().contains("cannot be managed by mallory"));
+        assert!(contract.circuit_version(&vk_hash(1)).is_none());
+
+        contract.register_circuit_version("admin", vk_hash(1)).unwrap();
+        assert_eq!(contract.circuit_version(&vk_hash(1)), Some(CircuitStatus::Active));
+        assert!(contract.register_circuit_version("admin", vk_hash(1)).is_err());
+        assert!(contract.register_circuit_version("admin", "not-a-hash".to_string()).is_err());
+        assert!(contract.deprecate_circuit_version("mallory", vk_hash(1)).is_err());
+    }
+
+    #[test]
+    fn test_verify_identity_requires_active_circuit_version() {
+        let mut contract = create_test_contract().with_registrar("admin");
+        let proof_data = create_test_proof_data();
+
+        // Before any registration, only unversioned proofs are accepted
+        assert!(contract.verify_identity_with("alice".to_string(), "CAN".to_string(), proof_data.clone(), vk_hash(1)).is_err());
+        contract.verify_identity("alice".to_string(), "CAN".to_string(), proof_data.clone()).unwrap();
+
+        contract.register_circuit_version("admin", vk_hash(1)).unwrap();
+        assert!(contract.verify_identity("bob".to_string(), "CAN".to_string(), proof_data.clone()).is_err());
+        assert!(contract.verify_identity_with("bob".to_string(), "CAN".to_string(), proof_data.clone(), vk_hash(2)).is_err());
+        contract.verify_identity_with("bob".to_string(), "CAN".to_string(), proof_data, vk_hash(1)).unwrap();
+        assert_eq!(contract.verification("bob").unwrap().circuit_version, vk_hash(1));
+    }
+
+    #[test]
+    fn test_deprecated_circuit_version_keeps_old_verifications() {
+        let mut contract = create_test_contract().with_registrar("admin");
+        let proof_data = create_test_proof_data();
+        contract.register_circuit_version("admin", vk_hash(1)).unwrap();
+        contract.verify_identity_with("alice".to_string(), "CAN".to_string(), proof_data.clone(), vk_hash(1)).unwrap();
+
+        // Upgrade: the new version goes live before the old one is retired
+        contract.register_circuit_version("admin", vk_hash(2)).unwrap();
+        contract.deprecate_circuit_version("admin", vk_hash(1)).unwrap();
+        assert_eq!(contract.circuit_version(&vk_hash(1)), Some(CircuitStatus::Deprecated));
+
+        assert!(contract.allows("alice"));
+        let result = contract.verify_identity_with("bob".to_string(), "CAN".to_string(), proof_data.clone(), vk_hash(1));
+        assert!(result.unwrap_err().contains("deprecated"));
+        contract.verify_identity_with("bob".to_string(), "CAN".to_string(), proof_data, vk_hash(2)).unwrap();
+        assert!(contract.allows("bob"));
+
+        assert!(contract.deprecate_circuit_version("admin", vk_hash(1)).is_err());
+        assert!(contract.register_circuit_version("admin", vk_hash(1)).is_err());
+        assert!(contract.deprecate_circuit_version("admin", vk_hash(3)).is_err());
+    }
+
+    #[test]
+    fn test_state_round_trips_through_its_commitment() {
+        let mut contract = create_test_contract().with_registrar("admin");
+        contract.register_circuit_version("admin", vk_hash(1)).unwrap();
+        contract.verify_identity_with("alice".to_string(), "CAN".to_string(), create_test_proof_data(), vk_hash(1)).unwrap();
+
+        let decoded = IdentityContract::try_from(sdk::ZkContract::commit(&contract)).unwrap();
+        assert!(decoded.allows("alice"));
+        assert_eq!(decoded.circuit_version(&vk_hash(1)), Some(CircuitStatus::Active));
+        assert_eq!(decoded.as_bytes().unwrap(), contract.as_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_corrupted_commitment_is_an_error() {
+        let mut contract = create_test_contract();
+        contract.verify_identity("alice".to_string(), "CAN".to_string(), create_test_proof_data()).unwrap();
+        let mut bytes = sdk::ZkContract::commit(&contract).0;
+
+        // Truncated, padded and foreign commitments are all rejected rather than panicking
+        let truncated = sdk::StateCommitment(bytes[..bytes.len() - 1].to_vec());
+        let err = IdentityContract::try_from(truncated).unwrap_err();
+        assert!(err.contains(&format!("{}-byte commitment", bytes.len() - 1)));
+        bytes.push(0);
+        assert!(IdentityContract::try_from(sdk::StateCommitment(bytes)).is_err());
+        assert!(IdentityContract::try_from(sdk::StateCommitment([7u8; 32].to_vec())).is_err());
+        assert!(IdentityContract::try_from(sdk::StateCommitment(vec![])).is_err());
+    }
 }
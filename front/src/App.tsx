@@ -79,6 +79,16 @@ function ScaffoldApp() {
     return `${tokens[0]}_${tokens[1]}`;
   };
 
+  // Balances are `[[user id, token id], amount]` entries in the contract state, keyed here as "user_token"
+  const getUserBalances = (state: any): Record<string, number> => {
+    const identities: string[] = state?.identities || [];
+    const symbols: string[] = state?.symbols || [];
+    const entries: [[number, number], number][] = state?.user_balances || [];
+    return Object.fromEntries(
+      entries.map(([[user, token], amount]) => [`${identities[user]}_${symbols[token]}`, amount])
+    );
+  };
+
   // Get current price ratio from pool reserves
   const getCurrentPrice = (pool: any, tokenA: string): number => {
     if (!pool || pool.reserve_a === 0 || pool.reserve_b === 0) return 0;
@@ -326,7 +336,11 @@ function ScaffoldApp() {
       const headers = new Headers();
       headers.append('content-type', 'application/json');
       headers.append('x-user', currentUser);
-      headers.append('x-session-key', 'test-session');
+      // Passkey session, which the server checks belongs to x-user
+      const sessionToken = localStorage.getItem('sessionToken');
+      if (sessionToken) {
+        headers.append('x-session-token', sessionToken);
+      }
       headers.append('x-request-signature', 'test-signature');
 
       const requestBody = {
@@ -641,7 +655,7 @@ function ScaffoldApp() {
     
     // Normal operation with loaded contract state
     const state = contract1State.state as any;
-    const userBalances = state.user_balances || {};
+    const userBalances = getUserBalances(state);
     
     return (
       <div className="balance-card">
@@ -714,7 +728,7 @@ function ScaffoldApp() {
     const state = contract1State?.state as any;
     const pools = state?.pools || {};
     const pool = pools[pairKey];
-    const userBalances = state?.user_balances || {};
+    const userBalances = getUserBalances(state);
     const userKey = currentUser.split('@')[0];
     
     const isNewPool = !pool || (pool.reserve_a === 0 && pool.reserve_b === 0);
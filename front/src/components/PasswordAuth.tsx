@@ -1,4 +1,4 @@
-import React, { useState } from 'react';
+import React, { useRef, useState } from 'react';
 import './PasswordAuth.css';
 
 interface PasswordAuthProps {
@@ -23,11 +23,36 @@ const PasswordAuth: React.FC<PasswordAuthProps> = ({ onAuthSuccess, onAuthError,
   const [username, setUsername] = useState('');
   const [password, setPassword] = useState('');
   const [isLoading, setIsLoading] = useState(false);
+  // Latest stage or tool output of the proof job, streamed by the server
+  const [progress, setProgress] = useState<string | null>(null);
+  const jobIdRef = useRef<string | null>(null);
+
+  // Stop the server's proving job, whose request then fails as cancelled
+  const cancelProof = () => {
+    if (jobIdRef.current) {
+      fetch(`${import.meta.env.VITE_SERVER_BASE_URL}/api/noir/jobs/${jobIdRef.current}`, { method: 'DELETE' });
+    }
+  };
 
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     setIsLoading(true);
 
+    // Follow nargo's progress, subscribing before the job starts so no line is missed
+    const jobId = crypto.randomUUID();
+    jobIdRef.current = jobId;
+    const jobEvents = new EventSource(`${import.meta.env.VITE_SERVER_BASE_URL}/api/noir/jobs/${jobId}/events`);
+    jobEvents.addEventListener('progress', (event) => {
+      const entry = JSON.parse((event as MessageEvent).data);
+      if (entry.kind === 'stage') {
+        setProgress(entry.stage);
+      } else if (entry.kind === 'output') {
+        setProgress(`${entry.tool}: ${entry.line}`);
+      } else if (entry.kind === 'done') {
+        jobEvents.close();
+      }
+    });
+
     try {
       console.log('🔐 Starting Noir circuit authentication...');
       
@@ -43,18 +68,22 @@ const PasswordAuth: React.FC<PasswordAuthProps> = ({ onAuthSuccess, onAuthError,
         // Send field representations for proof generation
         user_field: userField.toString(),
         password_field: passwordField.toString(),
-        proof_type: 'noir_circuit'
+        proof_type: 'noir_circuit',
+        job_id: jobId
       };
 
       console.log('📡 Sending authentication request to server...');
 
+      // Passkey session, which the server checks belongs to x-user
+      const sessionToken = localStorage.getItem('sessionToken');
+
       // Call server API for Noir circuit verification
       const response = await fetch(`${import.meta.env.VITE_SERVER_BASE_URL}/api/authenticate-noir`, {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
           'x-user': `${username}@zkpassport`,
-          'x-session-key': 'test-session',
+          ...(sessionToken ? { 'x-session-token': sessionToken } : {}),
           'x-request-signature': 'test-signature'
         },
         body: JSON.stringify(authRequest)
@@ -84,6 +113,9 @@ const PasswordAuth: React.FC<PasswordAuthProps> = ({ onAuthSuccess, onAuthError,
       const errorMessage = error instanceof Error ? error.message : 'Authentication failed';
       onAuthError(`Noir circuit verification failed: ${errorMessage}`);
     } finally {
+      jobEvents.close();
+      jobIdRef.current = null;
+      setProgress(null);
       setIsLoading(false);
     }
   };
@@ -133,13 +165,23 @@ const PasswordAuth: React.FC<PasswordAuthProps> = ({ onAuthSuccess, onAuthError,
             {isLoading ? (
               <>
                 <span className="spinner"></span>
-                Generating Noir Proof...
+                {progress ?? 'Generating Noir Proof...'}
               </>
             ) : (
               'Generate ZK Proof & Authenticate'
             )}
           </button>
           
+          {isLoading && (
+            <button
+              type="button"
+              className="cancel-button"
+              onClick={cancelProof}
+            >
+              Stop Proving
+            </button>
+          )}
+
           {onCancel && (
             <button 
               type="button" 
@@ -0,0 +1,187 @@
+use std::collections::{HashSet, VecDeque};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use contract1::Contract1;
+use sdk::{ContractName, TxHash};
+use tokio::sync::{Notify, RwLock};
+
+/// Number of applied transactions remembered for read-your-writes queries
+const MAX_APPLIED_TXS: usize = 10_000;
+
+/// Where the settled contract1 state is read from
+#[derive(Clone)]
+pub enum StateSource {
+    /// Served by a contract state indexer at this URL, as contract1 only commits to a hash or,
+    /// with `segmented-state`, a root of its state
+    StateIndexer(String),
+}
+
+/// AMM state as published by one update, never modified afterwards.
+///
+/// Readers hold it for as long as they need, so every read of a request sees the same
+/// state, whatever settles meanwhile.
+#[derive(Debug)]
+pub struct AmmSnapshot {
+    state: Contract1,
+    /// Number of updates published up to this one
+    pub version: u64,
+}
+
+impl AmmSnapshot {
+    pub fn state(&self) -> &Contract1 {
+        &self.state
+    }
+}
+
+impl Deref for AmmSnapshot {
+    type Target = Contract1;
+
+    fn deref(&self) -> &Contract1 {
+        &self.state
+    }
+}
+
+/// Latest AMM state known to the server, written by the app module only.
+///
+/// Seeded from the indexer at startup, then kept fresh from the state carried
+/// by every `AutoProverEvent::SuccessTx`, or re-fetched from the indexer on
+/// settled blocks when running as a read-only replica. Request handlers read
+/// it through an [`AmmStateReader`].
+#[derive(Clone, Default)]
+pub struct AmmStateView {
+    inner: Arc<RwLock<Published>>,
+    updated: Arc<Notify>,
+}
+
+/// Read side of the [`AmmStateView`], handing out whole snapshots
+#[derive(Clone)]
+pub struct AmmStateReader {
+    inner: Arc<RwLock<Published>>,
+    updated: Arc<Notify>,
+}
+
+/// A snapshot and the transactions it holds the effects of, swapped together so a
+/// transaction is never reported applied to a state that lacks it
+#[derive(Default)]
+struct Published {
+    snapshot: Option<Arc<AmmSnapshot>>,
+    /// Recent transactions whose effects the snapshot holds
+    applied: AppliedTxs,
+}
+
+#[derive(Default)]
+struct AppliedTxs {
+    hashes: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl AmmStateView {
+    /// Load the settled state from `source`, falling back to an empty view
+    pub async fn load(source: &StateSource, contract_name: &ContractName) -> Self {
+        let view = Self::default();
+        if let Some(state) = Self::fetch(source, contract_name).await {
+            view.update(state, &[]).await;
+        }
+        view
+    }
+
+    /// Latest settled state known to `source`
+    pub async fn fetch(source: &StateSource, contract_name: &ContractName) -> Option<Contract1> {
+        match source {
+            StateSource::StateIndexer(url) => Self::fetch_indexed(url, contract_name).await,
+        }
+    }
+
+    async fn fetch_indexed(url: &str, contract_name: &ContractName) -> Option<Contract1> {
+        let url = format!("{}/v1/indexer/contract/{}/state", url.trim_end_matches('/'), contract_name);
+        let response = reqwest::get(&url).await.and_then(|response| response.error_for_status());
+        match response {
+            Ok(response) => match response.json::<Contract1>().await {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    tracing::warn!("⚠️ Could not decode indexed {} state: {}", contract_name, e);
+                    None
+                }
+            },
+            Err(e) => {
+                tracing::warn!("⚠️ Could not fetch indexed {} state: {}", contract_name, e);
+                None
+            }
+        }
+    }
+
+    pub fn reader(&self) -> AmmStateReader {
+        AmmStateReader {
+            inner: self.inner.clone(),
+            updated: self.updated.clone(),
+        }
+    }
+
+    /// Publish a new state, recording the transactions it is the result of
+    pub async fn update(&self, state: Contract1, applied_txs: &[TxHash]) {
+        let mut published = self.inner.write().await;
+        let version = published.snapshot.as_ref().map_or(1, |snapshot| snapshot.version + 1);
+        published.snapshot = Some(Arc::new(AmmSnapshot { state, version }));
+        let applied = &mut published.applied;
+        for tx_hash in applied_txs {
+            if applied.hashes.insert(tx_hash.0.clone()) {
+                applied.order.push_back(tx_hash.0.clone());
+            }
+        }
+        while applied.order.len() > MAX_APPLIED_TXS {
+            if let Some(evicted) = applied.order.pop_front() {
+                applied.hashes.remove(&evicted);
+            }
+        }
+        drop(published);
+        self.updated.notify_waiters();
+    }
+
+    /// Publish an earlier state again, forgetting the transactions whose effects it lacks
+    pub async fn rollback(&self, state: Contract1, reverted_txs: &[TxHash]) {
+        let mut published = self.inner.write().await;
+        let version = published.snapshot.as_ref().map_or(1, |snapshot| snapshot.version + 1);
+        published.snapshot = Some(Arc::new(AmmSnapshot { state, version }));
+        let applied = &mut published.applied;
+        for tx_hash in reverted_txs {
+            if applied.hashes.remove(&tx_hash.0) {
+                applied.order.retain(|hash| hash != &tx_hash.0);
+            }
+        }
+        drop(published);
+        self.updated.notify_waiters();
+    }
+
+    pub async fn snapshot(&self) -> Option<Arc<AmmSnapshot>> {
+        self.reader().snapshot().await
+    }
+}
+
+impl AmmStateReader {
+    /// Latest published state, `None` until one was
+    pub async fn snapshot(&self) -> Option<Arc<AmmSnapshot>> {
+        self.inner.read().await.snapshot.clone()
+    }
+
+    /// Wait until the view holds the effects of a transaction, up to `timeout`.
+    ///
+    /// Returns whether it does; transactions applied before the last
+    /// `MAX_APPLIED_TXS` are reported as missing.
+    pub async fn wait_for(&self, tx_hash: &str, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                // Register before checking so an update in between is not missed
+                let updated = self.updated.notified();
+                tokio::pin!(updated);
+                updated.as_mut().enable();
+                if self.inner.read().await.applied.hashes.contains(tx_hash) {
+                    return;
+                }
+                updated.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+}
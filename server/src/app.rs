@@ -1,41 +1,148 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::{BTreeMap, BTreeSet, HashSet}, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use axum::{
-    extract::{Json, State},
-    http::{HeaderMap, Method, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{FromRef, Json, Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post, put},
     Router,
 };
 use client_sdk::{
     contract_indexer::AppError,
-    rest_client::{NodeApiClient, NodeApiHttpClient},
+    rest_client::{IndexerApiHttpClient, NodeApiClient, NodeApiHttpClient},
 };
-use contract1::{Contract1, Contract1Action};
+use contract1::{
+    token_adapter::TokenTransfer, AdminOperation, AmmError, BridgeDepositRecord, Contract1, Contract1Action,
+    FaucetAction, LiquidityPool, OtcOffer, StreamingSwap, SwapIntent, TokenAmount, Vesting, Withdrawal,
+    WithdrawalStatus, SECP256K1_CONTRACT,
+};
+use contract2::IdentityAction;
 // Contract2 removed - will be replaced with Noir identity verification
 
 use hyle_modules::{
-    bus::{BusClientReceiver, SharedMessageBus},
+    bus::{BusClientSender, SharedMessageBus},
     module_bus_client, module_handle_messages,
     modules::{prover::AutoProverEvent, BuildApiContextInner, Module},
+    node_state::module::NodeStateEvent,
 };
-use sdk::{Blob, BlobTransaction, ContractName};
+use sdk::{Blob, BlobTransaction, Block, ContractName, TxHash};
 use serde::{Serialize, Deserialize};
-use tokio::sync::Mutex;
-use tower_http::cors::{Any, CorsLayer};
+use futures::StreamExt;
+use opentelemetry::metrics::Histogram;
+use tokio::sync::{broadcast, mpsc};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{Any, CorsLayer},
+};
 
 // Import new Noir modules
+use crate::amm_state::{AmmStateReader, AmmStateView, StateSource};
+use crate::budget::{self, RouteBudgets};
+use crate::conf::{Conf, GenesisPool, PolicyAction, ValidationConf};
+use crate::etag;
+use crate::explorer::{Explorer, ExplorerCtx};
+use crate::feature_flags::{self, FeatureFlags, FlagOverride};
+use crate::credential_policy::CredentialPolicy;
+use crate::delivery::{self, Delivery, DeliverySettings};
+use crate::encryption::{self, Keyring};
+use crate::identity_index::{self, IdentityIndex};
+use crate::kyc::{self, KycGate};
+use crate::limits::TradingLimits;
+use crate::lp_positions::{self, PositionEntries};
+use crate::mock_node::{MockNode, MockSettlement};
+use crate::noir_jobs::{Job, JobLine, NoirJobs};
+use crate::noir_proofs::{ProofStore, StoredProof};
 use crate::noir_prover::NoirProver;
-use crate::noir_verifier::{NoirVerifier, NoirVerifierCtx};
+use crate::noir_verifier::{NoirProof, NoirVerifier, NoirVerifierCtx};
+use crate::noir_vk::{Registration, VkStore};
+use crate::notifications::{AlertDirection, NotificationEvent, Notifications};
+use crate::price_history::{Interval, PriceHistory};
+use crate::profile::{ProfileStore, ProfileUpdate};
+use crate::reconciliation::{self, Reconciler};
+use crate::recorder::{self, Recorder};
+use crate::reorg::{Reorg, UndoLog};
+use crate::retention::Pruner;
+use crate::screening::ScreeningGate;
+use crate::secrets::Secrets;
+use crate::spending::{self, BudgetWarning, DailySpend, SpendKind, SpendingTracker};
+use crate::state_cache::StateCache;
+use crate::state_dump::{self, StateDump};
+use crate::state_history::{HistoricalState, PortfolioPoint, StateHistory};
+use crate::sync_status::SyncMonitor;
+use crate::tls::{self, AdminGate};
+use crate::tx_status::{self, TxProgress, TxProgressEvent, TxRecord, TxStatus, TxStatusStore};
+use crate::versioning::{self, LegacyApi};
+use crate::webauthn::{
+    AuthenticationResponse, RegistrationResponse, WebAuthnProvider, WebAuthnProviderCtx,
+};
+use capabilities::ServerCapabilities;
+use validation::Valid;
+use runbook::{ModuleCommand, Runbooks};
+use self_test::SelfTestResult;
+
+mod capabilities;
+mod genesis;
+mod keeper;
+mod runbook;
+mod self_test;
+mod validation;
 
 pub struct AppModule {
     bus: AppModuleBusClient,
+    amm_state: AmmStateView,
+    tx_status: Arc<TxStatusStore>,
+    progress: broadcast::Receiver<TxProgressEvent>,
+    sync: SyncMonitor,
+    sync_poll_interval: Duration,
+    undo_log: UndoLog,
+    /// Blocks settled from the DA stream whose state is not published yet
+    pending: PendingSettlement,
+    catch_up_batch_blocks: usize,
+    batch_sizes: Histogram<u64>,
+    price_history: Arc<PriceHistory>,
+    lp_entries: Arc<PositionEntries>,
+    state_history: Option<Arc<StateHistory>>,
+    pruner: Pruner,
+    prune_interval: Duration,
+    noir_proofs: Arc<ProofStore>,
+    noir_proof_max_age_secs: Option<u64>,
+    spending: Arc<SpendingTracker>,
+    notifications: Arc<Notifications>,
+    /// No AutoProver in this process: outcomes come from blocks, state from the indexer
+    settle_from_blocks: bool,
+    state_source: StateSource,
+    contract1_cn: ContractName,
+    mock_settlements: Option<broadcast::Receiver<MockSettlement>>,
+    /// Runbook work handed over by the admin API
+    commands: mpsc::Receiver<ModuleCommand>,
 }
 
 pub struct AppModuleCtx {
     pub api: Arc<BuildApiContextInner>,
+    pub config: Arc<Conf>,
     pub node_client: Arc<NodeApiHttpClient>,
+    pub indexer_client: Arc<IndexerApiHttpClient>,
+    /// Shared by the tenants, which read the same secrets
+    pub secrets: Arc<Secrets>,
+    /// Path all routes are nested under when serving one of several tenants
+    pub route_prefix: Option<String>,
+    /// Serve only query endpoints
+    pub read_only: bool,
+    /// Proving happens in a separate `prover` process
+    pub external_prover: bool,
+    /// Run the canary pipeline check once the DA stream caught up
+    pub self_test: bool,
+    /// Execute and settle transactions in memory instead of going through a node
+    pub mock_node: Option<Arc<MockNode>>,
+    /// Settled outcomes are appended here when recording traffic
+    pub recorder: Option<Arc<Recorder>>,
+    /// Pools to create once synced, used by the `devnet` playground
+    pub genesis_pools: Vec<GenesisPool>,
     pub contract1_cn: ContractName,
     pub contract2_cn: ContractName, // Placeholder for Noir contract integration
 }
@@ -43,7 +150,9 @@ pub struct AppModuleCtx {
 module_bus_client! {
 #[derive(Debug)]
 pub struct AppModuleBusClient {
+    sender(TxProgressEvent),
     receiver(AutoProverEvent<Contract1>),
+    receiver(NodeStateEvent),
 }
 }
 
@@ -51,39 +160,305 @@ impl Module for AppModule {
     type Context = Arc<AppModuleCtx>;
 
     async fn build(bus: SharedMessageBus, ctx: Self::Context) -> Result<Self> {
+        let state_source = StateSource::StateIndexer(ctx.config.contract_state_url.clone());
+        let amm_state = match ctx.mock_node {
+            Some(_) => AmmStateView::default(),
+            None => AmmStateView::load(&state_source, &ctx.contract1_cn).await,
+        };
+        let keyring = Keyring::from_conf(&ctx.config.encryption, &ctx.secrets).await?;
+        let storage = encryption::open_storage(&ctx.config, &ctx.secrets, keyring.as_ref()).await?;
+        let tx_status = Arc::new(TxStatusStore::load(storage.clone(), &ctx.config.data_directory).await?);
+        let sync = SyncMonitor::new(ctx.node_client.clone(), ctx.config.sync_max_lag_blocks);
+        if ctx.mock_node.is_some() {
+            // Nothing to catch up with
+            sync.block_processed(0).await;
+        }
+        let price_history = Arc::new(PriceHistory::open(storage.clone(), &ctx.config.data_directory).await?);
+        let lp_entries = Arc::new(PositionEntries::new(storage.clone()));
+        let noir_proofs = Arc::new(ProofStore::load(storage.clone(), &ctx.config.data_directory).await?);
+        let spending = Arc::new(SpendingTracker::new(storage.clone(), ctx.config.spending.clone()));
+        let notifications = Arc::new(Notifications::load(storage.clone(), ctx.config.notifications.clone()).await?);
+        let delivery = Arc::new(Delivery::new(storage.clone(), ctx.config.notifications.delivery.clone())?);
+        let feature_flags = Arc::new(FeatureFlags::load(storage.clone(), ctx.config.feature_flags.clone()).await?);
+        let webauthn = Arc::new(WebAuthnProvider::new(WebAuthnProviderCtx {
+            rp_id: ctx.config.webauthn_rp_id.clone(),
+            rp_origin: ctx.config.webauthn_rp_origin.clone(),
+            data_directory: ctx.config.data_directory.clone(),
+            keyring,
+            accept_plaintext: ctx.config.encryption.accept_plaintext,
+        })?);
+        let (commands, command_receiver) = mpsc::channel(16);
+        let runbooks = Arc::new(Runbooks::load(storage.clone(), ctx.config.runbooks.clone(), commands).await?);
+        let state_history = match ctx.config.state_history.enabled {
+            true => Some(Arc::new(StateHistory::open(
+                &ctx.config.data_directory,
+                ctx.config.state_history.clone(),
+            )?)),
+            false => None,
+        };
+        let reconciler = (ctx.config.reconciliation.enabled && ctx.mock_node.is_none()).then(|| {
+            Arc::new(Reconciler::new(
+                ctx.node_client.clone(),
+                ctx.contract1_cn.clone(),
+                ctx.config.reconciliation.mismatch_threshold,
+            ))
+        });
+        // Identity state shared by screening and the identity index, read once per block
+        let identity_state = Arc::new(StateCache::new(
+            ctx.indexer_client.clone(),
+            ctx.contract2_cn.clone(),
+            sync.clone(),
+        ));
+        let screening = ScreeningGate::new(
+            &ctx.config.screening,
+            &ctx.config.data_directory,
+            identity_state.clone(),
+            ctx.contract2_cn.clone(),
+        )
+        .await?;
+
+        let identity_index = Arc::new(IdentityIndex::new(
+            &ctx.config.identity_index,
+            identity_state,
+            ctx.contract2_cn.clone(),
+        ));
+
         let state = RouterCtx {
-            bus: Arc::new(Mutex::new(bus.new_handle())),
             contract1_cn: ctx.contract1_cn.clone(),
             contract2_cn: ctx.contract2_cn.clone(), // Placeholder
             client: ctx.node_client.clone(),
             // Initialize Noir integration components
-            noir_prover: Arc::new(NoirProver::new("../noir-contracts/zkpassport_identity".to_string())),
+            noir_prover: Arc::new(NoirProver::new(NOIR_CIRCUIT_DIR.to_string())),
+            noir_vk: Arc::new(VkStore::new(
+                std::path::Path::new(NOIR_CIRCUIT_DIR),
+                ctx.mock_node.is_none().then(|| Registration {
+                    indexer: ctx.indexer_client.clone(),
+                    contract_name: ctx.contract2_cn.clone(),
+                }),
+            )),
+            noir_proofs: noir_proofs.clone(),
+            noir_jobs: Arc::new(NoirJobs::new(ctx.config.noir_jobs.clone())),
             noir_verifier: Arc::new(NoirVerifier::new(NoirVerifierCtx {
                 contract_name: ctx.contract2_cn.clone(),
                 node_client: ctx.node_client.clone(),
+                mock_node: ctx.mock_node.clone(),
+            })),
+            webauthn: webauthn.clone(),
+            admin: AdminAuth {
+                webauthn,
+                identities: Arc::new(ctx.config.admin.identities.iter().cloned().collect()),
+            },
+            profiles: Arc::new(ProfileStore::load(storage, &ctx.config.data_directory).await?),
+            amm_state: amm_state.reader(),
+            imported_state: ctx.mock_node.is_some().then(|| amm_state.clone()),
+            tx_status: tx_status.clone(),
+            explorer: Arc::new(Explorer::new(ExplorerCtx {
+                indexer: ctx.indexer_client.clone(),
+                amm_cn: ctx.contract1_cn.clone(),
+                identity_cn: ctx.contract2_cn.clone(),
             })),
+            sync: sync.clone(),
+            price_history: price_history.clone(),
+            lp_entries: lp_entries.clone(),
+            state_history: state_history.clone(),
+            self_test: Default::default(),
+            mock_node: ctx.mock_node.clone(),
+            limits: Arc::new(TradingLimits::new(ctx.config.limits.clone())),
+            spending: spending.clone(),
+            notifications: notifications.clone(),
+            delivery: delivery.clone(),
+            runbooks,
+            feature_flags: feature_flags.clone(),
+            capabilities: Arc::new(ServerCapabilities::new(&ctx.config, ctx.read_only)),
+            validation: Arc::new(ctx.config.validation.clone()),
+            screening: Arc::new(screening),
+            credential_policy: Arc::new(CredentialPolicy::new(
+                ctx.config.credential_policy.clone(),
+                identity_index.clone(),
+            )),
+            identity_index: identity_index.clone(),
+            identity_policy: ctx.config.identity_policy.enabled,
+            relayer_fee_bps: ctx.config.relayer.fee_bps,
+            min_tx_wait: Duration::from_millis(ctx.config.min_tx_wait_ms),
+            reconciler: reconciler.clone(),
+            allow_state_import: ctx.config.allow_state_import,
         };
 
+        if ctx.self_test {
+            tokio::spawn(self_test::run(state.clone(), state.self_test.clone()));
+        }
+        if let Some(recorder) = &ctx.recorder {
+            tokio::spawn(recorder::record_settlements(
+                recorder.clone(),
+                tx_status.subscribe(),
+                ctx.contract1_cn.0.clone(),
+            ));
+        }
+        if !ctx.genesis_pools.is_empty() {
+            tokio::spawn(genesis::seed(state.clone(), ctx.genesis_pools.clone()));
+        }
+        if let Some(reconciler) = reconciler {
+            let interval = Duration::from_secs(ctx.config.reconciliation.interval_secs);
+            tokio::spawn(reconciliation::run(reconciler, amm_state.reader(), sync.clone(), interval));
+        }
+        if delivery.enabled() && !ctx.read_only {
+            tokio::spawn(delivery::run(delivery.clone(), notifications.subscribe()));
+        }
+        if ctx.config.keeper.enabled && !ctx.read_only {
+            let interval = Duration::from_secs(ctx.config.keeper.interval_secs);
+            let compact_min_entries = ctx.config.keeper.compact_min_entries;
+            tokio::spawn(keeper::run(state.clone(), ctx.secrets.clone(), interval, compact_min_entries));
+        }
+        let interval = Duration::from_millis(ctx.config.identity_index.poll_interval_ms.max(1));
+        tokio::spawn(identity_index::run(identity_index.clone(), sync.clone(), interval));
+        // Pin the registered verification key before a rebuilt circuit can be mistaken for it
+        let noir_vk = state.noir_vk.clone();
+        tokio::spawn(async move { noir_vk.registered_hash().await });
+
         // Create CORS middleware
         let cors = CorsLayer::new()
             .allow_origin(Any) // Allow all origins (can be restricted)
-            .allow_methods(vec![Method::GET, Method::POST]) // Allow necessary methods
-            .allow_headers(Any); // Allow all headers
+            .allow_methods(vec![Method::GET, Method::POST, Method::PUT]) // Allow necessary methods
+            .allow_headers(Any) // Allow all headers
+            .expose_headers([header::HeaderName::from_static(spending::BUDGET_WARNING_HEADER)]);
+
+        // Query endpoints, served by every instance including read replicas.
+        // Paths are relative to the API version prefix they are mounted under
+        let mut api = Router::new()
+            .route("/config", get(capabilities::get_config))
+            .route("/noir-stats", get(get_noir_stats)) // New endpoint for verification stats
+            .route("/noir/vk", get(get_noir_vk).layer(axum::middleware::from_fn(etag::etag)))
+            .route("/noir/vk/rotate", post(rotate_noir_vk))
+            .route("/noir/proofs/{hash}", get(get_noir_proof))
+            .route("/identity/requirements", get(get_identity_requirements))
+            .route("/identity/changes", get(get_identity_changes))
+            .route("/tx/{hash}/details", get(get_tx_details))
+            .route("/blocks/{height}", get(get_block_details))
+            .route("/sync-status", get(get_sync_status))
+            .route("/quote", get(get_quote))
+            .route("/pools", get(get_pools).layer(axum::middleware::from_fn(etag::etag)))
+            .route("/pools/{pair}", get(get_pool))
+            .route("/balances/{user}", get(get_balances))
+            .route("/positions/{user}/{pair}/il", get(get_impermanent_loss))
+            .route("/portfolio/{user}/history", get(get_portfolio_history))
+            .route("/self-test", get(get_self_test))
+            .route("/price-history", get(get_price_history).layer(axum::middleware::from_fn(etag::etag)))
+            .route("/volume", get(get_volume).layer(axum::middleware::from_fn(etag::etag)))
+            .route("/volume/onchain", get(get_onchain_volume))
+            .route("/rewards", get(get_rewards))
+            .route("/streaming-swaps", get(get_streaming_swaps))
+            .route("/swap-batches", get(get_swap_batches))
+            .route("/otc-offers", get(get_otc_offers))
+            .route("/vestings", get(get_vestings))
+            .route("/insurance", get(get_insurance))
+            .route("/protocol-fees", get(get_protocol_fees))
+            .route("/faucet", get(get_faucet))
+            .route("/admin", get(get_admin_council))
+            .route("/admin/reconciliation", get(get_reconciliation))
+            .route("/admin/state/export", get(get_state_export))
+            .route("/admin/runbooks", get(runbook::get_runs))
+            .route("/admin/feature-flags", get(get_feature_flags))
+            .route("/votes", get(get_votes))
+            .route("/external-tokens", get(get_external_tokens))
+            .route("/token-decimals", get(get_token_decimals))
+            .route("/bridge/deposits", get(get_bridge_deposits))
+            .route("/bridge/withdrawals", get(get_withdrawals));
+
+        #[cfg(feature = "rounding-audit")]
+        {
+            api = api.route("/rounding-reserve", get(get_rounding_reserve));
+        }
+
+        // Endpoints submitting transactions or holding per-instance sessions
+        if !ctx.read_only {
+            api = api
+                .route("/mint-tokens", post(mint_tokens))
+                .route("/swap-tokens", post(swap_tokens))
+                .route("/add-liquidity", post(add_liquidity))
+                .route("/create-lbp-pool", post(create_lbp_pool))
+                .route("/create-batch-auction-pool", post(create_batch_auction_pool))
+                .route("/remove-liquidity", post(remove_liquidity))
+                .route("/get-user-balance", post(get_user_balance))
+                .route("/get-pool-reserves", post(get_pool_reserves))
+                .route("/test-amm", post(test_amm))
+                .route("/streaming-swaps", post(create_streaming_swap))
+                .route("/streaming-swaps/{id}/cancel", post(cancel_streaming_swap))
+                .route("/otc-offers", post(create_otc_offer))
+                .route("/otc-offers/{id}/accept", post(accept_otc_offer))
+                .route("/otc-offers/{id}/cancel", post(cancel_otc_offer))
+                .route("/vestings", post(create_vesting))
+                .route("/vestings/{id}/claim", post(claim_vesting))
+                .route("/external/deposit", post(deposit_external))
+                .route("/external/withdraw", post(withdraw_external))
+                .route("/bridge/deposit", post(bridge_deposit))
+                .route("/referrer", post(set_referrer))
+                .route("/bridge/withdrawals", post(request_withdrawal))
+                .route("/bridge/withdrawals/{id}/process", post(process_withdrawal))
+                .route("/bridge/withdrawals/{id}/cancel", post(cancel_withdrawal))
+                .route("/insurance/claims", post(file_insurance_claim))
+                .route("/insurance/claims/{id}/approve", post(approve_insurance_claim))
+                .route("/insurance/claims/{id}/reject", post(reject_insurance_claim))
+                .route("/protocol-fees/collect", post(collect_protocol_fees))
+                .route("/admin/proposals", post(propose_admin_action))
+                .route("/admin/proposals/{id}/approve", post(approve_admin_action))
+                .route("/admin/state/import", post(import_state))
+                .route("/admin/runbooks/resync", post(runbook::resync))
+                .route("/admin/runbooks/flush-settlements", post(runbook::flush_settlements))
+                .route("/admin/runbooks/resubmit-proofs", post(runbook::resubmit_proofs))
+                .route("/admin/runbooks/rotate-vk", post(runbook::rotate_vk))
+                .route("/admin/feature-flags/{name}", put(put_feature_flag))
+                .route("/relay/signing-key", post(register_signing_key))
+                .route("/relay/swap", post(relay_swap))
+                .route("/authenticate-noir", post(noir_authenticate))
+                .route("/noir/submit-proof", post(submit_noir_proof))
+                .route("/noir/jobs/{id}", delete(cancel_noir_job))
+                .route("/noir/jobs/{id}/events", get(get_noir_job_events))
+                .route("/auth/webauthn/register/start", post(webauthn_register_start))
+                .route("/auth/webauthn/register/finish", post(webauthn_register_finish))
+                .route("/auth/webauthn/login/start", post(webauthn_login_start))
+                .route("/auth/webauthn/login/finish", post(webauthn_login_finish))
+                .route("/profile", get(get_profile).put(put_profile))
+                .route("/portfolio", get(get_portfolio))
+                .route("/notifications", get(get_notifications))
+                .route("/notifications/read", post(mark_notifications_read))
+                .route("/notifications/events", get(get_notification_events))
+                .route("/notifications/price-alerts", get(get_price_alerts).post(create_price_alert))
+                .route("/notifications/price-alerts/{id}", delete(delete_price_alert))
+                .route("/notifications/delivery", get(get_delivery_settings).put(put_delivery_settings))
+                .route("/tx/{hash}/status", get(get_tx_status))
+                .route("/tx/{hash}/events", get(get_tx_events))
+                .route("/tx/events", get(get_identity_tx_events));
+        }
 
-        let api = Router::new()
+        let api = api.layer(axum::middleware::from_fn_with_state(state.clone(), read_your_writes));
+        // Existing clients keep the unversioned routes, answered with deprecation headers
+        // until `legacy_api_sunset`
+        let legacy = Arc::new(LegacyApi::new(&ctx.config.legacy_api_sunset)?);
+        let mut api = Router::new()
             .route("/_health", get(health))
-            .route("/api/mint-tokens", post(mint_tokens))
-            .route("/api/swap-tokens", post(swap_tokens))
-            .route("/api/add-liquidity", post(add_liquidity))
-            .route("/api/remove-liquidity", post(remove_liquidity))
-            .route("/api/get-user-balance", post(get_user_balance))
-            .route("/api/get-pool-reserves", post(get_pool_reserves))
-            .route("/api/test-amm", post(test_amm))
-            .route("/api/config", get(get_config))
-            .route("/api/authenticate-noir", post(noir_authenticate))
-            .route("/api/noir-stats", get(get_noir_stats)) // New endpoint for verification stats
-            .with_state(state)
-            .layer(cors); // Apply CORS middleware
+            .nest(versioning::API_V1, api.clone())
+            .nest(versioning::LEGACY_API, api.layer(axum::middleware::from_fn_with_state(legacy, versioning::deprecate)))
+            .with_state(state);
+        if ctx.config.kyc.enabled {
+            let gate = KycGate::new(&ctx.config.kyc, identity_index, ctx.contract2_cn.clone());
+            api = api.layer(axum::middleware::from_fn_with_state(Arc::new(gate), kyc::require_kyc));
+        }
+        if !ctx.config.feature_flags.is_empty() {
+            api = api.layer(axum::middleware::from_fn_with_state(feature_flags, feature_flags::require_feature));
+        }
+        if !ctx.config.route_budgets.is_empty() {
+            let budgets = Arc::new(RouteBudgets::new(ctx.config.route_budgets.clone()));
+            api = api.layer(axum::middleware::from_fn_with_state(budgets, budget::enforce));
+        }
+        if let Some(gate) = AdminGate::new(&ctx.config.tls) {
+            api = api.layer(axum::middleware::from_fn_with_state(Arc::new(gate), tls::require_client_certificate));
+        }
+        // Compress every response the client accepts gzip for
+        let api = api.layer(CompressionLayer::new()).layer(cors); // Apply CORS middleware
+        let api = match &ctx.route_prefix {
+            Some(prefix) => Router::new().nest(prefix, api),
+            None => api,
+        };
 
         if let Ok(mut guard) = ctx.api.router.lock() {
             if let Some(router) = guard.take() {
@@ -92,26 +467,343 @@ impl Module for AppModule {
         }
         let bus = AppModuleBusClient::new_from_bus(bus.new_handle()).await;
 
-        Ok(AppModule { bus })
+        Ok(AppModule {
+            bus,
+            amm_state,
+            progress: tx_status.subscribe_progress(),
+            tx_status,
+            sync,
+            sync_poll_interval: Duration::from_secs(ctx.config.sync_poll_interval_secs),
+            undo_log: UndoLog::new(ctx.config.reorg_undo_blocks),
+            pending: PendingSettlement::default(),
+            catch_up_batch_blocks: ctx.config.catch_up_batch_blocks.max(1),
+            batch_sizes: opentelemetry::global::meter("hyli_defi_app")
+                .u64_histogram("da_settlement_batch_blocks")
+                .build(),
+            price_history,
+            lp_entries,
+            state_history,
+            pruner: Pruner::new(ctx.config.data_directory.clone(), ctx.config.retention.clone())?,
+            prune_interval: Duration::from_secs(ctx.config.retention_interval_secs),
+            noir_proofs,
+            noir_proof_max_age_secs: ctx.config.storage.noir_proof_max_age_secs,
+            spending,
+            notifications,
+            settle_from_blocks: ctx.read_only || ctx.external_prover,
+            state_source,
+            contract1_cn: ctx.contract1_cn.clone(),
+            mock_settlements: ctx.mock_node.as_ref().map(|mock| mock.subscribe()),
+            commands: command_receiver,
+        })
     }
 
     async fn run(&mut self) -> Result<()> {
+        let mut head_poll = tokio::time::interval(self.sync_poll_interval);
+        let mut prune_tick = tokio::time::interval(self.prune_interval);
+        let mut flush_tick = tokio::time::interval(tx_status::FLUSH_INTERVAL);
+
         module_handle_messages! {
             on_bus self.bus,
+            listen<NodeStateEvent> NodeStateEvent::NewBlock(block) => {
+                #[cfg(feature = "chaos")]
+                crate::chaos::da_stall().await;
+                let amm_before = self.amm_state.snapshot().await;
+                if let Some(reorg) = self.undo_log.process(&block, amm_before) {
+                    self.flush_settlements().await;
+                    self.revert(reorg).await;
+                }
+                self.sync.block_processed(block.block_height.0).await;
+                self.track_progress(&block).await;
+                if self.settle_from_blocks {
+                    self.settle_block(&block).await;
+                }
+            }
+            listen<AutoProverEvent<Contract1>> event => {
+                self.handle_prover_event(event).await;
+            }
+            Some(settlement) = next_mock_settlement(&mut self.mock_settlements) => {
+                self.settle_mock(settlement).await;
+            }
+            Some(command) = self.commands.recv() => {
+                self.run_command(command).await;
+            }
+            Ok(event) = self.progress.recv() => {
+                self.notify_settled(&event).await;
+                self.bus.send(event)?;
+            }
+            _ = head_poll.tick() => {
+                // The mock node has no head to poll
+                if self.mock_settlements.is_none() {
+                    self.sync.poll_head().await;
+                }
+                // A batch never waits longer than a poll interval
+                self.flush_settlements().await;
+            }
+            _ = prune_tick.tick() => {
+                self.pruner.prune().await;
+                self.prune_noir_proofs().await;
+                self.prune_spending().await;
+                self.notice_expiring_proofs().await;
+            }
+            _ = flush_tick.tick() => {
+                if let Err(e) = self.tx_status.flush().await {
+                    tracing::warn!("⚠️ Could not flush tx history: {}", e);
+                }
+            }
         };
 
+        self.flush_settlements().await;
+        self.tx_status.flush().await?;
+
         Ok(())
     }
 }
 
+impl AppModule {
+    async fn handle_prover_event(&self, event: AutoProverEvent<Contract1>) {
+        #[cfg(feature = "chaos")]
+        if crate::chaos::proof_timeout() {
+            return;
+        }
+        #[cfg(feature = "cycle-accounting")]
+        {
+            let (AutoProverEvent::SuccessTx(tx_hash, _) | AutoProverEvent::FailedTx(tx_hash, _)) = &event;
+            // Measured from the state before the transaction, so before settling it
+            let state = self.amm_state.snapshot().await.map(|s| s.state().clone());
+            crate::cycles::measure(tx_hash, state, &self.contract1_cn, self.tx_status.clone());
+        }
+        let eta_secs = self.tx_status.proving_eta().await;
+        match event {
+            AutoProverEvent::SuccessTx(tx_hash, state) => {
+                // Publish the state first so whoever sees the success can read its effects
+                self.settle_state(&tx_hash.0, state, std::slice::from_ref(&tx_hash)).await;
+                self.tx_status.resolve(&tx_hash, TxStatus::Success).await;
+                self.tx_status.advance(&tx_hash, TxProgress::ProvingStarted { eta_secs }).await;
+            }
+            AutoProverEvent::FailedTx(tx_hash, error) => {
+                self.tx_status.resolve(&tx_hash, TxStatus::Failed { error }).await;
+                self.tx_status.advance(&tx_hash, TxProgress::ProvingStarted { eta_secs }).await;
+            }
+        }
+    }
+
+    /// Walk a mock transaction through the same stages a real one goes through
+    async fn settle_mock(&self, settlement: MockSettlement) {
+        let (tx_hash, success) = match &settlement.event {
+            AutoProverEvent::SuccessTx(tx_hash, _) => (tx_hash.clone(), true),
+            AutoProverEvent::FailedTx(tx_hash, _) => (tx_hash.clone(), false),
+        };
+        let block_height = settlement.block_height;
+        self.sync.block_processed(block_height).await;
+        self.tx_status.advance(&tx_hash, TxProgress::Sequenced { block_height }).await;
+        self.handle_prover_event(settlement.event).await;
+        self.tx_status.advance(&tx_hash, TxProgress::Settled { success }).await;
+    }
+
+    /// Take back what was derived from blocks the DA stream replaced
+    async fn revert(&self, reorg: Reorg) {
+        if let Some(before) = &reorg.amm_before {
+            self.amm_state.rollback(before.state().clone(), &reorg.txs).await;
+        }
+        if let Some(history) = &self.state_history {
+            if let Err(e) = history.rollback(reorg.from_height) {
+                tracing::warn!("⚠️ Could not roll the state history back to block {}: {}", reorg.from_height, e);
+            }
+        }
+        for tx_hash in &reorg.txs {
+            self.tx_status.reorged(tx_hash, reorg.from_height).await;
+        }
+    }
+
+    /// Advance submitted transactions sequenced or settled in this block
+    async fn track_progress(&self, block: &Block) {
+        let block_height = block.block_height.0;
+        for (tx_id, _) in &block.txs {
+            self.tx_status.advance(&tx_id.1, TxProgress::Sequenced { block_height }).await;
+        }
+        for tx_hash in &block.successful_txs {
+            self.tx_status.advance(tx_hash, TxProgress::Settled { success: true }).await;
+        }
+        for tx_hash in block.failed_txs.iter().chain(&block.timed_out_txs) {
+            self.tx_status.advance(tx_hash, TxProgress::Settled { success: false }).await;
+        }
+    }
+
+    /// Settle transactions as reported by the node when the AutoProver runs elsewhere.
+    ///
+    /// While the DA stream catches up, blocks are settled in batches of
+    /// `catch_up_batch_blocks`, fetching the state once per batch instead of once per block.
+    async fn settle_block(&mut self, block: &Block) {
+        let pending = &mut self.pending;
+        pending.blocks += 1;
+        pending.last_hash = block.hash.to_string();
+        pending.successful.extend(block.successful_txs.iter().cloned());
+        for tx_hash in &block.failed_txs {
+            pending.failed.push((tx_hash.clone(), "Transaction failed on chain".to_string()));
+        }
+        for tx_hash in &block.timed_out_txs {
+            pending.failed.push((tx_hash.clone(), "Transaction timed out".to_string()));
+        }
+        if pending.blocks >= self.catch_up_batch_blocks || !self.sync.catching_up().await {
+            self.flush_settlements().await;
+        }
+    }
+
+    /// Drop the submitted proofs older than `noir_proof_max_age_secs`
+    async fn prune_noir_proofs(&self) {
+        let Some(max_age) = self.noir_proof_max_age_secs else {
+            return;
+        };
+        let cutoff = chrono::Utc::now().timestamp() - max_age as i64;
+        match self.noir_proofs.prune(cutoff).await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!("🧹 Pruned {} stored Noir proofs", pruned),
+            Err(e) => tracing::warn!("⚠️ Could not prune stored Noir proofs: {}", e),
+        }
+    }
+
+    /// Warn users whose stored identity proof is about to be pruned
+    async fn notice_expiring_proofs(&self) {
+        let Some(max_age) = self.noir_proof_max_age_secs else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        match self.notifications.notice_expiring_proofs(&self.noir_proofs, max_age, now).await {
+            Ok(0) => {}
+            Ok(noticed) => tracing::info!("🔔 Warned the owners of {} expiring identity proofs", noticed),
+            Err(e) => tracing::warn!("⚠️ Could not notice expiring identity proofs: {}", e),
+        }
+    }
+
+    /// Tell the submitter of a transaction it settled
+    async fn notify_settled(&self, event: &TxProgressEvent) {
+        let TxProgress::Settled { success } = event.entry.progress else {
+            return;
+        };
+        let error = match self.tx_status.get(&event.tx_hash.0).await.map(|tx| tx.status) {
+            Some(TxStatus::Failed { error }) => Some(error),
+            _ => None,
+        };
+        let settled = NotificationEvent::TxSettled {
+            tx_hash: event.tx_hash.0.clone(),
+            success,
+            error,
+        };
+        if let Err(e) = self.notifications.notify(&event.identity, settled).await {
+            tracing::warn!("⚠️ Could not notify {} of {}: {}", event.identity, event.tx_hash, e);
+        }
+    }
+
+    /// Drop the daily spend older than `[spending] history_days`
+    async fn prune_spending(&self) {
+        match self.spending.prune().await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!("🧹 Pruned {} daily spending records", pruned),
+            Err(e) => tracing::warn!("⚠️ Could not prune daily spending: {}", e),
+        }
+    }
+
+    /// Publish the state of the pending blocks, then resolve their transactions
+    async fn flush_settlements(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        if pending.blocks == 0 {
+            return;
+        }
+        self.batch_sizes.record(pending.blocks as u64, &[]);
+        if !pending.successful.is_empty() {
+            if let Some(state) = AmmStateView::fetch(&self.state_source, &self.contract1_cn).await {
+                self.settle_state(&pending.last_hash, state, &pending.successful).await;
+            }
+        }
+
+        for tx_hash in &pending.successful {
+            self.tx_status.resolve(tx_hash, TxStatus::Success).await;
+        }
+        for (tx_hash, error) in pending.failed {
+            self.tx_status.resolve(&tx_hash, TxStatus::Failed { error }).await;
+        }
+    }
+
+    /// Publish a newly settled state, recording the price moves it caused under `source`
+    async fn settle_state(&self, source: &str, state: Contract1, applied_txs: &[TxHash]) {
+        let previous = self.amm_state.snapshot().await;
+        let now = chrono::Utc::now().timestamp();
+        if let Err(e) = self.price_history.record(source, previous.as_ref().map(|s| s.state()), &state, now).await {
+            tracing::warn!("⚠️ Could not record price history for {}: {}", source, e);
+        }
+        if let Err(e) = self.lp_entries.record(previous.as_ref().map(|s| s.state()), &state, now).await {
+            tracing::warn!("⚠️ Could not record LP entries for {}: {}", source, e);
+        }
+        if let Err(e) = self.notifications.check_alerts(&state).await {
+            tracing::warn!("⚠️ Could not check price alerts for {}: {}", source, e);
+        }
+        if let Some(history) = &self.state_history {
+            if let Err(e) = history.record(previous.as_ref().map(|s| s.state()), &state) {
+                tracing::warn!("⚠️ Could not record state history for {}: {}", source, e);
+            }
+        }
+        self.amm_state.update(state, applied_txs).await;
+    }
+}
+
+/// Outcomes of blocks settled from the DA stream, in block order
+#[derive(Default)]
+struct PendingSettlement {
+    blocks: usize,
+    /// Hash of the last block, the source the price moves are recorded under
+    last_hash: String,
+    successful: Vec<TxHash>,
+    failed: Vec<(TxHash, String)>,
+}
+
+/// Noir identity circuit, relative to the server's working directory
+const NOIR_CIRCUIT_DIR: &str = "../noir-contracts/zkpassport_identity";
+
 #[derive(Clone)]
 struct RouterCtx {
-    pub bus: Arc<Mutex<SharedMessageBus>>,
     pub client: Arc<NodeApiHttpClient>,
     pub contract1_cn: ContractName,
     pub contract2_cn: ContractName, // Placeholder for Noir contract
     pub noir_prover: Arc<NoirProver>,    // Real Noir proof generator
     pub noir_verifier: Arc<NoirVerifier>, // Real Noir proof verifier
+    pub noir_vk: Arc<VkStore>,
+    pub noir_jobs: Arc<NoirJobs>,
+    pub noir_proofs: Arc<ProofStore>,
+    pub webauthn: Arc<WebAuthnProvider>,
+    pub admin: AdminAuth,
+    pub profiles: Arc<ProfileStore>,
+    pub amm_state: AmmStateReader,
+    /// Where state imports are published, only on a mock node
+    pub imported_state: Option<AmmStateView>,
+    pub tx_status: Arc<TxStatusStore>,
+    pub explorer: Arc<Explorer>,
+    pub sync: SyncMonitor,
+    pub price_history: Arc<PriceHistory>,
+    pub lp_entries: Arc<PositionEntries>,
+    /// Unset when `[state_history]` is disabled
+    pub state_history: Option<Arc<StateHistory>>,
+    pub self_test: SelfTestResult,
+    pub mock_node: Option<Arc<MockNode>>,
+    pub limits: Arc<TradingLimits>,
+    pub spending: Arc<SpendingTracker>,
+    pub notifications: Arc<Notifications>,
+    pub delivery: Arc<Delivery>,
+    pub runbooks: Arc<Runbooks>,
+    pub feature_flags: Arc<FeatureFlags>,
+    pub capabilities: Arc<ServerCapabilities>,
+    pub validation: Arc<ValidationConf>,
+    pub screening: Arc<ScreeningGate>,
+    pub credential_policy: Arc<CredentialPolicy>,
+    pub identity_index: Arc<IdentityIndex>,
+    /// Whether contract1 wants an identity contract blob next to swaps and liquidity additions
+    pub identity_policy: bool,
+    /// Share of relayed swap outputs kept by the relayer
+    pub relayer_fee_bps: u128,
+    /// Longest a query with `min_tx` waits for that transaction to be applied
+    pub min_tx_wait: Duration,
+    /// Unset when reconciliation is disabled or there is no node to reconcile with
+    pub reconciler: Option<Arc<Reconciler>>,
+    pub allow_state_import: bool,
 }
 
 async fn health() -> impl IntoResponse {
@@ -123,6 +815,15 @@ async fn health() -> impl IntoResponse {
 // --------------------------------------------------------
 
 const USER_HEADER: &str = "x-user";
+/// Hex SHA-256 of the Noir verification key served
+const NOIR_VK_HASH_HEADER: &str = "x-noir-vk-hash";
+const BPS_DENOMINATOR: u128 = 10_000;
+const MAX_SLIPPAGE_BPS: u32 = 5_000;
+const SESSION_HEADER: &str = "x-session-token";
+/// Identity relayed swap transactions are sent as
+const RELAYER_USER: &str = "relayer";
+/// Points a portfolio history answers with at most
+const MAX_PORTFOLIO_POINTS: u64 = 500;
 
 #[derive(Debug)]
 struct AuthHeaders {
@@ -145,16 +846,198 @@ impl AuthHeaders {
             user: user.to_string(),
         })
     }
+
+    /// Resolve the caller, enforcing the passkey session when one is presented
+    async fn authenticate(ctx: &RouterCtx, headers: &HeaderMap) -> Result<Self, AppError> {
+        if headers.contains_key(SESSION_HEADER) {
+            return Self::require_session(&ctx.webauthn, headers).await;
+        }
+        Self::from_headers(headers)
+    }
+
+    /// Resolve the caller from a passkey session only, for routes a bare user header must not reach
+    async fn require_session(webauthn: &WebAuthnProvider, headers: &HeaderMap) -> Result<Self, AppError> {
+        let auth = Self::from_headers(headers)?;
+        let token = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()).ok_or_else(|| {
+            AppError(
+                StatusCode::UNAUTHORIZED,
+                anyhow::anyhow!("Missing session token"),
+            )
+        })?;
+        let identity = webauthn.session_identity(token).await.ok_or_else(|| {
+            AppError(
+                StatusCode::UNAUTHORIZED,
+                anyhow::anyhow!("Invalid or expired session"),
+            )
+        })?;
+        if identity != auth.user {
+            return Err(AppError(
+                StatusCode::UNAUTHORIZED,
+                anyhow::anyhow!("Session does not belong to {}", auth.user),
+            ));
+        }
+        Ok(auth)
+    }
+}
+
+/// Who may use the admin routes: a passkey session of an identity of `[admin] identities`.
+///
+/// Checked by the admin handlers themselves, so it holds without TLS client certificates too.
+#[derive(Clone)]
+pub struct AdminAuth {
+    webauthn: Arc<WebAuthnProvider>,
+    identities: Arc<HashSet<String>>,
+}
+
+impl AdminAuth {
+    /// The admin sending the request
+    async fn require(&self, headers: &HeaderMap) -> Result<String, AppError> {
+        let auth = AuthHeaders::require_session(&self.webauthn, headers).await?;
+        if !self.identities.contains(&auth.user) {
+            return Err(AppError(
+                StatusCode::FORBIDDEN,
+                anyhow::anyhow!("{} is not an admin", auth.user),
+            ));
+        }
+        Ok(auth.user)
+    }
+}
+
+impl FromRef<RouterCtx> for AdminAuth {
+    fn from_ref(ctx: &RouterCtx) -> Self {
+        ctx.admin.clone()
+    }
+}
+
+impl FromRef<RouterCtx> for Arc<FeatureFlags> {
+    fn from_ref(ctx: &RouterCtx) -> Self {
+        ctx.feature_flags.clone()
+    }
+}
+
+/// Refuse reads that depend on indexed state until the initial DA catch-up completed
+async fn require_synced(ctx: &RouterCtx) -> Result<(), AppError> {
+    if ctx.sync.is_ready().await {
+        return Ok(());
+    }
+    let status = ctx.sync.status().await;
+    Err(AppError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        anyhow::anyhow!(
+            "Server is catching up with the chain (processed {:?}, head {:?}), retry shortly",
+            status.processed_height,
+            status.node_head
+        ),
+    ))
+}
+
+/// Page size of list endpoints when no `limit` is given
+const DEFAULT_PAGE_LIMIT: usize = 50;
+const MAX_PAGE_LIMIT: usize = 500;
+
+/// Cursor pagination and ordering shared by list endpoints, next to their own filters
+#[derive(Deserialize)]
+struct PageQuery {
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+    limit: Option<usize>,
+    /// Order of the entries by their key (id, reference, pair or time)
+    #[serde(default)]
+    order: SortOrder,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
 }
 
+/// Response envelope of list endpoints
 #[derive(Serialize)]
-struct ConfigResponse {
-    contract_name: String,
+struct Page<T> {
+    items: Vec<T>,
+    /// Cursor of the next page, absent on the last one
+    next_cursor: Option<String>,
+}
+
+impl PageQuery {
+    /// Sort filtered entries by key and cut the page following the cursor
+    fn paginate<K, T>(&self, entries: impl IntoIterator<Item = (K, T)>) -> Result<Page<T>, AppError>
+    where
+        K: Ord + std::fmt::Display + std::str::FromStr,
+    {
+        let after = match &self.cursor {
+            Some(cursor) => Some(cursor.parse::<K>().map_err(|_| {
+                AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!("Invalid cursor {}", cursor))
+            })?),
+            None => None,
+        };
+        let mut entries: Vec<_> = entries
+            .into_iter()
+            .filter(|(key, _)| match (&after, self.order) {
+                (None, _) => true,
+                (Some(after), SortOrder::Asc) => key > after,
+                (Some(after), SortOrder::Desc) => key < after,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        if self.order == SortOrder::Desc {
+            entries.reverse();
+        }
+
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+        let next_cursor = match entries.len() > limit {
+            true => entries.get(limit - 1).map(|(key, _)| key.to_string()),
+            false => None,
+        };
+        entries.truncate(limit);
+        Ok(Page {
+            items: entries.into_iter().map(|(_, entry)| entry).collect(),
+            next_cursor,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ConsistencyQuery {
+    /// Transaction whose effects the answer must include
+    min_tx: Option<String>,
+}
+
+/// Read-your-writes for queries: with `min_tx=<hash>`, hold the query until the served
+/// state includes that transaction, answering 503 if it does not within `min_tx_wait`.
+///
+/// Settled transactions are answered right away: the state is published before the
+/// status, and failed transactions changed nothing.
+async fn read_your_writes(State(ctx): State<RouterCtx>, request: Request, next: Next) -> Response {
+    let min_tx = Query::<ConsistencyQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.min_tx);
+    let Some(tx_hash) = min_tx.filter(|_| request.method() == Method::GET) else {
+        return next.run(request).await;
+    };
+
+    let settled = ctx
+        .tx_status
+        .get(&tx_hash)
+        .await
+        .is_some_and(|record| record.status != TxStatus::Pending);
+    if !settled && !ctx.amm_state.wait_for(&tx_hash, ctx.min_tx_wait).await {
+        return AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("Transaction {} is not applied yet, retry shortly", tx_hash),
+        )
+        .into_response();
+    }
+    next.run(request).await
 }
 
 #[derive(Deserialize)]
 struct MintTokensRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token: String,
     amount: u128,
 }
@@ -162,25 +1045,76 @@ struct MintTokensRequest {
 #[derive(Deserialize)]
 struct SwapTokensRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token_in: String,
+    #[serde(deserialize_with = "validation::canonical")]
     token_out: String,
     amount_in: u128,
-    min_amount_out: u128,
+    /// Either an explicit minimum output...
+    min_amount_out: Option<u128>,
+    /// ...or a slippage tolerance the server turns into one from the latest reserves
+    slippage_bps: Option<u32>,
+    /// Re-quote and resubmit once if the swap fails on slippage (requires slippage_bps)
+    #[serde(default)]
+    auto_retry: bool,
+    /// Identity contract blob proving a higher tier, e.g. a verification
+    #[serde(default)]
+    identity_blob: Option<Blob>,
+    /// Oracle contract price blob, needed for large swaps when contract1 has an oracle guard
+    #[serde(default)]
+    oracle_blob: Option<Blob>,
 }
 
 #[derive(Deserialize)]
 struct AddLiquidityRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_b: String,
+    amount_a: u128,
+    amount_b: u128,
+    #[serde(default)]
+    identity_blob: Option<Blob>,
+}
+
+#[derive(Deserialize)]
+struct CreateBatchAuctionPoolRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_b: String,
+    amount_a: u128,
+    amount_b: u128,
+    #[serde(default)]
+    identity_blob: Option<Blob>,
+}
+
+#[derive(Deserialize)]
+struct CreateLbpPoolRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
     token_b: String,
     amount_a: u128,
     amount_b: u128,
+    /// Weight of token_a in basis points at start_block, token_b has the rest
+    start_weight_a: u128,
+    end_weight_a: u128,
+    start_block: u64,
+    end_block: u64,
+    #[serde(default)]
+    identity_blob: Option<Blob>,
 }
 
 #[derive(Deserialize)]
 struct RemoveLiquidityRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
     token_b: String,
     liquidity_amount: u128,
 }
@@ -188,13 +1122,16 @@ struct RemoveLiquidityRequest {
 #[derive(Deserialize)]
 struct GetUserBalanceRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token: String,
 }
 
 #[derive(Deserialize)]
 struct GetPoolReservesRequest {
     wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
     token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
     token_b: String,
 }
 
@@ -204,256 +1141,2776 @@ struct TestAmmRequest {
 }
 
 #[derive(Deserialize)]
-pub struct NoirAuthRequest {
-    pub username: String,
-    pub user_field: String,
-    pub password_field: String,
-    pub proof_type: String,
+struct CreateStreamingSwapRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token_in: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_out: String,
+    total_amount: u128,
+    chunks: u32,
+    interval_blocks: u64,
+    #[serde(default)]
+    min_amount_out_per_chunk: u128,
+    #[serde(default)]
+    identity_blob: Option<Blob>,
 }
 
-#[derive(Serialize)]
-pub struct NoirAuthResponse {
-    pub success: bool,
-    pub message: String,
-    pub proof_hash: Option<String>,
-    pub tx_hash: Option<String>,
+#[derive(Deserialize)]
+struct CancelStreamingSwapRequest {
+    wallet_blobs: [Blob; 2],
 }
 
-// Known correct values for demo (these would come from Noir circuit compilation)
-const EXPECTED_BOB_FIELD: &str = "12345"; // Placeholder - needs actual Poseidon2 hash
-const EXPECTED_PASSWORD_FIELD: &str = "54321"; // Placeholder - needs actual Poseidon2 hash
+#[derive(Deserialize)]
+struct PoolsQuery {
+    /// Only pools trading this token
+    #[serde(default, deserialize_with = "validation::canonical_option")]
+    token: Option<String>,
+}
 
-// --------------------------------------------------------
-//     Routes
-// --------------------------------------------------------
+#[derive(Deserialize)]
+struct AtBlockQuery {
+    /// Past block height to answer at, the latest state when unset
+    block: Option<u64>,
+}
 
-async fn mint_tokens(
-    State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<MintTokensRequest>
-) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    let action_contract1 = Contract1Action::MintTokens {
-        user: auth.user.clone(),
-        token: request.token,
-        amount: request.amount,
-    };
-    
-    // For now, only process AMM actions - Noir identity verification will be added later
-    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+#[derive(Serialize)]
+struct BalancesResponse {
+    user: String,
+    /// Height answered at, unset for the latest state
+    block: Option<u64>,
+    balances: BTreeMap<String, u128>,
 }
 
-async fn swap_tokens(
-    State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<SwapTokensRequest>
-) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    let action_contract1 = Contract1Action::SwapExactTokensForTokens {
-        user: auth.user.clone(),
-        token_in: request.token_in,
-        token_out: request.token_out,
-        amount_in: request.amount_in,
-        min_amount_out: request.min_amount_out,
-    };
-    
-    // TODO: Add Noir identity verification for @zkpassport users
-    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+#[derive(Deserialize)]
+struct PortfolioHistoryQuery {
+    /// Token everything is valued in
+    #[serde(deserialize_with = "validation::canonical")]
+    quote: String,
+    /// Blocks between two points, 100 by default
+    interval: Option<u64>,
+    /// First block, 100 intervals before `to` by default
+    from: Option<u64>,
+    /// Last block, the latest processed one by default
+    to: Option<u64>,
 }
 
-async fn add_liquidity(
-    State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<AddLiquidityRequest>
-) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    let action_contract1 = Contract1Action::AddLiquidity {
-        user: auth.user.clone(),
-        token_a: request.token_a,
-        token_b: request.token_b,
-        amount_a: request.amount_a,
-        amount_b: request.amount_b,
-    };
-    
-    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+#[derive(Serialize)]
+struct PortfolioHistoryResponse {
+    user: String,
+    quote: String,
+    interval: u64,
+    /// Oldest first, starting at the oldest block still in the state history
+    points: Vec<PortfolioPoint>,
+    /// Tokens held without a pool against the quote token, left out of the values
+    unpriced: BTreeSet<String>,
 }
 
-async fn remove_liquidity(
-    State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<RemoveLiquidityRequest>
-) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    let action_contract1 = Contract1Action::RemoveLiquidity {
-        user: auth.user.clone(),
-        token_a: request.token_a,
-        token_b: request.token_b,
-        liquidity_amount: request.liquidity_amount,
-    };
-    
-    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    #[serde(default)]
+    unread: bool,
 }
 
-async fn get_user_balance(
-    State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<GetUserBalanceRequest>
-) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
+#[derive(Deserialize)]
+struct MarkReadRequest {
+    /// Notifications to mark, every one of the user's when unset
+    ids: Option<Vec<u64>>,
+}
+
+#[derive(Serialize)]
+struct MarkReadResponse {
+    marked: usize,
+}
+
+/// Delivery settings without the bot token, which is only ever written
+#[derive(Serialize)]
+struct DeliverySettingsResponse {
+    /// Whether this server delivers at all
+    enabled: bool,
+    /// Channels this server can deliver on
+    channels: Vec<&'static str>,
+    email: Option<String>,
+    telegram_chat_id: Option<String>,
+    kinds: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PriceAlertRequest {
+    /// Pool pair key, e.g. "ETH_USDC"
+    #[serde(deserialize_with = "validation::canonical")]
+    pair: String,
+    direction: AlertDirection,
+    /// Spot price in token_b per token_a
+    price: f64,
+}
+
+#[derive(Serialize)]
+struct PortfolioResponse {
+    user: String,
+    balances: BTreeMap<String, u128>,
+    /// Today's spend by token, each with its soft budget
+    today: Vec<SpendLine>,
+    /// Budgets today's spend is over
+    warnings: Vec<BudgetWarning>,
+    /// Earlier days with any spend, most recent first
+    history: Vec<DaySpend>,
+}
+
+#[derive(Serialize)]
+struct SpendLine {
+    kind: SpendKind,
+    token: String,
+    spent: u128,
+    budget: Option<u128>,
+}
+
+#[derive(Serialize)]
+struct DaySpend {
+    /// UTC day, as YYYY-MM-DD
+    day: String,
+    #[serde(flatten)]
+    spend: DailySpend,
+}
+
+#[derive(Serialize)]
+struct PoolEntry {
+    pair: String,
+    /// Whether swaps clear in a batch auction per block rather than as sequenced
+    batch_auction: bool,
+    #[serde(flatten)]
+    pool: LiquidityPool,
+}
+
+#[derive(Deserialize)]
+struct StreamingSwapsQuery {
+    user: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StreamingSwapEntry {
+    order_id: u64,
+    #[serde(flatten)]
+    order: StreamingSwap,
+}
+
+#[derive(Deserialize)]
+struct CreateOtcOfferRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical_amount")]
+    give: TokenAmount,
+    #[serde(deserialize_with = "validation::canonical_amount")]
+    want: TokenAmount,
+    counterparty: String,
+    #[serde(default)]
+    identity_blob: Option<Blob>,
+}
+
+#[derive(Deserialize)]
+struct OtcOfferRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(default)]
+    identity_blob: Option<Blob>,
+}
+
+/// Filters offers made by or to a single identity
+#[derive(Deserialize)]
+struct OtcOffersQuery {
+    user: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OtcOfferEntry {
+    offer_id: u64,
+    #[serde(flatten)]
+    offer: OtcOffer,
+}
+
+#[derive(Deserialize)]
+struct CreateVestingRequest {
+    wallet_blobs: [Blob; 2],
+    beneficiary: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token: String,
+    amount: u128,
+    start_block: u64,
+    duration: u64,
+}
+
+#[derive(Deserialize)]
+struct ClaimVestingRequest {
+    wallet_blobs: [Blob; 2],
+}
+
+/// Filters schedules created by or for a single identity
+#[derive(Deserialize)]
+struct VestingsQuery {
+    user: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VestingEntry {
+    vesting_id: u64,
+    #[serde(flatten)]
+    vesting: Vesting,
+    /// Released as of the last indexed block, claimed or not
+    vested: u128,
+}
+
+#[derive(Deserialize)]
+struct FileInsuranceClaimRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token: String,
+    amount: u128,
+    reason: String,
+}
+
+/// Approval or rejection of a claim, sent by the insurance admin
+#[derive(Deserialize)]
+struct DecideInsuranceClaimRequest {
+    wallet_blobs: [Blob; 2],
+}
+
+/// Collection of the accrued protocol fees, sent by the protocol fee admin
+#[derive(Deserialize)]
+struct CollectProtocolFeesRequest {
+    wallet_blobs: [Blob; 2],
+}
+
+/// Faucet limits and what is left to mint under them
+#[derive(Serialize)]
+struct FaucetResponse {
+    cooldown_blocks: u64,
+    tokens: BTreeMap<String, FaucetTokenResponse>,
+}
+
+#[derive(Serialize)]
+struct FaucetTokenResponse {
+    minted: u128,
+    lifetime_cap: Option<u128>,
+    remaining: Option<u128>,
+}
+
+/// Protocol fee settings and the fees accrued since the last collection
+#[derive(Serialize)]
+struct ProtocolFeesResponse {
+    admin: String,
+    share_bps: u128,
+    accrued: BTreeMap<String, u128>,
+}
+
+#[derive(Deserialize)]
+struct ProposeAdminActionRequest {
+    wallet_blobs: [Blob; 2],
+    operation: AdminOperation,
+}
+
+#[derive(Deserialize)]
+struct ApproveAdminActionRequest {
+    wallet_blobs: [Blob; 2],
+}
+
+#[derive(Serialize)]
+struct AdminCouncilResponse {
+    paused: bool,
+    emergency: bool,
+    #[serde(flatten)]
+    council: Option<contract1::AdminCouncil>,
+}
+
+#[derive(Deserialize)]
+pub struct NoirAuthRequest {
+    pub username: String,
+    pub user_field: String,
+    pub password_field: String,
+    pub proof_type: String,
+    /// Id the client picked to follow the proof generation on `/noir/jobs/{id}/events`
+    #[serde(default)]
+    pub job_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NoirAuthResponse {
+    pub success: bool,
+    pub message: String,
+    pub proof_hash: Option<String>,
+    pub tx_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NoirProofSubmission {
+    username: String,
+    /// Hex proof, as generated in the browser by bb.js
+    proof: String,
+    /// Public inputs of the circuit, the password hash then the user hash
+    public_inputs: Vec<String>,
+    /// Hash of the verification key the proof was made for, from `x-noir-vk-hash`
+    vk_hash: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NoirProofSubmissionResponse {
+    tx_hash: String,
+    proof_hash: String,
+    vk_hash: String,
+}
+
+#[derive(Deserialize)]
+struct PriceHistoryQuery {
+    /// Pool pair key, e.g. "ETH_USDC"
+    #[serde(deserialize_with = "validation::canonical")]
+    pair: String,
+    interval: String,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ImpermanentLossQuery {
+    /// Candle interval of the simulated history, 1h by default
+    interval: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QuoteQuery {
+    #[serde(deserialize_with = "validation::canonical")]
+    token_in: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_out: String,
+    amount_in: u128,
+}
+
+#[derive(Serialize)]
+struct QuoteResponse {
+    amount_out: u128,
+}
+
+#[derive(Deserialize)]
+struct TxEventsQuery {
+    identity: String,
+}
+
+#[derive(Deserialize)]
+struct IdentityChangesQuery {
+    /// Last sequence number already consumed
+    #[serde(default)]
+    since: u64,
+}
+
+#[derive(Deserialize)]
+struct IdentityRequirementsQuery {
+    user: String,
+    action: PolicyAction,
+    /// Swap input, or the larger side of a liquidity deposit
+    #[serde(default)]
+    amount: u128,
+}
+
+#[derive(Deserialize)]
+struct VolumeQuery {
+    #[serde(deserialize_with = "validation::canonical")]
+    pair: String,
+}
+
+#[derive(Deserialize)]
+struct RewardsQuery {
+    #[serde(deserialize_with = "validation::canonical")]
+    token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_b: String,
+    user: String,
+}
+
+#[derive(Serialize)]
+struct RewardsResponse {
+    reward_points: u128,
+    /// Whether the position earns the verified identity boost
+    verified: bool,
+    /// Block the points are computed at
+    block_height: u64,
+}
+
+#[derive(Deserialize)]
+struct OnchainVolumeQuery {
+    #[serde(deserialize_with = "validation::canonical")]
+    token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_b: String,
+    /// Only this user's swaps
+    user: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExternalTransferRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token: String,
+    amount: u128,
+}
+
+#[derive(Deserialize)]
+struct BridgeDepositRequest {
+    wallet_blobs: [Blob; 2],
+    user: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token: String,
+    amount: u128,
+    /// Transaction of the deposit on the other chain, credited at most once
+    external_tx_ref: String,
+}
+
+#[derive(Deserialize)]
+struct BridgeDepositsQuery {
+    user: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BridgeDepositEntry {
+    external_tx_ref: String,
+    #[serde(flatten)]
+    deposit: BridgeDepositRecord,
+}
+
+#[derive(Deserialize)]
+struct RequestWithdrawalRequest {
+    wallet_blobs: [Blob; 2],
+    #[serde(deserialize_with = "validation::canonical")]
+    token: String,
+    amount: u128,
+    /// Recipient on the other chain
+    destination: String,
+}
+
+#[derive(Deserialize)]
+struct ProcessWithdrawalRequest {
+    wallet_blobs: [Blob; 2],
+    /// Transaction paying out the withdrawal on the other chain
+    external_tx_ref: String,
+}
+
+#[derive(Deserialize)]
+struct CancelWithdrawalRequest {
+    wallet_blobs: [Blob; 2],
+}
+
+#[derive(Deserialize)]
+struct WithdrawalsQuery {
+    user: Option<String>,
+    /// Only list withdrawals still waiting for the operator
+    #[serde(default)]
+    pending: bool,
+}
+
+#[derive(Serialize)]
+struct WithdrawalEntry {
+    withdrawal_id: u64,
+    #[serde(flatten)]
+    withdrawal: Withdrawal,
+}
+
+#[derive(Deserialize)]
+struct SetReferrerRequest {
+    wallet_blobs: [Blob; 2],
+    referrer: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterSigningKeyRequest {
+    wallet_blobs: [Blob; 2],
+    /// Hex-encoded compressed secp256k1 public key
+    public_key: String,
+}
+
+/// Swap intent signed off-chain, submitted without the user's wallet
+#[derive(Deserialize)]
+struct RelaySwapRequest {
+    intent: SwapIntent,
+    /// Hex-encoded compact ECDSA signature of the intent digest
+    signature: String,
+    /// Oracle contract price blob, needed for large swaps when contract1 has an oracle guard
+    #[serde(default)]
+    oracle_blob: Option<Blob>,
+}
+
+#[derive(Deserialize)]
+struct VotesQuery {
+    user: String,
+    /// Defaults to the next block
+    block: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct VotesResponse {
+    user: String,
+    block: u64,
+    votes: u128,
+}
+
+#[cfg(feature = "rounding-audit")]
+#[derive(Deserialize)]
+struct RoundingReserveQuery {
+    #[serde(deserialize_with = "validation::canonical")]
+    token_a: String,
+    #[serde(deserialize_with = "validation::canonical")]
+    token_b: String,
+}
+
+#[derive(Deserialize)]
+struct WebAuthnStartRequest {
+    username: String,
+}
+
+#[derive(Serialize)]
+struct WebAuthnRegisterResponse {
+    identity: String,
+}
+
+#[derive(Serialize)]
+struct WebAuthnLoginResponse {
+    identity: String,
+    session_token: String,
+}
+
+// Known correct values for demo (these would come from Noir circuit compilation)
+const EXPECTED_BOB_FIELD: &str = "12345"; // Placeholder - needs actual Poseidon2 hash
+const EXPECTED_PASSWORD_FIELD: &str = "54321"; // Placeholder - needs actual Poseidon2 hash
+
+// --------------------------------------------------------
+//     Routes
+// --------------------------------------------------------
+
+async fn mint_tokens(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<MintTokensRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    ctx.limits.check_mint(&request.token, request.amount)?;
+    let user = auth.user.clone();
+    
+    let action_contract1 = Contract1Action::Faucet(FaucetAction::Mint {
+        user: auth.user.clone(),
+        token: request.token.clone(),
+        amount: request.amount,
+    });
+    
+    // For now, only process AMM actions - Noir identity verification will be added later
+    let response = send_amm_action_only(ctx.clone(), auth, request.wallet_blobs, action_contract1).await?;
+    Ok(record_spend(&ctx, &user, SpendKind::Mint, &request.token, request.amount, response).await)
+}
+
+async fn swap_tokens(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<SwapTokensRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    ctx.limits.check_swap(&request.token_in, request.amount_in)?;
+    ctx.screening.check(&auth.user).await?;
+    ctx.credential_policy
+        .check(&auth.user, PolicyAction::Swap, request.amount_in)
+        .await?;
+
+    let (min_amount_out, slippage) = match (request.min_amount_out, request.slippage_bps) {
+        (Some(min_amount_out), None) => (min_amount_out, None),
+        (None, Some(slippage_bps)) => {
+            let quote = quote_swap(&ctx, &request.token_in, &request.token_out, request.amount_in).await?;
+            (apply_slippage(quote, slippage_bps)?, Some(slippage_bps))
+        }
+        _ => {
+            return Err(AppError(
+                StatusCode::BAD_REQUEST,
+                anyhow::anyhow!("Provide exactly one of min_amount_out or slippage_bps"),
+            ))
+        }
+    };
+    
+    let action_contract1 = Contract1Action::SwapExactTokensForTokens {
+        user: auth.user.clone(),
+        token_in: request.token_in.clone(),
+        token_out: request.token_out.clone(),
+        amount_in: request.amount_in,
+        min_amount_out,
+    };
+    
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob.clone())?);
+    blobs.extend(oracle_blob(&ctx, request.oracle_blob.clone()).await?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+    let outcome = submit_and_wait(&ctx, &auth.user, blobs, None).await?;
+
+    let outcome = match (outcome, slippage) {
+        (TxOutcome::Failed(tx_hash, error), Some(slippage_bps))
+            if request.auto_retry && AmmError::code_of(&error) == Some(AmmError::SlippageExceeded.code()) =>
+        {
+            retry_swap(&ctx, &auth, &request, min_amount_out, slippage_bps, tx_hash, error).await?
+        }
+        (outcome, _) => outcome,
+    };
+
+    let response = outcome_response(outcome)?;
+    Ok(record_spend(&ctx, &auth.user, SpendKind::Swap, &request.token_in, request.amount_in, response).await)
+}
+
+/// Re-quote a swap that failed on slippage and resubmit it once.
+///
+/// The refreshed minimum output never goes below the floor the user originally
+/// accepted; if the fresh quote itself is under that floor the price moved beyond
+/// the user's tolerance and the original failure is returned untouched.
+/// The wallet blobs are resubmitted as-is, so wallets enforcing one-time nonces
+/// will reject the retry, which then shows up as a failed link in the retry chain.
+async fn retry_swap(
+    ctx: &RouterCtx,
+    auth: &AuthHeaders,
+    request: &SwapTokensRequest,
+    floor: u128,
+    slippage_bps: u32,
+    failed_tx: TxHash,
+    error: String,
+) -> Result<TxOutcome, AppError> {
+    let fresh_quote = match quote_swap(ctx, &request.token_in, &request.token_out, request.amount_in).await {
+        Ok(quote) => quote,
+        Err(_) => return Ok(TxOutcome::Failed(failed_tx, error)),
+    };
+    if fresh_quote < floor {
+        tracing::info!("↩️ Not retrying {}: fresh quote {} below floor {}", failed_tx, fresh_quote, floor);
+        return Ok(TxOutcome::Failed(failed_tx, error));
+    }
+
+    let refreshed_min_amount_out = apply_slippage(fresh_quote, slippage_bps)?.max(floor);
+    tracing::info!(
+        "🔁 Retrying {} with min_amount_out {} (was {})",
+        failed_tx, refreshed_min_amount_out, floor
+    );
+
+    let action_contract1 = Contract1Action::SwapExactTokensForTokens {
+        user: auth.user.clone(),
+        token_in: request.token_in.clone(),
+        token_out: request.token_out.clone(),
+        amount_in: request.amount_in,
+        min_amount_out: refreshed_min_amount_out,
+    };
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(ctx, &auth.user, request.identity_blob.clone())?);
+    blobs.extend(oracle_blob(ctx, request.oracle_blob.clone()).await?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    submit_and_wait(ctx, &auth.user, blobs, Some(&failed_tx)).await
+}
+
+/// Quote a swap against the freshest known reserves
+async fn quote_swap(
+    ctx: &RouterCtx,
+    token_in: &str,
+    token_out: &str,
+    amount_in: u128,
+) -> Result<u128, AppError> {
+    require_synced(ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet, pass min_amount_out explicitly"),
+        )
+    })?;
+    state
+        .quote_exact_tokens_for_tokens(token_in, token_out, amount_in)
+        .map_err(|e| contract_error(e.into()))
+}
+
+/// Turn a quote into a minimum output given a slippage tolerance
+fn apply_slippage(quote: u128, slippage_bps: u32) -> Result<u128, AppError> {
+    if slippage_bps > MAX_SLIPPAGE_BPS {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("slippage_bps must be at most {}", MAX_SLIPPAGE_BPS),
+        ));
+    }
+    Ok(quote * (BPS_DENOMINATOR - slippage_bps as u128) / BPS_DENOMINATOR)
+}
+
+async fn add_liquidity(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<AddLiquidityRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    let state = ctx.amm_state.snapshot().await;
+    ctx.limits.check_pool_share(
+        state.as_ref().map(|s| s.state()),
+        &auth.user,
+        &request.token_a,
+        &request.token_b,
+        request.amount_a,
+        request.amount_b,
+    )?;
+    
+    let action_contract1 = Contract1Action::AddLiquidity {
+        user: auth.user.clone(),
+        token_a: request.token_a,
+        token_b: request.token_b,
+        amount_a: request.amount_a,
+        amount_b: request.amount_b,
+    };
+    ctx.screening.check(&auth.user).await?;
+    let amount = request.amount_a.max(request.amount_b);
+    ctx.credential_policy
+        .check(&auth.user, PolicyAction::AddLiquidity, amount)
+        .await?;
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+async fn create_lbp_pool(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<CreateLbpPoolRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CreateLbpPool {
+        user: auth.user.clone(),
+        token_a: request.token_a,
+        token_b: request.token_b,
+        amount_a: request.amount_a,
+        amount_b: request.amount_b,
+        start_weight_a: request.start_weight_a,
+        end_weight_a: request.end_weight_a,
+        start_block: request.start_block,
+        end_block: request.end_block,
+    };
+    ctx.screening.check(&auth.user).await?;
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+async fn create_batch_auction_pool(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<CreateBatchAuctionPoolRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CreateBatchAuctionPool {
+        user: auth.user.clone(),
+        token_a: request.token_a,
+        token_b: request.token_b,
+        amount_a: request.amount_a,
+        amount_b: request.amount_b,
+    };
+    ctx.screening.check(&auth.user).await?;
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+/// Identity contract blob to compose with a trade when contract1 enforces its identity policy.
+///
+/// Without one from the client, an identity lookup is composed, which only grants the
+/// unverified tier.
+fn identity_blob(ctx: &RouterCtx, user: &str, provided: Option<Blob>) -> Result<Option<Blob>, AppError> {
+    if let Some(blob) = &provided {
+        if blob.contract_name != ctx.contract2_cn {
+            return Err(AppError(
+                StatusCode::BAD_REQUEST,
+                anyhow::anyhow!("identity_blob must target {}", ctx.contract2_cn),
+            ));
+        }
+    }
+    Ok(provided.or_else(|| identity_lookup(ctx, user)))
+}
+
+/// Oracle price blob provided by the client, checked to target the oracle contract1 trusts
+async fn oracle_blob(ctx: &RouterCtx, provided: Option<Blob>) -> Result<Option<Blob>, AppError> {
+    let Some(blob) = provided else {
+        return Ok(None);
+    };
+    let oracle_contract = ctx
+        .amm_state
+        .snapshot()
+        .await
+        .and_then(|state| state.oracle_guard().map(|guard| guard.oracle_contract.clone()));
+    match oracle_contract {
+        Some(oracle_contract) if blob.contract_name == oracle_contract => Ok(Some(blob)),
+        Some(oracle_contract) => Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("oracle_blob must target {}", oracle_contract),
+        )),
+        None => Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("contract1 has no oracle guard"),
+        )),
+    }
+}
+
+/// Identity lookup blob required by contract1 for trades, if it enforces an identity policy
+fn identity_lookup(ctx: &RouterCtx, user: &str) -> Option<Blob> {
+    ctx.identity_policy.then(|| {
+        IdentityAction::IsUserAllowed { user: user.to_string() }.as_blob(ctx.contract2_cn.clone())
+    })
+}
+
+async fn remove_liquidity(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<RemoveLiquidityRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    
+    let action_contract1 = Contract1Action::RemoveLiquidity {
+        user: auth.user.clone(),
+        token_a: request.token_a,
+        token_b: request.token_b,
+        liquidity_amount: request.liquidity_amount,
+    };
+    
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn get_user_balance(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<GetUserBalanceRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    require_synced(&ctx).await?;
+    
     let action_contract1 = Contract1Action::GetUserBalance {
         user: auth.user.clone(),
         token: request.token,
     };
-    
+    
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn get_pool_reserves(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<GetPoolReservesRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    require_synced(&ctx).await?;
+    
+    let action_contract1 = Contract1Action::GetReserves {
+        token_a: request.token_a,
+        token_b: request.token_b,
+    };
+    
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn test_amm(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<TestAmmRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    
+    // Test action: Mint some USDC tokens for testing
+    let action_contract1 = Contract1Action::Faucet(FaucetAction::Mint {
+        user: auth.user.clone(),
+        token: "USDC".to_string(),
+        amount: 1000,
+    });
+    
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn create_streaming_swap(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<CreateStreamingSwapRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    ctx.limits.check_swap(&request.token_in, request.total_amount)?;
+
+    let action_contract1 = Contract1Action::CreateStreamingSwap {
+        user: auth.user.clone(),
+        token_in: request.token_in.clone(),
+        token_out: request.token_out,
+        total_amount: request.total_amount,
+        chunks: request.chunks,
+        interval_blocks: request.interval_blocks,
+        min_amount_out_per_chunk: request.min_amount_out_per_chunk,
+    };
+    ctx.screening.check(&auth.user).await?;
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    // The whole order counts towards today's budget once accepted, whenever its chunks execute
+    let response = outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)?;
+    Ok(record_spend(&ctx, &auth.user, SpendKind::Swap, &request.token_in, request.total_amount, response).await)
+}
+
+async fn cancel_streaming_swap(
+    State(ctx): State<RouterCtx>,
+    Path(order_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<CancelStreamingSwapRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CancelStreamingSwap {
+        user: auth.user.clone(),
+        order_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Running streaming swaps, optionally of a single user
+async fn get_pools(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<PoolsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let pools = state
+        .pools()
+        .iter()
+        .filter(|(_, pool)| {
+            query
+                .token
+                .as_ref()
+                .is_none_or(|token| &pool.token_a == token || &pool.token_b == token)
+        })
+        .map(|(pair, pool)| {
+            let entry = PoolEntry {
+                pair: pair.clone(),
+                batch_auction: state.is_batch_auction(pair),
+                pool: pool.clone(),
+            };
+            (pair.clone(), entry)
+        });
+    Ok(Json(page.paginate(pools)?))
+}
+
+/// A pool by pair key, e.g. `ETH_USDC`, now or after a past block
+async fn get_pool(
+    State(ctx): State<RouterCtx>,
+    Path(pair): Path<String>,
+    Query(query): Query<AtBlockQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let pool = match query.block {
+        Some(block) => historical_state(&ctx, block).await?.pools.remove(&pair),
+        None => {
+            let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+                AppError(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    anyhow::anyhow!("AMM state not indexed yet"),
+                )
+            })?;
+            state.pools().get(&pair).cloned()
+        }
+    };
+    let pool = pool.ok_or_else(|| AppError(StatusCode::NOT_FOUND, anyhow::anyhow!("No pool {}", pair)))?;
+    // A pool's kind is set at creation, the current state tells it for past blocks too
+    let batch_auction = ctx
+        .amm_state
+        .snapshot()
+        .await
+        .is_some_and(|state| state.is_batch_auction(&pair));
+    Ok(Json(PoolEntry { pair, batch_auction, pool }))
+}
+
+/// Token balances of a user, now or after a past block
+async fn get_balances(
+    State(ctx): State<RouterCtx>,
+    Path(user): Path<String>,
+    Query(query): Query<AtBlockQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let balances = match query.block {
+        Some(block) => historical_state(&ctx, block).await?.balances.remove(&user).unwrap_or_default(),
+        None => {
+            let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+                AppError(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    anyhow::anyhow!("AMM state not indexed yet"),
+                )
+            })?;
+            state
+                .user_balances()
+                .filter(|(holder, _, amount)| *holder == user && *amount > 0)
+                .map(|(_, token, amount)| (token.to_string(), amount))
+                .collect()
+        }
+    };
+    Ok(Json(BalancesResponse {
+        user,
+        block: query.block,
+        balances,
+    }))
+}
+
+/// Pools and balances after a past block, from `[state_history]`
+async fn historical_state(ctx: &RouterCtx, block: u64) -> Result<HistoricalState, AppError> {
+    let history = ctx.state_history.as_ref().ok_or_else(|| {
+        AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!("State history is disabled"))
+    })?;
+    let processed = ctx.sync.status().await.processed_height;
+    if processed.is_some_and(|processed| block > processed) {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("Block {} is not processed yet, latest is {:?}", block, processed),
+        ));
+    }
+    history
+        .at(block)
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, anyhow::anyhow!("Block {} is older than the state history", block)))
+}
+
+/// Value over time of a user's balances and liquidity positions, priced with the pools' reserves
+/// at each height
+async fn get_portfolio_history(
+    State(ctx): State<RouterCtx>,
+    Path(user): Path<String>,
+    Query(query): Query<PortfolioHistoryQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let history = ctx.state_history.as_ref().ok_or_else(|| {
+        AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!("State history is disabled"))
+    })?;
+    let latest = match ctx.sync.status().await.processed_height {
+        Some(processed) => processed,
+        None => history.latest_height()?.unwrap_or_default(),
+    };
+    let interval = query.interval.unwrap_or(100).max(1);
+    let to = query.to.unwrap_or(latest).min(latest);
+    let from = query.from.unwrap_or(to.saturating_sub(100 * interval));
+    if from > to {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("from {} is after to {}", from, to),
+        ));
+    }
+    if (to - from) / interval + 1 > MAX_PORTFOLIO_POINTS {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("At most {} points are served, raise the interval", MAX_PORTFOLIO_POINTS),
+        ));
+    }
+
+    let mut blocks: Vec<u64> = (from..=to).step_by(interval as usize).collect();
+    if blocks.last() != Some(&to) {
+        blocks.push(to);
+    }
+    let mut unpriced = BTreeSet::new();
+    let mut points = vec![];
+    for block in blocks {
+        // Blocks older than the retained history have no state
+        if let Some(state) = history.at(block)? {
+            points.push(state.portfolio(&user, block, &query.quote, &mut unpriced));
+        }
+    }
+    Ok(Json(PortfolioHistoryResponse {
+        user,
+        quote: query.quote,
+        interval,
+        points,
+        unpriced,
+    }))
+}
+
+async fn get_streaming_swaps(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<StreamingSwapsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let orders = state
+        .streaming_swaps()
+        .iter()
+        .filter(|(_, order)| query.user.as_ref().is_none_or(|user| &order.user == user))
+        .map(|(order_id, order)| {
+            (*order_id, StreamingSwapEntry { order_id: *order_id, order: order.clone() })
+        });
+    Ok(Json(page.paginate(orders)?))
+}
+
+/// Swaps waiting for their block to end under the batch auction ordering policy, by pair key
+async fn get_swap_batches(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    Ok(Json(state.swap_batches().clone()))
+}
+
+async fn create_otc_offer(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<CreateOtcOfferRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    ctx.screening.check(&auth.user).await?;
+
+    let action_contract1 = Contract1Action::CreateOtcOffer {
+        user: auth.user.clone(),
+        give: request.give,
+        want: request.want,
+        counterparty: request.counterparty,
+    };
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+async fn accept_otc_offer(
+    State(ctx): State<RouterCtx>,
+    Path(offer_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<OtcOfferRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    ctx.screening.check(&auth.user).await?;
+
+    let action_contract1 = Contract1Action::AcceptOffer {
+        user: auth.user.clone(),
+        offer_id,
+    };
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.extend(identity_blob(&ctx, &auth.user, request.identity_blob)?);
+    blobs.push(action_contract1.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+async fn cancel_otc_offer(
+    State(ctx): State<RouterCtx>,
+    Path(offer_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<OtcOfferRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CancelOffer {
+        user: auth.user.clone(),
+        offer_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Open OTC offers, optionally those made by or to a single identity
+async fn get_otc_offers(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<OtcOffersQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let offers = state
+        .otc_offers()
+        .iter()
+        .filter(|(_, offer)| {
+            query
+                .user
+                .as_ref()
+                .is_none_or(|user| &offer.maker == user || &offer.counterparty == user)
+        })
+        .map(|(offer_id, offer)| (*offer_id, OtcOfferEntry { offer_id: *offer_id, offer: offer.clone() }));
+    Ok(Json(page.paginate(offers)?))
+}
+
+async fn create_vesting(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<CreateVestingRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CreateVesting {
+        user: auth.user.clone(),
+        beneficiary: request.beneficiary,
+        token: request.token,
+        amount: request.amount,
+        start_block: request.start_block,
+        duration: request.duration,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn claim_vesting(
+    State(ctx): State<RouterCtx>,
+    Path(vesting_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<ClaimVestingRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::ClaimVesting {
+        user: auth.user.clone(),
+        vesting_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Open vesting schedules, optionally those created by or for a single identity
+async fn deposit_external(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<ExternalTransferRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    send_external_transfer(ctx, auth, request, true).await
+}
+
+async fn withdraw_external(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<ExternalTransferRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    send_external_transfer(ctx, auth, request, false).await
+}
+
+/// Move an external token between the user and the AMM, composing the token contract's
+/// transfer blob with the AMM action contract1 only applies alongside it
+async fn send_external_transfer(
+    ctx: RouterCtx,
+    auth: AuthHeaders,
+    request: ExternalTransferRequest,
+    deposit: bool,
+) -> Result<Json<TxHash>, AppError> {
+    ctx.screening.check(&auth.user).await?;
+    let token_contract = {
+        let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+            AppError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow::anyhow!("AMM state not indexed yet"),
+            )
+        })?;
+        state.external_tokens().get(&request.token).cloned().ok_or_else(|| {
+            AppError(
+                StatusCode::BAD_REQUEST,
+                anyhow::anyhow!("{} is not an external token", request.token),
+            )
+        })?
+    };
+
+    let amm = ctx.contract1_cn.0.clone();
+    let (transfer, action) = if deposit {
+        (
+            TokenTransfer { sender: auth.user.clone(), recipient: amm, amount: request.amount },
+            Contract1Action::DepositExternal { user: auth.user.clone(), token: request.token, amount: request.amount },
+        )
+    } else {
+        (
+            TokenTransfer { sender: amm, recipient: auth.user.clone(), amount: request.amount },
+            Contract1Action::WithdrawExternal { user: auth.user.clone(), token: request.token, amount: request.amount },
+        )
+    };
+
+    let mut blobs = request.wallet_blobs.to_vec();
+    blobs.push(transfer.as_blob(token_contract));
+    blobs.push(action.as_blob(ctx.contract1_cn.clone()));
+
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+async fn set_referrer(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<SetReferrerRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::SetReferrer {
+        user: auth.user.clone(),
+        referrer: request.referrer,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Credit a (mocked) deposit from another chain, signed by the bridge operator
+async fn bridge_deposit(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<BridgeDepositRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::BridgeDeposit {
+        user: request.user,
+        token: request.token,
+        amount: request.amount,
+        external_tx_ref: request.external_tx_ref,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Audit trail of credited bridge deposits
+async fn get_bridge_deposits(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<BridgeDepositsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let deposits = state
+        .bridge()
+        .map(|bridge| &bridge.deposits)
+        .into_iter()
+        .flatten()
+        .filter(|(_, deposit)| query.user.as_ref().is_none_or(|user| &deposit.user == user))
+        .map(|(external_tx_ref, deposit)| {
+            let entry = BridgeDepositEntry {
+                external_tx_ref: external_tx_ref.clone(),
+                deposit: deposit.clone(),
+            };
+            (external_tx_ref.clone(), entry)
+        });
+    Ok(Json(page.paginate(deposits)?))
+}
+
+async fn request_withdrawal(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<RequestWithdrawalRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::RequestWithdrawal {
+        user: auth.user.clone(),
+        token: request.token,
+        amount: request.amount,
+        destination: request.destination,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Mark a queued withdrawal as paid out, signed by the bridge operator
+async fn process_withdrawal(
+    State(ctx): State<RouterCtx>,
+    Path(withdrawal_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<ProcessWithdrawalRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::ProcessWithdrawal {
+        withdrawal_id,
+        external_tx_ref: request.external_tx_ref,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn cancel_withdrawal(
+    State(ctx): State<RouterCtx>,
+    Path(withdrawal_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<CancelWithdrawalRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CancelWithdrawal {
+        user: auth.user.clone(),
+        withdrawal_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Withdrawal queue, for users to follow theirs and the operator to find pending ones
+async fn get_withdrawals(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<WithdrawalsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let withdrawals = state
+        .bridge()
+        .map(|bridge| &bridge.withdrawals)
+        .into_iter()
+        .flatten()
+        .filter(|(_, withdrawal)| query.user.as_ref().is_none_or(|user| &withdrawal.user == user))
+        .filter(|(_, withdrawal)| !query.pending || withdrawal.status == WithdrawalStatus::Pending)
+        .map(|(withdrawal_id, withdrawal)| {
+            let entry = WithdrawalEntry { withdrawal_id: *withdrawal_id, withdrawal: withdrawal.clone() };
+            (*withdrawal_id, entry)
+        });
+    Ok(Json(page.paginate(withdrawals)?))
+}
+
+/// Token registry pools are scaled with
+async fn get_token_decimals(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    Ok(Json(state.token_decimals().clone()))
+}
+
+/// Token contracts of the tokens contract1 holds in custody
+async fn get_external_tokens(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    Ok(Json(state.external_tokens().clone()))
+}
+
+async fn get_vestings(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<VestingsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let vestings = state
+        .vestings()
+        .iter()
+        .filter(|(_, vesting)| {
+            query
+                .user
+                .as_ref()
+                .is_none_or(|user| &vesting.creator == user || &vesting.beneficiary == user)
+        })
+        .map(|(vesting_id, vesting)| {
+            let entry = VestingEntry {
+                vesting_id: *vesting_id,
+                vested: vesting.vested(state.block_height()),
+                vesting: vesting.clone(),
+            };
+            (*vesting_id, entry)
+        });
+    Ok(Json(page.paginate(vestings)?))
+}
+
+async fn file_insurance_claim(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Valid(request): Valid<FileInsuranceClaimRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::FileInsuranceClaim {
+        user: auth.user.clone(),
+        token: request.token,
+        amount: request.amount,
+        reason: request.reason,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn approve_insurance_claim(
+    State(ctx): State<RouterCtx>,
+    Path(claim_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<DecideInsuranceClaimRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::ApproveInsuranceClaim {
+        admin: auth.user.clone(),
+        claim_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn reject_insurance_claim(
+    State(ctx): State<RouterCtx>,
+    Path(claim_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<DecideInsuranceClaimRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::RejectInsuranceClaim {
+        admin: auth.user.clone(),
+        claim_id,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Fund balances and every claim filed against it
+async fn get_insurance(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let fund = state.insurance().cloned().ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("{} has no insurance fund", ctx.contract1_cn),
+        )
+    })?;
+    Ok(Json(fund))
+}
+
+async fn collect_protocol_fees(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<CollectProtocolFeesRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::CollectProtocolFees {
+        admin: auth.user.clone(),
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+/// Protocol fees accrued by token, not collected yet
+async fn get_protocol_fees(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let protocol_fee = state.protocol_fee().ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("{} has no protocol fee", ctx.contract1_cn),
+        )
+    })?;
+    Ok(Json(ProtocolFeesResponse {
+        admin: protocol_fee.admin.clone(),
+        share_bps: protocol_fee.share_bps,
+        accrued: state.protocol_fees().clone(),
+    }))
+}
+
+/// Faucet cooldown, and per token what was minted and may still be
+async fn get_faucet(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let faucet = state.faucet();
+    let tokens = faucet.minted.keys().chain(faucet.lifetime_caps.keys())
+        .map(|token| {
+            (token.clone(), FaucetTokenResponse {
+                minted: faucet.minted.get(token).copied().unwrap_or(0),
+                lifetime_cap: faucet.lifetime_caps.get(token).copied(),
+                remaining: faucet.remaining(token),
+            })
+        })
+        .collect();
+    Ok(Json(FaucetResponse { cooldown_blocks: faucet.cooldown_blocks, tokens }))
+}
+
+async fn propose_admin_action(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<ProposeAdminActionRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::ProposeAdminAction {
+        admin: auth.user.clone(),
+        operation: request.operation,
+    };
+
+    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+}
+
+async fn approve_admin_action(
+    State(ctx): State<RouterCtx>,
+    Path(proposal_id): Path<u64>,
+    headers: HeaderMap,
+    Json(request): Json<ApproveAdminActionRequest>
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+
+    let action_contract1 = Contract1Action::ApproveAdminAction {
+        admin: auth.user.clone(),
+        proposal_id,
+    };
+
     send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
 }
 
-async fn get_pool_reserves(
+/// Pause and emergency state, admins and pending proposals
+async fn get_admin_council(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    Ok(Json(AdminCouncilResponse {
+        paused: state.paused(),
+        emergency: state.emergency(),
+        council: state.admin_council().cloned(),
+    }))
+}
+
+/// Latest comparison of the indexed state with the node
+async fn get_reconciliation(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    let reconciler = ctx.reconciler.as_ref().ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("State reconciliation is disabled"),
+        )
+    })?;
+    Ok(Json(reconciler.status().await))
+}
+
+/// Every feature flag, as currently rolled out
+async fn get_feature_flags(State(ctx): State<RouterCtx>) -> impl IntoResponse {
+    Json(ctx.feature_flags.states().await)
+}
+
+/// Change the rollout of a feature flag, kept over the configured one across restarts
+async fn put_feature_flag(
+    State(admin): State<AdminAuth>,
+    State(feature_flags): State<Arc<FeatureFlags>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(update): Json<FlagOverride>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = admin.require(&headers).await?;
+    tracing::info!("🚩 {} changes the rollout of feature flag {}", actor, name);
+    let state = feature_flags
+        .update(&name, update)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    match state {
+        Some(state) => Ok(Json(state)),
+        None => Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No feature flag {}", name),
+        )),
+    }
+}
+
+/// Canonical JSON dump of the indexed state, with the metadata needed to import it again
+async fn get_state_export(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    Ok(Json(StateDump::new(&ctx.contract1_cn.0, state.state().clone())))
+}
+
+#[derive(Serialize)]
+struct StateImportResponse {
+    /// Whether the state was replaced, which only a mock node allows
+    applied: bool,
+    block_height: u64,
+    commitment: String,
+}
+
+/// Check a dump, and make it the state when running on a mock node.
+///
+/// Against a real node the settled state cannot be replaced, so the import is a dry run
+/// reporting the commitment a contract registered with the dump would start from.
+async fn import_state(
+    State(ctx): State<RouterCtx>,
+    Json(dump): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, AppError> {
+    if !ctx.allow_state_import {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!("State import is disabled"),
+        ));
+    }
+    let dump = StateDump::parse(dump).map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    if dump.contract_name != ctx.contract1_cn.0 {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow::anyhow!("Dump is of {}, not {}", dump.contract_name, ctx.contract1_cn),
+        ));
+    }
+    let state = dump
+        .into_state()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let response = StateImportResponse {
+        applied: ctx.mock_node.is_some(),
+        block_height: state.block_height(),
+        commitment: state_dump::commitment_digest(&state),
+    };
+    if let (Some(mock), Some(amm_state)) = (&ctx.mock_node, &ctx.imported_state) {
+        mock.replace_state(state.clone()).await;
+        amm_state.update(state, &[]).await;
+        tracing::warn!("📥 Replaced the mock {} state with an import at block {}", ctx.contract1_cn, response.block_height);
+    }
+    Ok(Json(response))
+}
+
+/// Verification key of the identity circuit, for clients verifying or generating proofs locally
+async fn get_noir_vk(State(ctx): State<RouterCtx>) -> Result<impl IntoResponse, AppError> {
+    let vk = ctx
+        .noir_vk
+        .current()
+        .await
+        .map_err(|e| AppError(StatusCode::SERVICE_UNAVAILABLE, e))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::HeaderName::from_static(NOIR_VK_HASH_HEADER), vk.hash.clone()),
+        ],
+        vk.bytes.clone(),
+    ))
+}
+
+/// Load the verification key again after the circuit changed
+async fn rotate_noir_vk(State(ctx): State<RouterCtx>, headers: HeaderMap) -> Result<impl IntoResponse, AppError> {
+    let actor = ctx.admin.require(&headers).await?;
+    tracing::info!("🔑 {} rotates the Noir verification key", actor);
+    let rotation = ctx
+        .noir_vk
+        .rotate()
+        .await
+        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(rotation))
+}
+
+async fn get_noir_stats(State(ctx): State<RouterCtx>) -> impl IntoResponse {
+    let stats = ctx.noir_verifier.get_verification_stats().await;
+    Json(stats)
+}
+
+async fn noir_authenticate(
+    State(state): State<RouterCtx>,
+    Json(request): Json<NoirAuthRequest>,
+) -> Result<Json<NoirAuthResponse>, StatusCode> {
+    let job = match state.noir_jobs.start(request.job_id.clone()).await {
+        Ok(job) => job,
+        Err(e) => {
+            return Ok(Json(NoirAuthResponse {
+                success: false,
+                message: e.to_string(),
+                proof_hash: None,
+                tx_hash: None,
+            }))
+        }
+    };
+    let response = run_noir_authentication(&state, &request, &job).await;
+    job.done(response.success, &response.message).await;
+    if !response.success {
+        let identity = format!("{}@zkpassport", request.username);
+        let failed = NotificationEvent::ProofFailed {
+            job_id: request.job_id.clone(),
+            message: response.message.clone(),
+        };
+        if let Err(e) = state.notifications.notify(&identity, failed).await {
+            tracing::warn!("⚠️ Could not notify {} of a failed proof: {}", identity, e);
+        }
+    }
+    Ok(Json(response))
+}
+
+async fn run_noir_authentication(
+    state: &RouterCtx,
+    request: &NoirAuthRequest,
+    job: &Job,
+) -> NoirAuthResponse {
+    tracing::info!("🔐 Starting real Noir authentication for user: {}", request.username);
+    
+    // Step 1: Validate proof type
+    if request.proof_type != "noir_circuit" {
+        tracing::error!("❌ Invalid proof type: {}", request.proof_type);
+        return NoirAuthResponse {
+            success: false,
+            message: "Invalid proof type".to_string(),
+            proof_hash: None,
+            tx_hash: None,
+        };
+    }
+
+    // Step 2: Ensure Noir circuit is compiled and ready
+    if let Err(e) = state.noir_prover.ensure_circuit_compiled(job).await {
+        tracing::error!("❌ Circuit compilation failed: {}", e);
+        return NoirAuthResponse {
+            success: false,
+            message: "Circuit compilation failed".to_string(),
+            proof_hash: None,
+            tx_hash: None,
+        };
+    }
+
+    // Step 3: Generate real Noir proof
+    tracing::info!("🧮 Generating real UltraHonk proof...");
+    let proof = match state.noir_prover.generate_password_proof(
+        &request.username,
+        "HyliForEver", // Using fixed password for demo - in production this would be derived from request
+        job,
+    ).await {
+        Ok(proof) => proof,
+        Err(e) => {
+            tracing::error!("❌ Proof generation failed: {}", e);
+            return NoirAuthResponse {
+                success: false,
+                message: format!("Proof generation failed: {}", e),
+                proof_hash: None,
+                tx_hash: None,
+            };
+        }
+    };
+
+    // Step 4: Verify proof locally before chain submission
+    tracing::info!("🔍 Verifying proof locally...");
+    job.stage("verifying proof…").await;
+    let is_valid = match state.noir_verifier.verify_proof_locally(&proof).await {
+        Ok(valid) => valid,
+        Err(e) => {
+            tracing::error!("❌ Local verification failed: {}", e);
+            return NoirAuthResponse {
+                success: false,
+                message: format!("Local verification failed: {}", e),
+                proof_hash: None,
+                tx_hash: None,
+            };
+        }
+    };
+
+    if !is_valid {
+        tracing::error!("❌ Proof verification failed - invalid proof");
+        return NoirAuthResponse {
+            success: false,
+            message: "Proof verification failed".to_string(),
+            proof_hash: None,
+            tx_hash: None,
+        };
+    }
+
+    // Refuse proofs the registered identity contract could never verify
+    if let Err(e) = state.noir_vk.check_registered(&proof.verification_key).await {
+        return NoirAuthResponse {
+            success: false,
+            message: e.to_string(),
+            proof_hash: None,
+            tx_hash: None,
+        };
+    }
+
+    // Step 5: Submit proof to Hyli chain
+    tracing::info!("⛓️ Submitting verified proof to Hyli chain...");
+    job.stage("submitting to Hyli…").await;
+    let user_identity = format!("{}@zkpassport", request.username);
+    let tx_hash = match state.noir_verifier.submit_proof_to_chain(proof.clone(), user_identity.clone()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!("❌ Chain submission failed: {}", e);
+            return NoirAuthResponse {
+                success: false,
+                message: format!("Chain submission failed: {}", e),
+                proof_hash: None,
+                tx_hash: None,
+            };
+        }
+    };
+
+    let proof_hash = store_noir_proof(state, &proof, &user_identity, &tx_hash).await;
+    tracing::info!("✅ Real Noir authentication successful for user: {}", request.username);
+
+    NoirAuthResponse {
+        success: true,
+        message: format!("Real Noir authentication successful for user: {}", request.username),
+        proof_hash: Some(proof_hash),
+        tx_hash: Some(tx_hash),
+    }
+}
+
+/// Credentials and circuits `[credential_policy]` requires of a user's action, and which they hold
+async fn get_identity_requirements(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<IdentityRequirementsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let requirements = ctx
+        .credential_policy
+        .requirements(&query.user, query.action, query.amount)
+        .await?;
+    Ok(Json(requirements))
+}
+
+/// Allow/deny changes of the identity contract after `since`, for consumers keeping their own view
+async fn get_identity_changes(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<IdentityChangesQuery>,
+) -> impl IntoResponse {
+    Json(ctx.identity_index.changes(query.since).await)
+}
+
+/// Keep a submitted proof for later audits, returning the hash it is stored under.
+///
+/// The transaction is already submitted, so failing to store it is only logged.
+async fn store_noir_proof(ctx: &RouterCtx, proof: &NoirProof, identity: &str, tx_hash: &str) -> String {
+    let stored = StoredProof::new(proof, identity, tx_hash);
+    if let Err(e) = ctx.noir_proofs.store(&stored).await {
+        tracing::warn!("⚠️ Could not store Noir proof {} of {}: {:#}", stored.proof_hash, tx_hash, e);
+    }
+    stored.proof_hash
+}
+
+/// A submitted identity proof with its public inputs and verification key, to verify it again
+async fn get_noir_proof(
+    State(ctx): State<RouterCtx>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    match ctx.noir_proofs.get(&hash).await? {
+        Some(proof) => Ok(Json(proof)),
+        None => Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No stored Noir proof {}", hash),
+        )),
+    }
+}
+
+/// Verify a proof generated in the browser and submit it as the user's identity blob.
+///
+/// The private inputs stay on the user's machine; the server only checks the proof against the
+/// verification key it serves and that it was made for the submitting user.
+async fn submit_noir_proof(
+    State(ctx): State<RouterCtx>,
+    Json(request): Json<NoirProofSubmission>,
+) -> Result<impl IntoResponse, AppError> {
+    let bad_request = |message: String| AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!(message));
+    let vk = ctx
+        .noir_vk
+        .current()
+        .await
+        .map_err(|e| AppError(StatusCode::SERVICE_UNAVAILABLE, e))?;
+    if let Some(vk_hash) = &request.vk_hash {
+        if *vk_hash != vk.hash {
+            return Err(AppError(
+                StatusCode::CONFLICT,
+                anyhow::anyhow!("Proof was made for verification key {}, the circuit now has {}", vk_hash, vk.hash),
+            ));
+        }
+    }
+
+    let proof_data = hex::decode(request.proof.trim_start_matches("0x"))
+        .map_err(|e| bad_request(format!("Proof is not hex: {}", e)))?;
+    let user_hash = ctx.noir_prover.hash_to_field(&request.username, 0)?;
+    if request.public_inputs.get(1) != Some(&user_hash) {
+        return Err(bad_request(format!("Proof was not made for user {}", request.username)));
+    }
+
+    let proof = NoirProof {
+        proof_data,
+        public_inputs: request.public_inputs,
+        verification_key: vk.bytes.clone(),
+    };
+    let valid = ctx.noir_verifier.verify_proof_locally(&proof).await?;
+    if !valid {
+        return Err(bad_request("Proof verification failed".to_string()));
+    }
+
+    ctx.noir_vk
+        .check_registered(&proof.verification_key)
+        .await
+        .map_err(|e| AppError(StatusCode::CONFLICT, e))?;
+
+    let user_identity = format!("{}@zkpassport", request.username);
+    let tx_hash = ctx
+        .noir_verifier
+        .submit_proof_to_chain(proof.clone(), user_identity.clone())
+        .await?;
+    let proof_hash = store_noir_proof(&ctx, &proof, &user_identity, &tx_hash).await;
+    tracing::info!("✅ Browser Noir proof of {} submitted in {}", request.username, tx_hash);
+
+    Ok(Json(NoirProofSubmissionResponse {
+        tx_hash,
+        proof_hash,
+        vk_hash: vk.hash.clone(),
+    }))
+}
+
+async fn webauthn_register_start(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<WebAuthnStartRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let session_token = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let options = ctx
+        .webauthn
+        .start_registration(&request.username, session_token)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(options))
+}
+
+async fn webauthn_register_finish(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<RegistrationResponse>,
+) -> Result<impl IntoResponse, AppError> {
+    let session_token = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let identity = ctx
+        .webauthn
+        .finish_registration(request, session_token)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(WebAuthnRegisterResponse { identity }))
+}
+
+async fn webauthn_login_start(
+    State(ctx): State<RouterCtx>,
+    Json(request): Json<WebAuthnStartRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = ctx
+        .webauthn
+        .start_authentication(&request.username)
+        .await
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))?;
+    Ok(Json(options))
+}
+
+async fn webauthn_login_finish(
+    State(ctx): State<RouterCtx>,
+    Json(request): Json<AuthenticationResponse>,
+) -> Result<impl IntoResponse, AppError> {
+    let (identity, session_token) = ctx
+        .webauthn
+        .finish_authentication(request)
+        .await
+        .map_err(|e| AppError(StatusCode::UNAUTHORIZED, e))?;
+    Ok(Json(WebAuthnLoginResponse {
+        identity,
+        session_token,
+    }))
+}
+
+#[derive(Serialize)]
+struct TxStatusResponse {
+    #[serde(flatten)]
+    tx: TxRecord,
+    /// Original submission followed by its automatic retries
+    retry_chain: Vec<TxRecord>,
+}
+
+async fn get_tx_status(
+    State(ctx): State<RouterCtx>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let tx = ctx.tx_status.get(&hash).await.ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("Unknown transaction {}", hash),
+        )
+    })?;
+    let retry_chain = ctx.tx_status.chain(&hash).await;
+    Ok(Json(TxStatusResponse { tx, retry_chain }))
+}
+
+/// Server-sent progress events of a submitted transaction, ending once it settled
+async fn get_tx_events(
+    State(ctx): State<RouterCtx>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    // Subscribe first so no stage is lost between the snapshot and the live feed
+    let live = ctx.tx_status.subscribe_progress();
+    let tx = ctx.tx_status.get(&hash).await.ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("Unknown transaction {}", hash),
+        )
+    })?;
+
+    let settled = |progress: &TxProgress| matches!(progress, TxProgress::Settled { .. });
+    // A reorged transaction settles anew, so only its latest stage tells whether it is done
+    let done = tx.progress.last().is_some_and(|entry| settled(&entry.progress));
+    let next_seq = tx.progress.len();
+
+    let live = futures::stream::unfold((live, done), move |(mut live, done)| {
+        let hash = hash.clone();
+        async move {
+            if done {
+                return None;
+            }
+            loop {
+                match live.recv().await {
+                    Ok(event) if event.tx_hash.0 == hash && event.entry.seq >= next_seq => {
+                        let done = settled(&event.entry.progress);
+                        return Some((event.entry, (live, done)));
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    let events = futures::stream::iter(tx.progress)
+        .chain(live)
+        .map(|entry| Event::default().event("progress").json_data(entry));
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Stop a queued or running Noir proof job; its id, picked by the client, is what authorizes it
+async fn cancel_noir_job(State(ctx): State<RouterCtx>, Path(id): Path<String>) -> Result<impl IntoResponse, AppError> {
+    if !ctx.noir_jobs.cancel(&id) {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No queued or running Noir job {}", id),
+        ));
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Server-sent log of a Noir proof job, ending with its `done` line.
+///
+/// Clients subscribe before starting the job, so an id nothing logged to yet streams from its
+/// first line rather than being unknown.
+async fn get_noir_job_events(State(ctx): State<RouterCtx>, Path(id): Path<String>) -> impl IntoResponse {
+    // Subscribe first so no line is lost between the snapshot and the live feed
+    let live = ctx.noir_jobs.subscribe();
+    let log = ctx.noir_jobs.log(&id).await;
+
+    let is_done = |line: &JobLine| matches!(line, JobLine::Done { .. });
+    let done = log.iter().any(|entry| is_done(&entry.line));
+    let next_seq = log.len();
+
+    let live = futures::stream::unfold((live, done), move |(mut live, done)| {
+        let id = id.clone();
+        async move {
+            if done {
+                return None;
+            }
+            loop {
+                match live.recv().await {
+                    Ok(event) if event.job_id == id && event.entry.seq >= next_seq => {
+                        let done = is_done(&event.entry.line);
+                        return Some((event.entry, (live, done)));
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    let events = futures::stream::iter(log)
+        .chain(live)
+        .map(|entry| Event::default().event("progress").json_data(entry));
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Server-sent progress events of every transaction an identity submits from now on.
+///
+/// Takes the identity as query parameter since `EventSource` cannot set headers;
+/// progress only reveals what the chain will publish anyway.
+async fn get_identity_tx_events(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<TxEventsQuery>,
+) -> impl IntoResponse {
+    let live = ctx.tx_status.subscribe_progress();
+    let events = futures::stream::unfold(live, move |mut live| {
+        let identity = query.identity.clone();
+        async move {
+            loop {
+                match live.recv().await {
+                    Ok(event) if event.identity == identity => return Some((event, live)),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    })
+    .map(|event| Event::default().event("progress").json_data(event));
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn get_tx_details(
+    State(ctx): State<RouterCtx>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let details = ctx
+        .explorer
+        .transaction(&hash)
+        .await
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))?;
+    Ok(Json(details))
+}
+
+async fn get_block_details(
+    State(ctx): State<RouterCtx>,
+    Path(height): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let details = ctx
+        .explorer
+        .block(height)
+        .await
+        .map_err(|e| AppError(StatusCode::NOT_FOUND, e))?;
+    Ok(Json(details))
+}
+
+async fn get_self_test(State(ctx): State<RouterCtx>) -> impl IntoResponse {
+    Json(ctx.self_test.read().await.clone())
+}
+
+async fn get_sync_status(State(ctx): State<RouterCtx>) -> impl IntoResponse {
+    Json(ctx.sync.status().await)
+}
+
+async fn get_quote(
     State(ctx): State<RouterCtx>,
-    headers: HeaderMap,
-    Json(request): Json<GetPoolReservesRequest>
+    Query(query): Query<QuoteQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    let action_contract1 = Contract1Action::GetReserves {
-        token_a: request.token_a,
-        token_b: request.token_b,
+    let amount_out = quote_swap(&ctx, &query.token_in, &query.token_out, query.amount_in).await?;
+    Ok(Json(QuoteResponse { amount_out }))
+}
+
+async fn get_price_history(
+    State(ctx): State<RouterCtx>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<PriceHistoryQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let interval: Interval = query
+        .interval
+        .parse()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let from = query.from.unwrap_or(to - 100 * interval.secs());
+
+    let candles = ctx
+        .price_history
+        .candles(&query.pair, interval, from, to)
+        .await?
+        .into_iter()
+        .map(|candle| (candle.open_time, candle));
+    Ok(Json(page.paginate(candles)?))
+}
+
+/// Impermanent loss of a user's position in a pool against holding its deposits, with the loss
+/// the price history since entry would have caused
+async fn get_impermanent_loss(
+    State(ctx): State<RouterCtx>,
+    Path((user, pair)): Path<(String, String)>,
+    Query(query): Query<ImpermanentLossQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let interval: Interval = query
+        .interval
+        .as_deref()
+        .unwrap_or("1h")
+        .parse()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let pool = state
+        .pools()
+        .get(&pair)
+        .ok_or_else(|| AppError(StatusCode::NOT_FOUND, anyhow::anyhow!("No pool {}", pair)))?;
+    let liquidity = state.user_liquidity(&user, &pool.token_a, &pool.token_b);
+    let checkpoints = ctx.lp_entries.checkpoints(&user, &pair).await?;
+
+    let mut report = lp_positions::report(
+        &user,
+        &pair,
+        pool,
+        state.block_height(),
+        liquidity,
+        &checkpoints,
+    )
+    .ok_or_else(|| {
+        AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("{} holds no recorded liquidity in {}", user, pair),
+        )
+    })?;
+    let now = chrono::Utc::now().timestamp();
+    let candles = ctx
+        .price_history
+        .candles(&pair, interval, report.entered_at, now)
+        .await?;
+    report.simulate(&candles);
+    Ok(Json(report))
+}
+
+async fn get_volume(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<VolumeQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(ctx.price_history.volume(&query.pair).await?))
+}
+
+/// Volume counted by the contract itself, as opposed to the reserve diffs of `/api/volume`
+async fn get_onchain_volume(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<OnchainVolumeQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    if state.pool(&query.token_a, &query.token_b).is_none() {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No {}/{} pool", query.token_a, query.token_b),
+        ));
+    }
+    let volume = match &query.user {
+        Some(user) => state.user_volume(user, &query.token_a, &query.token_b),
+        None => state.pool_volume(&query.token_a, &query.token_b),
     };
-    
-    send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
+    Ok(Json(volume))
 }
 
-async fn test_amm(
+/// Rounding remainders kept by a pool, in units of 1e-18 of a token
+#[cfg(feature = "rounding-audit")]
+async fn get_rounding_reserve(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<RoundingReserveQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    if state.pool(&query.token_a, &query.token_b).is_none() {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No {}/{} pool", query.token_a, query.token_b),
+        ));
+    }
+    Ok(Json(state.rounding_reserve(&query.token_a, &query.token_b)))
+}
+
+async fn register_signing_key(
     State(ctx): State<RouterCtx>,
     headers: HeaderMap,
-    Json(request): Json<TestAmmRequest>
+    Json(request): Json<RegisterSigningKeyRequest>
 ) -> Result<impl IntoResponse, AppError> {
-    let auth = AuthHeaders::from_headers(&headers)?;
-    
-    // Test action: Mint some USDC tokens for testing
-    let action_contract1 = Contract1Action::MintTokens {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    let public_key = hex::decode(&request.public_key)
+        .ok()
+        .and_then(|bytes| secp256k1::PublicKey::from_slice(&bytes).ok())
+        .ok_or_else(|| AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!("Invalid public key")))?;
+
+    let action_contract1 = Contract1Action::RegisterSigningKey {
         user: auth.user.clone(),
-        token: "USDC".to_string(),
-        amount: 1000,
+        public_key: public_key.serialize().to_vec(),
     };
-    
+
     send_amm_action_only(ctx, auth, request.wallet_blobs, action_contract1).await
 }
 
-async fn get_config(State(ctx): State<RouterCtx>) -> impl IntoResponse {
-    Json(ConfigResponse {
-        contract_name: ctx.contract1_cn.0,
-    })
+/// Submit a signed swap intent on the user's behalf.
+///
+/// The signature is checked here so bad intents never reach the node, which verifies it
+/// again natively, while contract1 checks it signs the intent with the user's key.
+async fn relay_swap(
+    State(ctx): State<RouterCtx>,
+    Valid(request): Valid<RelaySwapRequest>
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let intent = request.intent;
+    ctx.limits.check_swap(&intent.token_in, intent.amount_in)?;
+    ctx.screening.check(&intent.user).await?;
+    ctx.credential_policy
+        .check(&intent.user, PolicyAction::Swap, intent.amount_in)
+        .await?;
+
+    let bad_request = |e: String| AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!(e));
+    let public_key = {
+        let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+            AppError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow::anyhow!("AMM state not indexed yet"),
+            )
+        })?;
+        let key = state
+            .signing_key(&intent.user)
+            .ok_or_else(|| bad_request(format!("{} has no signing key", intent.user)))?;
+        secp256k1::PublicKey::from_slice(key).map_err(|e| bad_request(e.to_string()))?
+    };
+    let signature = hex::decode(&request.signature)
+        .ok()
+        .and_then(|bytes| secp256k1::ecdsa::Signature::from_compact(&bytes).ok())
+        .ok_or_else(|| bad_request("Invalid signature encoding".to_string()))?;
+    if intent.max_relayer_fee_bps < ctx.relayer_fee_bps {
+        return Err(bad_request(format!(
+            "The relayer fee is {} bps, above the signed maximum",
+            ctx.relayer_fee_bps
+        )));
+    }
+    let relayer = format!("{}@{}", RELAYER_USER, ctx.contract1_cn.0);
+    let digest = intent.digest(&ctx.contract1_cn);
+    secp256k1::Secp256k1::verification_only()
+        .verify_ecdsa(&secp256k1::Message::from_digest(digest), &signature, &public_key)
+        .map_err(|_| bad_request(format!("Swap intent is not signed by {}", intent.user)))?;
+
+    let signature_blob = sdk::verifiers::Secp256k1Blob {
+        identity: intent.user.clone().into(),
+        data: digest,
+        public_key: public_key.serialize(),
+        signature: signature.serialize_compact(),
+    };
+    let mut blobs = vec![Blob {
+        contract_name: SECP256K1_CONTRACT.into(),
+        data: sdk::BlobData(borsh::to_vec(&signature_blob)?),
+    }];
+    blobs.extend(oracle_blob(&ctx, request.oracle_blob).await?);
+    let (user, token_in, amount_in) = (intent.user.clone(), intent.token_in.clone(), intent.amount_in);
+    blobs.push(
+        Contract1Action::RelaySwap {
+            intent,
+            signature: signature.serialize_compact().to_vec(),
+            relayer: relayer.clone(),
+            relayer_fee_bps: ctx.relayer_fee_bps,
+        }
+        .as_blob(ctx.contract1_cn.clone()),
+    );
+
+    let response = outcome_response(submit_and_wait(&ctx, &relayer, blobs, None).await?)?;
+    Ok(record_spend(&ctx, &user, SpendKind::Swap, &token_in, amount_in, response).await)
 }
 
-async fn get_noir_stats(State(ctx): State<RouterCtx>) -> impl IntoResponse {
-    let stats = ctx.noir_verifier.get_verification_stats().await;
-    Json(stats)
+/// LP voting power of a user when a block started
+async fn get_votes(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<VotesQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let block = query.block.unwrap_or(state.block_height() + 1);
+    Ok(Json(VotesResponse {
+        votes: state.votes_at(&query.user, block),
+        user: query.user,
+        block,
+    }))
 }
 
-async fn noir_authenticate(
-    State(state): State<RouterCtx>,
-    Json(request): Json<NoirAuthRequest>,
-) -> Result<Json<NoirAuthResponse>, StatusCode> {
-    tracing::info!("🔐 Starting real Noir authentication for user: {}", request.username);
-    
-    // Step 1: Validate proof type
-    if request.proof_type != "noir_circuit" {
-        tracing::error!("❌ Invalid proof type: {}", request.proof_type);
-        return Ok(Json(NoirAuthResponse {
-            success: false,
-            message: "Invalid proof type".to_string(),
-            proof_hash: None,
-            tx_hash: None,
-        }));
-    }
+/// Liquidity mining points of a user in a pool
+async fn get_rewards(
+    State(ctx): State<RouterCtx>,
+    Query(query): Query<RewardsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    require_synced(&ctx).await?;
+    let state = ctx.amm_state.snapshot().await.ok_or_else(|| {
+        AppError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow::anyhow!("AMM state not indexed yet"),
+        )
+    })?;
+    let verified = state
+        .lp_position(&query.user, &query.token_a, &query.token_b)
+        .is_some_and(|p| p.verified);
+    Ok(Json(RewardsResponse {
+        reward_points: state.reward_points(&query.user, &query.token_a, &query.token_b),
+        verified,
+        block_height: state.block_height(),
+    }))
+}
 
-    // Step 2: Ensure Noir circuit is compiled and ready
-    if let Err(e) = state.noir_prover.ensure_circuit_compiled().await {
-        tracing::error!("❌ Circuit compilation failed: {}", e);
-        return Ok(Json(NoirAuthResponse {
-            success: false,
-            message: "Circuit compilation failed".to_string(),
-            proof_hash: None,
-            tx_hash: None,
-        }));
-    }
+async fn get_profile(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    Ok(Json(ctx.profiles.get(&auth.user).await?))
+}
 
-    // Step 3: Generate real Noir proof
-    tracing::info!("🧮 Generating real UltraHonk proof...");
-    let proof = match state.noir_prover.generate_password_proof(
-        &request.username,
-        "HyliForEver" // Using fixed password for demo - in production this would be derived from request
-    ).await {
-        Ok(proof) => proof,
-        Err(e) => {
-            tracing::error!("❌ Proof generation failed: {}", e);
-            return Ok(Json(NoirAuthResponse {
-                success: false,
-                message: format!("Proof generation failed: {}", e),
-                proof_hash: None,
-                tx_hash: None,
-            }));
-        }
+/// Balances of the authenticated user next to what they spent today against their soft budgets
+async fn get_portfolio(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    let balances = match ctx.amm_state.snapshot().await {
+        Some(state) => state
+            .user_balances()
+            .filter(|(holder, _, amount)| *holder == auth.user && *amount > 0)
+            .map(|(_, token, amount)| (token.to_string(), amount))
+            .collect(),
+        None => BTreeMap::new(),
     };
-
-    // Step 4: Verify proof locally before chain submission
-    tracing::info!("🔍 Verifying proof locally...");
-    let is_valid = match state.noir_verifier.verify_proof_locally(&proof).await {
-        Ok(valid) => valid,
-        Err(e) => {
-            tracing::error!("❌ Local verification failed: {}", e);
-            return Ok(Json(NoirAuthResponse {
-                success: false,
-                message: format!("Local verification failed: {}", e),
-                proof_hash: None,
-                tx_hash: None,
-            }));
-        }
+    let today = SpendingTracker::today();
+    let mut history = ctx.spending.history(&auth.user).await?;
+    let spend = match history.first() {
+        Some((day, _)) if *day == today => history.remove(0).1,
+        _ => DailySpend::default(),
     };
+    let spending = &ctx.spending;
+    let lines = [(SpendKind::Swap, &spend.swapped), (SpendKind::Mint, &spend.minted)]
+        .into_iter()
+        .flat_map(|(kind, amounts)| {
+            amounts.iter().map(move |(token, spent)| SpendLine {
+                kind,
+                token: token.clone(),
+                spent: *spent,
+                budget: spending.budget(kind, token),
+            })
+        })
+        .collect();
+    Ok(Json(PortfolioResponse {
+        warnings: ctx.spending.warnings(&spend),
+        today: lines,
+        history: history
+            .into_iter()
+            .map(|(day, spend)| DaySpend {
+                day: chrono::DateTime::from_timestamp(day * 86_400, 0)
+                    .map(|at| at.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default(),
+                spend,
+            })
+            .collect(),
+        user: auth.user,
+        balances,
+    }))
+}
 
-    if !is_valid {
-        tracing::error!("❌ Proof verification failed - invalid proof");
-        return Ok(Json(NoirAuthResponse {
-            success: false,
-            message: "Proof verification failed".to_string(),
-            proof_hash: None,
-            tx_hash: None,
-        }));
-    }
+/// The authenticated user's notifications, newest first, only the unread ones with `?unread=true`
+async fn get_notifications(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Query(query): Query<NotificationsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    Ok(Json(ctx.notifications.inbox(&auth.user, query.unread).await?))
+}
 
-    // Step 5: Submit proof to Hyli chain
-    tracing::info!("⛓️ Submitting verified proof to Hyli chain...");
-    let user_identity = format!("{}@zkpassport", request.username);
-    let tx_hash = match state.noir_verifier.submit_proof_to_chain(proof.clone(), user_identity).await {
-        Ok(hash) => hash,
-        Err(e) => {
-            tracing::error!("❌ Chain submission failed: {}", e);
-            return Ok(Json(NoirAuthResponse {
-                success: false,
-                message: format!("Chain submission failed: {}", e),
-                proof_hash: None,
-                tx_hash: None,
-            }));
+async fn mark_notifications_read(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<MarkReadRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    let marked = ctx
+        .notifications
+        .mark_read(&auth.user, request.ids.as_deref())
+        .await?;
+    Ok(Json(MarkReadResponse { marked }))
+}
+
+/// Server-sent notifications of the authenticated user from now on
+async fn get_notification_events(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    let live = ctx.notifications.subscribe();
+    let events = futures::stream::unfold(live, move |mut live| {
+        let user = auth.user.clone();
+        async move {
+            loop {
+                match live.recv().await {
+                    Ok(notification) if notification.user == user => return Some((notification, live)),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
         }
-    };
+    })
+    .map(|notification| Event::default().event("notification").json_data(notification));
 
-    let proof_hash = hex::encode(&proof.proof_data[..std::cmp::min(32, proof.proof_data.len())]);
-    tracing::info!("✅ Real Noir authentication successful for user: {}", request.username);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
 
-    Ok(Json(NoirAuthResponse {
-        success: true,
-        message: format!("Real Noir authentication successful for user: {}", request.username),
-        proof_hash: Some(proof_hash),
-        tx_hash: Some(tx_hash),
+async fn get_delivery_settings(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    let settings = ctx.delivery.settings(&auth.user).await?;
+    Ok(Json(DeliverySettingsResponse {
+        enabled: ctx.delivery.enabled(),
+        channels: ctx.delivery.channels(),
+        email: settings.email,
+        telegram_chat_id: settings.telegram.map(|telegram| telegram.chat_id),
+        kinds: settings.kinds,
     }))
 }
 
+/// Where the authenticated user's notifications are delivered besides the inbox, replacing the
+/// previous settings
+async fn put_delivery_settings(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(settings): Json<DeliverySettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    ctx.delivery
+        .set_settings(&auth.user, &settings)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_price_alerts(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    Ok(Json(ctx.notifications.alerts(&auth.user).await))
+}
+
+/// Notify the authenticated user once a pool's spot price crosses a threshold
+async fn create_price_alert(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<PriceAlertRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    let known = ctx
+        .amm_state
+        .snapshot()
+        .await
+        .is_some_and(|state| state.pools().contains_key(&request.pair));
+    if !known {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No pool {}", request.pair),
+        ));
+    }
+    let alert = ctx
+        .notifications
+        .add_alert(&auth.user, &request.pair, request.direction, request.price)
+        .await
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(alert))
+}
+
+async fn delete_price_alert(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::authenticate(&ctx, &headers).await?;
+    if !ctx.notifications.remove_alert(&auth.user, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No price alert {}", id),
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Count a settled transaction towards the user's daily spend, warning in a header once it is
+/// over a soft budget. The transaction went through either way, so failing to record is only
+/// logged.
+async fn record_spend(
+    ctx: &RouterCtx,
+    user: &str,
+    kind: SpendKind,
+    token: &str,
+    amount: u128,
+    response: impl IntoResponse,
+) -> Response {
+    let mut response = response.into_response();
+    match ctx.spending.record(user, kind, token, amount).await {
+        Ok(Some(warning)) => {
+            if let Ok(value) = header::HeaderValue::from_str(&warning.to_string()) {
+                response
+                    .headers_mut()
+                    .insert(header::HeaderName::from_static(spending::BUDGET_WARNING_HEADER), value);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("⚠️ Could not record the spend of {}: {:#}", user, e),
+    }
+    response
+}
+
+async fn put_profile(
+    State(ctx): State<RouterCtx>,
+    headers: HeaderMap,
+    Json(request): Json<ProfileUpdate>,
+) -> Result<impl IntoResponse, AppError> {
+    let auth = AuthHeaders::require_session(&ctx.webauthn, &headers).await?;
+    request
+        .validate()
+        .map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(ctx.profiles.update(&auth.user, request).await?))
+}
+
 // Simplified function for AMM-only actions (without identity verification for now)
 async fn send_amm_action_only(
     ctx: RouterCtx, 
@@ -461,16 +3918,67 @@ async fn send_amm_action_only(
     wallet_blobs: [Blob; 2],
     amm_action: Contract1Action
 ) -> Result<impl IntoResponse, AppError> {
-    let identity = auth.user.clone();
+    ctx.screening.check(&auth.user).await?;
 
     // For now, only send AMM blob - Noir identity verification will be added later
     let mut blobs = wallet_blobs.to_vec();
     blobs.push(amm_action.as_blob(ctx.contract1_cn.clone()));
 
-    let res = ctx
-        .client
-        .send_tx_blob(BlobTransaction::new(identity.clone(), blobs))
-        .await;
+    outcome_response(submit_and_wait(&ctx, &auth.user, blobs, None).await?)
+}
+
+/// Outcome of a submitted transaction once the AutoProver has executed it
+enum TxOutcome {
+    Success(TxHash),
+    Failed(TxHash, String),
+}
+
+fn outcome_response(outcome: TxOutcome) -> Result<Json<TxHash>, AppError> {
+    match outcome {
+        TxOutcome::Success(tx_hash) => Ok(Json(tx_hash)),
+        TxOutcome::Failed(_, error) => Err(contract_error(error)),
+    }
+}
+
+/// API error for a contract1 failure, its status following the error code the message starts with
+fn contract_error(error: String) -> AppError {
+    let status = match AmmError::code_of(&error) {
+        Some("not_found" | "pool_not_found" | "not_enabled") => StatusCode::NOT_FOUND,
+        Some("unauthorized") => StatusCode::FORBIDDEN,
+        Some("conflict" | "pool_exists") => StatusCode::CONFLICT,
+        Some("paused" | "emergency_mode") => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    AppError(status, anyhow::anyhow!(error))
+}
+
+/// Submit a blob transaction and wait for it to settle
+async fn submit_and_wait(
+    ctx: &RouterCtx,
+    identity: &str,
+    blobs: Vec<Blob>,
+    retry_of: Option<&TxHash>,
+) -> Result<TxOutcome, AppError> {
+    // Actions behind a flag that is off for the submitter never reach the node
+    let actions = blobs
+        .iter()
+        .filter(|blob| blob.contract_name == ctx.contract1_cn)
+        .filter_map(|blob| borsh::from_slice::<Contract1Action>(&blob.data.0).ok())
+        .map(|action| action.name());
+    ctx.feature_flags.check_actions(identity, actions).await?;
+
+    // Subscribe before submitting so the settlement event cannot be missed
+    let mut settled = ctx.tx_status.subscribe();
+
+    let tx = BlobTransaction::new(identity.to_string(), blobs);
+    #[cfg(feature = "cycle-accounting")]
+    let measured = tx.clone();
+    #[cfg(feature = "chaos")]
+    crate::chaos::node_fault("send_tx_blob").map_err(|e| AppError(StatusCode::BAD_REQUEST, e))?;
+    let res = match &ctx.mock_node {
+        Some(mock) => mock.send_tx_blob(tx).await,
+        None => ctx.client.send_tx_blob(tx).await,
+    };
 
     if let Err(ref e) = res {
         let root_cause = e.root_cause().to_string();
@@ -481,27 +3989,133 @@ async fn send_amm_action_only(
     }
 
     let tx_hash = res.unwrap();
-
-    let mut bus = {
-        let bus = ctx.bus.lock().await;
-        AppModuleBusClient::new_from_bus(bus.new_handle()).await
-    };
+    ctx.tx_status.submitted(&tx_hash, identity, retry_of).await;
+    #[cfg(feature = "cycle-accounting")]
+    crate::cycles::submitted(&tx_hash, measured);
+    if let Some(mock) = &ctx.mock_node {
+        mock.settle(&tx_hash).await;
+    }
 
     tokio::time::timeout(Duration::from_secs(30), async {
         loop {
-            match bus.recv().await? {
-                AutoProverEvent::<Contract1>::SuccessTx(sequenced_tx_hash, _) => {
-                    if sequenced_tx_hash == tx_hash {
-                        return Ok(Json(sequenced_tx_hash));
-                    }
-                }
-                AutoProverEvent::<Contract1>::FailedTx(sequenced_tx_hash, error) => {
-                    if sequenced_tx_hash == tx_hash {
-                        return Err(AppError(StatusCode::BAD_REQUEST, anyhow::anyhow!(error)));
-                    }
-                }
+            let (settled_tx_hash, status) = match settled.recv().await {
+                Ok(settlement) => settlement,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(e) => return Err(AppError::from(e)),
+            };
+            if settled_tx_hash != tx_hash {
+                continue;
             }
+            return Ok(match status {
+                TxStatus::Failed { error } => TxOutcome::Failed(settled_tx_hash, error),
+                _ => TxOutcome::Success(settled_tx_hash),
+            });
         }
     })
     .await?
 }
+
+/// Next mock settlement, pending forever when not running against the mock node
+async fn next_mock_settlement(
+    settlements: &mut Option<broadcast::Receiver<MockSettlement>>,
+) -> Option<MockSettlement> {
+    match settlements {
+        Some(settlements) => settlements.recv().await.ok(),
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webauthn::tests::provider;
+
+    fn headers(user: &str, session_token: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_HEADER, user.parse().unwrap());
+        if let Some(token) = session_token {
+            headers.insert(SESSION_HEADER, token.parse().unwrap());
+        }
+        headers
+    }
+
+    async fn session_status(webauthn: &WebAuthnProvider, headers: &HeaderMap) -> Result<String, StatusCode> {
+        AuthHeaders::require_session(webauthn, headers)
+            .await
+            .map(|auth| auth.user)
+            .map_err(|e| e.0)
+    }
+
+    #[derive(Clone)]
+    struct AdminRoutes {
+        admin: AdminAuth,
+        feature_flags: Arc<FeatureFlags>,
+    }
+
+    impl FromRef<AdminRoutes> for AdminAuth {
+        fn from_ref(routes: &AdminRoutes) -> Self {
+            routes.admin.clone()
+        }
+    }
+
+    impl FromRef<AdminRoutes> for Arc<FeatureFlags> {
+        fn from_ref(routes: &AdminRoutes) -> Self {
+            routes.feature_flags.clone()
+        }
+    }
+
+    async fn put_flag(router: &Router, headers: HeaderMap) -> StatusCode {
+        use tower::ServiceExt;
+        let mut request = Request::put("/admin/feature-flags/otc")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(r#"{"enabled": false}"#))
+            .unwrap();
+        request.headers_mut().extend(headers);
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_feature_flags_are_changed_by_admins_only() {
+        let webauthn = Arc::new(provider());
+        let admin = webauthn.open_session("admin@webauthn").await;
+        let bob = webauthn.open_session("bob@webauthn").await;
+        let data_directory = std::env::temp_dir().join(format!("feature-flags-{}", hex::encode(rand::random::<[u8; 8]>())));
+        let storage = Arc::new(crate::storage::SledStorage::open(&data_directory).unwrap());
+        let flags = BTreeMap::from([("otc".to_string(), crate::conf::FeatureFlagConf { enabled: true, ..Default::default() })]);
+        let router = Router::new()
+            .route("/admin/feature-flags/{name}", put(put_feature_flag))
+            .with_state(AdminRoutes {
+                admin: AdminAuth {
+                    webauthn,
+                    identities: Arc::new(HashSet::from(["admin@webauthn".to_string()])),
+                },
+                feature_flags: Arc::new(FeatureFlags::load(storage, flags).await.unwrap()),
+            });
+
+        assert_eq!(put_flag(&router, headers("admin@webauthn", None)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(put_flag(&router, headers("bob@webauthn", Some(&bob))).await, StatusCode::FORBIDDEN);
+        assert_eq!(put_flag(&router, headers("bob@webauthn", Some(&admin))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(put_flag(&router, headers("admin@webauthn", Some(&admin))).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_session_routes_refuse_a_bare_user_header() {
+        let webauthn = provider();
+        let alice = webauthn.open_session("alice@webauthn").await;
+        let bob = webauthn.open_session("bob@webauthn").await;
+
+        assert_eq!(session_status(&webauthn, &headers("alice@webauthn", None)).await, Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(
+            session_status(&webauthn, &headers("alice@webauthn", Some("expired"))).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            session_status(&webauthn, &headers("alice@webauthn", Some(&bob))).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            session_status(&webauthn, &headers("alice@webauthn", Some(&alice))).await,
+            Ok("alice@webauthn".to_string())
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! `GET /api/config`: what this server and its contracts offer, for clients to bootstrap from
+//! a single call.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Json, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use contract1::{Contract1, StateLimits};
+use serde::Serialize;
+
+use super::{AuthHeaders, RouterCtx};
+use crate::conf::{Conf, CredentialRule};
+use crate::sync_status::SyncStatus;
+use crate::versioning;
+
+/// What an instance offers as configured and started, the rest being read per request
+pub struct ServerCapabilities {
+    read_only: bool,
+    keeper: bool,
+    /// Empty unless KYC is enabled
+    kyc_routes: Vec<String>,
+    /// Empty unless the credential policy is enabled
+    credential_rules: Vec<CredentialRule>,
+    credential_circuits: BTreeMap<String, String>,
+}
+
+impl ServerCapabilities {
+    pub fn new(config: &Conf, read_only: bool) -> Self {
+        let credential_policy = &config.credential_policy;
+        ServerCapabilities {
+            read_only,
+            keeper: config.keeper.enabled && !read_only,
+            kyc_routes: match config.kyc.enabled {
+                true => config.kyc.routes.clone(),
+                false => vec![],
+            },
+            credential_rules: match credential_policy.enabled {
+                true => credential_policy.rules.clone(),
+                false => vec![],
+            },
+            credential_circuits: match credential_policy.enabled {
+                true => credential_policy.circuits.clone(),
+                false => BTreeMap::new(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ConfigResponse {
+    /// Name of the AMM contract, also under `contracts`
+    contract_name: String,
+    /// Whether each feature flag is on for the caller's `x-user`, or for anonymous callers
+    feature_flags: BTreeMap<String, bool>,
+    network: Network,
+    contracts: Contracts,
+    features: Features,
+    /// Unset until the AMM state is indexed
+    fees: Option<Fees>,
+    /// Tokens traded in pools or registered with contract1, by token; empty until the AMM state
+    /// is indexed
+    tokens: BTreeMap<String, TokenInfo>,
+    identity: IdentityRequirements,
+}
+
+#[derive(Serialize)]
+struct Network {
+    server_version: &'static str,
+    /// Prefix of the versioned API; routes are also served unversioned until the legacy sunset
+    api_prefix: &'static str,
+    /// Transactions execute in memory instead of going through a node
+    mock_node: bool,
+    /// Only query endpoints are served
+    read_only: bool,
+    sync: SyncStatus,
+}
+
+#[derive(Serialize)]
+struct Contracts {
+    amm: AmmContract,
+    identity: IdentityContract,
+}
+
+#[derive(Serialize)]
+struct AmmContract {
+    name: String,
+    /// Hex image id of the contract1 guest this server proves with
+    program_id: String,
+    /// Unset until the AMM state is indexed
+    paused: Option<bool>,
+    emergency: Option<bool>,
+    /// Unset until the AMM state is indexed
+    state_limits: Option<StateUsage>,
+}
+
+/// Caps on pools and balance entries, next to how many there are
+#[derive(Serialize)]
+struct StateUsage {
+    pools: usize,
+    entries: usize,
+    #[serde(flatten)]
+    limits: StateLimits,
+}
+
+#[derive(Serialize)]
+struct IdentityContract {
+    name: String,
+    /// Hex SHA-256 of the verification key the contract was registered with, once read
+    registered_vk_hash: Option<String>,
+    /// Hex SHA-256 of the verification key `GET /api/noir/vk` serves
+    vk_hash: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Features {
+    keeper: bool,
+    state_history: bool,
+    reconciliation: bool,
+    state_import: bool,
+    kyc: bool,
+    credential_policy: bool,
+    /// Whether swaps and liquidity additions carry an identity contract blob
+    identity_policy: bool,
+    /// Channels notifications are delivered on besides the inbox
+    delivery_channels: Vec<&'static str>,
+    /// Set up in the contract, false until the AMM state is indexed
+    insurance: bool,
+    oracle_guard: bool,
+    bridge: bool,
+    admin_council: bool,
+}
+
+#[derive(Serialize)]
+struct Fees {
+    /// Taken on swap inputs by pools without a fee of their own
+    swap_fee_bps: u128,
+    /// Fee each pool takes on swap inputs, by pair key
+    pool_swap_fee_bps: BTreeMap<String, u128>,
+    /// Share of the swap fee paid into the insurance fund, for pools without a fee distribution
+    insurance_fund_share_bps: Option<u128>,
+    /// Share of what the swap fee leaves liquidity providers kept for the protocol
+    protocol_fee_share_bps: Option<u128>,
+    /// Kept by the relayer out of relayed swap outputs
+    relayer_fee_bps: u128,
+}
+
+#[derive(Serialize, Default)]
+struct TokenInfo {
+    /// Decimals pools scale amounts by, when registered
+    decimals: Option<u8>,
+    /// Hyli token contract holding the token, for external tokens
+    contract: Option<String>,
+    /// Pools trading the token
+    pools: usize,
+}
+
+#[derive(Serialize)]
+struct IdentityRequirements {
+    /// Routes refused to users the identity contract does not allow
+    kyc_routes: Vec<String>,
+    /// Credentials actions need, from an amount on
+    credential_rules: Vec<CredentialRule>,
+    /// Noir circuit proving each credential
+    credential_circuits: BTreeMap<String, String>,
+    /// Swap caps per block contract1 applies by identity tier, when it has an identity policy
+    unverified_max_swap_per_block: Option<u128>,
+    verified_max_swap_per_block: Option<u128>,
+    verified_lp_boost_bps: Option<u128>,
+}
+
+pub async fn get_config(State(ctx): State<RouterCtx>, headers: HeaderMap) -> impl IntoResponse {
+    let user = AuthHeaders::from_headers(&headers)
+        .ok()
+        .map(|auth| auth.user);
+    let snapshot = ctx.amm_state.snapshot().await;
+    let state = snapshot.as_ref().map(|snapshot| snapshot.state());
+    let capabilities = &ctx.capabilities;
+    let identity_policy = state.and_then(|state| state.identity_policy());
+
+    Json(ConfigResponse {
+        contract_name: ctx.contract1_cn.0.clone(),
+        feature_flags: ctx.feature_flags.for_identity(user.as_deref()).await,
+        network: Network {
+            server_version: env!("CARGO_PKG_VERSION"),
+            api_prefix: versioning::API_V1,
+            mock_node: ctx.mock_node.is_some(),
+            read_only: capabilities.read_only,
+            sync: ctx.sync.status().await,
+        },
+        contracts: Contracts {
+            amm: AmmContract {
+                name: ctx.contract1_cn.0.clone(),
+                program_id: hex::encode(
+                    contract1::client::tx_executor_handler::metadata::PROGRAM_ID,
+                ),
+                paused: state.map(|state| state.paused()),
+                emergency: state.map(|state| state.emergency()),
+                state_limits: state.map(|state| {
+                    let (pools, entries) = state.state_size();
+                    StateUsage {
+                        pools,
+                        entries,
+                        limits: state.state_limits().clone(),
+                    }
+                }),
+            },
+            identity: IdentityContract {
+                name: ctx.contract2_cn.0.clone(),
+                registered_vk_hash: ctx.noir_vk.pinned_hash().await,
+                vk_hash: ctx.noir_vk.current().await.ok().map(|vk| vk.hash.clone()),
+            },
+        },
+        features: Features {
+            keeper: capabilities.keeper,
+            state_history: ctx.state_history.is_some(),
+            reconciliation: ctx.reconciler.is_some(),
+            state_import: ctx.allow_state_import,
+            kyc: !capabilities.kyc_routes.is_empty(),
+            credential_policy: !capabilities.credential_rules.is_empty(),
+            identity_policy: ctx.identity_policy,
+            delivery_channels: match ctx.delivery.enabled() {
+                true => ctx.delivery.channels(),
+                false => vec![],
+            },
+            insurance: state.is_some_and(|state| state.insurance().is_some()),
+            oracle_guard: state.is_some_and(|state| state.oracle_guard().is_some()),
+            bridge: state.is_some_and(|state| state.bridge().is_some()),
+            admin_council: state.is_some_and(|state| state.admin_council().is_some()),
+        },
+        fees: state.map(|state| Fees {
+            swap_fee_bps: state.insurance().map_or(0, |fund| fund.swap_fee_bps),
+            pool_swap_fee_bps: state
+                .pools()
+                .keys()
+                .map(|pair_key| (pair_key.clone(), state.swap_fee_bps(pair_key)))
+                .collect(),
+            insurance_fund_share_bps: state.insurance().map(|fund| fund.fund_share_bps),
+            protocol_fee_share_bps: state.protocol_fee().map(|protocol_fee| protocol_fee.share_bps),
+            relayer_fee_bps: ctx.relayer_fee_bps,
+        }),
+        tokens: state.map(tokens).unwrap_or_default(),
+        identity: IdentityRequirements {
+            kyc_routes: capabilities.kyc_routes.clone(),
+            credential_rules: capabilities.credential_rules.clone(),
+            credential_circuits: capabilities.credential_circuits.clone(),
+            unverified_max_swap_per_block: identity_policy
+                .map(|policy| policy.unverified_max_swap_per_block),
+            verified_max_swap_per_block: identity_policy
+                .map(|policy| policy.verified_max_swap_per_block),
+            verified_lp_boost_bps: identity_policy.map(|policy| policy.verified_lp_boost_bps),
+        },
+    })
+}
+
+fn tokens(state: &Contract1) -> BTreeMap<String, TokenInfo> {
+    let mut tokens: BTreeMap<String, TokenInfo> = BTreeMap::new();
+    for pool in state.pools().values() {
+        for token in [&pool.token_a, &pool.token_b] {
+            tokens.entry(token.clone()).or_default().pools += 1;
+        }
+    }
+    for (token, decimals) in state.token_decimals() {
+        tokens.entry(token.clone()).or_default().decimals = Some(*decimals);
+    }
+    for (token, contract) in state.external_tokens() {
+        tokens.entry(token.clone()).or_default().contract = Some(contract.0.clone());
+    }
+    tokens
+}
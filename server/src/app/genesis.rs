@@ -0,0 +1,70 @@
+//! Genesis liquidity of the `devnet` playground.
+
+use std::time::Duration;
+
+use contract1::{canonical_token, Contract1Action, FaucetAction};
+
+use super::{identity_lookup, submit_and_wait, RouterCtx, TxOutcome};
+use crate::conf::GenesisPool;
+
+const GENESIS_USER: &str = "genesis";
+/// How long to wait for the DA stream to catch up before giving up
+const SYNC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Mint and add liquidity for every configured pool that does not exist yet
+pub async fn seed(ctx: RouterCtx, pools: Vec<GenesisPool>) {
+    let started = tokio::time::Instant::now();
+    while !ctx.sync.is_ready().await {
+        if started.elapsed() > SYNC_TIMEOUT {
+            tracing::error!("❌ Genesis pools not seeded: DA stream did not catch up in time");
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let identity = format!("{}@{}", GENESIS_USER, ctx.contract1_cn.0);
+    for pool in pools {
+        let token_a = canonical_token(&pool.token_a);
+        let token_b = canonical_token(&pool.token_b);
+        let mut tokens = [token_a.as_str(), token_b.as_str()];
+        tokens.sort();
+        let pair = format!("{}_{}", tokens[0], tokens[1]);
+        let state = ctx.amm_state.snapshot().await;
+        if state.is_some_and(|state| state.pools().contains_key(&pair)) {
+            tracing::info!("✅ Genesis pool {} already exists", pair);
+            continue;
+        }
+
+        let actions = [
+            Contract1Action::Faucet(FaucetAction::Mint {
+                user: identity.clone(),
+                token: token_a.clone(),
+                amount: pool.amount_a,
+            }),
+            Contract1Action::Faucet(FaucetAction::Mint {
+                user: identity.clone(),
+                token: token_b.clone(),
+                amount: pool.amount_b,
+            }),
+            Contract1Action::AddLiquidity {
+                user: identity.clone(),
+                token_a: token_a.clone(),
+                token_b: token_b.clone(),
+                amount_a: pool.amount_a,
+                amount_b: pool.amount_b,
+            },
+        ];
+        for action in actions {
+            let mut blobs: Vec<_> = identity_lookup(&ctx, &identity).into_iter().collect();
+            blobs.push(action.as_blob(ctx.contract1_cn.clone()));
+            let error = match submit_and_wait(&ctx, &identity, blobs, None).await {
+                Ok(TxOutcome::Success(_)) => continue,
+                Ok(TxOutcome::Failed(_, error)) => error,
+                Err(e) => e.1.to_string(),
+            };
+            tracing::error!("❌ Could not seed genesis pool {}: {}", pair, error);
+            return;
+        }
+        tracing::info!("🌱 Seeded genesis pool {}", pair);
+    }
+}
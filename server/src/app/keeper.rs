@@ -0,0 +1,122 @@
+//! Keeper submitting `ExecuteDue` whenever a streaming swap chunk or a swap batch is due, and
+//! `Compact` once enough zero balances and drained pools pile up.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use contract1::{Contract1, Contract1Action, SECP256K1_CONTRACT};
+use sdk::{Blob, BlobData};
+use tokio::sync::watch;
+
+use super::{submit_and_wait, RouterCtx, TxOutcome};
+use crate::{
+    secrets::{self, Secrets},
+    tenant::keeper_identity,
+};
+
+pub async fn run(ctx: RouterCtx, secrets: Arc<Secrets>, interval: Duration, compact_min_entries: usize) {
+    let identity = keeper_identity(&ctx.contract1_cn);
+    let key = match secrets.watch(secrets::KEEPER_KEY).await {
+        Ok(key) => key,
+        Err(e) => {
+            tracing::warn!("⚠️ Keeper could not read its key, it will not compact: {:#}", e);
+            None
+        }
+    };
+    let mut tick = tokio::time::interval(interval);
+    loop {
+        tick.tick().await;
+        if !ctx.sync.is_ready().await {
+            continue;
+        }
+        let Some(state) = ctx.amm_state.snapshot().await else {
+            continue;
+        };
+        let compactable = state.compactable().entries();
+        if compact_min_entries > 0 && compactable >= compact_min_entries {
+            match compaction_blobs(&ctx, &state, key.as_ref()) {
+                Ok(blobs) => match submit_and_wait(&ctx, &identity, blobs, None).await {
+                    Ok(TxOutcome::Success(tx_hash)) => {
+                        tracing::info!("🧹 Compacted {} state entries in {}", compactable, tx_hash)
+                    }
+                    Ok(TxOutcome::Failed(tx_hash, error)) => {
+                        tracing::warn!("⚠️ Keeper transaction {} failed: {}", tx_hash, error)
+                    }
+                    Err(e) => tracing::warn!("⚠️ Keeper could not submit: {}", e.1),
+                },
+                Err(e) => tracing::warn!("⚠️ Keeper cannot compact: {:#}", e),
+            }
+        }
+        // ExecuteDue is rejected while paused or in emergency mode
+        if state.paused() || state.emergency() {
+            continue;
+        }
+        // Our transaction lands after the latest block the contract has seen
+        let head = ctx
+            .sync
+            .status()
+            .await
+            .node_head
+            .unwrap_or(0)
+            .max(state.block_height() + 1);
+        // Batches clear once their block is over, when no later swap touches their pool first
+        let chunk_due = state
+            .streaming_swaps()
+            .values()
+            .any(|order| order.next_block <= head);
+        let batch_due = state
+            .swap_batches()
+            .values()
+            .any(|batch| batch.block < head);
+        if !chunk_due && !batch_due {
+            continue;
+        }
+
+        let blobs = vec![Contract1Action::ExecuteDue {}.as_blob(ctx.contract1_cn.clone())];
+        match submit_and_wait(&ctx, &identity, blobs, None).await {
+            Ok(TxOutcome::Success(tx_hash)) => {
+                tracing::info!(
+                    "⏱️ Executed due streaming swaps and swap batches in {}",
+                    tx_hash
+                )
+            }
+            Ok(TxOutcome::Failed(tx_hash, error)) => {
+                tracing::warn!("⚠️ Keeper transaction {} failed: {}", tx_hash, error)
+            }
+            Err(e) => tracing::warn!("⚠️ Keeper could not submit: {}", e.1),
+        }
+    }
+}
+
+/// The keeper's signature of its next compaction, then the compaction
+fn compaction_blobs(ctx: &RouterCtx, state: &Contract1, key: Option<&watch::Receiver<String>>) -> anyhow::Result<Vec<Blob>> {
+    let keeper = state
+        .keeper()
+        .with_context(|| format!("{} was registered without a keeper key", ctx.contract1_cn))?;
+    let key = key.with_context(|| format!("secret {} is not set", secrets::KEEPER_KEY))?;
+    let secret_key = hex::decode(key.borrow().trim())
+        .ok()
+        .and_then(|bytes| secp256k1::SecretKey::from_slice(&bytes).ok())
+        .with_context(|| format!("secret {} is not a secp256k1 private key", secrets::KEEPER_KEY))?;
+    let secp = secp256k1::Secp256k1::signing_only();
+    let public_key = secret_key.public_key(&secp).serialize();
+    if public_key.as_slice() != keeper.public_key {
+        bail!("secret {} is not the key of the keeper of {}", secrets::KEEPER_KEY, ctx.contract1_cn);
+    }
+
+    let digest = keeper.digest(&ctx.contract1_cn);
+    let signature = secp.sign_ecdsa(&secp256k1::Message::from_digest(digest), &secret_key);
+    let signature_blob = sdk::verifiers::Secp256k1Blob {
+        identity: keeper.identity.clone().into(),
+        data: digest,
+        public_key,
+        signature: signature.serialize_compact(),
+    };
+    Ok(vec![
+        Blob {
+            contract_name: SECP256K1_CONTRACT.into(),
+            data: BlobData(borsh::to_vec(&signature_blob)?),
+        },
+        Contract1Action::Compact {}.as_blob(ctx.contract1_cn.clone()),
+    ])
+}
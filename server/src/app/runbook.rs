@@ -0,0 +1,508 @@
+//! Operator runbooks: recovery procedures run as single audited calls, each with a dry run.
+
+use std::{collections::BTreeMap, future::Future, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Json, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use client_sdk::contract_indexer::AppError;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+use super::{AdminAuth, AppModule, RouterCtx};
+use crate::amm_state::AmmStateView;
+use crate::conf::RunbooksConf;
+use crate::noir_proofs::StoredProof;
+use crate::noir_verifier::NoirProof;
+use crate::state_dump;
+use crate::storage::{Storage, Write};
+
+/// Storage namespace of the audit log, keyed by run id
+const NAMESPACE: &str = "runbook_audit";
+/// Runs listed when no limit is given
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Publish the state the indexer settled in place of the one the server derived
+    Resync,
+    /// Publish the blocks settled since the last catch-up batch without waiting for it to fill
+    FlushSettlements,
+    /// Submit again the stored identity proofs the identity contract never recorded
+    ResubmitProofs,
+    /// Load the Noir verification key again after the circuit was rebuilt
+    RotateVk,
+}
+
+/// One run of a runbook, as kept in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunbookRun {
+    pub id: u64,
+    pub operation: Operation,
+    /// Admin who ran it, unknown for runs recorded before admins were authenticated
+    #[serde(default)]
+    pub actor: Option<String>,
+    pub dry_run: bool,
+    /// Why the operator ran it, as they gave it
+    pub reason: Option<String>,
+    pub started_at: i64,
+    pub finished_at: i64,
+    /// What was found, and what was done unless dry running
+    pub report: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+/// Work only the app module can do, being the one publishing settled states
+pub enum ModuleCommand {
+    Resync {
+        dry_run: bool,
+        reply: oneshot::Sender<Result<ResyncReport>>,
+    },
+    FlushSettlements {
+        dry_run: bool,
+        reply: oneshot::Sender<FlushReport>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResyncReport {
+    /// Blocks settled but not published yet, flushed before comparing unless dry running
+    pub pending_blocks: usize,
+    pub local_height: Option<u64>,
+    pub local_commitment: Option<String>,
+    pub indexed_height: u64,
+    pub indexed_commitment: String,
+    /// Whether the states differ, in which case the indexed one is published
+    pub changed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlushReport {
+    pub blocks: usize,
+    pub successful_txs: usize,
+    pub failed_txs: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StuckProof {
+    pub proof_hash: String,
+    pub identity: String,
+    pub stored_at: i64,
+    /// Transaction the proof was first submitted in
+    pub tx_hash: String,
+    /// Transaction this run submitted it in again
+    pub resubmitted_tx_hash: Option<String>,
+    /// Why it could not be submitted again
+    pub skipped: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunbookQuery {
+    #[serde(default)]
+    dry_run: bool,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResubmitProofsQuery {
+    #[serde(default)]
+    dry_run: bool,
+    reason: Option<String>,
+    /// Defaults to `[runbooks] stuck_proof_after_secs`
+    older_than_secs: Option<u64>,
+    /// Defaults to `[runbooks] stuck_proof_lookback_secs`
+    lookback_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunbookLogQuery {
+    limit: Option<usize>,
+}
+
+/// Recovery procedures of the admin API, each run recorded in an audit log persisted in the
+/// server storage.
+///
+/// Runs are serialized, so two recoveries never interleave, and a dry run goes through the same
+/// checks as a real one, reporting what it would do.
+pub struct Runbooks {
+    storage: Arc<dyn Storage>,
+    conf: RunbooksConf,
+    commands: mpsc::Sender<ModuleCommand>,
+    /// Id of the next run, held while one runs
+    next_id: Mutex<u64>,
+}
+
+impl Runbooks {
+    pub async fn load(
+        storage: Arc<dyn Storage>,
+        conf: RunbooksConf,
+        commands: mpsc::Sender<ModuleCommand>,
+    ) -> Result<Self> {
+        let next_id = storage
+            .range(NAMESPACE, &[], None)
+            .await?
+            .last()
+            .and_then(|(key, _)| key.as_slice().try_into().ok())
+            .map_or(1, |id| u64::from_be_bytes(id) + 1);
+        Ok(Self {
+            storage,
+            conf,
+            commands,
+            next_id: Mutex::new(next_id),
+        })
+    }
+
+    /// Latest runs, newest first
+    pub async fn runs(&self, limit: usize) -> Result<Vec<RunbookRun>> {
+        self.storage
+            .range(NAMESPACE, &[], None)
+            .await?
+            .into_iter()
+            .rev()
+            .take(limit)
+            .map(|(_, bytes)| {
+                serde_json::from_slice(&bytes).context("Could not parse a stored runbook run")
+            })
+            .collect()
+    }
+
+    /// Run an operation and record how it went, failed runs included
+    async fn audited<T: Serialize>(
+        &self,
+        operation: Operation,
+        actor: String,
+        dry_run: bool,
+        reason: Option<String>,
+        run: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<RunbookRun, AppError> {
+        let mut next_id = self.next_id.lock().await;
+        let started_at = chrono::Utc::now().timestamp();
+        let outcome = run.await;
+        let mut entry = RunbookRun {
+            id: *next_id,
+            operation,
+            actor: Some(actor),
+            dry_run,
+            reason,
+            started_at,
+            finished_at: chrono::Utc::now().timestamp(),
+            report: None,
+            error: None,
+        };
+        let outcome = match outcome {
+            Ok(report) => {
+                entry.report = Some(serde_json::to_value(report)?);
+                Ok(())
+            }
+            Err(e) => {
+                entry.error = Some(format!("{:#}", e.1));
+                Err(e)
+            }
+        };
+        self.storage
+            .apply(vec![Write::put(
+                NAMESPACE,
+                entry.id.to_be_bytes().to_vec(),
+                serde_json::to_vec(&entry)?,
+            )])
+            .await
+            .context("Failed to persist runbook run")?;
+        *next_id += 1;
+
+        match (&entry.error, entry.dry_run) {
+            (Some(error), _) => tracing::warn!(
+                "⚠️ Runbook run {} ({:?}) by {} failed: {}",
+                entry.id,
+                operation,
+                entry.actor.as_deref().unwrap_or_default(),
+                error
+            ),
+            (None, true) => tracing::info!(
+                "🧰 Runbook run {} ({:?}) by {} dry run done",
+                entry.id,
+                operation,
+                entry.actor.as_deref().unwrap_or_default()
+            ),
+            (None, false) => tracing::warn!(
+                "🧰 Runbook run {} ({:?}) by {} applied",
+                entry.id,
+                operation,
+                entry.actor.as_deref().unwrap_or_default()
+            ),
+        }
+        outcome.map(|()| entry)
+    }
+
+    /// Hand a command to the app module and wait for its reply
+    async fn ask<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> ModuleCommand) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .ok()
+            .context("The app module is not running")?;
+        response.await.context("The app module dropped the command")
+    }
+}
+
+impl AppModule {
+    pub(super) async fn run_command(&mut self, command: ModuleCommand) {
+        // A requester that went away needs no reply
+        match command {
+            ModuleCommand::Resync { dry_run, reply } => {
+                let _ = reply.send(self.resync(dry_run).await);
+            }
+            ModuleCommand::FlushSettlements { dry_run, reply } => {
+                let report = FlushReport {
+                    blocks: self.pending.blocks,
+                    successful_txs: self.pending.successful.len(),
+                    failed_txs: self.pending.failed.len(),
+                };
+                if !dry_run {
+                    self.flush_settlements().await;
+                }
+                let _ = reply.send(report);
+            }
+        }
+    }
+
+    /// Publish the state the indexer holds when it differs from the local one
+    async fn resync(&mut self, dry_run: bool) -> Result<ResyncReport> {
+        let pending_blocks = self.pending.blocks;
+        if !dry_run {
+            self.flush_settlements().await;
+        }
+        let indexed = AmmStateView::fetch(&self.state_source, &self.contract1_cn)
+            .await
+            .with_context(|| format!("Could not read the indexed {} state", self.contract1_cn))?;
+        let local = self.amm_state.snapshot().await;
+        let local_commitment = local
+            .as_ref()
+            .map(|snapshot| state_dump::commitment_digest(snapshot.state()));
+        let indexed_commitment = state_dump::commitment_digest(&indexed);
+        let report = ResyncReport {
+            pending_blocks,
+            local_height: local
+                .as_ref()
+                .map(|snapshot| snapshot.state().block_height()),
+            changed: local_commitment.as_ref() != Some(&indexed_commitment),
+            local_commitment,
+            indexed_height: indexed.block_height(),
+            indexed_commitment,
+        };
+        if report.changed && !dry_run {
+            // Derived indexes see the jump like any settlement, recorded once per indexed state
+            let source = format!("resync:{}", report.indexed_commitment);
+            self.settle_state(&source, indexed, &[]).await;
+            tracing::warn!(
+                "🔁 Resynced {} from the indexed state at block {}",
+                self.contract1_cn,
+                report.indexed_height
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// Runbook runs of the audit log, newest first
+pub async fn get_runs(
+    State(ctx): State<RouterCtx>,
+    State(admin): State<AdminAuth>,
+    headers: HeaderMap,
+    Query(query): Query<RunbookLogQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    admin.require(&headers).await?;
+    let runs = ctx
+        .runbooks
+        .runs(query.limit.unwrap_or(DEFAULT_LIMIT))
+        .await?;
+    Ok(Json(runs))
+}
+
+/// Replace the state the server derived from blocks with the one the indexer settled, then read
+/// the identity contract again
+pub async fn resync(
+    State(ctx): State<RouterCtx>,
+    State(admin): State<AdminAuth>,
+    headers: HeaderMap,
+    Query(query): Query<RunbookQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = admin.require(&headers).await?;
+    let dry_run = query.dry_run;
+    let run = async {
+        if ctx.mock_node.is_some() {
+            return Err(AppError(
+                StatusCode::CONFLICT,
+                anyhow::anyhow!("The mock node has no indexer to resync from"),
+            ));
+        }
+        let report = ctx
+            .runbooks
+            .ask(|reply| ModuleCommand::Resync { dry_run, reply })
+            .await?
+            .map_err(|e| AppError(StatusCode::BAD_GATEWAY, e))?;
+        if !dry_run {
+            let height = ctx.sync.status().await.processed_height;
+            ctx.identity_index
+                .refresh(height)
+                .await
+                .map_err(|e| AppError(StatusCode::BAD_GATEWAY, e))?;
+        }
+        Ok(report)
+    };
+    let entry = ctx
+        .runbooks
+        .audited(Operation::Resync, actor, dry_run, query.reason, run)
+        .await?;
+    Ok(Json(entry))
+}
+
+/// Publish the blocks of the pending catch-up batch now
+pub async fn flush_settlements(
+    State(ctx): State<RouterCtx>,
+    State(admin): State<AdminAuth>,
+    headers: HeaderMap,
+    Query(query): Query<RunbookQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = admin.require(&headers).await?;
+    let dry_run = query.dry_run;
+    let run = async {
+        let report = ctx
+            .runbooks
+            .ask(|reply| ModuleCommand::FlushSettlements { dry_run, reply })
+            .await?;
+        Ok(report)
+    };
+    let entry = ctx
+        .runbooks
+        .audited(Operation::FlushSettlements, actor, dry_run, query.reason, run)
+        .await?;
+    Ok(Json(entry))
+}
+
+/// Submit again the latest stored proof of each identity the identity contract has no
+/// verification of, once it is older than `older_than_secs`
+pub async fn resubmit_proofs(
+    State(ctx): State<RouterCtx>,
+    State(admin): State<AdminAuth>,
+    headers: HeaderMap,
+    Query(query): Query<ResubmitProofsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = admin.require(&headers).await?;
+    let dry_run = query.dry_run;
+    let older_than = query
+        .older_than_secs
+        .unwrap_or(ctx.runbooks.conf.stuck_proof_after_secs) as i64;
+    let lookback = query
+        .lookback_secs
+        .unwrap_or(ctx.runbooks.conf.stuck_proof_lookback_secs) as i64;
+    let run = async {
+        let now = chrono::Utc::now().timestamp();
+        let vk = ctx
+            .noir_vk
+            .current()
+            .await
+            .map_err(|e| AppError(StatusCode::SERVICE_UNAVAILABLE, e))?;
+        // Stored oldest first, so each identity ends up with its latest proof
+        let latest: BTreeMap<String, StoredProof> = ctx
+            .noir_proofs
+            .stored_between(now - lookback, now + 1)
+            .await?
+            .into_iter()
+            .map(|proof| (proof.identity.clone(), proof))
+            .collect();
+
+        let mut stuck = vec![];
+        for proof in latest.into_values() {
+            // Younger proofs may still settle
+            if proof.stored_at > now - older_than {
+                continue;
+            }
+            let verified = ctx
+                .identity_index
+                .verified(&proof.identity)
+                .await
+                .map_err(|e| AppError(StatusCode::BAD_GATEWAY, e))?;
+            if verified {
+                continue;
+            }
+            let mut entry = StuckProof {
+                proof_hash: proof.proof_hash.clone(),
+                identity: proof.identity.clone(),
+                stored_at: proof.stored_at,
+                tx_hash: proof.tx_hash.clone(),
+                resubmitted_tx_hash: None,
+                skipped: None,
+            };
+            if proof.vk_hash != vk.hash {
+                entry.skipped = Some(format!(
+                    "Made for verification key {}, the circuit now has {}",
+                    proof.vk_hash, vk.hash
+                ));
+            } else if !dry_run {
+                match resubmit(&ctx, &proof).await {
+                    Ok(tx_hash) => entry.resubmitted_tx_hash = Some(tx_hash),
+                    Err(e) => entry.skipped = Some(format!("{:#}", e)),
+                }
+            }
+            stuck.push(entry);
+        }
+        Ok(stuck)
+    };
+    let entry = ctx
+        .runbooks
+        .audited(Operation::ResubmitProofs, actor, dry_run, query.reason, run)
+        .await?;
+    Ok(Json(entry))
+}
+
+/// Load the verification key again, or only report whether it changed on a dry run
+pub async fn rotate_vk(
+    State(ctx): State<RouterCtx>,
+    State(admin): State<AdminAuth>,
+    headers: HeaderMap,
+    Query(query): Query<RunbookQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let actor = admin.require(&headers).await?;
+    let dry_run = query.dry_run;
+    let run = async {
+        let rotation = match dry_run {
+            true => ctx.noir_vk.preview_rotation().await,
+            false => ctx.noir_vk.rotate().await,
+        };
+        rotation.map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e))
+    };
+    let entry = ctx
+        .runbooks
+        .audited(Operation::RotateVk, actor, dry_run, query.reason, run)
+        .await?;
+    Ok(Json(entry))
+}
+
+/// Submit a stored proof in a new identity transaction, as it was checked the first time
+async fn resubmit(ctx: &RouterCtx, stored: &StoredProof) -> Result<String> {
+    let proof = NoirProof {
+        proof_data: hex::decode(&stored.proof).context("Stored proof is not hex")?,
+        public_inputs: stored.public_inputs.clone(),
+        verification_key: hex::decode(&stored.verification_key)
+            .context("Stored verification key is not hex")?,
+    };
+    ctx.noir_vk
+        .check_registered(&proof.verification_key)
+        .await?;
+    let tx_hash = ctx
+        .noir_verifier
+        .submit_proof_to_chain(proof, stored.identity.clone())
+        .await?;
+    tracing::info!(
+        "🔁 Resubmitted Noir proof {} of {} in {}",
+        stored.proof_hash,
+        stored.identity,
+        tx_hash
+    );
+    Ok(tx_hash)
+}
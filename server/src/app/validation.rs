@@ -0,0 +1,354 @@
+//! Checks of request bodies at the API boundary, so obviously invalid requests are refused with
+//! every offending field before anything is screened, proven or submitted.
+
+use axum::{
+    extract::{FromRequest, Json, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use contract1::{canonical_token, TokenAmount};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+
+use super::{
+    AddLiquidityRequest, BridgeDepositRequest, CreateBatchAuctionPoolRequest, CreateLbpPoolRequest,
+    CreateOtcOfferRequest, CreateStreamingSwapRequest, CreateVestingRequest,
+    ExternalTransferRequest, FileInsuranceClaimRequest, MintTokensRequest, RelaySwapRequest,
+    RemoveLiquidityRequest, RequestWithdrawalRequest, RouterCtx, SwapTokensRequest,
+    BPS_DENOMINATOR, MAX_SLIPPAGE_BPS,
+};
+use crate::conf::ValidationConf;
+
+/// Token symbol, or pair key of token symbols, read in the uppercase form contract1 accepts
+pub fn canonical<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|token| canonical_token(&token))
+}
+
+pub fn canonical_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)
+        .map(|token| token.map(|token| canonical_token(&token)))
+}
+
+pub fn canonical_amount<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TokenAmount, D::Error> {
+    let mut token_amount = TokenAmount::deserialize(deserializer)?;
+    token_amount.token = canonical_token(&token_amount.token);
+    Ok(token_amount)
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    /// Path of the field in the request body, e.g. `give.amount`
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct InvalidRequest {
+    /// `invalid_request:` followed by every field error, like other error codes
+    error: String,
+    fields: Vec<FieldError>,
+}
+
+/// Collects the field errors of a request against the configured bounds
+pub struct Validator<'a> {
+    conf: &'a ValidationConf,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(conf: &'a ValidationConf) -> Self {
+        Validator {
+            conf,
+            errors: vec![],
+        }
+    }
+
+    fn fail(&mut self, field: &str, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// Amounts are positive and at most `max_amount`
+    pub fn amount(&mut self, field: &str, amount: u128) {
+        if amount == 0 {
+            self.fail(field, "must be positive");
+        } else if amount > self.conf.max_amount {
+            self.fail(field, format!("must be at most {}", self.conf.max_amount));
+        }
+    }
+
+    /// Token symbols are ASCII letters, digits, `_` or `-`, starting with a letter
+    pub fn token(&mut self, field: &str, token: &str) {
+        if token.is_empty() {
+            self.fail(field, "must not be empty");
+        } else if token.len() > self.conf.max_token_len {
+            self.fail(
+                field,
+                format!("must be at most {} characters", self.conf.max_token_len),
+            );
+        } else if !token.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            self.fail(
+                field,
+                "must start with a letter and contain only ASCII letters, digits, '_' or '-'",
+            );
+        }
+    }
+
+    /// Both sides of a pair or a trade, `second` being reported when they are the same
+    pub fn pair(&mut self, (first, token_a): (&str, &str), (second, token_b): (&str, &str)) {
+        self.token(first, token_a);
+        self.token(second, token_b);
+        if token_a == token_b {
+            self.fail(second, format!("must differ from {}", first));
+        }
+    }
+
+    /// Symbols the server cannot uppercase itself, e.g. those of a signed intent
+    pub fn uppercase(&mut self, field: &str, token: &str) {
+        if token != canonical_token(token) {
+            self.fail(
+                field,
+                format!("must be uppercase, i.e. {}", canonical_token(token)),
+            );
+        }
+    }
+
+    pub fn token_amount(&mut self, field: &str, token_amount: &TokenAmount) {
+        self.token(&format!("{}.token", field), &token_amount.token);
+        self.amount(&format!("{}.amount", field), token_amount.amount);
+    }
+
+    pub fn at_most(&mut self, field: &str, value: u128, max: u128) {
+        if value > max {
+            self.fail(field, format!("must be at most {}", max));
+        }
+    }
+
+    pub fn positive(&mut self, field: &str, value: u64) {
+        if value == 0 {
+            self.fail(field, "must be positive");
+        }
+    }
+
+    /// Free text sent along, e.g. a claim reason or a withdrawal destination
+    pub fn text(&mut self, field: &str, text: &str) {
+        if text.trim().is_empty() {
+            self.fail(field, "must not be empty");
+        } else if text.len() > self.conf.max_text_len {
+            self.fail(
+                field,
+                format!("must be at most {} bytes", self.conf.max_text_len),
+            );
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.errors),
+        }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self, check: &mut Validator);
+}
+
+/// JSON body refused with `400 invalid_request` and its field errors when it does not validate
+pub struct Valid<T>(pub T);
+
+impl<T> FromRequest<RouterCtx> for Valid<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, ctx: &RouterCtx) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<T>::from_request(request, ctx)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let mut check = Validator::new(&ctx.validation);
+        body.validate(&mut check);
+        check.finish().map_err(invalid_request)?;
+        Ok(Valid(body))
+    }
+}
+
+fn invalid_request(fields: Vec<FieldError>) -> Response {
+    let details = fields
+        .iter()
+        .map(|error| format!("{} {}", error.field, error.message))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let body = InvalidRequest {
+        error: format!("invalid_request: {}", details),
+        fields,
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+impl Validate for MintTokensRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+    }
+}
+
+impl Validate for SwapTokensRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_in", &self.token_in), ("token_out", &self.token_out));
+        check.amount("amount_in", self.amount_in);
+        match (self.min_amount_out, self.slippage_bps) {
+            (Some(_), Some(_)) => {
+                check.fail("slippage_bps", "must not be set along with min_amount_out")
+            }
+            (None, None) => check.fail("min_amount_out", "or slippage_bps must be set"),
+            (_, Some(slippage_bps)) => check.at_most(
+                "slippage_bps",
+                slippage_bps as u128,
+                MAX_SLIPPAGE_BPS as u128,
+            ),
+            (Some(_), None) => {}
+        }
+        if self.auto_retry && self.slippage_bps.is_none() {
+            check.fail("auto_retry", "needs slippage_bps");
+        }
+    }
+}
+
+impl Validate for AddLiquidityRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_a", &self.token_a), ("token_b", &self.token_b));
+        check.amount("amount_a", self.amount_a);
+        check.amount("amount_b", self.amount_b);
+    }
+}
+
+impl Validate for CreateBatchAuctionPoolRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_a", &self.token_a), ("token_b", &self.token_b));
+        check.amount("amount_a", self.amount_a);
+        check.amount("amount_b", self.amount_b);
+    }
+}
+
+impl Validate for CreateLbpPoolRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_a", &self.token_a), ("token_b", &self.token_b));
+        check.amount("amount_a", self.amount_a);
+        check.amount("amount_b", self.amount_b);
+        for (field, weight) in [
+            ("start_weight_a", self.start_weight_a),
+            ("end_weight_a", self.end_weight_a),
+        ] {
+            if weight == 0 || weight >= BPS_DENOMINATOR {
+                check.fail(
+                    field,
+                    format!("must be between 1 and {}", BPS_DENOMINATOR - 1),
+                );
+            }
+        }
+        if self.end_block <= self.start_block {
+            check.fail("end_block", "must be after start_block");
+        }
+    }
+}
+
+impl Validate for RemoveLiquidityRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_a", &self.token_a), ("token_b", &self.token_b));
+        check.amount("liquidity_amount", self.liquidity_amount);
+    }
+}
+
+impl Validate for CreateStreamingSwapRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.pair(("token_in", &self.token_in), ("token_out", &self.token_out));
+        check.amount("total_amount", self.total_amount);
+        check.positive("chunks", self.chunks as u64);
+        check.at_most(
+            "chunks",
+            self.chunks as u128,
+            check.conf.max_streaming_chunks as u128,
+        );
+        if self.total_amount < self.chunks as u128 {
+            check.fail("chunks", "must be at most total_amount");
+        }
+        check.positive("interval_blocks", self.interval_blocks);
+    }
+}
+
+impl Validate for CreateOtcOfferRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token_amount("give", &self.give);
+        check.token_amount("want", &self.want);
+        if self.give.token == self.want.token {
+            check.fail("want.token", "must differ from give.token");
+        }
+    }
+}
+
+impl Validate for CreateVestingRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+    }
+}
+
+impl Validate for FileInsuranceClaimRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+        check.text("reason", &self.reason);
+    }
+}
+
+impl Validate for ExternalTransferRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+    }
+}
+
+impl Validate for BridgeDepositRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+        check.text("external_tx_ref", &self.external_tx_ref);
+    }
+}
+
+impl Validate for RequestWithdrawalRequest {
+    fn validate(&self, check: &mut Validator) {
+        check.token("token", &self.token);
+        check.amount("amount", self.amount);
+        check.text("destination", &self.destination);
+    }
+}
+
+impl Validate for RelaySwapRequest {
+    fn validate(&self, check: &mut Validator) {
+        let intent = &self.intent;
+        check.pair(
+            ("intent.token_in", &intent.token_in),
+            ("intent.token_out", &intent.token_out),
+        );
+        check.uppercase("intent.token_in", &intent.token_in);
+        check.uppercase("intent.token_out", &intent.token_out);
+        check.amount("intent.amount_in", intent.amount_in);
+        check.at_most(
+            "intent.max_relayer_fee_bps",
+            intent.max_relayer_fee_bps,
+            BPS_DENOMINATOR,
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+const MANIFEST_NAME: &str = "backup_manifest.json";
+/// Archive prefix of the data directory contents
+const DATA_PREFIX: &str = "data";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupManifest {
+    server_version: String,
+    created_at: i64,
+}
+
+/// Archive the whole data directory (tx history, indexer and prover state,
+/// snapshots, price history, profiles...) into a single `.tar.gz`.
+///
+/// Must run while the server is stopped so the stores are consistent on disk.
+pub fn backup(data_directory: &Path, archive: &Path) -> Result<()> {
+    if !data_directory.is_dir() {
+        bail!("Data directory {:?} does not exist", data_directory);
+    }
+    if archive.starts_with(data_directory) {
+        bail!("The archive cannot be written inside the data directory");
+    }
+
+    let file = File::create(archive).with_context(|| format!("Failed to create {:?}", archive))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let manifest = serde_json::to_vec_pretty(&BackupManifest {
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: chrono::Utc::now().timestamp(),
+    })?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, manifest.as_slice())?;
+
+    builder
+        .append_dir_all(DATA_PREFIX, data_directory)
+        .with_context(|| format!("Failed to archive {:?}", data_directory))?;
+    builder.into_inner()?.finish()?;
+
+    tracing::info!("💾 Backed up {:?} to {:?}", data_directory, archive);
+    Ok(())
+}
+
+/// Replace the data directory with the contents of a backup archive.
+///
+/// An existing data directory is moved aside rather than deleted.
+pub fn restore(archive: &Path, data_directory: &Path) -> Result<()> {
+    let staging = sibling(data_directory, "restoring");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+
+    let file = File::open(archive).with_context(|| format!("Failed to open {:?}", archive))?;
+    // `unpack` refuses entries escaping the destination
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(&staging)
+        .with_context(|| format!("Failed to extract {:?}", archive))?;
+
+    let manifest: BackupManifest = match std::fs::read(staging.join(MANIFEST_NAME)) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(_) => {
+            std::fs::remove_dir_all(&staging)?;
+            bail!("Not a server backup: {} is missing", MANIFEST_NAME);
+        }
+    };
+    tracing::info!(
+        "📦 Restoring backup taken at {} by server {}",
+        manifest.created_at, manifest.server_version
+    );
+
+    if data_directory.exists() {
+        let aside = sibling(data_directory, &format!("before-restore-{}", chrono::Utc::now().timestamp()));
+        std::fs::rename(data_directory, &aside)
+            .with_context(|| format!("Failed to move {:?} aside", data_directory))?;
+        tracing::info!("📁 Previous data directory kept at {:?}", aside);
+    }
+    std::fs::rename(staging.join(DATA_PREFIX), data_directory)?;
+    std::fs::remove_dir_all(&staging)?;
+
+    tracing::info!("✅ Restored {:?} into {:?}", archive, data_directory);
+    Ok(())
+}
+
+/// `<data_directory>.<suffix>`, next to the data directory
+fn sibling(data_directory: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", data_directory.display(), suffix))
+}
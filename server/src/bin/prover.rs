@@ -0,0 +1,108 @@
+//! Standalone prover: follows the DA stream and proves contract1 transactions.
+//!
+//! Runs next to an API server started with `--external-prover`; both sides only
+//! talk through the node, which settles the transactions this process proves.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use client_sdk::{helpers::risc0::Risc0Prover, rest_client::NodeApiHttpClient};
+use contract1::Contract1;
+use hyle_modules::{
+    bus::{metrics::BusMetrics, SharedMessageBus},
+    modules::{
+        da_listener::{DAListener, DAListenerConf},
+        prover::{AutoProver, AutoProverCtx},
+        ModulesHandler,
+    },
+    utils::logger::setup_tracing,
+};
+use sdk::info;
+use std::sync::Arc;
+
+// Shared with the server binary; only part of them is used here
+#[allow(dead_code)]
+#[path = "../conf.rs"]
+mod conf;
+#[allow(dead_code)]
+#[path = "../tenant.rs"]
+mod tenant;
+
+use conf::Conf;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Proves contract1 transactions for an API server running with --external-prover", long_about = None)]
+pub struct Args {
+    #[arg(long, default_value = "config.toml")]
+    pub config_file: Vec<String>,
+
+    #[arg(long, default_value = "contract1")]
+    pub contract1_cn: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = Conf::new(args.config_file).context("reading config file")?;
+
+    setup_tracing(&config.log_format, format!("{}-prover(nopkey)", config.id))
+        .context("setting up tracing")?;
+
+    let config = Arc::new(config);
+
+    info!("Starting prover with config: {:?}", &config);
+
+    let node_client =
+        Arc::new(NodeApiHttpClient::new(config.node_url.clone()).context("build node client")?);
+
+    let tenants = tenant::resolve(&config, &args.contract1_cn).context("resolving tenants")?;
+
+    // One bus per tenant, mirroring the server layout
+    let mut handlers = vec![];
+    for tenant in &tenants {
+        let config = &tenant.config;
+        // Separate from the server's data so both can share a volume
+        let data_directory = config.data_directory.join("prover");
+        std::fs::create_dir_all(&data_directory).context("creating data directory")?;
+
+        let bus = SharedMessageBus::new(BusMetrics::global(format!(
+            "{}-prover-{}",
+            config.id,
+            tenant.label()
+        )));
+        let mut handler = ModulesHandler::new(&bus).await;
+
+        handler
+            .build_module::<AutoProver<Contract1>>(Arc::new(AutoProverCtx {
+                data_directory: data_directory.clone(),
+                prover: Arc::new(Risc0Prover::new(contracts::CONTRACT1_ELF)),
+                contract_name: tenant.contract1_cn.clone(),
+                node: node_client.clone(),
+                default_state: tenant.initial_state()?,
+                buffer_blocks: config.buffer_blocks,
+                max_txs_per_proof: config.max_txs_per_proof,
+            }))
+            .await?;
+
+        handler
+            .build_module::<DAListener>(DAListenerConf {
+                start_block: None,
+                data_directory,
+                da_read_from: config.da_read_from.clone(),
+            })
+            .await?;
+
+        handler.start_modules().await?;
+        handlers.push(handler);
+    }
+
+    // Run until shut down or an error occurs
+    let exits: Vec<_> = handlers
+        .into_iter()
+        .map(|mut handler| tokio::spawn(async move { handler.exit_process().await }))
+        .collect();
+    for exit in exits {
+        exit.await??;
+    }
+
+    Ok(())
+}
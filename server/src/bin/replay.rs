@@ -0,0 +1,155 @@
+//! Replays a `--record` file against a running server and diffs the responses.
+//!
+//! Wallet blob payloads are stripped when recording, so the target should run with
+//! `--mock-node`: it ignores them and starts from the same empty AMM state.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Fields that differ between runs by nature (hashes, clocks, chain heights)
+const VOLATILE_FIELDS: &[&str] = &[
+    "at",
+    "submitted_at",
+    "finished_at",
+    "timestamp",
+    "tx_hash",
+    "hash",
+    "proof_hash",
+    "retry_of",
+    "retried_by",
+    "eta_secs",
+    "block_height",
+    "processed_height",
+    "node_head",
+    "lag",
+];
+const MASKED: &str = "<volatile>";
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Replay recorded API traffic against a server and diff the results", long_about = None)]
+pub struct Args {
+    /// Record file written by `server --record`
+    pub file: PathBuf,
+
+    #[arg(long, default_value = "http://localhost:4002")]
+    pub target: String,
+
+    /// Additional response fields to ignore when comparing
+    #[arg(long = "ignore-field", value_name = "FIELD")]
+    pub ignore_fields: Vec<String>,
+}
+
+/// Subset of the server's recorded entries the replay needs
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum RecordedEntry {
+    Request {
+        method: String,
+        uri: String,
+        user: Option<String>,
+        body: Option<Value>,
+        status: u16,
+        response: Option<Value>,
+        #[serde(default)]
+        stream: bool,
+    },
+    Settlement {},
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let content = std::fs::read_to_string(&args.file)
+        .with_context(|| format!("Failed to read {:?}", args.file))?;
+    let client = reqwest::Client::new();
+    let ignored: Vec<&str> = VOLATILE_FIELDS
+        .iter()
+        .copied()
+        .chain(args.ignore_fields.iter().map(String::as_str))
+        .collect();
+
+    let (mut replayed, mut mismatches) = (0, 0);
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: RecordedEntry = serde_json::from_str(line)
+            .with_context(|| format!("Invalid entry on line {}", line_number + 1))?;
+        let RecordedEntry::Request { method, uri, user, body, status, response, stream } = entry else {
+            continue;
+        };
+        if stream {
+            continue;
+        }
+
+        let mut request = client.request(method.parse()?, format!("{}{}", args.target, uri));
+        if let Some(user) = &user {
+            request = request.header("x-user", user);
+        }
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let reply = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to replay {} {}", method, uri))?;
+        let replayed_status = reply.status().as_u16();
+        let bytes = reply.bytes().await?;
+        let replayed_response = (!bytes.is_empty()).then(|| {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        });
+        replayed += 1;
+
+        let expected = response.map(|r| masked(r, &ignored));
+        let actual = replayed_response.map(|r| masked(r, &ignored));
+        if status != replayed_status || expected != actual {
+            mismatches += 1;
+            println!("✗ line {}: {} {}", line_number + 1, method, uri);
+            println!("    recorded: {} {}", status, render(&expected));
+            println!("    replayed: {} {}", replayed_status, render(&actual));
+        }
+    }
+
+    println!("{} requests replayed, {} mismatches", replayed, mismatches);
+    if mismatches > 0 {
+        bail!("Replay diverged from the recording");
+    }
+    Ok(())
+}
+
+/// Replace volatile fields and anything shaped like a hash with a fixed marker
+fn masked(mut value: Value, ignored: &[&str]) -> Value {
+    mask(&mut value, ignored);
+    value
+}
+
+fn mask(value: &mut Value, ignored: &[&str]) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if ignored.contains(&key.as_str()) {
+                    *field = Value::String(MASKED.into());
+                } else {
+                    mask(field, ignored);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| mask(item, ignored)),
+        Value::String(s) if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) => {
+            *s = MASKED.into();
+        }
+        _ => {}
+    }
+}
+
+fn render(value: &Option<Value>) -> String {
+    value
+        .as_ref()
+        .map(Value::to_string)
+        .unwrap_or_else(|| "<empty>".into())
+}
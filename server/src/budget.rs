@@ -0,0 +1,115 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    KeyValue,
+};
+
+use crate::conf::RouteBudget;
+use crate::versioning;
+
+/// Per-route limits on request and response sizes and latency
+pub struct RouteBudgets {
+    budgets: HashMap<String, RouteBudget>,
+    exceeded: Counter<u64>,
+    latency: Histogram<u64>,
+}
+
+impl RouteBudgets {
+    pub fn new(budgets: HashMap<String, RouteBudget>) -> Self {
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        RouteBudgets {
+            budgets,
+            exceeded: meter.u64_counter("route_budget_exceeded").build(),
+            latency: meter.u64_histogram("route_latency_ms").build(),
+        }
+    }
+
+    fn exceeded(&self, route: &str, budget: &'static str) {
+        self.exceeded.add(
+            1,
+            &[KeyValue::new("route", route.to_string()), KeyValue::new("budget", budget)],
+        );
+    }
+}
+
+/// Middleware enforcing the budget of the matched route.
+///
+/// Oversized requests are refused with `413`. Responses are only buffered, and refused when
+/// too large, on routes with a `max_response_bytes`; event streams are never buffered.
+/// Latency over the SLO is logged and counted but the response is still served.
+pub async fn enforce(State(budgets): State<Arc<RouteBudgets>>, request: Request, next: Next) -> Response {
+    // Matched paths include the prefix of the tenant, which budgets are shared by
+    let Some(route) = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| path.as_str().find(versioning::LEGACY_API).map(|at| &path.as_str()[at..]))
+        .map(versioning::legacy_path)
+    else {
+        return next.run(request).await;
+    };
+    let Some(budget) = budgets.budgets.get(&route) else {
+        return next.run(request).await;
+    };
+
+    let request = match budget.max_body_bytes {
+        Some(max) => {
+            let (parts, body) = request.into_parts();
+            match to_bytes(body, max).await {
+                Ok(bytes) => Request::from_parts(parts, Body::from(bytes)),
+                Err(_) => {
+                    budgets.exceeded(&route, "body_size");
+                    tracing::warn!("⚠️ Request body to {} is over its {} bytes budget", route, max);
+                    return (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("Request body is over the {} bytes allowed on {}", max, route),
+                    )
+                        .into_response();
+                }
+            }
+        }
+        None => request,
+    };
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = start.elapsed().as_millis() as u64;
+    budgets.latency.record(elapsed, &[KeyValue::new("route", route.clone())]);
+    if budget.latency_slo_ms.is_some_and(|slo| elapsed > slo) {
+        budgets.exceeded(&route, "latency");
+        tracing::warn!(
+            "🐢 {} took {} ms, over its {} ms SLO",
+            route,
+            elapsed,
+            budget.latency_slo_ms.unwrap_or_default()
+        );
+    }
+
+    let streaming = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    let Some(max) = budget.max_response_bytes.filter(|_| !streaming) else {
+        return response;
+    };
+    let (parts, body) = response.into_parts();
+    match to_bytes(body, max).await {
+        Ok(bytes) => Response::from_parts(parts, Body::from(bytes)),
+        Err(_) => {
+            budgets.exceeded(&route, "response_size");
+            tracing::warn!("⚠️ Response of {} is over its {} bytes budget", route, max);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Response is over the {} bytes allowed on {}, narrow the query or paginate", max, route),
+            )
+                .into_response()
+        }
+    }
+}
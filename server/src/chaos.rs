@@ -0,0 +1,47 @@
+//! Fault injection for resilience tests, only compiled with the `chaos` feature.
+//!
+//! Faults are drawn independently at each hook with the rates of `[chaos]`.
+
+use std::{sync::OnceLock, time::Duration};
+
+use anyhow::{bail, Result};
+
+use crate::conf::ChaosConf;
+
+static CHAOS: OnceLock<ChaosConf> = OnceLock::new();
+
+pub fn install(conf: ChaosConf) {
+    tracing::warn!("💥 Fault injection enabled: {:?}", conf);
+    let _ = CHAOS.set(conf);
+}
+
+fn roll(rate: impl Fn(&ChaosConf) -> f64) -> Option<&'static ChaosConf> {
+    let conf = CHAOS.get()?;
+    (rand::random::<f64>() < rate(conf)).then_some(conf)
+}
+
+/// Fail a node call as if the node answered with a 500
+pub fn node_fault(operation: &str) -> Result<()> {
+    if roll(|c| c.node_error_rate).is_some() {
+        tracing::warn!("💥 Injected node failure on {}", operation);
+        bail!("Injected fault: node returned 500 Internal Server Error on {}", operation);
+    }
+    Ok(())
+}
+
+/// Whether to drop a prover outcome, so waiters run into their timeout
+pub fn proof_timeout() -> bool {
+    let dropped = roll(|c| c.proof_timeout_rate).is_some();
+    if dropped {
+        tracing::warn!("💥 Injected proof timeout");
+    }
+    dropped
+}
+
+/// Hold up the DA stream before a block is processed
+pub async fn da_stall() {
+    if let Some(conf) = roll(|c| c.da_stall_rate) {
+        tracing::warn!("💥 Injected DA stream stall of {}s", conf.da_stall_secs);
+        tokio::time::sleep(Duration::from_secs(conf.da_stall_secs)).await;
+    }
+}
@@ -1,6 +1,8 @@
 use config::{Config, Environment, File};
+use contract1::batch_auction::OrderingPolicy;
+use contract1::{FeeDistribution, StateLimits};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf};
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Conf {
@@ -13,12 +15,558 @@ pub struct Conf {
     pub da_read_from: String,
     pub node_url: String,
     pub indexer_url: String,
+    /// Base URL of the contract state indexer serving the full contract1 state, which its
+    /// commitment only hashes
+    pub contract_state_url: String,
 
     pub rest_server_port: u16,
     pub rest_server_max_body_size: usize,
 
     pub buffer_blocks: u32,
     pub max_txs_per_proof: usize,
+
+    /// WebAuthn relying party id, i.e. the domain passkeys are scoped to
+    pub webauthn_rp_id: String,
+    /// Origin the frontend is served from, checked against the passkey client data
+    pub webauthn_rp_origin: String,
+
+    /// Stateful reads are refused until the DA stream is within this many blocks of the node head
+    pub sync_max_lag_blocks: u64,
+    /// Blocks settled together, with one state fetch, while the DA stream catches up
+    pub catch_up_batch_blocks: usize,
+    /// Blocks whose derived state can be reverted if the DA stream replaces them
+    pub reorg_undo_blocks: usize,
+    /// How often the node head is polled to measure DA stream lag
+    pub sync_poll_interval_secs: u64,
+    /// Longest a query with `min_tx` waits for that transaction to be applied
+    pub min_tx_wait_ms: u64,
+    /// HTTP-date sent in the `Sunset` header of the deprecated unversioned `/api` routes
+    pub legacy_api_sunset: String,
+    /// Accept `POST /api/admin/state/import`, which only replaces the state on a mock node
+    pub allow_state_import: bool,
+
+    /// Independent deployments served by this process; empty means a single unprefixed one
+    pub tenants: Vec<TenantConf>,
+
+    /// Backend of the tx history, submitted proofs, price history and profiles
+    pub storage: StorageConf,
+
+    /// Envelope encryption of sensitive records and files
+    pub encryption: EncryptionConf,
+
+    /// Provider of the keys and credentials kept out of this file, refreshed to pick up rotations
+    pub secrets: SecretsConf,
+
+    /// Pruning policies for sub-directories of the data directory, keyed by category
+    pub retention: HashMap<String, RetentionPolicy>,
+    pub retention_interval_secs: u64,
+
+    /// Size and latency budgets, keyed by route as registered without version, e.g. "/api/price-history"
+    pub route_budgets: HashMap<String, RouteBudget>,
+
+    /// Server-side trading caps, checked before submission
+    pub limits: LimitsConf,
+
+    /// Bounds of request fields, checked before anything is screened, proven or submitted
+    pub validation: ValidationConf,
+
+    /// Daily spend tracked per identity and the soft budgets warned about
+    pub spending: SpendingConf,
+
+    /// Per-user inbox of asynchronous events
+    pub notifications: NotificationsConf,
+
+    /// Identity screening applied before user transactions are submitted
+    pub screening: ScreeningConf,
+
+    /// Routes restricted to users allowed by the identity contract
+    pub kyc: KycConf,
+
+    /// Allow/deny changes of the identity contract, read by the KYC and credential checks
+    pub identity_index: IdentityIndexConf,
+
+    /// Credentials swaps and liquidity deposits need, by amount
+    pub credential_policy: CredentialPolicyConf,
+
+    /// Routes and contract1 actions rolled out progressively, keyed by flag name
+    #[serde(default)]
+    pub feature_flags: BTreeMap<String, FeatureFlagConf>,
+
+    /// HTTPS listener serving the API next to the plain one, and client certificates for admin routes
+    pub tls: TlsConf,
+
+    /// Identities allowed on the admin routes, whatever the TLS setup
+    pub admin: AdminConf,
+
+    /// Per-tier swap caps contract1 enforces itself, set when the contract is registered
+    pub identity_policy: IdentityPolicyConf,
+
+    /// Swap fees and the insurance fund they feed, set when the contract is registered
+    pub insurance: InsuranceConf,
+
+    /// Share of swap fees kept for the protocol, set when the contract is registered
+    pub protocol_fee: ProtocolFeeConf,
+
+    /// Limits the contract enforces on faucet mints, set when the contract is registered
+    pub faucet: FaucetConf,
+
+    /// Smallest swap output contract1 accepts, set when the contract is registered
+    pub min_swap_output: u128,
+
+    /// Whether swaps sequenced in one block execute in arrival order or clear together at one
+    /// price per direction (`batch_auction`), set when the contract is registered
+    pub ordering_policy: OrderingPolicy,
+
+    /// M-of-N admins required to pause, change fees or mint beyond the cap
+    pub admin_council: AdminCouncilConf,
+
+    /// Sponsoring of signed swap intents
+    pub relayer: RelayerConf,
+
+    /// Tokens held in their own Hyli token contracts, by token, set when the contract is registered
+    pub external_tokens: BTreeMap<String, String>,
+
+    /// Token registry: decimals of each token, which pools scale amounts by in their math;
+    /// set when the contract is registered
+    pub token_decimals: BTreeMap<String, u8>,
+
+    /// Swap fee split of new pools, the insurance fund's share when unset; set when the
+    /// contract is registered and changed per pool by the admin council
+    pub fee_distribution: Option<FeeDistribution>,
+
+    /// Swap fee of new pools in basis points, the insurance fund's fee when unset; set when the
+    /// contract is registered and changed per pool by the admin council
+    pub pool_swap_fee_bps: Option<u128>,
+
+    /// Caps on pools and balance entries, set when the contract is registered and raised by the
+    /// admin council
+    pub state_limits: StateLimits,
+
+    /// Oracle price bound on large swaps, set when the contract is registered
+    pub oracle_guard: OracleGuardConf,
+
+    /// Operator crediting deposits from other chains, set when the contract is registered
+    pub bridge: BridgeConf,
+
+    /// Background submission of due streaming swap chunks
+    pub keeper: KeeperConf,
+
+    /// Periodic comparison of the indexed state with the commitment on the node
+    pub reconciliation: ReconciliationConf,
+
+    /// Recovery procedures of the admin API
+    pub runbooks: RunbooksConf,
+
+    /// Local playground started by the `devnet` subcommand
+    pub devnet: DevnetConf,
+
+    /// Slots and limits of the `nargo` runs proving Noir identities on the server
+    pub noir_jobs: NoirJobsConf,
+
+    /// Pools and balances kept for queries at past block heights
+    pub state_history: StateHistoryConf,
+
+    #[cfg(feature = "chaos")]
+    pub chaos: ChaosConf,
+}
+
+/// Failure rates between 0 and 1 of the fault injection hooks
+#[cfg(feature = "chaos")]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ChaosConf {
+    /// Node calls (tx submission, head polling) failing as a 500
+    pub node_error_rate: f64,
+    /// Prover outcomes dropped, leaving transactions to time out
+    pub proof_timeout_rate: f64,
+    /// Blocks delayed by `da_stall_secs` before being processed
+    pub da_stall_rate: f64,
+    pub da_stall_secs: u64,
+}
+
+/// Global caps, overridable per token and per pool (keyed `<TOKEN_A>_<TOKEN_B>`, sorted)
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LimitsConf {
+    pub max_swap_in: Option<u128>,
+    pub max_mint: Option<u128>,
+    /// Largest share of an existing pool a user may hold after adding liquidity
+    pub max_pool_share_bps: Option<u32>,
+    #[serde(default)]
+    pub tokens: HashMap<String, TokenLimits>,
+    #[serde(default)]
+    pub pools: HashMap<String, PoolLimits>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ValidationConf {
+    /// Largest amount any request may carry, whatever the token
+    pub max_amount: u128,
+    /// Longest token symbol
+    pub max_token_len: usize,
+    /// Most chunks a streaming swap may be split into
+    pub max_streaming_chunks: u32,
+    /// Longest free text, e.g. an insurance claim reason or a withdrawal destination
+    pub max_text_len: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SpendingConf {
+    /// Swap input per identity and UTC day above which responses carry a warning
+    pub daily_swap_budget: Option<u128>,
+    pub daily_mint_budget: Option<u128>,
+    /// Days of spend kept and shown by `GET /api/portfolio`
+    pub history_days: u32,
+    #[serde(default)]
+    pub tokens: HashMap<String, TokenBudgets>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NotificationsConf {
+    /// Notifications kept per user, the oldest being dropped
+    pub max_per_user: usize,
+    pub max_price_alerts_per_user: usize,
+    /// How long before a stored identity proof is dropped its owner is warned
+    pub identity_expiry_notice_secs: u64,
+    /// Email and Telegram delivery to users who set them up
+    pub delivery: DeliveryConf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DeliveryConf {
+    pub enabled: bool,
+    /// Messages sent per user and channel within an hour, the next ones staying in the inbox only
+    pub max_per_hour: usize,
+    /// `host:port` of the SMTP relay mail goes through, email delivery being off when unset
+    pub smtp_relay: Option<String>,
+    pub smtp_from: String,
+    pub telegram_api: String,
+    /// Subject and body of each notification kind, `{field}` standing for its fields
+    #[serde(default)]
+    pub templates: HashMap<String, MessageTemplate>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MessageTemplate {
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TokenBudgets {
+    pub daily_swap_budget: Option<u128>,
+    pub daily_mint_budget: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TokenLimits {
+    pub max_swap_in: Option<u128>,
+    pub max_mint: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PoolLimits {
+    pub max_pool_share_bps: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ScreeningConf {
+    /// "allow_all", "http" or "identity_contract"
+    pub provider: String,
+    /// Endpoint of the http provider
+    pub url: Option<String>,
+    /// Let submissions through when the provider cannot be reached
+    pub fail_open: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct KycConf {
+    pub enabled: bool,
+    /// Paths as routed within a tenant, e.g. "/api/swap-tokens"
+    pub routes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IdentityIndexConf {
+    /// How often the identity contract state is read off the indexer
+    pub poll_interval_ms: u64,
+    /// Changes kept for `GET /api/identity/changes`, the oldest being dropped first
+    pub max_changes: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CredentialPolicyConf {
+    pub enabled: bool,
+    /// Noir circuit proving each credential, e.g. `country = "zkpassport_identity"`
+    #[serde(default)]
+    pub circuits: BTreeMap<String, String>,
+    #[serde(default)]
+    pub rules: Vec<CredentialRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    Swap,
+    AddLiquidity,
+}
+
+/// Credentials an action needs from `min_amount` on
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CredentialRule {
+    pub action: PolicyAction,
+    /// Swap input, or larger side of a liquidity deposit; every amount when unset
+    pub min_amount: Option<u128>,
+    pub credentials: Vec<String>,
+}
+
+/// A feature on for a share of identities, its routes and actions being refused to the others
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FeatureFlagConf {
+    pub enabled: bool,
+    /// Share of identities the feature is on for; every identity when unset
+    pub rollout_percent: Option<u8>,
+    /// Identities the feature is on for whatever the rollout, e.g. testers
+    #[serde(default)]
+    pub identities: Vec<String>,
+    /// Routes as registered without version, e.g. "/api/otc-offers", subpaths included
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Contract1 actions by name, e.g. "create_otc_offer"
+    #[serde(default)]
+    pub actions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AdminConf {
+    /// Identities whose passkey session authorizes admin requests, nobody's when empty
+    pub identities: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TlsConf {
+    pub enabled: bool,
+    pub port: u16,
+    /// PEM certificate chain and private key of the server, unless the secrets provider holds
+    /// `tls_cert` and `tls_key`
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// PEM CA certificates client certificates must chain to; unset disables client authentication
+    pub client_ca_path: Option<PathBuf>,
+    /// Path prefixes as routed within a tenant, e.g. "/api/admin", only served to verified client certificates
+    pub admin_routes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IdentityPolicyConf {
+    pub enabled: bool,
+    /// Input amount a user may swap per block with only an identity lookup in the transaction
+    pub unverified_max_swap_per_block: u128,
+    /// Input amount a user may swap per block when the transaction verifies an allowed identity
+    pub verified_max_swap_per_block: u128,
+    /// Liquidity mining multiplier of positions deposited with a verified identity, in basis points
+    pub verified_lp_boost_bps: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct InsuranceConf {
+    pub enabled: bool,
+    /// Identity allowed to approve and reject claims
+    pub admin: String,
+    pub swap_fee_bps: u128,
+    /// Share of each swap fee paid into the fund, in basis points of the fee
+    pub fund_share_bps: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProtocolFeeConf {
+    pub enabled: bool,
+    /// Identity allowed to collect the accrued fees
+    pub admin: String,
+    /// Share of what each swap fee leaves liquidity providers, in basis points
+    pub share_bps: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FaucetConf {
+    /// Blocks a user waits between two mints of the same token, 0 for none
+    pub cooldown_blocks: u64,
+    /// Most the faucet ever mints of each token, genesis pools included; uncapped when absent
+    #[serde(default)]
+    pub lifetime_caps: BTreeMap<String, u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AdminCouncilConf {
+    pub enabled: bool,
+    pub admins: Vec<String>,
+    /// Approvals needed before an admin operation executes
+    pub threshold: usize,
+    /// Largest amount minted without a proposal, unlimited when unset
+    pub mint_cap: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OracleGuardConf {
+    pub enabled: bool,
+    /// Contract whose `OraclePrice` blobs are trusted
+    pub oracle_contract: String,
+    /// Swaps with at least this input need an oracle price
+    pub min_amount_in: u128,
+    /// Largest deviation of the swap output from the oracle price, in basis points
+    pub max_deviation_bps: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BridgeConf {
+    pub enabled: bool,
+    /// Identity allowed to credit bridged deposits and process withdrawals
+    pub operator: String,
+    /// Largest amount of a token a user may have queued for withdrawal, unlimited when unset
+    pub withdrawal_limit: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RelayerConf {
+    /// Share of the output of relayed swaps kept as a fee, in basis points
+    pub fee_bps: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReconciliationConf {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Consecutive mismatches before the states are reported as diverged
+    pub mismatch_threshold: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RunbooksConf {
+    /// Age after which a stored identity proof the identity contract has no verification of
+    /// counts as stuck
+    pub stuck_proof_after_secs: u64,
+    /// How far back stuck proofs are looked for
+    pub stuck_proof_lookback_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StateHistoryConf {
+    pub enabled: bool,
+    /// Blocks between two full checkpoints, the ones in between only keeping what they changed
+    pub checkpoint_interval_blocks: u64,
+    /// Blocks queries can go back, older ones being dropped
+    pub retain_blocks: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct KeeperConf {
+    pub enabled: bool,
+    /// How often to look for due chunks
+    pub interval_secs: u64,
+    /// Zero balances and drained pools to pile up before submitting `Compact`, 0 never does
+    pub compact_min_entries: usize,
+    /// Hex compressed secp256k1 key the keeper signs compactions with, whose private key is
+    /// the `keeper_key` secret. Without it only the admin council compacts.
+    pub public_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NoirJobsConf {
+    /// Jobs proving at once, later ones queue
+    pub max_concurrent: usize,
+    /// Wall-clock time a job may take once it started, compiling included
+    pub timeout_secs: u64,
+    /// Address space of each tool a job runs, unlimited when unset
+    pub max_memory_bytes: Option<u64>,
+    /// CPU time of each tool a job runs, unlimited when unset
+    pub max_cpu_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DevnetConf {
+    /// Compose file declaring the node and wallet services
+    pub compose_file: PathBuf,
+    pub services: Vec<String>,
+    /// How long to wait for the node to produce blocks
+    pub ready_timeout_secs: u64,
+    /// Pools created on first start, skipped when they already exist
+    pub genesis_pools: Vec<GenesisPool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GenesisPool {
+    pub token_a: String,
+    pub token_b: String,
+    pub amount_a: u128,
+    pub amount_b: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StorageConf {
+    /// "sled", embedded in the data directory, or "postgres"
+    pub backend: String,
+    /// Separates the records of deployments sharing a database; each tenant gets its id
+    pub scope: String,
+    /// Submitted identity proofs stored longer ago are dropped, kept forever when unset
+    pub noir_proof_max_age_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SecretsConf {
+    /// "env", "file" or "vault"
+    pub provider: String,
+    /// Prepended to the upper-cased name of a secret to get the variable the env provider reads
+    pub env_prefix: String,
+    /// Directory the file provider reads one file per secret from
+    pub dir: Option<PathBuf>,
+    /// Address of the Vault server, e.g. "https://vault.internal:8200"
+    pub vault_addr: Option<String>,
+    /// KV v2 engine mount and path of the Vault secret holding one key per secret
+    pub vault_mount: String,
+    pub vault_path: String,
+    /// File holding the Vault token, e.g. written by a Vault agent; read from `vault_token_env` when unset
+    pub vault_token_path: Option<PathBuf>,
+    pub vault_token_env: String,
+    /// How often secrets are fetched again to pick up rotations, never when 0
+    pub refresh_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EncryptionConf {
+    pub enabled: bool,
+    /// Storage namespaces whose values are sealed
+    pub namespaces: Vec<String>,
+    /// Read records and files still in clear instead of refusing them, until `--rotate-keys` ran
+    pub accept_plaintext: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Directory relative to the data directory; files outside of it are never touched
+    pub path: PathBuf,
+    /// Files last modified longer ago than this are removed
+    pub max_age_secs: Option<u64>,
+    /// Oldest files are removed until the directory fits
+    pub max_size_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RouteBudget {
+    /// Larger request bodies are refused
+    pub max_body_bytes: Option<usize>,
+    /// Larger responses are replaced by an error
+    pub max_response_bytes: Option<usize>,
+    /// Slower responses are logged and counted
+    pub latency_slo_ms: Option<u64>,
+}
+
+/// One isolated market, e.g. a workshop team
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TenantConf {
+    /// Used as routing prefix (`/t/<id>`) and data sub-directory
+    pub id: String,
+    /// Prepended to the contract names of this tenant
+    pub contract_prefix: String,
+    pub buffer_blocks: Option<u32>,
+    pub max_txs_per_proof: Option<usize>,
 }
 
 impl Conf {
@@ -31,7 +579,7 @@ impl Conf {
         for config_file in config_files {
             s = s.add_source(File::with_name(&config_file).required(false));
         }
-        let conf: Self = s
+        let conf = s
             .add_source(
                 Environment::with_prefix("hyle")
                     .separator("__")
@@ -40,7 +588,19 @@ impl Conf {
                     .try_parsing(true),
             )
             .build()?
-            .try_deserialize()?;
-        Ok(conf)
+            .try_deserialize::<serde_json::Value>()?;
+        // config cannot deserialize u128 fields itself, serde_json widens its integers to them
+        Ok(serde_json::from_value(conf)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_loads() {
+        let conf = Conf::new(vec![]).unwrap();
+        assert_eq!(conf.validation.max_amount, 1_000_000_000_000_000_000);
     }
 }
@@ -0,0 +1,129 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use client_sdk::contract_indexer::AppError;
+use serde::Serialize;
+
+use crate::conf::{CredentialPolicyConf, PolicyAction};
+use crate::identity_index::IdentityIndex;
+
+/// Credential proving the user's country is allowed, recorded by `VerifyIdentity`
+pub const COUNTRY: &str = "country";
+
+/// What a request must present, for the frontend to prompt for the missing proofs
+#[derive(Serialize, Debug)]
+pub struct Requirements {
+    pub action: PolicyAction,
+    pub amount: u128,
+    /// Whether the user holds every required credential
+    pub satisfied: bool,
+    pub requirements: Vec<Requirement>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Requirement {
+    pub credential: String,
+    /// Noir circuit proving the credential, when one is configured
+    pub circuit: Option<String>,
+    pub satisfied: bool,
+}
+
+/// Decides from `[credential_policy]` which credentials an action needs.
+///
+/// Every rule of the action whose `min_amount` the amount reaches adds its credentials, so
+/// larger trades ask for more.
+pub struct CredentialPolicy {
+    conf: CredentialPolicyConf,
+    index: Arc<IdentityIndex>,
+}
+
+impl CredentialPolicy {
+    pub fn new(conf: CredentialPolicyConf, index: Arc<IdentityIndex>) -> Self {
+        Self { conf, index }
+    }
+
+    /// Credentials an action of this amount needs, in rule order
+    fn required(&self, action: PolicyAction, amount: u128) -> Vec<String> {
+        let mut required: Vec<String> = vec![];
+        let rules = self.conf.rules.iter().filter(|rule| rule.action == action);
+        for rule in rules.filter(|rule| rule.min_amount.is_none_or(|min| amount >= min)) {
+            for credential in &rule.credentials {
+                if !required.contains(credential) {
+                    required.push(credential.clone());
+                }
+            }
+        }
+        required
+    }
+
+    /// Requirements of an action for a user, reading the identity index only when some apply
+    pub async fn requirements(&self, user: &str, action: PolicyAction, amount: u128) -> Result<Requirements, AppError> {
+        let required = match self.conf.enabled {
+            true => self.required(action, amount),
+            false => vec![],
+        };
+        let held = match required.is_empty() {
+            true => BTreeSet::new(),
+            false => {
+                let allowed = self.index.allows(user).await.ok_or_else(|| {
+                    tracing::warn!("⚠️ Credentials of {} unavailable: identity index not loaded", user);
+                    AppError(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        anyhow::anyhow!("credentials_unavailable: identity state could not be read"),
+                    )
+                })?;
+                held_credentials(allowed)
+            }
+        };
+
+        let requirements: Vec<Requirement> = required
+            .into_iter()
+            .map(|credential| Requirement {
+                circuit: self.conf.circuits.get(&credential).cloned(),
+                satisfied: held.contains(credential.as_str()),
+                credential,
+            })
+            .collect();
+        Ok(Requirements {
+            action,
+            amount,
+            satisfied: requirements.iter().all(|r| r.satisfied),
+            requirements,
+        })
+    }
+
+    /// Refuse an action whose required credentials the user does not hold
+    pub async fn check(&self, user: &str, action: PolicyAction, amount: u128) -> Result<(), AppError> {
+        if !self.conf.enabled {
+            return Ok(());
+        }
+        let requirements = self.requirements(user, action, amount).await?;
+        if requirements.satisfied {
+            return Ok(());
+        }
+        let missing: Vec<&str> = requirements
+            .requirements
+            .iter()
+            .filter(|r| !r.satisfied)
+            .map(|r| r.credential.as_str())
+            .collect();
+        Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!(
+                "credentials_required: {} lacks {} for this amount, see /api/identity/requirements",
+                user,
+                missing.join(", ")
+            ),
+        ))
+    }
+}
+
+/// Credentials the identity contract records for a user, from whether it allows them
+fn held_credentials(allowed: bool) -> BTreeSet<&'static str> {
+    let mut held = BTreeSet::new();
+    if allowed {
+        held.insert(COUNTRY);
+    }
+    held
+}
@@ -0,0 +1,129 @@
+//! Cycle accounting of contract1 actions, only compiled with the `cycle-accounting` feature.
+//!
+//! The AutoProver does not report what a proof cost, so transactions submitted through the API
+//! are re-executed in the risc0 executor when their `AutoProverEvent` arrives, from the state the
+//! AutoProver proved them against. Executing takes a fraction of the proving time and yields the
+//! same cycle counts.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use contract1::{AmmAction, Contract1};
+use opentelemetry::{metrics::Histogram, KeyValue};
+use risc0_zkvm::{default_executor, ExecutorEnv, SessionInfo};
+use sdk::{BlobIndex, BlobTransaction, BlockHeight, Calldata, ContractName, TxContext, TxHash};
+
+use crate::tx_status::{ActionCycles, TxStatusStore};
+
+/// Submitted transactions kept for measurement, beyond which new ones are not measured
+const MAX_PENDING: usize = 1_000;
+
+struct Metrics {
+    user_cycles: Histogram<u64>,
+    total_cycles: Histogram<u64>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let meter = opentelemetry::global::meter("hyli_defi_app");
+    Metrics {
+        user_cycles: meter.u64_histogram("contract_user_cycles").build(),
+        total_cycles: meter.u64_histogram("contract_total_cycles").build(),
+    }
+});
+
+static SUBMITTED: LazyLock<Mutex<HashMap<TxHash, BlobTransaction>>> = LazyLock::new(Default::default);
+
+/// Remember a submitted transaction until the AutoProver reports on it
+pub fn submitted(tx_hash: &TxHash, tx: BlobTransaction) {
+    let mut submitted = SUBMITTED.lock().unwrap_or_else(|e| e.into_inner());
+    if submitted.len() < MAX_PENDING {
+        submitted.insert(tx_hash.clone(), tx);
+    }
+}
+
+/// Measure a transaction the AutoProver reported on, from the state it was proven against.
+///
+/// Runs in the background; the outcome lands in the metrics and the transaction record.
+pub fn measure(tx_hash: &TxHash, state: Option<Contract1>, contract1_cn: &ContractName, tx_status: Arc<TxStatusStore>) {
+    let tx = SUBMITTED.lock().unwrap_or_else(|e| e.into_inner()).remove(tx_hash);
+    let (Some(tx), Some(state)) = (tx, state) else {
+        return;
+    };
+    let tx_hash = tx_hash.clone();
+    let contract1_cn = contract1_cn.clone();
+    tokio::spawn(async move {
+        let action = action_name(&tx, &contract1_cn);
+        let hash = tx_hash.clone();
+        let session = tokio::task::spawn_blocking(move || execute(&hash, &tx, &contract1_cn, &state)).await;
+        let session = match session {
+            Ok(Ok(session)) => session,
+            Ok(Err(e)) => {
+                tracing::warn!("⚠️ Could not execute {} to count its cycles: {:#}", tx_hash, e);
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("⚠️ Cycle accounting of {} panicked: {}", tx_hash, e);
+                return;
+            }
+        };
+
+        let cycles = ActionCycles {
+            action,
+            user_cycles: session.segments.iter().map(|segment| segment.cycles as u64).sum(),
+            total_cycles: session.cycles(),
+            segments: session.segments.len(),
+        };
+        let labels = [KeyValue::new("action", cycles.action.clone())];
+        METRICS.user_cycles.record(cycles.user_cycles, &labels);
+        METRICS.total_cycles.record(cycles.total_cycles, &labels);
+        tracing::info!(
+            "⏱️ {} in {} took {} cycles, {} proven over {} segments",
+            cycles.action,
+            tx_hash,
+            cycles.user_cycles,
+            cycles.total_cycles,
+            cycles.segments
+        );
+        tx_status.record_cycles(&tx_hash, cycles).await;
+    });
+}
+
+fn action_name(tx: &BlobTransaction, contract1_cn: &ContractName) -> String {
+    let names: Vec<&str> = tx
+        .blobs
+        .iter()
+        .filter(|blob| &blob.contract_name == contract1_cn)
+        .map(|blob| borsh::from_slice::<AmmAction>(&blob.data.0).map_or("unknown", |action| action.name()))
+        .collect();
+    names.join("+")
+}
+
+/// Run the contract1 guest on the transaction's blobs, with the input the AutoProver gives it
+fn execute(tx_hash: &TxHash, tx: &BlobTransaction, contract1_cn: &ContractName, state: &Contract1) -> Result<SessionInfo> {
+    let calldata: Vec<Calldata> = tx
+        .blobs
+        .iter()
+        .enumerate()
+        .filter(|(_, blob)| &blob.contract_name == contract1_cn)
+        .map(|(index, _)| Calldata {
+            tx_hash: tx_hash.clone(),
+            identity: tx.identity.clone(),
+            blobs: tx.blobs.clone().into(),
+            tx_blob_count: tx.blobs.len(),
+            index: BlobIndex(index),
+            tx_ctx: Some(TxContext {
+                block_height: BlockHeight(state.block_height() + 1),
+                ..Default::default()
+            }),
+            private_input: vec![],
+        })
+        .collect();
+
+    let commitment_metadata = borsh::to_vec(state)?;
+    let input = borsh::to_vec(&(commitment_metadata, calldata))?;
+    let env = ExecutorEnv::builder().write(&input.len())?.write_slice(&input).build()?;
+    default_executor().execute(env, contracts::CONTRACT1_ELF)
+}
@@ -0,0 +1,424 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, Mutex};
+
+use crate::conf::{DeliveryConf, MessageTemplate};
+use crate::notifications::{Notification, NotificationEvent};
+use crate::storage::{Storage, Write};
+
+/// Storage namespace of delivery settings, keyed by user
+const NAMESPACE: &str = "notification_delivery";
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_EMAIL_LEN: usize = 254;
+const RATE_WINDOW_SECS: i64 = 3600;
+
+/// Where a user wants their notifications delivered besides the inbox
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeliverySettings {
+    pub email: Option<String>,
+    pub telegram: Option<TelegramTarget>,
+    /// Kinds delivered, every kind when empty
+    #[serde(default)]
+    pub kinds: Vec<String>,
+}
+
+/// A bot the user created, and the chat it writes to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramTarget {
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+impl DeliverySettings {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(email) = &self.email {
+            let well_formed = email.len() <= MAX_EMAIL_LEN
+                && email
+                    .split_once('@')
+                    .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+                && !email
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == '<' || c == '>');
+            if !well_formed {
+                bail!("Invalid email address '{}'", email);
+            }
+        }
+        if let Some(telegram) = &self.telegram {
+            let token_ok = telegram
+                .bot_token
+                .split_once(':')
+                .is_some_and(|(id, secret)| {
+                    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) && !secret.is_empty()
+                })
+                && telegram
+                    .bot_token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ":_-".contains(c));
+            if !token_ok {
+                bail!("Invalid Telegram bot token");
+            }
+            if telegram.chat_id.is_empty() || telegram.chat_id.chars().any(|c| c.is_whitespace()) {
+                bail!("Invalid Telegram chat id");
+            }
+        }
+        if let Some(kind) = self
+            .kinds
+            .iter()
+            .find(|kind| !NotificationEvent::KINDS.contains(&kind.as_str()))
+        {
+            bail!(
+                "Unknown notification kind '{}', expected one of {}",
+                kind,
+                NotificationEvent::KINDS.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn wants(&self, kind: &str) -> bool {
+        self.kinds.is_empty() || self.kinds.iter().any(|wanted| wanted == kind)
+    }
+}
+
+/// A notification rendered with its kind's template
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends rendered notifications over one channel
+pub trait DeliveryAdapter: Send + Sync {
+    /// Channel name, which rate limits and logs go by
+    fn channel(&self) -> &'static str;
+
+    /// Whether the user set this channel up
+    fn configured(&self, settings: &DeliverySettings) -> bool;
+
+    fn deliver<'a>(
+        &'a self,
+        settings: &'a DeliverySettings,
+        message: &'a Message,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Mails through a plain SMTP relay, which handles TLS and authentication upstream
+pub struct SmtpAdapter {
+    relay: String,
+    from: String,
+}
+
+impl DeliveryAdapter for SmtpAdapter {
+    fn channel(&self) -> &'static str {
+        "email"
+    }
+
+    fn configured(&self, settings: &DeliverySettings) -> bool {
+        settings.email.is_some()
+    }
+
+    fn deliver<'a>(
+        &'a self,
+        settings: &'a DeliverySettings,
+        message: &'a Message,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let to = settings.email.as_deref().context("No email address set")?;
+            tokio::time::timeout(SEND_TIMEOUT, self.send(to, message))
+                .await
+                .context("SMTP relay timed out")?
+        })
+    }
+}
+
+impl SmtpAdapter {
+    async fn send(&self, to: &str, message: &Message) -> Result<()> {
+        let stream = TcpStream::connect(&self.relay)
+            .await
+            .with_context(|| format!("connecting to SMTP relay {}", self.relay))?;
+        let (read, mut write) = stream.into_split();
+        let mut replies = BufReader::new(read).lines();
+
+        expect_reply(&mut replies, 220).await?;
+        for (command, code) in [
+            ("EHLO hyli-defi".to_string(), 250),
+            (format!("MAIL FROM:<{}>", self.from), 250),
+            (format!("RCPT TO:<{}>", to), 250),
+            ("DATA".to_string(), 354),
+        ] {
+            write
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await?;
+            expect_reply(&mut replies, code).await?;
+        }
+
+        // Header values never span lines, and body lines starting with a dot are escaped
+        let subject = message.subject.replace(['\r', '\n'], " ");
+        let mut data = format!(
+            "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from, to, subject
+        );
+        for line in message.body.lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        write.write_all(data.as_bytes()).await?;
+        expect_reply(&mut replies, 250).await?;
+        write.write_all(b"QUIT\r\n").await?;
+        Ok(())
+    }
+}
+
+/// Reads a possibly multi-line SMTP reply, failing unless it has the expected code
+async fn expect_reply(
+    replies: &mut tokio::io::Lines<BufReader<tokio::net::tcp::OwnedReadHalf>>,
+    code: u16,
+) -> Result<()> {
+    loop {
+        let line = replies
+            .next_line()
+            .await?
+            .context("SMTP relay closed the connection")?;
+        // "250-..." continues the reply, "250 ..." ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if !line.starts_with(&code.to_string()) {
+            bail!("SMTP relay answered '{}', expected {}", line, code);
+        }
+        return Ok(());
+    }
+}
+
+/// Posts through the Telegram bot API with the user's own bot
+pub struct TelegramAdapter {
+    api: String,
+    client: reqwest::Client,
+}
+
+impl DeliveryAdapter for TelegramAdapter {
+    fn channel(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn configured(&self, settings: &DeliverySettings) -> bool {
+        settings.telegram.is_some()
+    }
+
+    fn deliver<'a>(
+        &'a self,
+        settings: &'a DeliverySettings,
+        message: &'a Message,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let telegram = settings.telegram.as_ref().context("No Telegram chat set")?;
+            self.client
+                .post(format!(
+                    "{}/bot{}/sendMessage",
+                    self.api, telegram.bot_token
+                ))
+                .json(&serde_json::json!({
+                    "chat_id": telegram.chat_id,
+                    "text": format!("{}\n\n{}", message.subject, message.body),
+                }))
+                .send()
+                .await
+                // The URL holds the bot token, keep it out of the logs
+                .map_err(|e| anyhow::anyhow!("Telegram unreachable: {}", e.without_url()))?
+                .error_for_status()
+                .map_err(|e| {
+                    anyhow::anyhow!("Telegram refused the message: {}", e.without_url())
+                })?;
+            Ok(())
+        })
+    }
+}
+
+/// Delivers new notifications to the channels each user set up, within `max_per_hour` per user
+/// and channel. Notifications over the limit, or that could not be sent, stay in the inbox.
+pub struct Delivery {
+    storage: Arc<dyn Storage>,
+    conf: DeliveryConf,
+    adapters: Vec<Box<dyn DeliveryAdapter>>,
+    /// Send times within the rate window, by user and channel
+    sent: Mutex<HashMap<(String, &'static str), VecDeque<i64>>>,
+}
+
+impl Delivery {
+    pub fn new(storage: Arc<dyn Storage>, conf: DeliveryConf) -> Result<Self> {
+        let mut adapters: Vec<Box<dyn DeliveryAdapter>> = vec![];
+        if let Some(relay) = &conf.smtp_relay {
+            adapters.push(Box::new(SmtpAdapter {
+                relay: relay.clone(),
+                from: conf.smtp_from.clone(),
+            }));
+        }
+        adapters.push(Box::new(TelegramAdapter {
+            api: conf.telegram_api.trim_end_matches('/').to_string(),
+            client: reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?,
+        }));
+        Ok(Self {
+            storage,
+            conf,
+            adapters,
+            sent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Channels the server can deliver on
+    pub fn channels(&self) -> Vec<&'static str> {
+        self.adapters
+            .iter()
+            .map(|adapter| adapter.channel())
+            .collect()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.conf.enabled
+    }
+
+    pub async fn settings(&self, user: &str) -> Result<DeliverySettings> {
+        Ok(match self.storage.get(NAMESPACE, user.as_bytes()).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .context("Could not parse stored delivery settings")?,
+            None => DeliverySettings::default(),
+        })
+    }
+
+    /// Save validated settings, `email` being refused without an SMTP relay to send it through
+    pub async fn set_settings(&self, user: &str, settings: &DeliverySettings) -> Result<()> {
+        settings.validate()?;
+        if settings.email.is_some() && self.conf.smtp_relay.is_none() {
+            bail!("Email delivery is not available on this server");
+        }
+        self.storage
+            .apply(vec![Write::put(
+                NAMESPACE,
+                user,
+                serde_json::to_vec(settings)?,
+            )])
+            .await
+            .context("Failed to persist delivery settings")
+    }
+
+    /// Deliver one notification on every channel its user set up
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        let settings = self.settings(&notification.user).await?;
+        let kind = notification.event.kind();
+        if !settings.wants(kind) {
+            return Ok(());
+        }
+        let fallback = MessageTemplate {
+            subject: kind.replace('_', " "),
+            body: "{kind}".to_string(),
+        };
+        let template = self.conf.templates.get(kind).unwrap_or(&fallback);
+        let fields = serde_json::to_value(notification)?;
+        let message = Message {
+            subject: render(&template.subject, &fields),
+            body: render(&template.body, &fields),
+        };
+
+        for adapter in self
+            .adapters
+            .iter()
+            .filter(|adapter| adapter.configured(&settings))
+        {
+            if !self.allow(&notification.user, adapter.channel()).await {
+                tracing::info!(
+                    "🔕 {} reached {} {} messages this hour, notification {} stays in the inbox",
+                    notification.user,
+                    self.conf.max_per_hour,
+                    adapter.channel(),
+                    notification.id
+                );
+                continue;
+            }
+            if let Err(e) = adapter.deliver(&settings, &message).await {
+                tracing::warn!(
+                    "⚠️ Could not deliver notification {} of {} by {}: {:#}",
+                    notification.id,
+                    notification.user,
+                    adapter.channel(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a message against the user's hourly limit of a channel, unless it is reached
+    async fn allow(&self, user: &str, channel: &'static str) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        let mut sent = self.sent.lock().await;
+        let times = sent.entry((user.to_string(), channel)).or_default();
+        while times
+            .front()
+            .is_some_and(|at| *at <= now - RATE_WINDOW_SECS)
+        {
+            times.pop_front();
+        }
+        if times.len() >= self.conf.max_per_hour {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+/// Deliver every notification published from now on
+pub async fn run(delivery: Arc<Delivery>, mut notifications: broadcast::Receiver<Notification>) {
+    loop {
+        let notification = match notifications.recv().await {
+            Ok(notification) => notification,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    "⚠️ Delivery skipped {} notifications, left in their inboxes",
+                    skipped
+                );
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if let Err(e) = delivery.deliver(&notification).await {
+            tracing::warn!(
+                "⚠️ Could not deliver notification {}: {:#}",
+                notification.id,
+                e
+            );
+        }
+    }
+}
+
+/// Replace each `{field}` of a template by that field of the notification, empty when unset
+fn render(template: &str, fields: &serde_json::Value) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let name = &rest[start + 1..start + end];
+        match fields.get(name) {
+            Some(serde_json::Value::String(value)) => rendered.push_str(value),
+            Some(serde_json::Value::Null) => {}
+            Some(value) => rendered.push_str(&value.to_string()),
+            // Not a field, kept as written
+            None => rendered.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
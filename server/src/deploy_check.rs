@@ -0,0 +1,117 @@
+//! `deploy-check` subcommand: compare what this build would register with what the network has.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Args as ClapArgs;
+use client_sdk::rest_client::IndexerApiHttpClient;
+use sdk::{ContractName, ZkContract};
+
+use crate::init;
+use crate::tenant::Tenant;
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct DeployCheckArgs {
+    /// Verification key of the compiled Noir identity circuit
+    #[arg(long, value_name = "PATH", default_value = "noir-contracts/zkpassport_identity/target/vk")]
+    pub noir_vk: PathBuf,
+}
+
+/// One registered property, locally and on the network
+struct Line {
+    label: &'static str,
+    local: String,
+    registered: Option<String>,
+    /// Whether a difference is expected on a live contract, rather than blocking a deployment
+    may_drift: bool,
+}
+
+/// Print how the contracts of every tenant differ from their registration, without registering.
+///
+/// Fails when a registered contract runs another program, which a deployment could not fix.
+pub async fn run(args: &DeployCheckArgs, tenants: &[Tenant], indexer: &IndexerApiHttpClient) -> Result<()> {
+    let program_id = contract1::client::tx_executor_handler::metadata::PROGRAM_ID;
+    let vk = std::fs::read(&args.noir_vk).ok();
+    if vk.is_none() {
+        println!("No Noir verification key at {:?}, identity contracts are not compared\n", args.noir_vk);
+    }
+
+    let mut blocking = 0;
+    for tenant in tenants {
+        println!("Tenant {}", tenant.label());
+
+        let registered = indexer.get_indexer_contract(&tenant.contract1_cn).await.ok();
+        let initial_state = tenant.initial_state()?.commit();
+        blocking += print_contract(
+            &tenant.contract1_cn,
+            registered.is_some(),
+            &[
+                Line {
+                    label: "program id",
+                    local: hex::encode(program_id),
+                    registered: registered.as_ref().map(|c| hex::encode(&c.program_id)),
+                    may_drift: false,
+                },
+                Line {
+                    label: "state (sha256)",
+                    local: init::commitment_digest(&initial_state.0),
+                    registered: registered.as_ref().map(|c| init::commitment_digest(&c.state_commitment)),
+                    may_drift: true,
+                },
+            ],
+        );
+
+        // Noir contracts are registered with their verification key as program id
+        if let Some(vk) = &vk {
+            let registered = indexer.get_indexer_contract(&tenant.identity_cn).await.ok();
+            blocking += print_contract(
+                &tenant.identity_cn,
+                registered.is_some(),
+                &[Line {
+                    label: "verification key (sha256)",
+                    local: init::commitment_digest(vk),
+                    registered: registered.as_ref().map(|c| init::commitment_digest(&c.program_id)),
+                    may_drift: false,
+                }],
+            );
+        }
+        println!();
+    }
+
+    if blocking > 0 {
+        bail!("{} registered contracts run another program than this build", blocking);
+    }
+    println!("Nothing blocks a deployment of this build");
+    Ok(())
+}
+
+/// Print the diff of a contract, returning 1 when it blocks a deployment
+fn print_contract(name: &ContractName, registered: bool, lines: &[Line]) -> usize {
+    if !registered {
+        println!("  + {}: not registered, would be registered with", name);
+        for line in lines {
+            println!("      {:<26} {}", line.label, line.local);
+        }
+        return 0;
+    }
+
+    let mut blocking = false;
+    println!("    {}:", name);
+    for line in lines {
+        let registered = line.registered.as_deref().unwrap_or_default();
+        if registered == line.local {
+            println!("      {:<26} {}", line.label, line.local);
+            continue;
+        }
+        let note = match line.may_drift {
+            true => " (settled transactions also change it)",
+            false => {
+                blocking = true;
+                ""
+            }
+        };
+        println!("    - {:<26} {} registered{}", line.label, registered, note);
+        println!("    + {:<26} {} local", line.label, line.local);
+    }
+    blocking as usize
+}
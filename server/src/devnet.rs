@@ -0,0 +1,82 @@
+//! `devnet` subcommand: bring up a local node and wallet before starting the app.
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use clap::Args as ClapArgs;
+use client_sdk::rest_client::{NodeApiClient, NodeApiHttpClient};
+use tokio::process::{Child, Command};
+
+use crate::conf::DevnetConf;
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct DevnetArgs {
+    /// Run this `hyle` binary instead of the docker compose services (no wallet server)
+    #[arg(long, value_name = "PATH")]
+    pub hyle_bin: Option<PathBuf>,
+
+    /// Do not create the genesis pools
+    #[arg(long)]
+    pub no_seed: bool,
+}
+
+/// Local node launched by the server; a spawned binary is stopped with the server
+pub struct Devnet {
+    _node: Option<Child>,
+}
+
+/// Start the node (and wallet server when using docker) and wait until it produces blocks
+pub async fn launch(args: &DevnetArgs, conf: &DevnetConf, node: &NodeApiHttpClient) -> Result<Devnet> {
+    let child = match &args.hyle_bin {
+        Some(hyle_bin) => {
+            tracing::info!("🛠️ Starting local node from {:?}", hyle_bin);
+            let child = Command::new(hyle_bin)
+                .args(["--run-indexer", "true"])
+                .env("RISC0_DEV_MODE", "true")
+                .env("SP1_PROVER", "mock")
+                .stdout(Stdio::null())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start {:?}", hyle_bin))?;
+            Some(child)
+        }
+        None => {
+            tracing::info!("🐳 Starting {:?} from {:?}", conf.services, conf.compose_file);
+            let status = Command::new("docker")
+                .arg("compose")
+                .arg("-f")
+                .arg(&conf.compose_file)
+                .args(["up", "-d"])
+                .args(&conf.services)
+                .status()
+                .await
+                .context("Failed to run docker compose, is docker installed?")?;
+            if !status.success() {
+                bail!("docker compose exited with {}", status);
+            }
+            None
+        }
+    };
+
+    wait_ready(node, Duration::from_secs(conf.ready_timeout_secs)).await?;
+    Ok(Devnet { _node: child })
+}
+
+async fn wait_ready(node: &NodeApiHttpClient, ready_timeout: Duration) -> Result<()> {
+    tokio::time::timeout(ready_timeout, async {
+        loop {
+            match node.get_block_height().await {
+                Ok(height) if height.0 > 0 => {
+                    tracing::info!("✅ Devnet node ready at block {}", height.0);
+                    return;
+                }
+                _ => {
+                    tracing::info!("⏰ Waiting for the devnet node to produce blocks");
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+            }
+        }
+    })
+    .await
+    .context("Devnet node did not become ready in time")
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use aes_gcm::{aead::Aead, aead::Payload, Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use futures::future::BoxFuture;
+
+use crate::conf::{Conf, EncryptionConf};
+use crate::secrets::{self, Secrets};
+use crate::storage::{self, Entries, Storage, Write};
+use crate::webauthn::WEBAUTHN_CREDENTIALS;
+
+/// Prefix of sealed values, telling them apart from plaintext written before encryption
+const MAGIC: &[u8; 4] = b"HDE1";
+/// Records re-sealed per write batch by `EncryptedStorage::rotate`
+const ROTATION_BATCH: usize = 500;
+
+/// A value sealed with its own data key, itself wrapped by a key of the keyring
+#[derive(BorshSerialize, BorshDeserialize)]
+struct Envelope {
+    /// Keyring key the data key is wrapped with
+    key_id: String,
+    wrap_nonce: [u8; 12],
+    /// Data key, encrypted and authenticated by the keyring key
+    wrapped_key: Vec<u8>,
+    nonce: [u8; 12],
+    /// Value, encrypted and authenticated by the data key
+    ciphertext: Vec<u8>,
+}
+
+/// Keys wrapping the data keys of sealed values, read from the `encryption_keys` secret.
+///
+/// The secret holds `id:base64-key` pairs separated by commas, each key being 32 bytes. The
+/// first seals new values; the others only open values sealed before a rotation, until
+/// `--rotate-keys` re-wrapped them all. A new value of the secret replaces the keys in place.
+pub struct Keyring {
+    keys: RwLock<Keys>,
+}
+
+struct Keys {
+    current: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Keys {
+    fn parse(value: &str) -> Result<Self> {
+        let mut current = None;
+        let mut keys = HashMap::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (id, key) = entry.split_once(':').context("Encryption keys must be given as id:base64-key")?;
+            let key = STANDARD
+                .decode(key)
+                .with_context(|| format!("Encryption key {} is not base64", id))?;
+            if key.len() != 32 {
+                bail!("Encryption key {} must be 32 bytes, not {}", id, key.len());
+            }
+            if keys.insert(id.to_string(), Aes256Gcm::new_from_slice(&key)?).is_some() {
+                bail!("Encryption key {} is given twice", id);
+            }
+            current.get_or_insert_with(|| id.to_string());
+        }
+        Ok(Self {
+            current: current.context("No encryption key given")?,
+            keys,
+        })
+    }
+
+    /// Wrap a data key with the current key, returning its id, nonce and the wrapped key
+    fn wrap_key(&self, data_key: &[u8]) -> Result<(String, [u8; 12], Vec<u8>)> {
+        let wrap_nonce: [u8; 12] = rand::random();
+        let wrapped = self.keys[&self.current]
+            .encrypt(
+                Nonce::from_slice(&wrap_nonce),
+                Payload {
+                    msg: data_key,
+                    aad: self.current.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Could not wrap data key"))?;
+        Ok((self.current.clone(), wrap_nonce, wrapped))
+    }
+
+    fn unwrap_key(&self, envelope: &Envelope) -> Result<Vec<u8>> {
+        let key = self
+            .keys
+            .get(&envelope.key_id)
+            .with_context(|| format!("Encryption key {} is not in the keyring", envelope.key_id))?;
+        key.decrypt(
+            Nonce::from_slice(&envelope.wrap_nonce),
+            Payload {
+                msg: &envelope.wrapped_key,
+                aad: envelope.key_id.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Integrity check failed: the data key was altered"))
+    }
+}
+
+impl Keyring {
+    /// Keyring of `[encryption]`, `None` when encryption is disabled
+    pub async fn from_conf(conf: &EncryptionConf, secrets: &Secrets) -> Result<Option<Arc<Self>>> {
+        if !conf.enabled {
+            return Ok(None);
+        }
+        let value = secrets.require(secrets::ENCRYPTION_KEYS).await?;
+        let initial = value.borrow().clone();
+        let keyring = Arc::new(Keyring {
+            keys: RwLock::new(Keys::parse(&initial)?),
+        });
+        let reloaded = keyring.clone();
+        secrets::on_rotation(secrets::ENCRYPTION_KEYS, value, move |value| {
+            Box::pin(futures::future::ready(reloaded.reload(&value)))
+        });
+        Ok(Some(keyring))
+    }
+
+    /// Replace the keys; a value that does not parse leaves them as they were
+    fn reload(&self, value: &str) -> Result<()> {
+        let keys = Keys::parse(value)?;
+        tracing::info!("🔑 Sealing new values under encryption key {}", keys.current);
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Key new values are sealed under
+    pub fn current(&self) -> String {
+        self.keys.read().unwrap().current.clone()
+    }
+
+    /// Seal a value under a fresh data key; `aad` binds it to where it is stored
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let data_key: [u8; 32] = rand::random();
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = Aes256Gcm::new_from_slice(&data_key)?
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| anyhow!("Could not encrypt value"))?;
+        let (key_id, wrap_nonce, wrapped_key) = self.keys.read().unwrap().wrap_key(&data_key)?;
+        let envelope = Envelope {
+            key_id,
+            wrap_nonce,
+            wrapped_key,
+            nonce,
+            ciphertext,
+        };
+        Ok([MAGIC.as_slice(), &borsh::to_vec(&envelope)?].concat())
+    }
+
+    /// Open a sealed value, failing if it was altered or moved from `aad`
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        let envelope = Self::envelope(sealed)?;
+        let data_key = self.keys.read().unwrap().unwrap_key(&envelope)?;
+        Aes256Gcm::new_from_slice(&data_key)?
+            .decrypt(
+                Nonce::from_slice(&envelope.nonce),
+                Payload {
+                    msg: &envelope.ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("Integrity check failed: the value was altered or moved"))
+    }
+
+    /// Wrap the data key of a sealed value with the current key, the value staying as sealed
+    fn rewrap(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let mut envelope = Self::envelope(sealed)?;
+        let keys = self.keys.read().unwrap();
+        let data_key = keys.unwrap_key(&envelope)?;
+        (envelope.key_id, envelope.wrap_nonce, envelope.wrapped_key) = keys.wrap_key(&data_key)?;
+        Ok([MAGIC.as_slice(), &borsh::to_vec(&envelope)?].concat())
+    }
+
+    pub fn is_sealed(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Whether a sealed value is wrapped with another key than the current one
+    fn is_stale(&self, sealed: &[u8]) -> bool {
+        Self::envelope(sealed).map_or(true, |envelope| envelope.key_id != self.keys.read().unwrap().current)
+    }
+
+    fn envelope(sealed: &[u8]) -> Result<Envelope> {
+        let body = sealed.strip_prefix(MAGIC).context("Value is not sealed")?;
+        borsh::from_slice(body).context("Sealed value is malformed")
+    }
+}
+
+/// Storage of a deployment, sealing the sensitive namespaces when a keyring is given
+pub async fn open_storage(
+    config: &Conf,
+    secrets: &Secrets,
+    keyring: Option<&Arc<Keyring>>,
+) -> Result<Arc<dyn Storage>> {
+    let storage = storage::open(&config.storage, &config.data_directory, secrets).await?;
+    Ok(match keyring {
+        Some(keyring) => Arc::new(EncryptedStorage::new(storage, keyring.clone(), &config.encryption)),
+        None => storage,
+    })
+}
+
+/// Read a file sealed as a whole, as passkey credentials are, `None` when there is none
+pub fn read_sealed_file(path: &Path, keyring: Option<&Keyring>, accept_plaintext: bool) -> Result<Option<Vec<u8>>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+    };
+    match keyring {
+        Some(keyring) if Keyring::is_sealed(&bytes) => {
+            Ok(Some(keyring.open(&file_aad(path), &bytes).with_context(|| format!("opening {:?}", path))?))
+        }
+        None if Keyring::is_sealed(&bytes) => bail!("{:?} is encrypted but encryption is disabled", path),
+        Some(_) if !accept_plaintext => bail!("{:?} is not encrypted, run --rotate-keys to encrypt it", path),
+        _ => Ok(Some(bytes)),
+    }
+}
+
+/// Write a file sealed as a whole when a keyring is given
+pub fn write_sealed_file(path: &Path, keyring: Option<&Keyring>, bytes: Vec<u8>) -> Result<()> {
+    let bytes = match keyring {
+        Some(keyring) => keyring.seal(&file_aad(path), &bytes)?,
+        None => bytes,
+    };
+    std::fs::write(path, bytes).with_context(|| format!("writing {:?}", path))
+}
+
+/// A file is sealed for its name, so it cannot be swapped with another sealed file
+fn file_aad(path: &Path) -> Vec<u8> {
+    path.file_name().unwrap_or_default().as_encoded_bytes().to_vec()
+}
+
+/// Seal what a deployment still keeps in clear, and re-wrap what was sealed with an older key
+pub async fn rotate(config: &Conf, secrets: &Secrets) -> Result<()> {
+    let keyring = Keyring::from_conf(&config.encryption, secrets)
+        .await?
+        .context("[encryption] is not enabled")?;
+    let storage = storage::open(&config.storage, &config.data_directory, secrets).await?;
+    let records = EncryptedStorage::new(storage, keyring.clone(), &config.encryption)
+        .rotate()
+        .await?;
+    let mut files = 0;
+    for path in [config.data_directory.join(WEBAUTHN_CREDENTIALS)] {
+        if let Some(bytes) = read_sealed_file(&path, Some(&keyring), true)? {
+            write_sealed_file(&path, Some(&keyring), bytes)?;
+            files += 1;
+        }
+    }
+    tracing::info!(
+        "🔑 Sealed {} records and {} files of {:?} under key {}",
+        records,
+        files,
+        config.data_directory,
+        keyring.current()
+    );
+    Ok(())
+}
+
+/// `<namespace>\0<key>`, what a stored value is sealed for
+fn aad(namespace: &str, key: &[u8]) -> Vec<u8> {
+    [namespace.as_bytes(), &[0], key].concat()
+}
+
+/// Storage sealing the values of sensitive namespaces before they reach the backend.
+///
+/// Keys stay in clear, so lookups and ranges work as before. Values found in clear are refused,
+/// unless `accept_plaintext` lets records written before encryption was enabled be read until
+/// `--rotate-keys` sealed them.
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    keyring: Arc<Keyring>,
+    namespaces: Vec<String>,
+    accept_plaintext: bool,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Arc<dyn Storage>, keyring: Arc<Keyring>, conf: &EncryptionConf) -> Self {
+        Self {
+            inner,
+            keyring,
+            namespaces: conf.namespaces.clone(),
+            accept_plaintext: conf.accept_plaintext,
+        }
+    }
+
+    fn sealed(&self, namespace: &str) -> bool {
+        self.namespaces.iter().any(|sealed| sealed == namespace)
+    }
+
+    fn open(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<Vec<u8>> {
+        if Keyring::is_sealed(&value) {
+            return self
+                .keyring
+                .open(&aad(namespace, key), &value)
+                .with_context(|| format!("opening a {} record", namespace));
+        }
+        if !self.accept_plaintext {
+            bail!("A {} record is not encrypted, run --rotate-keys to encrypt it", namespace);
+        }
+        Ok(value)
+    }
+
+    /// Seal the plaintext records of sensitive namespaces, and re-wrap those sealed with another
+    /// key than the current one, returning how many were written
+    pub async fn rotate(&self) -> Result<usize> {
+        let mut rotated = 0;
+        for namespace in &self.namespaces {
+            let mut writes = vec![];
+            for (key, value) in self.inner.range(namespace, &[], None).await? {
+                let resealed = if !Keyring::is_sealed(&value) {
+                    self.keyring.seal(&aad(namespace, &key), &value)?
+                } else if self.keyring.is_stale(&value) {
+                    self.keyring.rewrap(&value)?
+                } else {
+                    continue;
+                };
+                writes.push(Write::put(namespace, key, resealed));
+                if writes.len() == ROTATION_BATCH {
+                    rotated += writes.len();
+                    self.inner.apply(std::mem::take(&mut writes)).await?;
+                }
+            }
+            rotated += writes.len();
+            self.inner.apply(writes).await?;
+        }
+        Ok(rotated)
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let value = self.inner.get(namespace, key).await?;
+            match value {
+                Some(value) if self.sealed(namespace) => Ok(Some(self.open(namespace, key, value)?)),
+                value => Ok(value),
+            }
+        })
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        from: &'a [u8],
+        to: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<Entries>> {
+        Box::pin(async move {
+            let entries = self.inner.range(namespace, from, to).await?;
+            if !self.sealed(namespace) {
+                return Ok(entries);
+            }
+            entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = self.open(namespace, &key, value)?;
+                    Ok((key, value))
+                })
+                .collect()
+        })
+    }
+
+    fn apply(&self, writes: Vec<Write>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let writes = writes
+                .into_iter()
+                .map(|mut write| {
+                    if let Some(value) = write.value.as_ref().filter(|_| self.sealed(&write.namespace)) {
+                        write.value = Some(self.keyring.seal(&aad(&write.namespace, &write.key), value)?);
+                    }
+                    Ok(write)
+                })
+                .collect::<Result<_>>()?;
+            self.inner.apply(writes).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring_with(value: &str) -> Keyring {
+        Keyring {
+            keys: RwLock::new(Keys::parse(value).unwrap()),
+        }
+    }
+
+    fn key(id: &str, byte: u8) -> String {
+        format!("{}:{}", id, STANDARD.encode([byte; 32]))
+    }
+
+    #[test]
+    fn test_sealed_values_open_to_the_plaintext() {
+        let keyring = keyring_with(&key("k1", 1));
+        let sealed = keyring.seal(b"profiles", b"alice").unwrap();
+        assert!(Keyring::is_sealed(&sealed));
+        assert!(!sealed.windows(5).any(|window| window == b"alice"));
+        assert_eq!(keyring.open(b"profiles", &sealed).unwrap(), b"alice");
+    }
+
+    #[test]
+    fn test_values_sealed_before_a_rotation_open_with_the_old_key() {
+        let keyring = keyring_with(&key("k1", 1));
+        let sealed = keyring.seal(b"profiles", b"alice").unwrap();
+
+        keyring.reload(&format!("{},{}", key("k2", 2), key("k1", 1))).unwrap();
+        assert_eq!(keyring.current(), "k2");
+        assert!(keyring.is_stale(&sealed));
+        assert_eq!(keyring.open(b"profiles", &sealed).unwrap(), b"alice");
+
+        // Once re-wrapped, the old key can be dropped
+        let rewrapped = keyring.rewrap(&sealed).unwrap();
+        assert!(!keyring.is_stale(&rewrapped));
+        keyring.reload(&key("k2", 2)).unwrap();
+        assert_eq!(keyring.open(b"profiles", &rewrapped).unwrap(), b"alice");
+        assert!(keyring.open(b"profiles", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_tampered_values_and_wrong_keys_are_refused() {
+        let keyring = keyring_with(&key("k1", 1));
+        let sealed = keyring.seal(b"profiles", b"alice").unwrap();
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(keyring.open(b"profiles", &tampered).is_err());
+        assert!(keyring.open(b"delivery", &sealed).is_err());
+        assert!(keyring_with(&key("k1", 3)).open(b"profiles", &sealed).is_err());
+        assert!(keyring_with(&key("k2", 1)).open(b"profiles", &sealed).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use client_sdk::rest_client::IndexerApiHttpClient;
+use contract1::{Contract1Action, FaucetAction};
+use sdk::{BlockHeight, ContractName, TxHash};
+use serde::Serialize;
+
+use crate::noir_verifier::describe_proof_blob;
+
+/// Decodes app-specific blobs of settled transactions into readable actions
+pub struct Explorer {
+    indexer: Arc<IndexerApiHttpClient>,
+    amm_cn: ContractName,
+    identity_cn: ContractName,
+}
+
+pub struct ExplorerCtx {
+    pub indexer: Arc<IndexerApiHttpClient>,
+    pub amm_cn: ContractName,
+    pub identity_cn: ContractName,
+}
+
+#[derive(Serialize)]
+pub struct BlockDetails {
+    pub height: u64,
+    pub hash: String,
+    pub timestamp: String,
+    /// Only transactions touching the AMM or identity contracts
+    pub transactions: Vec<TxDetails>,
+}
+
+#[derive(Serialize)]
+pub struct TxDetails {
+    pub tx_hash: String,
+    pub identity: Option<String>,
+    pub status: String,
+    pub actions: Vec<DecodedBlob>,
+}
+
+#[derive(Serialize)]
+pub struct DecodedBlob {
+    pub index: u32,
+    pub contract_name: String,
+    pub verified: bool,
+    pub description: String,
+}
+
+impl Explorer {
+    pub fn new(ctx: ExplorerCtx) -> Self {
+        Self {
+            indexer: ctx.indexer,
+            amm_cn: ctx.amm_cn,
+            identity_cn: ctx.identity_cn,
+        }
+    }
+
+    pub async fn block(&self, height: u64) -> Result<BlockDetails> {
+        let height = BlockHeight(height);
+        let block = self
+            .indexer
+            .get_block_by_height(&height)
+            .await
+            .with_context(|| format!("Block {} not found", height.0))?;
+        let txs = self
+            .indexer
+            .get_transactions_by_height(&height)
+            .await
+            .context("Failed to fetch block transactions")?;
+
+        let mut transactions = vec![];
+        for tx in txs {
+            let actions = self.decode_blobs(&tx.tx_hash).await?;
+            if actions.is_empty() {
+                continue;
+            }
+            transactions.push(TxDetails {
+                tx_hash: tx.tx_hash.to_string(),
+                identity: tx.identity,
+                status: format!("{:?}", tx.transaction_status),
+                actions,
+            });
+        }
+
+        Ok(BlockDetails {
+            height: height.0,
+            hash: block.hash.to_string(),
+            timestamp: block.timestamp.to_string(),
+            transactions,
+        })
+    }
+
+    pub async fn transaction(&self, tx_hash: &str) -> Result<TxDetails> {
+        let tx_hash = TxHash(tx_hash.to_string());
+        let tx = self
+            .indexer
+            .get_transaction_with_hash(&tx_hash)
+            .await
+            .with_context(|| format!("Transaction {} not found", tx_hash))?;
+        let actions = self.decode_blobs(&tx_hash).await?;
+
+        Ok(TxDetails {
+            tx_hash: tx_hash.to_string(),
+            identity: tx.identity,
+            status: format!("{:?}", tx.transaction_status),
+            actions,
+        })
+    }
+
+    /// Decode the blobs of a transaction that belong to this app's contracts
+    async fn decode_blobs(&self, tx_hash: &TxHash) -> Result<Vec<DecodedBlob>> {
+        let blobs = self
+            .indexer
+            .get_blobs_by_tx_hash(tx_hash)
+            .await
+            .with_context(|| format!("Failed to fetch blobs of {}", tx_hash))?;
+
+        Ok(blobs
+            .into_iter()
+            .filter_map(|blob| {
+                let description = if blob.contract_name == self.amm_cn.0 {
+                    describe_amm_blob(&blob.data)
+                } else if blob.contract_name == self.identity_cn.0 {
+                    describe_proof_blob(&blob.data)
+                } else {
+                    return None;
+                };
+                Some(DecodedBlob {
+                    index: blob.blob_index,
+                    contract_name: blob.contract_name,
+                    verified: blob.verified,
+                    description,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Human-readable description of an AMM action blob
+pub fn describe_amm_blob(data: &[u8]) -> String {
+    let Ok(action) = borsh::from_slice::<Contract1Action>(data) else {
+        return format!("Undecodable AMM blob ({} bytes)", data.len());
+    };
+    describe_amm_action(action)
+}
+
+fn describe_amm_action(action: Contract1Action) -> String {
+    match action {
+        Contract1Action::Faucet(FaucetAction::Mint { user, token, amount }) => {
+            format!("{} minted {} {}", user, amount, token)
+        }
+        Contract1Action::AddLiquidity { user, token_a, token_b, amount_a, amount_b } => {
+            format!("{} added {} {} and {} {} of liquidity", user, amount_a, token_a, amount_b, token_b)
+        }
+        Contract1Action::CreateLbpPool { user, token_a, token_b, amount_a, amount_b, start_block, end_block, .. } => {
+            format!(
+                "{} launched a {}/{} bootstrapping pool with {} {} and {} {}, blocks {} to {}",
+                user, token_a, token_b, amount_a, token_a, amount_b, token_b, start_block, end_block
+            )
+        }
+        Contract1Action::CreateBatchAuctionPool { user, token_a, token_b, amount_a, amount_b } => {
+            format!(
+                "{} created a {}/{} batch auction pool with {} {} and {} {}",
+                user, token_a, token_b, amount_a, token_a, amount_b, token_b
+            )
+        }
+        Contract1Action::RemoveLiquidity { user, token_a, token_b, liquidity_amount } => {
+            format!("{} removed {} liquidity tokens from {}/{}", user, liquidity_amount, token_a, token_b)
+        }
+        Contract1Action::SwapExactTokensForTokens { user, token_in, token_out, amount_in, min_amount_out } => {
+            format!(
+                "{} swapped {} {} for at least {} {}",
+                user, amount_in, token_in, min_amount_out, token_out
+            )
+        }
+        Contract1Action::CreateStreamingSwap { user, token_in, token_out, total_amount, chunks, interval_blocks, .. } => {
+            format!(
+                "{} streams {} {} into {} over {} chunks every {} blocks",
+                user, total_amount, token_in, token_out, chunks, interval_blocks
+            )
+        }
+        Contract1Action::ExecuteDue {} => "Executed due streaming swap chunks and swap batches".to_string(),
+        Contract1Action::Compact {} => "Compacted zero balances and drained pools".to_string(),
+        Contract1Action::CancelStreamingSwap { user, order_id } => {
+            format!("{} cancelled streaming swap #{}", user, order_id)
+        }
+        Contract1Action::CreateOtcOffer { user, give, want, counterparty } => format!(
+            "{} offered {} {} to {} for {} {}",
+            user, give.amount, give.token, counterparty, want.amount, want.token
+        ),
+        Contract1Action::AcceptOffer { user, offer_id } => {
+            format!("{} accepted OTC offer #{}", user, offer_id)
+        }
+        Contract1Action::CancelOffer { user, offer_id } => {
+            format!("{} cancelled OTC offer #{}", user, offer_id)
+        }
+        Contract1Action::CreateVesting { user, beneficiary, token, amount, start_block, duration } => format!(
+            "{} vested {} {} to {} from block {} over {} blocks",
+            user, amount, token, beneficiary, start_block, duration
+        ),
+        Contract1Action::ClaimVesting { user, vesting_id } => {
+            format!("{} claimed vesting #{}", user, vesting_id)
+        }
+        Contract1Action::GetReserves { token_a, token_b } => {
+            format!("Queried {}/{} reserves", token_a, token_b)
+        }
+        Contract1Action::GetUserBalance { user, token } => {
+            format!("Queried {} balance of {}", token, user)
+        }
+        Contract1Action::GetVolume { user: Some(user), token_a, token_b } => {
+            format!("Queried {}/{} volume of {}", token_a, token_b, user)
+        }
+        Contract1Action::GetVolume { user: None, token_a, token_b } => {
+            format!("Queried {}/{} volume", token_a, token_b)
+        }
+        Contract1Action::GetRewardPoints { user, token_a, token_b } => {
+            format!("Queried {}/{} reward points of {}", token_a, token_b, user)
+        }
+        Contract1Action::FileInsuranceClaim { user, token, amount, reason } => {
+            format!("{} claimed {} {} from the insurance fund: {}", user, amount, token, reason)
+        }
+        Contract1Action::ApproveInsuranceClaim { admin, claim_id } => {
+            format!("{} approved insurance claim #{}", admin, claim_id)
+        }
+        Contract1Action::RejectInsuranceClaim { admin, claim_id } => {
+            format!("{} rejected insurance claim #{}", admin, claim_id)
+        }
+        Contract1Action::GetInsuranceFund {} => "Queried the insurance fund".to_string(),
+        Contract1Action::RegisterSigningKey { user, .. } => {
+            format!("{} registered a signing key", user)
+        }
+        Contract1Action::RelaySwap { intent, relayer_fee_bps, .. } => format!(
+            "Relayed swap of {} {} for at least {} {} signed by {}, {} bps relayer fee",
+            intent.amount_in, intent.token_in, intent.min_amount_out, intent.token_out, intent.user,
+            relayer_fee_bps
+        ),
+        Contract1Action::BridgeDeposit { user, token, amount, external_tx_ref } => {
+            format!("Bridged {} {} to {} from {}", amount, token, user, external_tx_ref)
+        }
+        Contract1Action::RequestWithdrawal { user, token, amount, destination } => {
+            format!("{} requested a withdrawal of {} {} to {}", user, amount, token, destination)
+        }
+        Contract1Action::ProcessWithdrawal { withdrawal_id, external_tx_ref } => {
+            format!("Processed withdrawal {} in {}", withdrawal_id, external_tx_ref)
+        }
+        Contract1Action::CancelWithdrawal { user, withdrawal_id } => {
+            format!("{} cancelled withdrawal {}", user, withdrawal_id)
+        }
+        Contract1Action::SetReferrer { user, referrer } => {
+            format!("{} was referred by {}", user, referrer)
+        }
+        Contract1Action::DepositExternal { user, token, amount } => {
+            format!("{} deposited {} {}", user, amount, token)
+        }
+        Contract1Action::WithdrawExternal { user, token, amount } => {
+            format!("{} withdrew {} {}", user, amount, token)
+        }
+        Contract1Action::GetVotesAt { user, block } => {
+            format!("Queried votes of {} at block {}", user, block)
+        }
+        Contract1Action::GetRoundingReserve { token_a, token_b } => {
+            format!("Queried {}/{} rounding reserve", token_a, token_b)
+        }
+        Contract1Action::ProposeAdminAction { admin, operation } => {
+            format!("{} proposed {:?}", admin, operation)
+        }
+        Contract1Action::ApproveAdminAction { admin, proposal_id } => {
+            format!("{} approved admin proposal #{}", admin, proposal_id)
+        }
+        Contract1Action::CollectProtocolFees { admin } => {
+            format!("{} collected the protocol fees", admin)
+        }
+        Contract1Action::Nonced { identity, nonce, action } => {
+            format!("{} (nonce {} of {})", describe_amm_action(*action), nonce, identity)
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use client_sdk::contract_indexer::AppError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use crate::conf::FeatureFlagConf;
+use crate::storage::{Storage, Write};
+use crate::versioning;
+
+/// Storage namespace of the rollouts changed through the admin API, keyed by flag name
+const NAMESPACE: &str = "feature_flags";
+/// Header carrying the caller identity, as read by the handlers
+const USER_HEADER: &str = "x-user";
+
+/// Rollout changed through the admin API, taking precedence over the configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlagOverride {
+    pub enabled: Option<bool>,
+    pub rollout_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagState {
+    pub enabled: bool,
+    pub rollout_percent: u8,
+    /// Identities the feature is on for whatever the rollout, while enabled
+    pub identities: Vec<String>,
+    pub routes: Vec<String>,
+    pub actions: Vec<String>,
+    /// Whether the admin API changed the configured rollout
+    pub overridden: bool,
+}
+
+/// Features rolled out to a share of identities, their routes and contract1 actions being
+/// refused to the others.
+///
+/// Which identities are in a rollout follows a hash of the flag name and the identity, so raising
+/// the percentage only adds identities, and each flag picks its own share. Callers without an
+/// identity only get the features rolled out to everyone.
+pub struct FeatureFlags {
+    storage: Arc<dyn Storage>,
+    flags: BTreeMap<String, FeatureFlagConf>,
+    overrides: RwLock<BTreeMap<String, FlagOverride>>,
+}
+
+impl FeatureFlags {
+    /// The configured flags, with the rollouts last set through the admin API
+    pub async fn load(
+        storage: Arc<dyn Storage>,
+        flags: BTreeMap<String, FeatureFlagConf>,
+    ) -> Result<Self> {
+        let mut overrides = BTreeMap::new();
+        for (key, bytes) in storage.range(NAMESPACE, &[], None).await? {
+            let name = String::from_utf8_lossy(&key).to_string();
+            // Flags since removed from the configuration are left alone
+            if flags.contains_key(&name) {
+                let flag_override = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("Could not parse the stored feature flag {}", name))?;
+                overrides.insert(name, flag_override);
+            }
+        }
+        Ok(Self {
+            storage,
+            flags,
+            overrides: RwLock::new(overrides),
+        })
+    }
+
+    /// Every flag, as currently rolled out
+    pub async fn states(&self) -> BTreeMap<String, FlagState> {
+        let overrides = self.overrides.read().await;
+        self.flags
+            .iter()
+            .map(|(name, conf)| (name.clone(), state(conf, overrides.get(name))))
+            .collect()
+    }
+
+    /// Whether each flag is on for an identity, as the frontend reads them
+    pub async fn for_identity(&self, identity: Option<&str>) -> BTreeMap<String, bool> {
+        let overrides = self.overrides.read().await;
+        self.flags
+            .iter()
+            .map(|(name, conf)| {
+                let on = is_on(name, &state(conf, overrides.get(name)), identity);
+                (name.clone(), on)
+            })
+            .collect()
+    }
+
+    /// Change the rollout of a flag, keeping what the update leaves unset
+    pub async fn update(&self, name: &str, update: FlagOverride) -> Result<Option<FlagState>> {
+        let Some(conf) = self.flags.get(name) else {
+            return Ok(None);
+        };
+        if update.rollout_percent.is_some_and(|percent| percent > 100) {
+            bail!("Rollout percentage must be at most 100");
+        }
+        let mut overrides = self.overrides.write().await;
+        let mut flag_override = overrides.get(name).cloned().unwrap_or_default();
+        flag_override.enabled = update.enabled.or(flag_override.enabled);
+        flag_override.rollout_percent = update.rollout_percent.or(flag_override.rollout_percent);
+        self.storage
+            .apply(vec![Write::put(
+                NAMESPACE,
+                name,
+                serde_json::to_vec(&flag_override)?,
+            )])
+            .await
+            .context("Failed to persist feature flag")?;
+        let state = state(conf, Some(&flag_override));
+        tracing::warn!(
+            "🚩 Feature flag {} is now {} for {}% of identities",
+            name,
+            if state.enabled { "enabled" } else { "disabled" },
+            state.rollout_percent
+        );
+        overrides.insert(name.to_string(), flag_override);
+        Ok(Some(state))
+    }
+
+    /// Refuse contract1 actions behind a flag that is off for the identity submitting them
+    pub async fn check_actions<'a>(
+        &self,
+        identity: &str,
+        actions: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), AppError> {
+        let overrides = self.overrides.read().await;
+        for action in actions {
+            for (name, conf) in &self.flags {
+                let guards = conf.actions.iter().any(|guarded| guarded == action);
+                if guards && !is_on(name, &state(conf, overrides.get(name)), Some(identity)) {
+                    return Err(disabled(name, identity, action));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flag guarding a route that is off for the identity, if any
+    async fn route_off(&self, path: &str, identity: Option<&str>) -> Option<&str> {
+        let overrides = self.overrides.read().await;
+        self.flags.iter().find_map(|(name, conf)| {
+            let guards = conf.routes.iter().any(|route| {
+                path == route
+                    || path
+                        .strip_prefix(route.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            });
+            (guards && !is_on(name, &state(conf, overrides.get(name)), identity))
+                .then_some(name.as_str())
+        })
+    }
+}
+
+fn state(conf: &FeatureFlagConf, flag_override: Option<&FlagOverride>) -> FlagState {
+    let flag_override = flag_override.cloned().unwrap_or_default();
+    FlagState {
+        enabled: flag_override.enabled.unwrap_or(conf.enabled),
+        rollout_percent: flag_override
+            .rollout_percent
+            .or(conf.rollout_percent)
+            .unwrap_or(100)
+            .min(100),
+        identities: conf.identities.clone(),
+        routes: conf.routes.clone(),
+        actions: conf.actions.clone(),
+        overridden: flag_override.enabled.is_some() || flag_override.rollout_percent.is_some(),
+    }
+}
+
+fn is_on(name: &str, state: &FlagState, identity: Option<&str>) -> bool {
+    if !state.enabled {
+        return false;
+    }
+    if state.rollout_percent >= 100 {
+        return true;
+    }
+    let Some(identity) = identity else {
+        return false;
+    };
+    if state.identities.iter().any(|listed| listed == identity) {
+        return true;
+    }
+    let digest = Sha256::digest(format!("{}\0{}", name, identity));
+    u16::from_be_bytes([digest[0], digest[1]]) % 100 < state.rollout_percent as u16
+}
+
+fn disabled(flag: &str, identity: &str, what: &str) -> AppError {
+    AppError(
+        StatusCode::FORBIDDEN,
+        anyhow::anyhow!(
+            "feature_disabled: {} is behind feature flag {}, not rolled out to {}",
+            what,
+            flag,
+            identity
+        ),
+    )
+}
+
+/// Middleware refusing routes behind a flag that is off for the caller
+pub async fn require_feature(
+    State(flags): State<Arc<FeatureFlags>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = versioning::legacy_path(request.uri().path());
+    let user = request
+        .headers()
+        .get(USER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    if let Some(flag) = flags.route_off(&path, user.as_deref()).await {
+        let identity = user.as_deref().unwrap_or("anonymous callers");
+        return disabled(flag, identity, &path).into_response();
+    }
+    next.run(request).await
+}
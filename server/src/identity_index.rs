@@ -0,0 +1,150 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use contract2::IdentityContract;
+use sdk::ContractName;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::conf::IdentityIndexConf;
+use crate::state_cache::StateCache;
+use crate::sync_status::SyncMonitor;
+
+/// A user the identity contract started or stopped allowing
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentityChange {
+    /// Position in the feed, increasing by one per change
+    pub seq: u64,
+    pub user: String,
+    pub allowed: bool,
+    /// Country of the user's latest verification, when there is one
+    pub country_code: Option<String>,
+    /// Processed block height when the change was seen
+    pub block_height: Option<u64>,
+    pub seen_at: i64,
+}
+
+/// Changes after a `since`, answered by `GET /api/identity/changes`
+#[derive(Debug, Serialize)]
+pub struct IdentityChanges {
+    /// `since` of the next poll
+    pub next: u64,
+    /// Some changes after `since` were already dropped: re-read the full state, then poll from `next`
+    pub truncated: bool,
+    pub changes: Vec<IdentityChange>,
+}
+
+#[derive(Default)]
+struct Index {
+    /// Users allowed in the last state read, unset until one was
+    allowed: Option<HashSet<String>>,
+    /// Latest changes, oldest first
+    changes: VecDeque<IdentityChange>,
+    last_seq: u64,
+    /// Whether the last read failed, so a failing indexer is only reported once
+    failing: bool,
+}
+
+/// Allow/deny changes of the identity contract, from successive states read off the indexer.
+///
+/// Per-request checks read the allowed set kept here instead of fetching and decoding the full
+/// state. Replaying the feed from 0 rebuilds that set, as the users allowed in the first state read
+/// open it, as long as no change was dropped yet.
+pub struct IdentityIndex {
+    state: Arc<StateCache<IdentityContract>>,
+    identity_cn: ContractName,
+    max_changes: usize,
+    inner: RwLock<Index>,
+}
+
+impl IdentityIndex {
+    pub fn new(conf: &IdentityIndexConf, state: Arc<StateCache<IdentityContract>>, identity_cn: ContractName) -> Self {
+        Self {
+            state,
+            identity_cn,
+            max_changes: conf.max_changes.max(1),
+            inner: Default::default(),
+        }
+    }
+
+    /// Whether the identity contract allows a user, `None` until its state was read once
+    pub async fn allows(&self, user: &str) -> Option<bool> {
+        let inner = self.inner.read().await;
+        inner.allowed.as_ref().map(|allowed| allowed.contains(user))
+    }
+
+    /// Whether the identity contract recorded a verification of a user, allowing them or not
+    pub async fn verified(&self, user: &str) -> Result<bool> {
+        Ok(self.state.get().await?.verification(user).is_some())
+    }
+
+    /// Changes with a sequence number above `since`
+    pub async fn changes(&self, since: u64) -> IdentityChanges {
+        let inner = self.inner.read().await;
+        let oldest = inner.changes.front().map_or(inner.last_seq + 1, |change| change.seq);
+        IdentityChanges {
+            next: inner.last_seq.max(since),
+            truncated: since + 1 < oldest,
+            changes: inner.changes.iter().filter(|change| change.seq > since).cloned().collect(),
+        }
+    }
+
+    /// Read the current state and record how its allowed users differ from the last one read
+    pub async fn refresh(&self, block_height: Option<u64>) -> Result<()> {
+        let state = self.state.get().await?;
+        let current: HashSet<String> = state.allowed_users().cloned().collect();
+
+        let mut inner = self.inner.write().await;
+        let previous = inner.allowed.take().unwrap_or_default();
+        let mut changed: Vec<(&String, bool)> = current
+            .difference(&previous)
+            .map(|user| (user, true))
+            .chain(previous.difference(&current).map(|user| (user, false)))
+            .collect();
+        changed.sort();
+        let seen_at = chrono::Utc::now().timestamp();
+        for (user, allowed) in changed {
+            inner.last_seq += 1;
+            let change = IdentityChange {
+                seq: inner.last_seq,
+                user: user.clone(),
+                allowed,
+                country_code: state.verification(user).map(|v| v.country_code.clone()),
+                block_height,
+                seen_at,
+            };
+            if inner.changes.len() >= self.max_changes {
+                inner.changes.pop_front();
+            }
+            inner.changes.push_back(change);
+        }
+        inner.allowed = Some(current);
+        Ok(())
+    }
+}
+
+/// Refresh the index at every interval, stamping changes with the processed height
+pub async fn run(index: Arc<IdentityIndex>, sync: SyncMonitor, interval: Duration) {
+    let mut tick = tokio::time::interval(interval);
+    loop {
+        tick.tick().await;
+        let height = sync.status().await.processed_height;
+        let result = index.refresh(height).await;
+        let mut inner = index.inner.write().await;
+        match result {
+            Ok(()) if inner.failing => {
+                tracing::info!("✅ {} state is read again for the identity index", index.identity_cn);
+                inner.failing = false;
+            }
+            Err(e) if !inner.failing => {
+                tracing::warn!("⚠️ Could not read {} state for the identity index: {:#}", index.identity_cn, e);
+                inner.failing = true;
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,60 +1,138 @@
 use anyhow::{bail, Result};
 use client_sdk::rest_client::{IndexerApiHttpClient, NodeApiClient, NodeApiHttpClient};
-use sdk::{api::APIRegisterContract, info, ContractName, ProgramId, StateCommitment};
+use borsh::BorshSerialize;
+use sdk::{api::APIRegisterContract, info, ContractName, ProgramId, StateCommitment, ZkContract};
+use sha2::{Digest, Sha256};
 use std::{sync::Arc, time::Duration};
 use tokio::time::timeout;
 
+/// Suffixes tried when `--force-reset` looks for a free contract name
+const MAX_RESET_GENERATIONS: u32 = 1_000;
+
 pub struct ContractInit {
     pub name: ContractName,
     pub program_id: [u8; 32],
     pub initial_state: StateCommitment,
+    /// Borsh encoding of the initial state, which provers construct the contract state from
+    pub constructor_metadata: Vec<u8>,
+}
+
+impl ContractInit {
+    pub fn new<S: ZkContract + BorshSerialize>(name: ContractName, program_id: [u8; 32], initial_state: &S) -> Result<Self> {
+        Ok(Self {
+            name,
+            program_id,
+            initial_state: initial_state.commit(),
+            constructor_metadata: borsh::to_vec(initial_state)?,
+        })
+    }
 }
 
+/// Register the contracts the node does not know yet, and check those it does.
+///
+/// Safe to re-run against a live node. Returns the name each contract is registered under, which
+/// differs from the requested one when `force_reset` redeployed it.
 pub async fn init_node(
     node: Arc<NodeApiHttpClient>,
     indexer: Arc<IndexerApiHttpClient>,
     contracts: Vec<ContractInit>,
-) -> Result<()> {
+    force_reset: bool,
+) -> Result<Vec<ContractName>> {
+    let mut names = Vec::with_capacity(contracts.len());
     for contract in contracts {
-        init_contract(&node, &indexer, contract).await?;
+        names.push(init_contract(&node, &indexer, contract, force_reset).await?);
     }
-    Ok(())
+    Ok(names)
 }
 
 async fn init_contract(
     node: &NodeApiHttpClient,
     indexer: &IndexerApiHttpClient,
     contract: ContractInit,
+    force_reset: bool,
+) -> Result<ContractName> {
+    let Ok(existing) = indexer.get_indexer_contract(&contract.name).await else {
+        register(node, indexer, &contract.name, &contract).await?;
+        return Ok(contract.name);
+    };
+
+    let onchain_program_id = hex::encode(existing.program_id.as_slice());
+    let program_id = hex::encode(contract.program_id);
+    let program_matches = onchain_program_id == program_id;
+    let state_matches = existing.state_commitment == contract.initial_state.0;
+    if program_matches && state_matches {
+        info!("✅ {} contract is registered with this build and its initial state", contract.name);
+        return Ok(contract.name);
+    }
+
+    if force_reset {
+        let name = free_name(indexer, &contract.name).await?;
+        tracing::warn!(
+            "♻️ {} does not match this build, redeploying it as {}. Pass --contract1-cn {} to keep using it.",
+            contract.name, name, name
+        );
+        register(node, indexer, &name, &contract).await?;
+        return Ok(name);
+    }
+
+    if !program_matches {
+        bail!(
+            "{} is registered with program id {}, this build has {}. \
+             Register the contract under another name, or pass --force-reset on a dev node to redeploy it.",
+            contract.name,
+            onchain_program_id,
+            program_id
+        );
+    }
+    // Transactions move the state away from the initial one, so only a diagnostic is due
+    tracing::warn!(
+        "⚠️ {} commits to state {}, not to the initial state {} of this build. \
+         That is expected once it settled transactions; if its genesis config changed instead, \
+         pass --force-reset on a dev node to redeploy it.",
+        contract.name,
+        commitment_digest(&existing.state_commitment),
+        commitment_digest(&contract.initial_state.0)
+    );
+    Ok(contract.name)
+}
+
+async fn register(
+    node: &NodeApiHttpClient,
+    indexer: &IndexerApiHttpClient,
+    name: &ContractName,
+    contract: &ContractInit,
 ) -> Result<()> {
-    match indexer.get_indexer_contract(&contract.name).await {
-        Ok(existing) => {
-            let onchain_program_id = hex::encode(existing.program_id.as_slice());
-            let program_id = hex::encode(contract.program_id);
-            if onchain_program_id != program_id {
-                bail!(
-                    "Invalid program_id for {}. On-chain version is {}, expected {}",
-                    contract.name,
-                    onchain_program_id,
-                    program_id
-                );
-            }
-            info!("✅ {} contract is up to date", contract.name);
-        }
-        Err(_) => {
-            info!("🚀 Registering {} contract", contract.name);
-            node.register_contract(APIRegisterContract {
-                verifier: "risc0-1".into(),
-                program_id: ProgramId(contract.program_id.to_vec()),
-                state_commitment: contract.initial_state,
-                contract_name: contract.name.clone(),
-                ..Default::default()
-            })
-            .await?;
-            wait_contract_state(indexer, &contract.name).await?;
+    info!("🚀 Registering {} contract", name);
+    node.register_contract(registration(name, contract)).await?;
+    wait_contract_state(indexer, name).await
+}
+
+fn registration(name: &ContractName, contract: &ContractInit) -> APIRegisterContract {
+    APIRegisterContract {
+        verifier: "risc0-1".into(),
+        program_id: ProgramId(contract.program_id.to_vec()),
+        state_commitment: contract.initial_state.clone(),
+        contract_name: name.clone(),
+        constructor_metadata: Some(contract.constructor_metadata.clone()),
+        ..Default::default()
+    }
+}
+
+/// First `<name>-<n>` the node has no contract under
+async fn free_name(indexer: &IndexerApiHttpClient, name: &ContractName) -> Result<ContractName> {
+    for generation in 1..=MAX_RESET_GENERATIONS {
+        let candidate = ContractName(format!("{}-{}", name, generation));
+        if indexer.get_indexer_contract(&candidate).await.is_err() {
+            return Ok(candidate);
         }
     }
-    Ok(())
+    bail!("No free name left to redeploy {} under", name)
+}
+
+pub fn commitment_digest(commitment: &[u8]) -> String {
+    hex::encode(Sha256::digest(commitment))
 }
+
 async fn wait_contract_state(
     indexer: &IndexerApiHttpClient,
     contract: &ContractName,
@@ -72,3 +150,34 @@ async fn wait_contract_state(
     })
     .await?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{conf::Conf, tenant};
+    use client_sdk::transaction_builder::TxExecutorHandler;
+    use contract1::{client::tx_executor_handler::metadata::PROGRAM_ID, Contract1};
+    use sdk::RegisterContractEffect;
+
+    #[test]
+    fn test_provers_construct_the_registered_state() {
+        let config = Arc::new(Conf::new(vec![]).unwrap());
+        let tenant = tenant::resolve(&config, "contract1").unwrap().remove(0);
+        let initial_state = tenant.initial_state().unwrap();
+        let contract = ContractInit::new(tenant.contract1_cn.clone(), PROGRAM_ID, &initial_state).unwrap();
+
+        let request = registration(&tenant.contract1_cn, &contract);
+        let effect = RegisterContractEffect {
+            verifier: request.verifier.clone(),
+            program_id: request.program_id.clone(),
+            state_commitment: request.state_commitment.clone(),
+            contract_name: request.contract_name.clone(),
+            timeout_window: None,
+        };
+        let state = Contract1::construct_state(&effect, &request.constructor_metadata).unwrap();
+        assert_eq!(state.commit(), initial_state.commit());
+
+        // The configured genesis state is not the default one, so it cannot be guessed
+        assert!(Contract1::construct_state(&effect, &None).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use client_sdk::contract_indexer::AppError;
+use sdk::ContractName;
+
+use crate::conf::KycConf;
+use crate::identity_index::IdentityIndex;
+use crate::versioning;
+
+/// Header carrying the caller identity, as read by the handlers
+const USER_HEADER: &str = "x-user";
+
+/// Routes restricted to users the identity contract allows, as last seen by the identity index
+pub struct KycGate {
+    index: Arc<IdentityIndex>,
+    identity_cn: ContractName,
+    routes: HashSet<String>,
+}
+
+impl KycGate {
+    pub fn new(conf: &KycConf, index: Arc<IdentityIndex>, identity_cn: ContractName) -> Self {
+        Self {
+            index,
+            identity_cn,
+            routes: conf.routes.iter().cloned().collect(),
+        }
+    }
+
+    async fn check(&self, user: &str) -> Result<(), AppError> {
+        let allowed = self.index.allows(user).await.ok_or_else(|| {
+            tracing::warn!("⚠️ KYC check of {} unavailable: identity index not loaded", user);
+            AppError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow::anyhow!("kyc_unavailable: identity state could not be read"),
+            )
+        })?;
+        if allowed {
+            return Ok(());
+        }
+        Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!(
+                "kyc_required: {} is not verified as allowed by {}",
+                user,
+                self.identity_cn
+            ),
+        ))
+    }
+}
+
+/// Middleware rejecting gated routes for users without an allowed identity.
+///
+/// Requests without a user header are left to the handler, which refuses them.
+pub async fn require_kyc(State(gate): State<Arc<KycGate>>, request: Request, next: Next) -> Response {
+    if gate.routes.contains(&versioning::legacy_path(request.uri().path())) {
+        let user = request
+            .headers()
+            .get(USER_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        if let Some(user) = user {
+            if let Err(e) = gate.check(&user).await {
+                return e.into_response();
+            }
+        }
+    }
+    next.run(request).await
+}
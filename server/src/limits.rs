@@ -0,0 +1,153 @@
+use axum::http::StatusCode;
+use client_sdk::contract_indexer::AppError;
+use contract1::{Contract1, Contract1Action};
+
+use crate::conf::LimitsConf;
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+/// A request rejected by the server-side trading limits
+#[derive(Debug)]
+pub enum LimitViolation {
+    SwapSize { token: String, amount: u128, max: u128 },
+    MintSize { token: String, amount: u128, max: u128 },
+    PoolShare { pool: String, share_bps: u128, max_bps: u32 },
+}
+
+impl LimitViolation {
+    /// Stable code clients can match on, prefixed to the error message
+    pub fn code(&self) -> &'static str {
+        match self {
+            LimitViolation::SwapSize { .. } => "swap_size_limit",
+            LimitViolation::MintSize { .. } => "mint_size_limit",
+            LimitViolation::PoolShare { .. } => "pool_share_limit",
+        }
+    }
+}
+
+impl std::fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitViolation::SwapSize { token, amount, max } => {
+                write!(f, "swapping {} {} exceeds the limit of {}", amount, token, max)
+            }
+            LimitViolation::MintSize { token, amount, max } => {
+                write!(f, "minting {} {} exceeds the limit of {}", amount, token, max)
+            }
+            LimitViolation::PoolShare { pool, share_bps, max_bps } => write!(
+                f,
+                "this would give you {} bps of the {} pool, above the limit of {} bps",
+                share_bps, pool, max_bps
+            ),
+        }
+    }
+}
+
+impl From<LimitViolation> for AppError {
+    fn from(violation: LimitViolation) -> Self {
+        AppError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            anyhow::anyhow!("{}: {}", violation.code(), violation),
+        )
+    }
+}
+
+/// Caps checked before submission, until the contract enforces its own
+pub struct TradingLimits {
+    conf: LimitsConf,
+}
+
+impl TradingLimits {
+    pub fn new(conf: LimitsConf) -> Self {
+        Self { conf }
+    }
+
+    pub fn check_swap(&self, token_in: &str, amount_in: u128) -> Result<(), LimitViolation> {
+        let max = self
+            .conf
+            .tokens
+            .get(token_in)
+            .and_then(|t| t.max_swap_in)
+            .or(self.conf.max_swap_in);
+        match max {
+            Some(max) if amount_in > max => Err(LimitViolation::SwapSize {
+                token: token_in.to_string(),
+                amount: amount_in,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_mint(&self, token: &str, amount: u128) -> Result<(), LimitViolation> {
+        let max = self
+            .conf
+            .tokens
+            .get(token)
+            .and_then(|t| t.max_mint)
+            .or(self.conf.max_mint);
+        match max {
+            Some(max) if amount > max => Err(LimitViolation::MintSize {
+                token: token.to_string(),
+                amount,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the user's share of an existing pool once the liquidity is added.
+    ///
+    /// Creating a pool is always allowed, as is anything that does not grow an existing
+    /// share or that the contract would reject anyway.
+    pub fn check_pool_share(
+        &self,
+        state: Option<&Contract1>,
+        user: &str,
+        token_a: &str,
+        token_b: &str,
+        amount_a: u128,
+        amount_b: u128,
+    ) -> Result<(), LimitViolation> {
+        let mut tokens = [token_a, token_b];
+        tokens.sort();
+        let pool = format!("{}_{}", tokens[0], tokens[1]);
+        let Some(max_bps) = self
+            .conf
+            .pools
+            .get(&pool)
+            .and_then(|p| p.max_pool_share_bps)
+            .or(self.conf.max_pool_share_bps)
+        else {
+            return Ok(());
+        };
+        let Some(state) = state else {
+            return Ok(());
+        };
+        let Some(before) = state.pool(token_a, token_b).filter(|p| p.total_liquidity > 0) else {
+            return Ok(());
+        };
+        let share_before =
+            state.user_liquidity(user, token_a, token_b) * BPS_DENOMINATOR / before.total_liquidity;
+
+        let mut after = state.clone();
+        let action = Contract1Action::AddLiquidity {
+            user: user.to_string(),
+            token_a: token_a.to_string(),
+            token_b: token_b.to_string(),
+            amount_a,
+            amount_b,
+        };
+        if after.apply(action).is_err() {
+            return Ok(());
+        }
+        let Some(total) = after.pool(token_a, token_b).map(|p| p.total_liquidity) else {
+            return Ok(());
+        };
+        let share_bps = after.user_liquidity(user, token_a, token_b) * BPS_DENOMINATOR / total;
+        if share_bps > max_bps as u128 && share_bps > share_before {
+            return Err(LimitViolation::PoolShare { pool, share_bps, max_bps });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use contract1::{Contract1, LiquidityPool};
+use serde::{Deserialize, Serialize};
+
+use crate::price_history::Candle;
+use crate::storage::{Storage, Write};
+
+/// Storage namespace of entry checkpoints, keyed by user then pair
+const NAMESPACE: &str = "lp_entries";
+
+/// A change of a user's liquidity in a pool, with what it moved at the pool's reserves then
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryCheckpoint {
+    pub at: i64,
+    pub block: u64,
+    /// Liquidity held after the change
+    pub liquidity: u128,
+    /// Tokens deposited, or withdrawn when the liquidity went down
+    pub amount_a: u128,
+    pub amount_b: u128,
+    /// Spot price of token_a in token_b
+    pub price: f64,
+}
+
+/// Token amounts and their value in token_b at the current price
+#[derive(Debug, Clone, Serialize)]
+pub struct Holdings {
+    pub amount_a: u128,
+    pub amount_b: u128,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IlPoint {
+    pub at: i64,
+    pub price: f64,
+    pub impermanent_loss: f64,
+}
+
+/// Impermanent loss of a position against holding what was deposited
+#[derive(Debug, Clone, Serialize)]
+pub struct ImpermanentLossReport {
+    pub user: String,
+    pub pair: String,
+    pub token_a: String,
+    pub token_b: String,
+    pub liquidity: u128,
+    pub entered_at: i64,
+    /// Price the deposits still held were made at on average, in token_b per token_a
+    pub entry_price: f64,
+    pub price: f64,
+    /// What the liquidity withdraws now
+    pub position: Holdings,
+    /// The deposits still held, less what withdrawals took out pro rata
+    pub hodl: Holdings,
+    /// Position value over HODL value minus one, so fees earned are included
+    pub vs_hodl: f64,
+    /// Loss the price move alone causes under the pool's curve, fees excluded
+    pub impermanent_loss: f64,
+    /// The same simulated at each candle close since entry
+    pub history: Vec<IlPoint>,
+    /// Weights of token_a and token_b the loss is computed with, as fractions
+    #[serde(skip)]
+    weights: (f64, f64),
+}
+
+/// Entry checkpoints of LP positions, derived from settled states and persisted in the server
+/// storage.
+///
+/// Each settled state is diffed against the previous one; positions whose liquidity moved get a
+/// checkpoint unless the last one stored already holds the new liquidity, so a replay after a
+/// restart records nothing twice. Positions are followed from the first state the server saw
+/// them change in.
+pub struct PositionEntries {
+    storage: Arc<dyn Storage>,
+}
+
+impl PositionEntries {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// Record the liquidity changes between two settled states
+    pub async fn record(
+        &self,
+        previous: Option<&Contract1>,
+        current: &Contract1,
+        timestamp: i64,
+    ) -> Result<()> {
+        let before = previous.map(positions).unwrap_or_default();
+        let after = positions(current);
+        let mut changes: Vec<((&str, &str), u128, u128)> = vec![];
+        for (position, to) in &after {
+            let from = before.get(position).copied().unwrap_or(0);
+            if from != *to {
+                changes.push((*position, from, *to));
+            }
+        }
+        for (position, from) in &before {
+            if !after.contains_key(position) && *from > 0 {
+                changes.push((*position, *from, 0));
+            }
+        }
+
+        let mut writes = vec![];
+        for ((user, pair), from, to) in changes {
+            let mut checkpoints = self.checkpoints(user, pair).await?;
+            if checkpoints
+                .last()
+                .map_or(0, |checkpoint| checkpoint.liquidity)
+                == to
+            {
+                continue;
+            }
+            // Deposits are valued at the reserves they went into, withdrawals at those they left
+            let pool = match to > from {
+                true => current.pools().get(pair),
+                false => previous.and_then(|state| state.pools().get(pair)),
+            };
+            let Some(pool) = pool.filter(|pool| pool.total_liquidity > 0) else {
+                continue;
+            };
+            let moved = to.abs_diff(from);
+            checkpoints.push(EntryCheckpoint {
+                at: timestamp,
+                block: current.block_height(),
+                liquidity: to,
+                amount_a: moved * pool.reserve_a / pool.total_liquidity,
+                amount_b: moved * pool.reserve_b / pool.total_liquidity,
+                price: spot_price(pool, current.block_height()),
+            });
+            writes.push(Write::put(
+                NAMESPACE,
+                key(user, pair),
+                serde_json::to_vec(&checkpoints)?,
+            ));
+        }
+        if !writes.is_empty() {
+            self.storage
+                .apply(writes)
+                .await
+                .context("Failed to persist LP entries")?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoints of a position, oldest first
+    pub async fn checkpoints(&self, user: &str, pair: &str) -> Result<Vec<EntryCheckpoint>> {
+        Ok(match self.storage.get(NAMESPACE, &key(user, pair)).await? {
+            Some(bytes) => {
+                serde_json::from_slice(&bytes).context("Could not parse stored LP entries")?
+            }
+            None => vec![],
+        })
+    }
+}
+
+/// Liquidity of every position of a state, by user and pair
+fn positions(state: &Contract1) -> HashMap<(&str, &str), u128> {
+    state
+        .liquidity_balances()
+        .map(|(user, pair, liquidity)| ((user, pair), liquidity))
+        .collect()
+}
+
+/// Impermanent loss of a position holding `liquidity` of `pool` now, `None` without a deposit
+/// since it was last emptied. The history is left for `simulate` to fill.
+pub fn report(
+    user: &str,
+    pair: &str,
+    pool: &LiquidityPool,
+    block_height: u64,
+    liquidity: u128,
+    checkpoints: &[EntryCheckpoint],
+) -> Option<ImpermanentLossReport> {
+    // What the position would hold had its deposits not been pooled
+    let (mut hodl_a, mut hodl_b, mut held, mut entered_at) = (0u128, 0u128, 0u128, None);
+    for checkpoint in checkpoints {
+        if checkpoint.liquidity > held {
+            hodl_a += checkpoint.amount_a;
+            hodl_b += checkpoint.amount_b;
+            entered_at.get_or_insert(checkpoint.at);
+        } else if checkpoint.liquidity < held {
+            hodl_a = hodl_a * checkpoint.liquidity / held;
+            hodl_b = hodl_b * checkpoint.liquidity / held;
+        }
+        if checkpoint.liquidity == 0 {
+            entered_at = None;
+        }
+        held = checkpoint.liquidity;
+    }
+    if liquidity == 0 || pool.total_liquidity == 0 || hodl_a == 0 || hodl_b == 0 {
+        return None;
+    }
+
+    let (weight_a, weight_b) = pool.weights(block_height);
+    let weights = (weight_a as f64 / 10_000.0, weight_b as f64 / 10_000.0);
+    let (weight_a, weight_b) = weights;
+    let entry_price = (hodl_b as f64 * weight_a) / (hodl_a as f64 * weight_b);
+    let price = spot_price(pool, block_height);
+    let value = |amount_a: u128, amount_b: u128| amount_a as f64 * price + amount_b as f64;
+
+    let amount_a = liquidity * pool.reserve_a / pool.total_liquidity;
+    let amount_b = liquidity * pool.reserve_b / pool.total_liquidity;
+    let position = Holdings {
+        amount_a,
+        amount_b,
+        value: value(amount_a, amount_b),
+    };
+    let hodl = Holdings {
+        amount_a: hodl_a,
+        amount_b: hodl_b,
+        value: value(hodl_a, hodl_b),
+    };
+
+    Some(ImpermanentLossReport {
+        user: user.to_string(),
+        pair: pair.to_string(),
+        token_a: pool.token_a.clone(),
+        token_b: pool.token_b.clone(),
+        liquidity,
+        entered_at: entered_at.unwrap_or_default(),
+        entry_price,
+        price,
+        vs_hodl: position.value / hodl.value - 1.0,
+        impermanent_loss: impermanent_loss(price / entry_price, weight_a, weight_b),
+        history: vec![],
+        position,
+        hodl,
+        weights,
+    })
+}
+
+impl ImpermanentLossReport {
+    /// Fill the history with the impermanent loss at each close of the given candles
+    pub fn simulate(&mut self, candles: &[Candle]) {
+        let (weight_a, weight_b) = self.weights;
+        self.history = candles
+            .iter()
+            .map(|candle| IlPoint {
+                at: candle.open_time,
+                price: candle.close,
+                impermanent_loss: impermanent_loss(
+                    candle.close / self.entry_price,
+                    weight_a,
+                    weight_b,
+                ),
+            })
+            .collect();
+    }
+}
+
+/// Value of a weighted pool position over holding its deposits, minus one, once the price of
+/// token_a moved by `ratio`: `ratio^w_a / (w_a * ratio + w_b) - 1`, which is
+/// `2 * sqrt(ratio) / (1 + ratio) - 1` for equal weights
+fn impermanent_loss(ratio: f64, weight_a: f64, weight_b: f64) -> f64 {
+    ratio.powf(weight_a) / (weight_a * ratio + weight_b) - 1.0
+}
+
+/// Spot price of token_a in token_b, as price history records it
+fn spot_price(pool: &LiquidityPool, block_height: u64) -> f64 {
+    let (weight_a, weight_b) = pool.weights(block_height);
+    (pool.reserve_b * weight_a) as f64 / (pool.reserve_a * weight_b) as f64
+}
+
+/// `<user>\0<pair>`
+fn key(user: &str, pair: &str) -> Vec<u8> {
+    let mut key = user.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(pair.as_bytes());
+    key
+}
@@ -1,12 +1,14 @@
 use anyhow::{Context, Result};
 use app::{AppModule, AppModuleCtx};
 use axum::Router;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use client_sdk::{
     helpers::risc0::Risc0Prover,
     rest_client::{IndexerApiHttpClient, NodeApiHttpClient},
 };
 use conf::Conf;
+use mock_node::MockNode;
+use recorder::Recorder;
 use contract1::Contract1;
 // Contract2 removed - will be replaced with Noir identity verification
 use hyle_modules::{
@@ -22,34 +24,130 @@ use hyle_modules::{
     utils::logger::setup_tracing,
 };
 use prometheus::Registry;
-use sdk::{api::NodeInfo, info, ZkContract};
-use std::sync::{Arc, Mutex};
+use sdk::{api::NodeInfo, info};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tenant::Tenant;
 use tracing::error;
 
+mod amm_state;
 mod app;
+mod backup;
+mod budget;
+#[cfg(feature = "chaos")]
+mod chaos;
 mod conf;
+mod credential_policy;
+#[cfg(feature = "cycle-accounting")]
+mod cycles;
+mod delivery;
+mod deploy_check;
+mod devnet;
+mod encryption;
+mod etag;
+mod explorer;
+mod feature_flags;
+mod identity_index;
 mod init;
+mod kyc;
+mod limits;
+mod lp_positions;
+mod mock_node;
+mod noir_abi;
+mod noir_jobs;
 mod noir_verifier; // New Noir verification module
+mod noir_proofs;
 mod noir_prover;   // New Noir proof generation module
+mod noir_vk;
+mod notifications;
+mod price_history;
+mod profile;
+mod reconciliation;
+mod reorg;
+mod recorder;
+mod retention;
+mod screening;
+mod secrets;
+mod spending;
+mod state_cache;
+mod state_dump;
+mod state_history;
+mod storage;
+mod sync_status;
+mod tenant;
+mod tls;
+mod tx_status;
+mod versioning;
+mod webauthn;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(long, default_value = "config.toml")]
     pub config_file: Vec<String>,
 
     #[arg(long, default_value = "contract1")]
     pub contract1_cn: String,
 
+    /// Run as a read replica: no prover and no transaction submission
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Leave proving to a separately deployed `prover` binary
+    #[arg(long)]
+    pub external_prover: bool,
+
+    /// Push a canary through mint, liquidity and swaps on boot and report the outcome
+    #[arg(long, conflicts_with = "read_only")]
+    pub self_test: bool,
+
+    /// Execute actions against in-memory state with instant settlement, no node or prover needed
+    #[arg(long, conflicts_with_all = ["read_only", "external_prover"])]
+    pub mock_node: bool,
+
+    /// Append sanitized API requests, responses and settlements to this file, see the `replay` binary
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Redeploy contracts registered with another program or initial state under a new name, for dev loops
+    #[arg(long, conflicts_with_all = ["read_only", "mock_node"])]
+    pub force_reset: bool,
+
+    /// Archive the data directory to this path and exit
+    #[arg(long, value_name = "PATH", conflicts_with = "restore")]
+    pub backup: Option<PathBuf>,
+
+    /// Replace the data directory with this archive and exit
+    #[arg(long, value_name = "PATH")]
+    pub restore: Option<PathBuf>,
+
+    /// Encrypt sensitive records still in clear and re-wrap those sealed with an older key, then exit
+    #[arg(long, conflicts_with_all = ["backup", "restore"])]
+    pub rotate_keys: bool,
+
     // Contract2 removed - will use Noir identity verification
     // #[arg(long, default_value = "contract2")]
     // pub contract2_cn: String,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start a local node and wallet, register the contracts, seed genesis pools and run the app
+    Devnet(devnet::DevnetArgs),
+    /// Compare the contracts this build would register with those on the network, then exit
+    DeployCheck(deploy_check::DeployCheckArgs),
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let config = Conf::new(args.config_file).context("reading config file")?;
+    let config = Conf::new(args.config_file.clone()).context("reading config file")?;
 
     setup_tracing(
         &config.log_format,
@@ -59,28 +157,78 @@ async fn main() -> Result<()> {
 
     let config = Arc::new(config);
 
+    if let Some(archive) = &args.backup {
+        return backup::backup(&config.data_directory, archive);
+    }
+    if let Some(archive) = &args.restore {
+        return backup::restore(archive, &config.data_directory);
+    }
+    let secrets = secrets::Secrets::new(&config.secrets)?;
+    if args.rotate_keys {
+        for tenant in tenant::resolve(&config, &args.contract1_cn)? {
+            encryption::rotate(&tenant.config, &secrets).await?;
+        }
+        return Ok(());
+    }
+    if config.secrets.refresh_interval_secs > 0 {
+        tokio::spawn(
+            secrets
+                .clone()
+                .run(Duration::from_secs(config.secrets.refresh_interval_secs)),
+        );
+    }
+
     info!("Starting app with config: {:?}", &config);
 
+    #[cfg(feature = "chaos")]
+    chaos::install(config.chaos.clone());
+
     let node_client =
         Arc::new(NodeApiHttpClient::new(config.node_url.clone()).context("build node client")?);
     let indexer_client = Arc::new(
         IndexerApiHttpClient::new(config.indexer_url.clone()).context("build indexer client")?,
     );
 
-    let contracts = vec![
-        init::ContractInit {
-            name: args.contract1_cn.clone().into(),
-            program_id: contract1::client::tx_executor_handler::metadata::PROGRAM_ID,
-            initial_state: Contract1::default().commit(),
-        },
+    // Kept alive so a node spawned for the devnet stops with the server
+    let _devnet = match &args.command {
+        Some(Command::Devnet(devnet_args)) => {
+            Some(devnet::launch(devnet_args, &config.devnet, &node_client).await?)
+        }
+        Some(Command::DeployCheck(_)) | None => None,
+    };
+
+    let mut tenants = tenant::resolve(&config, &args.contract1_cn).context("resolving tenants")?;
+
+    if let Some(Command::DeployCheck(check_args)) = &args.command {
+        return deploy_check::run(check_args, &tenants, &indexer_client).await;
+    }
+
+    let contracts = tenants
+        .iter()
+        .map(|tenant| {
+            init::ContractInit::new(
+                tenant.contract1_cn.clone(),
+                contract1::client::tx_executor_handler::metadata::PROGRAM_ID,
+                &tenant.initial_state()?,
+            )
+        })
         // Contract2 initialization removed - will be replaced with Noir contract
-    ];
+        .collect::<Result<_>>()?;
 
-    match init::init_node(node_client.clone(), indexer_client.clone(), contracts).await {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error initializing node: {:?}", e);
-            return Ok(());
+    // Read replicas rely on the proving instance having registered the contracts
+    if args.mock_node {
+        info!("🧪 Running against the in-memory mock node");
+    } else if !args.read_only {
+        match init::init_node(node_client.clone(), indexer_client.clone(), contracts, args.force_reset).await {
+            Ok(names) => {
+                for (tenant, name) in tenants.iter_mut().zip(names) {
+                    tenant.contract1_cn = name;
+                }
+            }
+            Err(e) => {
+                error!("Error initializing node: {:?}", e);
+                return Ok(());
+            }
         }
     }
     let bus = SharedMessageBus::new(BusMetrics::global(config.id.clone()));
@@ -94,19 +242,145 @@ async fn main() -> Result<()> {
         openapi: Default::default(),
     });
 
+    let recorder = match &args.record {
+        Some(path) => Some(Recorder::open(path).await?),
+        None => None,
+    };
+
+    // Each tenant runs on its own bus so prover events never cross deployments
+    let mut tenant_handlers = vec![];
+    for tenant in &tenants {
+        let tenant_bus = SharedMessageBus::new(BusMetrics::global(format!(
+            "{}-{}",
+            config.id,
+            tenant.label()
+        )));
+        let mut tenant_handler = ModulesHandler::new(&tenant_bus).await;
+        build_tenant_modules(
+            &mut tenant_handler,
+            tenant,
+            &args,
+            api_ctx.clone(),
+            node_client.clone(),
+            indexer_client.clone(),
+            secrets.clone(),
+            recorder.clone(),
+        )
+        .await
+        .with_context(|| format!("building modules of tenant {}", tenant.label()))?;
+        tenant_handlers.push(tenant_handler);
+    }
+
+    // Should come last so the other modules have nested their own routes.
+    #[allow(clippy::expect_used, reason = "Fail on misconfiguration")]
+    let router = api_ctx
+        .router
+        .lock()
+        .expect("Context router should be available.")
+        .take()
+        .expect("Context router should be available.");
+    let router = match &recorder {
+        Some(recorder) => router.layer(axum::middleware::from_fn_with_state(
+            recorder.clone(),
+            recorder::record_traffic,
+        )),
+        None => router,
+    };
+    if config.tls.enabled {
+        let listener = tls::TlsListener::bind(&config.tls, &secrets).await?;
+        tokio::spawn(listener.serve(router.clone()));
+    }
+    #[allow(clippy::expect_used, reason = "Fail on misconfiguration")]
+    let openapi = api_ctx
+        .openapi
+        .lock()
+        .expect("OpenAPI should be available")
+        .clone();
+
+    handler
+        .build_module::<RestApi>(RestApiRunContext {
+            port: config.rest_server_port,
+            max_body_size: config.rest_server_max_body_size,
+            registry: Registry::new(),
+            router,
+            openapi,
+            info: NodeInfo {
+                id: config.id.clone(),
+                da_address: config.da_read_from.clone(),
+                pubkey: None,
+            },
+        })
+        .await?;
+
+    for tenant_handler in &mut tenant_handlers {
+        tenant_handler.start_modules().await?;
+    }
+    handler.start_modules().await?;
+
+    let tenant_exits: Vec<_> = tenant_handlers
+        .into_iter()
+        .map(|mut tenant_handler| tokio::spawn(async move { tenant_handler.exit_process().await }))
+        .collect();
+
+    // Run until shut down or an error occurs
+    handler.exit_process().await?;
+    for tenant_exit in tenant_exits {
+        tenant_exit.await??;
+    }
+
+    Ok(())
+}
+
+/// Build the app, indexer, prover (unless proving happens elsewhere) and DA listener of one tenant.
+///
+/// With `--mock-node` only the app and indexer are built.
+#[allow(clippy::too_many_arguments)]
+async fn build_tenant_modules(
+    handler: &mut ModulesHandler,
+    tenant: &Tenant,
+    args: &Args,
+    api_ctx: Arc<BuildApiContextInner>,
+    node_client: Arc<NodeApiHttpClient>,
+    indexer_client: Arc<IndexerApiHttpClient>,
+    secrets: Arc<secrets::Secrets>,
+    recorder: Option<Arc<Recorder>>,
+) -> Result<()> {
+    let config = &tenant.config;
+    std::fs::create_dir_all(&config.data_directory).context("creating data directory")?;
+
+    let mock_node = match args.mock_node {
+        true => Some(Arc::new(MockNode::new(tenant.contract1_cn.clone(), tenant.initial_state()?))),
+        false => None,
+    };
+
     let app_ctx = Arc::new(AppModuleCtx {
         api: api_ctx.clone(),
+        config: config.clone(),
         node_client,
-        contract1_cn: args.contract1_cn.clone().into(),
+        indexer_client,
+        secrets,
+        route_prefix: tenant.route_prefix(),
+        read_only: args.read_only,
+        external_prover: args.external_prover,
+        self_test: args.self_test,
+        mock_node: mock_node.clone(),
+        recorder,
+        genesis_pools: match &args.command {
+            Some(Command::Devnet(devnet_args)) if !devnet_args.no_seed => {
+                config.devnet.genesis_pools.clone()
+            }
+            _ => vec![],
+        },
+        contract1_cn: tenant.contract1_cn.clone(),
         // Contract2 removed - Noir identity will be handled separately
-        contract2_cn: "zkpassport_identity".into(), // Placeholder for Noir contract
+        contract2_cn: tenant.identity_cn.clone(), // Placeholder for Noir contract
     });
 
     handler.build_module::<AppModule>(app_ctx.clone()).await?;
 
     handler
         .build_module::<ContractStateIndexer<Contract1>>(ContractStateIndexerCtx {
-            contract_name: args.contract1_cn.clone().into(),
+            contract_name: tenant.contract1_cn.clone(),
             data_directory: config.data_directory.clone(),
             api: api_ctx.clone(),
         })
@@ -121,17 +395,29 @@ async fn main() -> Result<()> {
     //     })
     //     .await?;
 
-    handler
-        .build_module::<AutoProver<Contract1>>(Arc::new(AutoProverCtx {
-            data_directory: config.data_directory.clone(),
-            prover: Arc::new(Risc0Prover::new(contracts::CONTRACT1_ELF)),
-            contract_name: args.contract1_cn.clone().into(),
-            node: app_ctx.node_client.clone(),
-            default_state: Default::default(),
-            buffer_blocks: config.buffer_blocks,
-            max_txs_per_proof: config.max_txs_per_proof,
-        }))
-        .await?;
+    // The mock node settles transactions itself and has no DA stream to follow
+    if mock_node.is_some() {
+        return Ok(());
+    }
+
+    if !args.read_only && !args.external_prover {
+        // A proof witness only carries the pools of its own transaction
+        #[cfg(feature = "segmented-state")]
+        let max_txs_per_proof = 1;
+        #[cfg(not(feature = "segmented-state"))]
+        let max_txs_per_proof = config.max_txs_per_proof;
+        handler
+            .build_module::<AutoProver<Contract1>>(Arc::new(AutoProverCtx {
+                data_directory: config.data_directory.clone(),
+                prover: Arc::new(Risc0Prover::new(contracts::CONTRACT1_ELF)),
+                contract_name: tenant.contract1_cn.clone(),
+                node: app_ctx.node_client.clone(),
+                default_state: tenant.initial_state()?,
+                buffer_blocks: config.buffer_blocks,
+                max_txs_per_proof,
+            }))
+            .await?;
+    }
 
     // Contract2 prover removed - Noir proofs handled separately
     // handler
@@ -155,40 +441,5 @@ async fn main() -> Result<()> {
         })
         .await?;
 
-    // Should come last so the other modules have nested their own routes.
-    #[allow(clippy::expect_used, reason = "Fail on misconfiguration")]
-    let router = api_ctx
-        .router
-        .lock()
-        .expect("Context router should be available.")
-        .take()
-        .expect("Context router should be available.");
-    #[allow(clippy::expect_used, reason = "Fail on misconfiguration")]
-    let openapi = api_ctx
-        .openapi
-        .lock()
-        .expect("OpenAPI should be available")
-        .clone();
-
-    handler
-        .build_module::<RestApi>(RestApiRunContext {
-            port: config.rest_server_port,
-            max_body_size: config.rest_server_max_body_size,
-            registry: Registry::new(),
-            router,
-            openapi,
-            info: NodeInfo {
-                id: config.id.clone(),
-                da_address: config.da_read_from.clone(),
-                pubkey: None,
-            },
-        })
-        .await?;
-
-    handler.start_modules().await?;
-
-    // Run until shut down or an error occurs
-    handler.exit_process().await?;
-
     Ok(())
 }
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use contract1::Contract1;
+use hyle_modules::modules::prover::AutoProverEvent;
+use sdk::{
+    BlobIndex, BlobTransaction, BlockHeight, Calldata, ContractName, TxContext, TxHash, ZkContract,
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, Mutex};
+
+/// Outcome of a mock transaction, delivered to the app module as if proven
+#[derive(Debug, Clone)]
+pub struct MockSettlement {
+    pub block_height: u64,
+    pub event: AutoProverEvent<Contract1>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Contract1,
+    block_height: u64,
+    /// Executed but not yet announced, see [`MockNode::settle`]
+    pending: HashMap<TxHash, MockSettlement>,
+}
+
+/// In-memory stand-in for the node and the AutoProver (`--mock-node`).
+///
+/// Contract1 blobs are executed right away against a local state, as the prover
+/// would; every other blob (wallet, identity) is accepted as verified. Each transaction gets its
+/// own block and settles as soon as the caller asks for it.
+pub struct MockNode {
+    contract1_cn: ContractName,
+    inner: Mutex<Inner>,
+    settlements: broadcast::Sender<MockSettlement>,
+}
+
+impl MockNode {
+    pub fn new(contract1_cn: ContractName, initial_state: Contract1) -> Self {
+        let (settlements, _) = broadcast::channel(256);
+        Self {
+            contract1_cn,
+            inner: Mutex::new(Inner {
+                state: initial_state,
+                ..Default::default()
+            }),
+            settlements,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MockSettlement> {
+        self.settlements.subscribe()
+    }
+
+    /// Sequence and execute a transaction; a failing action leaves the state untouched
+    pub async fn send_tx_blob(&self, tx: BlobTransaction) -> Result<TxHash> {
+        if tx.blobs.is_empty() {
+            bail!("Transaction has no blobs");
+        }
+
+        let mut inner = self.inner.lock().await;
+        inner.block_height += 1;
+        let block_height = inner.block_height;
+
+        let mut hasher = Sha256::new();
+        hasher.update(block_height.to_be_bytes());
+        hasher.update(tx.identity.0.as_bytes());
+        for blob in &tx.blobs {
+            hasher.update(blob.contract_name.0.as_bytes());
+            hasher.update(&blob.data.0);
+        }
+        let tx_hash = TxHash(hex::encode(hasher.finalize()));
+
+        let mut state = inner.state.clone();
+        let mut error = None;
+        let contract1_blobs = tx
+            .blobs
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.contract_name == self.contract1_cn);
+        for (index, _) in contract1_blobs {
+            let calldata = Calldata {
+                tx_hash: tx_hash.clone(),
+                identity: tx.identity.clone(),
+                blobs: tx.blobs.clone().into(),
+                tx_blob_count: tx.blobs.len(),
+                index: BlobIndex(index),
+                tx_ctx: Some(TxContext {
+                    block_height: BlockHeight(block_height),
+                    ..Default::default()
+                }),
+                private_input: vec![],
+            };
+            if let Err(e) = state.execute(&calldata) {
+                error = Some(e);
+                break;
+            }
+        }
+
+        let event = match error {
+            Some(error) => AutoProverEvent::FailedTx(tx_hash.clone(), error),
+            None => {
+                inner.state = state.clone();
+                AutoProverEvent::SuccessTx(tx_hash.clone(), state)
+            }
+        };
+        inner
+            .pending
+            .insert(tx_hash.clone(), MockSettlement { block_height, event });
+
+        tracing::info!("🧪 Mock node sequenced {} in block {}", tx_hash, block_height);
+        Ok(tx_hash)
+    }
+
+    /// Replace the state, e.g. with an imported dump; later blocks follow the ones it has seen
+    pub async fn replace_state(&self, state: Contract1) {
+        let mut inner = self.inner.lock().await;
+        inner.block_height = inner.block_height.max(state.block_height());
+        inner.state = state;
+    }
+
+    /// Announce the outcome of a sequenced transaction.
+    ///
+    /// Split from `send_tx_blob` so callers can start tracking the hash first.
+    pub async fn settle(&self, tx_hash: &TxHash) {
+        if let Some(settlement) = self.inner.lock().await.pending.remove(tx_hash) {
+            // No subscriber simply means nobody is listening
+            let _ = self.settlements.send(settlement);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Validation of witness inputs against the ABI `nargo compile` writes next to the circuit.
+//!
+//! `nargo prove` reports a malformed `Prover.toml` with errors about its own internals, if at all;
+//! checking inputs first names the input at fault and what is wrong with it.
+
+use std::{fmt, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Order of the BN254 scalar field, which Noir fields live in, in decimal
+const FIELD_MODULUS: &str = "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+#[derive(Debug, Deserialize)]
+pub struct CircuitAbi {
+    pub parameters: Vec<AbiParameter>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AbiParameter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub typ: AbiType,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sign {
+    Unsigned,
+    Signed,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum AbiType {
+    Field,
+    Boolean,
+    Integer {
+        sign: Sign,
+        width: u32,
+    },
+    Array {
+        length: usize,
+        #[serde(rename = "type")]
+        typ: Box<AbiType>,
+    },
+    String {
+        length: usize,
+    },
+    Struct {
+        fields: Vec<AbiParameter>,
+    },
+    Tuple {
+        fields: Vec<AbiType>,
+    },
+}
+
+/// What is wrong with one input, named by its path in the witness, e.g. `user.keys[2]`
+#[derive(Debug)]
+pub struct InputError {
+    pub input: String,
+    pub problem: String,
+}
+
+/// Every input of a witness that does not match the ABI
+#[derive(Debug)]
+pub struct InvalidWitness(pub Vec<InputError>);
+
+impl fmt::Display for InvalidWitness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid circuit inputs: ")?;
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", error.input, error.problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidWitness {}
+
+#[derive(Deserialize)]
+struct CompiledCircuit {
+    abi: CircuitAbi,
+}
+
+impl CircuitAbi {
+    /// Read the ABI of a compiled circuit, e.g. `target/zkpassport_identity.json`
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read(path).with_context(|| format!("reading the circuit ABI {:?}", path))?;
+        let circuit: CompiledCircuit =
+            serde_json::from_slice(&json).with_context(|| format!("parsing the circuit ABI {:?}", path))?;
+        Ok(circuit.abi)
+    }
+
+    /// Check that a witness gives every parameter, and nothing else, a value of its type
+    pub fn validate(&self, witness: &Value) -> Result<(), InvalidWitness> {
+        let mut errors = Vec::new();
+        check_fields(&self.parameters, witness, "", &mut errors);
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(InvalidWitness(errors)),
+        }
+    }
+}
+
+fn check_fields(fields: &[AbiParameter], value: &Value, path: &str, errors: &mut Vec<InputError>) {
+    let join = |name: &str| match path {
+        "" => name.to_string(),
+        _ => format!("{}.{}", path, name),
+    };
+    let Some(object) = value.as_object() else {
+        return errors.push(error(path, "expected a table of inputs".to_string()));
+    };
+    for field in fields {
+        match object.get(&field.name) {
+            Some(value) => check(&field.typ, value, &join(&field.name), errors),
+            None => errors.push(error(&join(&field.name), "missing input".to_string())),
+        }
+    }
+    for name in object.keys() {
+        if !fields.iter().any(|field| &field.name == name) {
+            errors.push(error(&join(name), "not an input of the circuit".to_string()));
+        }
+    }
+}
+
+fn check(typ: &AbiType, value: &Value, path: &str, errors: &mut Vec<InputError>) {
+    let problem = match typ {
+        AbiType::Field => scalar(value).and_then(check_field_element),
+        AbiType::Boolean => match value {
+            Value::Bool(_) => Ok(()),
+            Value::String(s) if s == "true" || s == "false" => Ok(()),
+            _ => Err("expected a boolean".to_string()),
+        },
+        AbiType::Integer { sign, width } => scalar(value).and_then(|s| check_integer(&s, sign, *width)),
+        AbiType::String { length } => match value {
+            Value::String(s) if s.len() == *length => Ok(()),
+            Value::String(s) => Err(format!("expected a string of {} bytes, got {}", length, s.len())),
+            _ => Err("expected a string".to_string()),
+        },
+        AbiType::Array { length, typ } => match value.as_array() {
+            Some(items) if items.len() == *length => {
+                for (i, item) in items.iter().enumerate() {
+                    check(typ, item, &format!("{}[{}]", path, i), errors);
+                }
+                Ok(())
+            }
+            Some(items) => Err(format!("expected {} elements, got {}", length, items.len())),
+            None => Err(format!("expected an array of {} elements", length)),
+        },
+        AbiType::Tuple { fields } => match value.as_array() {
+            Some(items) if items.len() == fields.len() => {
+                for (i, (typ, item)) in fields.iter().zip(items).enumerate() {
+                    check(typ, item, &format!("{}.{}", path, i), errors);
+                }
+                Ok(())
+            }
+            _ => Err(format!("expected a tuple of {} elements", fields.len())),
+        },
+        AbiType::Struct { fields } => {
+            check_fields(fields, value, path, errors);
+            Ok(())
+        }
+    };
+    if let Err(problem) = problem {
+        errors.push(error(path, problem));
+    }
+}
+
+/// A numeric input, which TOML and JSON witnesses give as a string or a number
+fn scalar(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err("expected a number".to_string()),
+    }
+}
+
+fn check_field_element(value: String) -> Result<(), String> {
+    let decimal = match value.strip_prefix("0x") {
+        Some(hex) => hex_to_decimal(hex).ok_or_else(|| format!("{} is not a hexadecimal number", value))?,
+        None if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => value.clone(),
+        None => return Err(format!("{} is not a field element", value)),
+    };
+    let digits = decimal.trim_start_matches('0');
+    // Equal-length decimal strings order like the numbers they spell
+    if (digits.len(), digits) >= (FIELD_MODULUS.len(), FIELD_MODULUS) {
+        return Err(format!("{} is not below the field modulus", value));
+    }
+    Ok(())
+}
+
+fn check_integer(value: &str, sign: &Sign, width: u32) -> Result<(), String> {
+    let not_integer = || format!("{} is not an integer of {} bits", value, width);
+    let fits = match sign {
+        Sign::Unsigned => {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u128::from_str_radix(hex, 16),
+                None => value.parse::<u128>(),
+            }
+            .map_err(|_| not_integer())?;
+            width >= 128 || parsed >> width == 0
+        }
+        Sign::Signed => {
+            let parsed = value.parse::<i128>().map_err(|_| not_integer())?;
+            let bound = 1i128.checked_shl(width.saturating_sub(1)).filter(|_| width < 128);
+            bound.is_none_or(|bound| (-bound..bound).contains(&parsed))
+        }
+    };
+    match fits {
+        true => Ok(()),
+        false => Err(format!("{} does not fit in {} bits", value, width)),
+    }
+}
+
+/// Decimal digits of a hexadecimal number of any length
+fn hex_to_decimal(hex: &str) -> Option<String> {
+    if hex.is_empty() {
+        return None;
+    }
+    // Little-endian base 10^9 limbs
+    let mut limbs: Vec<u64> = vec![0];
+    for c in hex.chars() {
+        let mut carry = c.to_digit(16)? as u64;
+        for limb in limbs.iter_mut() {
+            let value = *limb * 16 + carry;
+            *limb = value % 1_000_000_000;
+            carry = value / 1_000_000_000;
+        }
+        if carry > 0 {
+            limbs.push(carry);
+        }
+    }
+    let mut decimal = limbs.last()?.to_string();
+    for limb in limbs.iter().rev().skip(1) {
+        decimal.push_str(&format!("{:09}", limb));
+    }
+    Some(decimal)
+}
+
+fn error(input: &str, problem: String) -> InputError {
+    InputError {
+        input: input.to_string(),
+        problem,
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    process::ExitStatus,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::{broadcast, watch, Mutex, OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::conf::NoirJobsConf;
+
+/// Jobs whose log is kept for late subscribers, the oldest being dropped first
+const MAX_JOBS: usize = 100;
+/// Lines kept per job; `nargo` and `bb` print a few dozen
+const MAX_LINES: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobLine {
+    /// A step of the job, e.g. "compiling circuit"
+    Stage { stage: String },
+    /// A line a tool printed, on stdout or stderr
+    Output { tool: String, line: String },
+    /// Last line of a job
+    Done { success: bool, message: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEntry {
+    pub seq: usize,
+    #[serde(flatten)]
+    pub line: JobLine,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobEvent {
+    pub job_id: String,
+    pub entry: JobEntry,
+}
+
+#[derive(Default)]
+struct JobLogs {
+    entries: HashMap<String, Vec<JobEntry>>,
+    /// Job ids, oldest first
+    order: VecDeque<String>,
+}
+
+/// Noir proof jobs: the slots they run in, their limits, and their progress logs streamed to
+/// clients over SSE
+pub struct NoirJobs {
+    conf: NoirJobsConf,
+    /// One permit per job allowed to run at once, later jobs queue for one
+    slots: Arc<Semaphore>,
+    /// Cancellation switch of each queued or running job
+    running: std::sync::Mutex<HashMap<String, watch::Sender<bool>>>,
+    logs: Mutex<JobLogs>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl NoirJobs {
+    pub fn new(conf: NoirJobsConf) -> Self {
+        NoirJobs {
+            slots: Arc::new(Semaphore::new(conf.max_concurrent.max(1))),
+            conf,
+            running: Default::default(),
+            logs: Default::default(),
+            events: broadcast::channel(1_024).0,
+        }
+    }
+
+    /// Queue a job for a slot, then start its clock.
+    ///
+    /// Progress is only logged under an id the client picked; others get one for cancellation.
+    pub async fn start(self: &Arc<Self>, job_id: Option<String>) -> Result<Job> {
+        let reported = job_id.is_some();
+        let id = job_id.unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+        let (cancel, cancelled) = watch::channel(false);
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.contains_key(&id) {
+                bail!("Noir job {} is already running", id);
+            }
+            running.insert(id.clone(), cancel);
+        }
+        let mut job = Job {
+            jobs: self.clone(),
+            id,
+            reported,
+            cancelled,
+            deadline: Instant::now(),
+            _slot: None,
+        };
+
+        if self.slots.available_permits() == 0 {
+            job.stage("waiting for a prover…").await;
+        }
+        let mut cancelled = job.cancelled.clone();
+        job._slot = tokio::select! {
+            slot = self.slots.clone().acquire_owned() => Some(slot?),
+            _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+        };
+        if job._slot.is_none() {
+            job.done(false, "Cancelled while waiting for a prover").await;
+            bail!("Noir job {} was cancelled", job.id);
+        }
+        job.deadline = Instant::now() + Duration::from_secs(self.conf.timeout_secs);
+        Ok(job)
+    }
+
+    /// Stop a queued or running job, killing the tools it runs; false if there is none
+    pub fn cancel(&self, job_id: &str) -> bool {
+        match self.running.lock().unwrap().get(job_id) {
+            Some(cancel) => {
+                cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    /// Lines a job logged so far
+    pub async fn log(&self, job_id: &str) -> Vec<JobEntry> {
+        self.logs.lock().await.entries.get(job_id).cloned().unwrap_or_default()
+    }
+
+    async fn push(&self, job_id: &str, line: JobLine) {
+        let mut logs = self.logs.lock().await;
+        let JobLogs { entries, order } = &mut *logs;
+        if !entries.contains_key(job_id) {
+            if order.len() >= MAX_JOBS {
+                if let Some(oldest) = order.pop_front() {
+                    entries.remove(&oldest);
+                }
+            }
+            order.push_back(job_id.to_string());
+        }
+        let log = entries.entry(job_id.to_string()).or_default();
+        if log.len() >= MAX_LINES && !matches!(line, JobLine::Done { .. }) {
+            return;
+        }
+        let entry = JobEntry { seq: log.len(), line };
+        log.push(entry.clone());
+        let _ = self.events.send(JobEvent {
+            job_id: job_id.to_string(),
+            entry,
+        });
+    }
+}
+
+/// A queued or running Noir job, holding its slot until dropped
+pub struct Job {
+    jobs: Arc<NoirJobs>,
+    id: String,
+    /// Whether the client follows this job's log
+    reported: bool,
+    cancelled: watch::Receiver<bool>,
+    /// When the job is killed, counted from when it got its slot
+    deadline: Instant,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl Job {
+    pub async fn stage(&self, stage: &str) {
+        self.push(JobLine::Stage { stage: stage.to_string() }).await;
+    }
+
+    pub async fn done(&self, success: bool, message: &str) {
+        self.push(JobLine::Done {
+            success,
+            message: message.to_string(),
+        })
+        .await;
+    }
+
+    /// Fail once the job was cancelled or ran out of time, checked between steps
+    pub fn check(&self) -> Result<()> {
+        if *self.cancelled.borrow() {
+            bail!("Noir job {} was cancelled", self.id);
+        }
+        if Instant::now() >= self.deadline {
+            bail!("Noir job {} timed out after {}s", self.id, self.jobs.conf.timeout_secs);
+        }
+        Ok(())
+    }
+
+    async fn output(&self, tool: &str, line: &str) {
+        self.push(JobLine::Output {
+            tool: tool.to_string(),
+            line: line.to_string(),
+        })
+        .await;
+    }
+
+    async fn push(&self, line: JobLine) {
+        if self.reported {
+            self.jobs.push(&self.id, line).await;
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.jobs.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+pub struct ToolOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run a tool within the job's limits, forwarding every line it prints to the job's log as it
+/// comes.
+///
+/// The tool leads its own process group, which is killed whole, helpers included, when the job is
+/// cancelled or times out.
+pub async fn run_streamed(tool: &str, args: &[&str], dir: &str, job: &Job) -> Result<ToolOutput> {
+    job.check()?;
+    let mut command = Command::new(tool);
+    command
+        .args(args)
+        .current_dir(dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .process_group(0);
+    limit_resources(&mut command, &job.jobs.conf);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to execute {} {}", tool, args.join(" ")))?;
+    let pid = child.id().context("tool exited before it was watched")?;
+    let stdout = child.stdout.take().context("stdout is piped")?;
+    let stderr = child.stderr.take().context("stderr is piped")?;
+
+    let run = async {
+        let (stdout, stderr) = tokio::join!(forward(tool, stdout, job), forward(tool, stderr, job));
+        let status = child.wait().await?;
+        anyhow::Ok(ToolOutput { status, stdout, stderr })
+    };
+    let mut cancelled = job.cancelled.clone();
+    let stopped = tokio::select! {
+        output = run => return output,
+        _ = tokio::time::sleep_until(job.deadline) => "timed out",
+        _ = cancelled.wait_for(|cancelled| *cancelled) => "cancelled",
+    };
+    // SAFETY: killpg only sends a signal, to the group the tool leads
+    unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+    tracing::warn!("🛑 Killed {} {} of Noir job {}: {}", tool, args.join(" "), job.id, stopped);
+    job.check()?;
+    bail!("{} {} of Noir job {} was {}", tool, args.join(" "), job.id, stopped)
+}
+
+/// Cap the address space and CPU time of a tool, and of the helpers it starts, which inherit them
+fn limit_resources(command: &mut Command, conf: &NoirJobsConf) {
+    let limits = [
+        (libc::RLIMIT_AS, conf.max_memory_bytes),
+        (libc::RLIMIT_CPU, conf.max_cpu_secs),
+    ];
+    // SAFETY: the closure only calls setrlimit, which is async-signal-safe, between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, limit) in limits {
+                let Some(limit) = limit else { continue };
+                let rlimit = libc::rlimit {
+                    rlim_cur: limit as libc::rlim_t,
+                    rlim_max: limit as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &rlimit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+async fn forward(tool: &str, stream: impl AsyncRead + Unpin, job: &Job) -> String {
+    let mut lines = BufReader::new(stream).lines();
+    let mut output = String::new();
+    while let Ok(Some(line)) = lines.next_line().await {
+        job.output(tool, &line).await;
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
@@ -0,0 +1,160 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::noir_verifier::NoirProof;
+use crate::storage::{self, Storage, Write};
+
+/// Storage namespace of proofs, keyed by proof hash
+const NAMESPACE: &str = "noir_proofs";
+/// Storage namespace indexing proofs by `by_time_key`, for pruning
+const BY_TIME_NAMESPACE: &str = "noir_proofs_by_time";
+/// Sub-directory of the data directory proofs were kept in, one JSON file per proof hash
+const LEGACY_PROOFS_DIR: &str = "noir_proofs";
+
+/// A submitted identity proof, with everything needed to verify it again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredProof {
+    /// Hex SHA-256 of the proof, which it is stored under
+    pub proof_hash: String,
+    /// Hex proof bytes
+    pub proof: String,
+    pub public_inputs: Vec<String>,
+    /// Hex verification key the proof was checked against before submission
+    pub verification_key: String,
+    pub vk_hash: String,
+    pub identity: String,
+    pub tx_hash: String,
+    pub stored_at: i64,
+}
+
+impl StoredProof {
+    pub fn new(proof: &NoirProof, identity: &str, tx_hash: &str) -> Self {
+        StoredProof {
+            proof_hash: proof_hash(&proof.proof_data),
+            proof: hex::encode(&proof.proof_data),
+            public_inputs: proof.public_inputs.clone(),
+            verification_key: hex::encode(&proof.verification_key),
+            vk_hash: hex::encode(Sha256::digest(&proof.verification_key)),
+            identity: identity.to_string(),
+            tx_hash: tx_hash.to_string(),
+            stored_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Hex SHA-256 of proof bytes, the address of a stored proof
+pub fn proof_hash(proof_data: &[u8]) -> String {
+    hex::encode(Sha256::digest(proof_data))
+}
+
+/// Submitted proofs kept in the server storage under their hash
+pub struct ProofStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl ProofStore {
+    /// Open the store, importing the proofs of the data directory's `noir_proofs/` once
+    pub async fn load(storage: Arc<dyn Storage>, data_directory: &Path) -> Result<Self> {
+        let store = ProofStore { storage };
+        let dir = data_directory.join(LEGACY_PROOFS_DIR);
+        if tokio::fs::try_exists(&dir).await.unwrap_or(false) {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            let mut imported = 0;
+            while let Some(entry) = entries.next_entry().await? {
+                let Ok(json) = tokio::fs::read(entry.path()).await else {
+                    continue;
+                };
+                match serde_json::from_slice::<StoredProof>(&json) {
+                    Ok(proof) => {
+                        store.store(&proof).await?;
+                        imported += 1;
+                    }
+                    Err(e) => tracing::warn!("⚠️ Skipping unreadable proof {:?}: {}", entry.path(), e),
+                }
+            }
+            tokio::fs::rename(&dir, storage::imported(&dir))
+                .await
+                .with_context(|| format!("renaming {:?}", dir))?;
+            tracing::info!("🗄️ Imported {} proofs of {:?} into storage", imported, dir);
+        }
+        Ok(store)
+    }
+
+    /// Persist a proof under its hash; storing the same proof again keeps the first copy
+    pub async fn store(&self, proof: &StoredProof) -> Result<()> {
+        if !well_formed(&proof.proof_hash) {
+            bail!("proof hash is not a SHA-256");
+        }
+        if self.storage.get(NAMESPACE, proof.proof_hash.as_bytes()).await?.is_some() {
+            return Ok(());
+        }
+        self.storage
+            .apply(vec![
+                Write::put(NAMESPACE, proof.proof_hash.clone(), serde_json::to_vec(proof)?),
+                Write::put(BY_TIME_NAMESPACE, by_time_key(proof.stored_at, &proof.proof_hash), vec![]),
+            ])
+            .await
+            .with_context(|| format!("storing proof {}", proof.proof_hash))
+    }
+
+    /// The proof stored under a hash, `None` for unknown or malformed hashes
+    pub async fn get(&self, proof_hash: &str) -> Result<Option<StoredProof>> {
+        if !well_formed(proof_hash) {
+            return Ok(None);
+        }
+        match self.storage.get(NAMESPACE, proof_hash.as_bytes()).await? {
+            Some(json) => Ok(Some(
+                serde_json::from_slice(&json).with_context(|| format!("parsing proof {}", proof_hash))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Proofs stored within `[from, to)`, oldest first
+    pub async fn stored_between(&self, from: i64, to: i64) -> Result<Vec<StoredProof>> {
+        // Keys exactly at `to` sort after it, only earlier ones are here
+        let keys = self
+            .storage
+            .range(BY_TIME_NAMESPACE, &from.max(0).to_be_bytes(), Some(&to.max(0).to_be_bytes()))
+            .await?;
+        let mut proofs = vec![];
+        for (key, _) in keys {
+            let proof_hash = String::from_utf8_lossy(&key[8..]).to_string();
+            if let Some(proof) = self.get(&proof_hash).await? {
+                proofs.push(proof);
+            }
+        }
+        Ok(proofs)
+    }
+
+    /// Drop the proofs stored before `cutoff`, returning how many were
+    pub async fn prune(&self, cutoff: i64) -> Result<usize> {
+        let expired = self
+            .storage
+            .range(BY_TIME_NAMESPACE, &[], Some(&cutoff.max(0).to_be_bytes()))
+            .await?;
+        let mut writes = vec![];
+        for (key, _) in &expired {
+            // Keys exactly at the cutoff sort after it, only older ones are here
+            writes.push(Write::remove(NAMESPACE, &key[8..]));
+            writes.push(Write::remove(BY_TIME_NAMESPACE, key.as_slice()));
+        }
+        self.storage.apply(writes).await?;
+        Ok(expired.len())
+    }
+}
+
+/// `<stored_at, big endian><proof hash>`, so proofs sort by age
+fn by_time_key(stored_at: i64, proof_hash: &str) -> Vec<u8> {
+    let mut key = stored_at.max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(proof_hash.as_bytes());
+    key
+}
+
+/// Only lowercase hex SHA-256 are proof hashes
+fn well_formed(proof_hash: &str) -> bool {
+    proof_hash.len() == 64 && proof_hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
@@ -1,20 +1,36 @@
 use std::sync::Arc;
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
+use borsh::{BorshDeserialize, BorshSerialize};
 use sdk::{Blob, ContractName, BlobTransaction};
 use client_sdk::rest_client::{NodeApiHttpClient, NodeApiClient};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
+use crate::mock_node::MockNode;
+
+/// Envelope version of identity proof blobs, as their first byte.
+///
+/// Blobs submitted before it are a JSON object, whose first byte is `{`.
+const PROOF_BLOB_VERSION: u8 = 1;
+/// Largest UltraHonk proof accepted, far above the few kilobytes they weigh
+const MAX_PROOF_BYTES: usize = 1024 * 1024;
+const MAX_VERIFICATION_KEY_BYTES: usize = 64 * 1024;
+const MAX_PUBLIC_INPUTS: usize = 64;
+/// Largest identity blob, kept under the transaction size the node accepts
+const MAX_PROOF_BLOB_BYTES: usize = 2 * 1024 * 1024;
+
 /// Noir proof verification module for UltraHonk backend integration
 pub struct NoirVerifier {
     contract_name: ContractName,
     node_client: Arc<NodeApiHttpClient>,
+    mock_node: Option<Arc<MockNode>>,
     verification_stats: Arc<Mutex<VerificationStats>>,
 }
 
 pub struct NoirVerifierCtx {
     pub contract_name: ContractName,
     pub node_client: Arc<NodeApiHttpClient>,
+    pub mock_node: Option<Arc<MockNode>>,
 }
 
 #[derive(Debug, Clone)]
@@ -48,6 +64,7 @@ impl NoirVerifier {
         Self {
             contract_name: ctx.contract_name,
             node_client: ctx.node_client,
+            mock_node: ctx.mock_node,
             verification_stats: Arc::new(Mutex::new(VerificationStats::default())),
         }
     }
@@ -64,11 +81,21 @@ impl NoirVerifier {
         let proof_blob = self.create_proof_blob(proof)?;
         let blob_tx = BlobTransaction::new(user_identity.clone(), vec![proof_blob]);
 
+        #[cfg(feature = "chaos")]
+        crate::chaos::node_fault("send_tx_blob")?;
+
         // Submit transaction to Hyli node
-        let tx_hash = self.node_client
-            .send_tx_blob(blob_tx)
-            .await
-            .context("Failed to submit Noir proof transaction to Hyli")?;
+        let tx_hash = match &self.mock_node {
+            Some(mock) => {
+                let tx_hash = mock.send_tx_blob(blob_tx).await?;
+                mock.settle(&tx_hash).await;
+                tx_hash
+            }
+            None => self.node_client
+                .send_tx_blob(blob_tx)
+                .await
+                .context("Failed to submit Noir proof transaction to Hyli")?,
+        };
 
         tracing::info!("✅ Noir proof submitted to chain with tx_hash: {}", tx_hash);
         Ok(tx_hash.to_string())
@@ -114,7 +141,7 @@ impl NoirVerifier {
     }
 
     /// Create proof blob for chain submission
-    fn create_proof_blob(&self, proof: NoirProof) -> Result<Blob> {
+    pub fn create_proof_blob(&self, proof: NoirProof) -> Result<Blob> {
         // Serialize proof data for blockchain storage
         let proof_payload = ProofPayload {
             proof_data: proof.proof_data,
@@ -124,12 +151,9 @@ impl NoirVerifier {
             proof_type: "ultrahonk".to_string(),
         };
 
-        let serialized_proof = serde_json::to_vec(&proof_payload)
-            .context("Failed to serialize Noir proof for blockchain submission")?;
-
         Ok(Blob {
             contract_name: self.contract_name.clone(),
-            data: sdk::BlobData(serialized_proof),
+            data: sdk::BlobData(encode_proof_blob(&proof_payload)?),
         })
     }
 
@@ -152,7 +176,7 @@ impl NoirVerifier {
         }
 
         // Validate proof data size (reasonable bounds)
-        if proof.proof_data.len() < 32 || proof.proof_data.len() > 1024 * 1024 {
+        if proof.proof_data.len() < 32 || proof.proof_data.len() > MAX_PROOF_BYTES {
             tracing::warn!("❌ Invalid proof: proof data size out of bounds ({})", proof.proof_data.len());
             return Ok(false);
         }
@@ -163,7 +187,7 @@ impl NoirVerifier {
 }
 
 /// Proof payload for blockchain storage
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 struct ProofPayload {
     proof_data: Vec<u8>,
     public_inputs: Vec<String>,
@@ -172,5 +196,63 @@ struct ProofPayload {
     proof_type: String,
 }
 
+impl ProofPayload {
+    fn check_limits(&self) -> Result<()> {
+        if self.proof_data.len() > MAX_PROOF_BYTES {
+            bail!("proof of {} bytes exceeds {} bytes", self.proof_data.len(), MAX_PROOF_BYTES);
+        }
+        if self.verification_key.len() > MAX_VERIFICATION_KEY_BYTES {
+            bail!(
+                "verification key of {} bytes exceeds {} bytes",
+                self.verification_key.len(),
+                MAX_VERIFICATION_KEY_BYTES
+            );
+        }
+        if self.public_inputs.len() > MAX_PUBLIC_INPUTS {
+            bail!("{} public inputs exceed {}", self.public_inputs.len(), MAX_PUBLIC_INPUTS);
+        }
+        Ok(())
+    }
+}
+
+/// Encode a proof as the versioned borsh envelope, rather than JSON spelling each byte as a number
+fn encode_proof_blob(payload: &ProofPayload) -> Result<Vec<u8>> {
+    payload.check_limits()?;
+    let mut data = vec![PROOF_BLOB_VERSION];
+    BorshSerialize::serialize(payload, &mut data).context("Failed to serialize Noir proof for blockchain submission")?;
+    if data.len() > MAX_PROOF_BLOB_BYTES {
+        bail!("identity blob of {} bytes exceeds {} bytes", data.len(), MAX_PROOF_BLOB_BYTES);
+    }
+    Ok(data)
+}
+
+/// Decode an identity blob, whether a borsh envelope or JSON from before it
+fn decode_proof_blob(data: &[u8]) -> Result<ProofPayload> {
+    if data.len() > MAX_PROOF_BLOB_BYTES {
+        bail!("identity blob of {} bytes exceeds {} bytes", data.len(), MAX_PROOF_BLOB_BYTES);
+    }
+    let payload: ProofPayload = match data.split_first() {
+        Some((&PROOF_BLOB_VERSION, envelope)) => borsh::from_slice(envelope)?,
+        Some((b'{', _)) => serde_json::from_slice(data)?,
+        Some((version, _)) => bail!("unknown identity blob version {}", version),
+        None => bail!("empty identity blob"),
+    };
+    payload.check_limits()?;
+    Ok(payload)
+}
+
+/// Human-readable description of an identity proof blob
+pub fn describe_proof_blob(data: &[u8]) -> String {
+    match decode_proof_blob(data) {
+        Ok(payload) => format!(
+            "Noir {} identity proof ({} bytes, {} public inputs)",
+            payload.proof_type,
+            payload.proof_data.len(),
+            payload.public_inputs.len()
+        ),
+        Err(_) => format!("Undecodable identity blob ({} bytes)", data.len()),
+    }
+}
+
 // TODO: Implement actual UltraHonk verification when Hyli provides the integration
 // This module provides the foundation for real proof verification 
\ No newline at end of file
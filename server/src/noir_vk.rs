@@ -0,0 +1,226 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use client_sdk::rest_client::IndexerApiHttpClient;
+use opentelemetry::metrics::Gauge;
+use sdk::ContractName;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+/// Verification key of the Noir identity circuit, as last loaded from `target/vk`
+pub struct VerificationKey {
+    pub bytes: Vec<u8>,
+    /// Hex SHA-256 of the key, which clients compare to know when to fetch it again
+    pub hash: String,
+}
+
+impl VerificationKey {
+    fn new(bytes: Vec<u8>) -> Self {
+        let hash = hex::encode(Sha256::digest(&bytes));
+        VerificationKey { bytes, hash }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Rotation {
+    pub previous_hash: Option<String>,
+    pub hash: String,
+    /// Whether the key changed, in which case cached proofs were dropped
+    pub rotated: bool,
+    pub invalidated_proofs: usize,
+}
+
+/// The identity contract, registered with the verification key as program id
+pub struct Registration {
+    pub indexer: Arc<IndexerApiHttpClient>,
+    pub contract_name: ContractName,
+}
+
+/// The verification key the server serves, and checks browser proofs against.
+///
+/// It is read once and kept until rotated, so a circuit being rebuilt in place never serves a
+/// half-written key.
+pub struct VkStore {
+    vk_path: PathBuf,
+    /// Proofs generated against the current key, stale once it rotates
+    proofs_dir: PathBuf,
+    current: RwLock<Option<Arc<VerificationKey>>>,
+    /// Unset against a mock node, which registers no identity contract
+    registration: Option<Registration>,
+    /// Hex SHA-256 of the key the identity contract was registered with, once read
+    registered_hash: RwLock<Option<String>>,
+    /// Whether the last submission was refused for a key the contract does not verify
+    diverged: AtomicBool,
+    diverged_gauge: Gauge<u64>,
+}
+
+impl VkStore {
+    pub fn new(circuit_dir: &Path, registration: Option<Registration>) -> Self {
+        VkStore {
+            vk_path: circuit_dir.join("target").join("vk"),
+            proofs_dir: circuit_dir.join("proofs"),
+            current: RwLock::new(None),
+            registration,
+            registered_hash: RwLock::new(None),
+            diverged: AtomicBool::new(false),
+            diverged_gauge: opentelemetry::global::meter("hyli_defi_app")
+                .u64_gauge("noir_vk_diverged")
+                .build(),
+        }
+    }
+
+    /// The current key, read on first use
+    pub async fn current(&self) -> Result<Arc<VerificationKey>> {
+        if let Some(vk) = self.current.read().await.as_ref() {
+            return Ok(vk.clone());
+        }
+        let mut current = self.current.write().await;
+        if let Some(vk) = current.as_ref() {
+            return Ok(vk.clone());
+        }
+        let vk = Arc::new(self.read()?);
+        *current = Some(vk.clone());
+        Ok(vk)
+    }
+
+    /// Load `target/vk` again after the circuit changed, dropping the proofs made for the old key
+    pub async fn rotate(&self) -> Result<Rotation> {
+        let vk = self.read()?;
+        let mut current = self.current.write().await;
+        let previous_hash = current.as_ref().map(|previous| previous.hash.clone());
+        if previous_hash.as_ref() == Some(&vk.hash) {
+            return Ok(Rotation {
+                previous_hash,
+                hash: vk.hash,
+                rotated: false,
+                invalidated_proofs: 0,
+            });
+        }
+
+        let invalidated_proofs = self.clear_proofs()?;
+        tracing::warn!(
+            "🔑 Rotated the Noir verification key from {} to {}, dropping {} cached proofs",
+            previous_hash.as_deref().unwrap_or("none"),
+            vk.hash,
+            invalidated_proofs
+        );
+        let hash = vk.hash.clone();
+        *current = Some(Arc::new(vk));
+        Ok(Rotation {
+            previous_hash,
+            hash,
+            rotated: true,
+            invalidated_proofs,
+        })
+    }
+
+    /// What `rotate` would do now, without loading the key or dropping proofs
+    pub async fn preview_rotation(&self) -> Result<Rotation> {
+        let vk = self.read()?;
+        let previous_hash = self.current.read().await.as_ref().map(|previous| previous.hash.clone());
+        let rotated = previous_hash.as_ref() != Some(&vk.hash);
+        Ok(Rotation {
+            previous_hash,
+            hash: vk.hash,
+            rotated,
+            invalidated_proofs: if rotated { self.cached_proofs()?.len() } else { 0 },
+        })
+    }
+
+    /// Hash of the key the identity contract was registered with, pinned on the first successful
+    /// read from the indexer; `None` while the contract cannot be read
+    pub async fn registered_hash(&self) -> Option<String> {
+        if let Some(hash) = self.registered_hash.read().await.as_ref() {
+            return Some(hash.clone());
+        }
+        let registration = self.registration.as_ref()?;
+        let contract = match registration.indexer.get_indexer_contract(&registration.contract_name).await {
+            Ok(contract) => contract,
+            Err(e) => {
+                tracing::warn!(
+                    "⚠️ Could not read the registration of {}, its verification key is not pinned yet: {}",
+                    registration.contract_name,
+                    e
+                );
+                return None;
+            }
+        };
+        let hash = hex::encode(Sha256::digest(&contract.program_id));
+        tracing::info!("📌 Pinned the verification key {} registered for {}", hash, registration.contract_name);
+        *self.registered_hash.write().await = Some(hash.clone());
+        Some(hash)
+    }
+
+    /// Hash of the registered key if already pinned, without reading the indexer
+    pub async fn pinned_hash(&self) -> Option<String> {
+        self.registered_hash.read().await.clone()
+    }
+
+    /// Refuse to submit a proof made with another key than the identity contract was registered
+    /// with, which the contract could never verify
+    pub async fn check_registered(&self, verification_key: &[u8]) -> Result<()> {
+        let Some(registered) = self.registered_hash().await else {
+            return Ok(());
+        };
+        let hash = hex::encode(Sha256::digest(verification_key));
+        let diverged = hash != registered;
+        self.diverged_gauge.record(diverged as u64, &[]);
+        if !diverged {
+            if self.diverged.swap(false, Ordering::Relaxed) {
+                tracing::info!("✅ The Noir verification key matches the registered one again");
+            }
+            return Ok(());
+        }
+        if !self.diverged.swap(true, Ordering::Relaxed) {
+            tracing::error!(
+                "🚨 The Noir verification key {} differs from the one the identity contract was registered with ({}): \
+                 identity proofs are refused until the circuit is rebuilt as registered or the contract registered again",
+                hash,
+                registered
+            );
+        }
+        bail!(
+            "Verification key {} is not the one the identity contract was registered with ({}), \
+             the circuit changed since",
+            hash,
+            registered
+        )
+    }
+
+    fn read(&self) -> Result<VerificationKey> {
+        let bytes = std::fs::read(&self.vk_path)
+            .with_context(|| format!("reading the Noir verification key {:?}", self.vk_path))?;
+        Ok(VerificationKey::new(bytes))
+    }
+
+    fn clear_proofs(&self) -> Result<usize> {
+        let proofs = self.cached_proofs()?;
+        for path in &proofs {
+            std::fs::remove_file(path).with_context(|| format!("removing {:?}", path))?;
+        }
+        Ok(proofs.len())
+    }
+
+    fn cached_proofs(&self) -> Result<Vec<PathBuf>> {
+        let entries = match std::fs::read_dir(&self.proofs_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("listing {:?}", self.proofs_dir)),
+        };
+        let mut proofs = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                proofs.push(path);
+            }
+        }
+        Ok(proofs)
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use contract1::Contract1;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::conf::NotificationsConf;
+use crate::noir_proofs::ProofStore;
+use crate::storage::{Storage, Write};
+
+/// Storage namespace of notifications, keyed by user then id
+const NAMESPACE: &str = "notifications";
+/// Storage namespace of price alerts, keyed by id
+const ALERTS: &str = "price_alerts";
+/// Storage namespace of the counters below
+const COUNTERS: &str = "notification_counters";
+/// Next id of a notification or price alert
+const NEXT_ID: &str = "next_id";
+/// Proofs stored before this were already noticed as expiring
+const EXPIRY_NOTICED_UNTIL: &str = "expiry_noticed_until";
+
+/// Something that happened to a user asynchronously, after the request that caused it returned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A transaction the user submitted settled
+    TxSettled {
+        tx_hash: String,
+        success: bool,
+        error: Option<String>,
+    },
+    /// A Noir proof job of the user ended without an identity transaction
+    ProofFailed {
+        job_id: Option<String>,
+        message: String,
+    },
+    /// A pool's spot price crossed one of the user's price alerts, which is then removed
+    PriceAlert {
+        alert_id: u64,
+        pair: String,
+        direction: AlertDirection,
+        threshold: f64,
+        price: f64,
+    },
+    /// The stored identity proof of the user is dropped at `expires_at`, after which it has to
+    /// be proven again
+    IdentityExpiring { proof_hash: String, expires_at: i64 },
+}
+
+impl NotificationEvent {
+    /// Every kind, as serialized
+    pub const KINDS: [&'static str; 4] = [
+        "tx_settled",
+        "proof_failed",
+        "price_alert",
+        "identity_expiring",
+    ];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotificationEvent::TxSettled { .. } => "tx_settled",
+            NotificationEvent::ProofFailed { .. } => "proof_failed",
+            NotificationEvent::PriceAlert { .. } => "price_alert",
+            NotificationEvent::IdentityExpiring { .. } => "identity_expiring",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: u64,
+    pub user: String,
+    pub at: i64,
+    pub read: bool,
+    #[serde(flatten)]
+    pub event: NotificationEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertDirection {
+    /// Fires once the price is at or above the threshold
+    Above,
+    /// Fires once the price is at or below the threshold
+    Below,
+}
+
+/// Spot price threshold of a pool, in token_b per token_a, firing once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceAlert {
+    pub id: u64,
+    pub user: String,
+    pub pair: String,
+    pub direction: AlertDirection,
+    pub price: f64,
+    pub created_at: i64,
+}
+
+/// Per-user inbox of notifications, persisted in the server storage and broadcast live.
+///
+/// Each inbox keeps the latest `max_per_user` notifications, dropping the oldest. Price alerts
+/// are kept in memory as well, since every settled state is checked against them.
+pub struct Notifications {
+    storage: Arc<dyn Storage>,
+    conf: NotificationsConf,
+    alerts: Mutex<BTreeMap<u64, PriceAlert>>,
+    events: broadcast::Sender<Notification>,
+    /// Serializes id allocation and inbox trimming
+    writing: Mutex<()>,
+}
+
+impl Notifications {
+    pub async fn load(storage: Arc<dyn Storage>, conf: NotificationsConf) -> Result<Self> {
+        let alerts = storage
+            .range(ALERTS, &[], None)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| serde_json::from_slice::<PriceAlert>(&bytes))
+            .map(|alert| alert.map(|alert| (alert.id, alert)))
+            .collect::<Result<_, _>>()
+            .context("Could not parse stored price alerts")?;
+        Ok(Self {
+            storage,
+            conf,
+            alerts: Mutex::new(alerts),
+            events: broadcast::channel(1_024).0,
+            writing: Mutex::new(()),
+        })
+    }
+
+    /// Stream of new notifications of every user
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.events.subscribe()
+    }
+
+    /// Add a notification to a user's inbox
+    pub async fn notify(&self, user: &str, event: NotificationEvent) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let id = self.next_id().await?;
+        let notification = Notification {
+            id,
+            user: user.to_string(),
+            at: chrono::Utc::now().timestamp(),
+            read: false,
+            event,
+        };
+
+        let mut writes = vec![
+            Write::put(NAMESPACE, key(user, id), serde_json::to_vec(&notification)?),
+            Write::put(COUNTERS, NEXT_ID, (id + 1).to_be_bytes().to_vec()),
+        ];
+        let stored = self
+            .storage
+            .range(NAMESPACE, &key(user, 0), Some(&key(user, u64::MAX)))
+            .await?;
+        let excess = (stored.len() + 1).saturating_sub(self.conf.max_per_user.max(1));
+        writes.extend(
+            stored
+                .into_iter()
+                .take(excess)
+                .map(|(key, _)| Write::remove(NAMESPACE, key)),
+        );
+        self.storage
+            .apply(writes)
+            .await
+            .context("Failed to persist notification")?;
+
+        // Nobody listening is fine, the inbox has it
+        let _ = self.events.send(notification);
+        Ok(())
+    }
+
+    /// A user's notifications, newest first
+    pub async fn inbox(&self, user: &str, unread_only: bool) -> Result<Vec<Notification>> {
+        let mut notifications = self.stored(user).await?;
+        notifications.retain(|notification| !unread_only || !notification.read);
+        notifications.reverse();
+        Ok(notifications)
+    }
+
+    /// Mark the given notifications of a user as read, all of them when `ids` is unset,
+    /// returning how many were unread
+    pub async fn mark_read(&self, user: &str, ids: Option<&[u64]>) -> Result<usize> {
+        let _writing = self.writing.lock().await;
+        let mut writes = vec![];
+        for mut notification in self.stored(user).await? {
+            if notification.read || ids.is_some_and(|ids| !ids.contains(&notification.id)) {
+                continue;
+            }
+            notification.read = true;
+            writes.push(Write::put(
+                NAMESPACE,
+                key(user, notification.id),
+                serde_json::to_vec(&notification)?,
+            ));
+        }
+        let marked = writes.len();
+        self.storage
+            .apply(writes)
+            .await
+            .context("Failed to persist read notifications")?;
+        Ok(marked)
+    }
+
+    /// Price alerts of a user still waiting to fire
+    pub async fn alerts(&self, user: &str) -> Vec<PriceAlert> {
+        let alerts = self.alerts.lock().await;
+        alerts
+            .values()
+            .filter(|alert| alert.user == user)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn add_alert(
+        &self,
+        user: &str,
+        pair: &str,
+        direction: AlertDirection,
+        price: f64,
+    ) -> Result<PriceAlert> {
+        if !price.is_finite() || price <= 0.0 {
+            bail!("Alert price must be positive");
+        }
+        let _writing = self.writing.lock().await;
+        let mut alerts = self.alerts.lock().await;
+        if alerts.values().filter(|alert| alert.user == user).count()
+            >= self.conf.max_price_alerts_per_user
+        {
+            bail!(
+                "At most {} price alerts per user",
+                self.conf.max_price_alerts_per_user
+            );
+        }
+        let id = self.next_id().await?;
+        let alert = PriceAlert {
+            id,
+            user: user.to_string(),
+            pair: pair.to_string(),
+            direction,
+            price,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.storage
+            .apply(vec![
+                Write::put(
+                    ALERTS,
+                    id.to_be_bytes().to_vec(),
+                    serde_json::to_vec(&alert)?,
+                ),
+                Write::put(COUNTERS, NEXT_ID, (id + 1).to_be_bytes().to_vec()),
+            ])
+            .await
+            .context("Failed to persist price alert")?;
+        alerts.insert(id, alert.clone());
+        Ok(alert)
+    }
+
+    /// Remove a user's price alert, returning whether they had it
+    pub async fn remove_alert(&self, user: &str, id: u64) -> Result<bool> {
+        let mut alerts = self.alerts.lock().await;
+        if alerts.get(&id).is_none_or(|alert| alert.user != user) {
+            return Ok(false);
+        }
+        self.storage
+            .apply(vec![Write::remove(ALERTS, id.to_be_bytes().to_vec())])
+            .await?;
+        alerts.remove(&id);
+        Ok(true)
+    }
+
+    /// Fire the price alerts a settled state crossed
+    pub async fn check_alerts(&self, state: &Contract1) -> Result<()> {
+        let fired: Vec<(PriceAlert, f64)> = {
+            let alerts = self.alerts.lock().await;
+            alerts
+                .values()
+                .filter_map(|alert| {
+                    let pool = state.pools().get(&alert.pair)?;
+                    if pool.reserve_a == 0 || pool.reserve_b == 0 {
+                        return None;
+                    }
+                    let (weight_a, weight_b) = pool.weights(state.block_height());
+                    let price =
+                        (pool.reserve_b * weight_a) as f64 / (pool.reserve_a * weight_b) as f64;
+                    let crossed = match alert.direction {
+                        AlertDirection::Above => price >= alert.price,
+                        AlertDirection::Below => price <= alert.price,
+                    };
+                    crossed.then(|| (alert.clone(), price))
+                })
+                .collect()
+        };
+        for (alert, price) in fired {
+            if !self.remove_alert(&alert.user, alert.id).await? {
+                continue;
+            }
+            let event = NotificationEvent::PriceAlert {
+                alert_id: alert.id,
+                pair: alert.pair,
+                direction: alert.direction,
+                threshold: alert.price,
+                price,
+            };
+            self.notify(&alert.user, event).await?;
+        }
+        Ok(())
+    }
+
+    /// Warn the owners of stored identity proofs dropped within `identity_expiry_notice_secs`,
+    /// each proof once, returning how many were
+    pub async fn notice_expiring_proofs(
+        &self,
+        proofs: &ProofStore,
+        max_age_secs: u64,
+        now: i64,
+    ) -> Result<usize> {
+        let max_age = max_age_secs as i64;
+        // Proofs stored before `until` are dropped within the notice period
+        let until = now - max_age + self.conf.identity_expiry_notice_secs as i64;
+        let from = match self
+            .storage
+            .get(COUNTERS, EXPIRY_NOTICED_UNTIL.as_bytes())
+            .await?
+        {
+            Some(bytes) => bytes
+                .as_slice()
+                .try_into()
+                .map(i64::from_be_bytes)
+                .unwrap_or_default(),
+            None => now - max_age,
+        };
+        if until <= from {
+            return Ok(0);
+        }
+        let expiring = proofs.stored_between(from, until).await?;
+        for proof in &expiring {
+            let event = NotificationEvent::IdentityExpiring {
+                proof_hash: proof.proof_hash.clone(),
+                expires_at: proof.stored_at + max_age,
+            };
+            self.notify(&proof.identity, event).await?;
+        }
+        self.storage
+            .apply(vec![Write::put(
+                COUNTERS,
+                EXPIRY_NOTICED_UNTIL,
+                until.to_be_bytes().to_vec(),
+            )])
+            .await?;
+        Ok(expiring.len())
+    }
+
+    /// Notifications of a user, oldest first
+    async fn stored(&self, user: &str) -> Result<Vec<Notification>> {
+        self.storage
+            .range(NAMESPACE, &key(user, 0), Some(&key(user, u64::MAX)))
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| {
+                serde_json::from_slice(&bytes).context("Could not parse a stored notification")
+            })
+            .collect()
+    }
+
+    async fn next_id(&self) -> Result<u64> {
+        Ok(
+            match self.storage.get(COUNTERS, NEXT_ID.as_bytes()).await? {
+                Some(bytes) => bytes
+                    .as_slice()
+                    .try_into()
+                    .map(u64::from_be_bytes)
+                    .unwrap_or_default(),
+                None => 1,
+            },
+        )
+    }
+}
+
+/// `<user>\0<id, big endian>`, so a user's notifications sort oldest first
+fn key(user: &str, id: u64) -> Vec<u8> {
+    let mut key = user.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
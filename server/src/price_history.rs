@@ -0,0 +1,299 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use contract1::Contract1;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::storage::{self, Storage, Write};
+
+/// Storage namespaces, and the sled trees of the legacy `price_history/` database they replace
+const CANDLES: &str = "price_candles";
+const VOLUMES: &str = "price_volumes";
+const PROCESSED_TXS: &str = "price_processed_txs";
+const LEGACY_TREES: [(&str, &str); 3] =
+    [("candles", CANDLES), ("volumes", VOLUMES), ("processed_txs", PROCESSED_TXS)];
+
+/// Minute candles older than this are dropped by compaction
+const MINUTE_RETENTION_SECS: i64 = 7 * 24 * 3600;
+/// Hour candles older than this are dropped by compaction
+const HOUR_RETENTION_SECS: i64 = 90 * 24 * 3600;
+/// Processed tx markers only need to outlive a DA replay window
+const TX_MARKER_RETENTION_SECS: i64 = MINUTE_RETENTION_SECS;
+const COMPACTION_PERIOD_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 3] = [Interval::Minute, Interval::Hour, Interval::Day];
+
+    pub fn secs(self) -> i64 {
+        match self {
+            Interval::Minute => 60,
+            Interval::Hour => 3600,
+            Interval::Day => 86_400,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Interval::Minute => "1m",
+            Interval::Hour => "1h",
+            Interval::Day => "1d",
+        }
+    }
+
+    /// How long candles of this interval are kept, `None` meaning forever
+    fn retention_secs(self) -> Option<i64> {
+        match self {
+            Interval::Minute => Some(MINUTE_RETENTION_SECS),
+            Interval::Hour => Some(HOUR_RETENTION_SECS),
+            Interval::Day => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "1m" => Ok(Interval::Minute),
+            "1h" => Ok(Interval::Hour),
+            "1d" => Ok(Interval::Day),
+            _ => bail!("Unknown interval '{}', expected one of 1m, 1h, 1d", s),
+        }
+    }
+}
+
+/// OHLCV bucket; prices are token_b per token_a of the pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub open_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume_a: u128,
+    pub volume_b: u128,
+    pub trades: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CumulativeVolume {
+    pub volume_a: u128,
+    pub volume_b: u128,
+    pub trades: u64,
+}
+
+/// Price point or trade observed on a pool between two consecutive states
+struct PoolUpdate {
+    pair: String,
+    price: f64,
+    /// Set when the reserves moved in opposite directions, i.e. a swap happened
+    trade: Option<(u128, u128)>,
+}
+
+/// OHLCV buckets and cumulative volume per pair, persisted in the server storage.
+///
+/// Trades are derived by diffing consecutive AMM states. Each settled tx (or block,
+/// on read replicas) is marked as processed, along with its candles, so a DA replay
+/// after restart does not count it twice.
+pub struct PriceHistory {
+    storage: Arc<dyn Storage>,
+    last_compaction: Mutex<i64>,
+}
+
+impl PriceHistory {
+    /// Open the history, importing the sled database of the data directory's `price_history/` once
+    pub async fn open(storage: Arc<dyn Storage>, data_directory: &Path) -> Result<Self> {
+        let path = data_directory.join("price_history");
+        if path.exists() {
+            let db = sled::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+            let mut writes = vec![];
+            for (tree, namespace) in LEGACY_TREES {
+                for entry in db.open_tree(tree)?.iter() {
+                    let (key, value) = entry?;
+                    writes.push(Write::put(namespace, key.to_vec(), value.to_vec()));
+                }
+            }
+            drop(db);
+            let count = writes.len();
+            storage.apply(writes).await?;
+            std::fs::rename(&path, storage::imported(&path))
+                .with_context(|| format!("renaming {:?}", path))?;
+            tracing::info!("🗄️ Imported {} price history records of {:?} into storage", count, path);
+        }
+
+        Ok(Self {
+            storage,
+            last_compaction: Mutex::new(0),
+        })
+    }
+
+    /// Record the price moves caused by a settled transaction or block
+    pub async fn record(
+        &self,
+        source: &str,
+        previous: Option<&Contract1>,
+        current: &Contract1,
+        timestamp: i64,
+    ) -> Result<()> {
+        if self.storage.get(PROCESSED_TXS, source.as_bytes()).await?.is_some() {
+            return Ok(());
+        }
+
+        // Pairs of a diff are distinct, so no two writes below share a key
+        let mut writes = vec![];
+        for update in Self::diff(previous, current) {
+            for interval in Interval::ALL {
+                writes.push(self.apply(&update, interval, timestamp).await?);
+            }
+            if let Some((volume_a, volume_b)) = update.trade {
+                let mut cumulative = self.volume(&update.pair).await?;
+                cumulative.volume_a += volume_a;
+                cumulative.volume_b += volume_b;
+                cumulative.trades += 1;
+                writes.push(Write::put(VOLUMES, update.pair.clone(), serde_json::to_vec(&cumulative)?));
+            }
+        }
+        writes.push(Write::put(PROCESSED_TXS, source, timestamp.to_be_bytes().to_vec()));
+        self.storage.apply(writes).await?;
+
+        self.maybe_compact(timestamp).await
+    }
+
+    /// Candles of a pair within `[from, to]`, oldest first
+    pub async fn candles(&self, pair: &str, interval: Interval, from: i64, to: i64) -> Result<Vec<Candle>> {
+        let start = candle_key(pair, interval, from.max(0) - from.max(0) % interval.secs());
+        let end = candle_key(pair, interval, to.max(0));
+
+        self.storage
+            .range(CANDLES, start.as_bytes(), Some(end.as_bytes()))
+            .await?
+            .into_iter()
+            .map(|(_, value)| Ok(serde_json::from_slice(&value)?))
+            .collect()
+    }
+
+    pub async fn volume(&self, pair: &str) -> Result<CumulativeVolume> {
+        Ok(match self.storage.get(VOLUMES, pair.as_bytes()).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => CumulativeVolume::default(),
+        })
+    }
+
+    fn diff(previous: Option<&Contract1>, current: &Contract1) -> Vec<PoolUpdate> {
+        current
+            .pools()
+            .iter()
+            .filter(|(_, pool)| pool.reserve_a > 0 && pool.reserve_b > 0)
+            .filter_map(|(pair, pool)| {
+                let before = previous.and_then(|p| p.pools().get(pair));
+                let trade = match before {
+                    Some(b) if b.reserve_a == pool.reserve_a && b.reserve_b == pool.reserve_b => {
+                        return None;
+                    }
+                    Some(b) if (pool.reserve_a > b.reserve_a) != (pool.reserve_b > b.reserve_b) => {
+                        Some((pool.reserve_a.abs_diff(b.reserve_a), pool.reserve_b.abs_diff(b.reserve_b)))
+                    }
+                    _ => None,
+                };
+                // Spot price of a weighted pool: (reserve_b / weight_b) / (reserve_a / weight_a)
+                let (weight_a, weight_b) = pool.weights(current.block_height());
+                Some(PoolUpdate {
+                    pair: pair.clone(),
+                    price: (pool.reserve_b * weight_a) as f64 / (pool.reserve_a * weight_b) as f64,
+                    trade,
+                })
+            })
+            .collect()
+    }
+
+    /// Write of the candle an update falls in, moved by it
+    async fn apply(&self, update: &PoolUpdate, interval: Interval, timestamp: i64) -> Result<Write> {
+        let open_time = timestamp - timestamp % interval.secs();
+        let key = candle_key(&update.pair, interval, open_time);
+
+        let mut candle = match self.storage.get(CANDLES, key.as_bytes()).await? {
+            Some(bytes) => serde_json::from_slice::<Candle>(&bytes)?,
+            None => Candle {
+                open_time,
+                open: update.price,
+                high: update.price,
+                low: update.price,
+                close: update.price,
+                volume_a: 0,
+                volume_b: 0,
+                trades: 0,
+            },
+        };
+        candle.high = candle.high.max(update.price);
+        candle.low = candle.low.min(update.price);
+        candle.close = update.price;
+        if let Some((volume_a, volume_b)) = update.trade {
+            candle.volume_a += volume_a;
+            candle.volume_b += volume_b;
+            candle.trades += 1;
+        }
+
+        Ok(Write::put(CANDLES, key, serde_json::to_vec(&candle)?))
+    }
+
+    /// Drop expired high-resolution candles and tx markers, at most once per period
+    async fn maybe_compact(&self, now: i64) -> Result<()> {
+        let mut last_compaction = self.last_compaction.lock().await;
+        if now - *last_compaction < COMPACTION_PERIOD_SECS {
+            return Ok(());
+        }
+        *last_compaction = now;
+
+        let mut writes = vec![];
+        for (key, _) in self.storage.range(CANDLES, &[], None).await? {
+            let Some((interval, open_time)) = parse_candle_key(&key) else {
+                continue;
+            };
+            if interval
+                .retention_secs()
+                .is_some_and(|retention| open_time < now - retention)
+            {
+                writes.push(Write::remove(CANDLES, key));
+            }
+        }
+        let removed = writes.len();
+        for (key, value) in self.storage.range(PROCESSED_TXS, &[], None).await? {
+            let seen_at = value
+                .as_slice()
+                .try_into()
+                .map(i64::from_be_bytes)
+                .unwrap_or_default();
+            if seen_at < now - TX_MARKER_RETENTION_SECS {
+                writes.push(Write::remove(PROCESSED_TXS, key));
+            }
+        }
+        self.storage.apply(writes).await?;
+
+        if removed > 0 {
+            tracing::info!("🧹 Compacted {} expired price history candles", removed);
+        }
+        Ok(())
+    }
+}
+
+/// `<pair>/<interval>/<open_time>`, zero padded so keys sort chronologically
+fn candle_key(pair: &str, interval: Interval, open_time: i64) -> String {
+    format!("{}/{}/{:020}", pair, interval.as_str(), open_time)
+}
+
+fn parse_candle_key(key: &[u8]) -> Option<(Interval, i64)> {
+    let key = std::str::from_utf8(key).ok()?;
+    let mut parts = key.rsplitn(3, '/');
+    let open_time = parts.next()?.parse().ok()?;
+    let interval = parts.next()?.parse().ok()?;
+    Some((interval, open_time))
+}
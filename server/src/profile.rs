@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::storage::{self, Storage, Write};
+
+/// Storage namespace of profiles, keyed by identity
+const NAMESPACE: &str = "profiles";
+
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+const MAX_AVATAR_URL_LEN: usize = 512;
+const MAX_FAVORITE_PAIRS: usize = 20;
+/// 50% - anything above is almost certainly a client bug
+const MAX_SLIPPAGE_BPS: u32 = 5_000;
+
+/// User preferences persisted server-side, keyed by Hyli identity
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub preferred_slippage_bps: u32,
+    /// Pairs formatted as "TOKENA/TOKENB"
+    pub favorite_pairs: Vec<String>,
+    pub updated_at: i64,
+}
+
+impl Default for UserProfile {
+    fn default() -> Self {
+        Self {
+            display_name: None,
+            avatar_url: None,
+            preferred_slippage_bps: 50,
+            favorite_pairs: vec![],
+            updated_at: 0,
+        }
+    }
+}
+
+/// Body of `PUT /api/profile`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub preferred_slippage_bps: u32,
+    pub favorite_pairs: Vec<String>,
+}
+
+impl ProfileUpdate {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(name) = &self.display_name {
+            if name.trim().is_empty() || name.len() > MAX_DISPLAY_NAME_LEN {
+                bail!("display_name must be between 1 and {} characters", MAX_DISPLAY_NAME_LEN);
+            }
+        }
+        if let Some(url) = &self.avatar_url {
+            if url.len() > MAX_AVATAR_URL_LEN || !(url.starts_with("https://") || url.starts_with("http://")) {
+                bail!("avatar_url must be an http(s) URL of at most {} characters", MAX_AVATAR_URL_LEN);
+            }
+        }
+        if self.preferred_slippage_bps > MAX_SLIPPAGE_BPS {
+            bail!("preferred_slippage_bps must be at most {}", MAX_SLIPPAGE_BPS);
+        }
+        if self.favorite_pairs.len() > MAX_FAVORITE_PAIRS {
+            bail!("At most {} favorite pairs are allowed", MAX_FAVORITE_PAIRS);
+        }
+        for pair in &self.favorite_pairs {
+            match pair.split_once('/') {
+                Some((a, b)) if !a.is_empty() && !b.is_empty() && a != b => {}
+                _ => bail!("Invalid pair '{}', expected TOKENA/TOKENB", pair),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Profiles kept in the server storage, one entry per identity
+pub struct ProfileStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl ProfileStore {
+    /// Open the store, importing the `profiles.json` of the data directory once
+    pub async fn load(storage: Arc<dyn Storage>, data_directory: &Path) -> Result<Self> {
+        storage::import_legacy(&*storage, &data_directory.join("profiles.json"), |bytes| {
+            let profiles: HashMap<String, UserProfile> = serde_json::from_slice(bytes)?;
+            profiles
+                .into_iter()
+                .map(|(identity, profile)| Ok(Write::put(NAMESPACE, identity, serde_json::to_vec(&profile)?)))
+                .collect()
+        })
+        .await?;
+
+        Ok(Self { storage })
+    }
+
+    /// Profile of the given identity, or defaults if none was saved yet
+    pub async fn get(&self, identity: &str) -> Result<UserProfile> {
+        Ok(match self.storage.get(NAMESPACE, identity.as_bytes()).await? {
+            Some(bytes) => serde_json::from_slice(&bytes).context("Could not parse stored profile")?,
+            None => UserProfile::default(),
+        })
+    }
+
+    /// Save a validated update as the identity's profile
+    pub async fn update(&self, identity: &str, update: ProfileUpdate) -> Result<UserProfile> {
+        let profile = UserProfile {
+            display_name: update.display_name.map(|n| n.trim().to_string()),
+            avatar_url: update.avatar_url,
+            preferred_slippage_bps: update.preferred_slippage_bps,
+            favorite_pairs: update.favorite_pairs,
+            updated_at: chrono::Utc::now().timestamp(),
+        };
+
+        self.storage
+            .apply(vec![Write::put(NAMESPACE, identity, serde_json::to_vec(&profile)?)])
+            .await
+            .context("Failed to persist profile")?;
+
+        Ok(profile)
+    }
+}
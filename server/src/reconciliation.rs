@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Duration};
+
+use client_sdk::rest_client::{NodeApiClient, NodeApiHttpClient};
+use opentelemetry::metrics::{Counter, Gauge};
+use sdk::{ContractName, ZkContract};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+use crate::amm_state::AmmStateReader;
+use crate::sync_status::SyncMonitor;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconciliationStatus {
+    /// Processed height of the last comparison
+    pub checked_height: Option<u64>,
+    /// SHA-256 of the commitment recomputed from the local state
+    pub local_commitment: Option<String>,
+    /// SHA-256 of the commitment the node holds for the contract
+    pub node_commitment: Option<String>,
+    pub consecutive_mismatches: u32,
+    /// Set once `mismatch_threshold` comparisons in a row disagreed, until one agrees again
+    pub diverged: bool,
+}
+
+/// Compares the state the server indexed with the commitment settled on the node.
+///
+/// Both are only compared once the DA stream reached the node head, and a single
+/// mismatch may still be a block settling in between: the states are reported as
+/// diverged after `mismatch_threshold` consecutive mismatches.
+pub struct Reconciler {
+    node: Arc<NodeApiHttpClient>,
+    contract_name: ContractName,
+    mismatch_threshold: u32,
+    status: RwLock<ReconciliationStatus>,
+    mismatches: Counter<u64>,
+    diverged: Gauge<u64>,
+}
+
+impl Reconciler {
+    pub fn new(node: Arc<NodeApiHttpClient>, contract_name: ContractName, mismatch_threshold: u32) -> Self {
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        Self {
+            node,
+            contract_name,
+            mismatch_threshold: mismatch_threshold.max(1),
+            status: Default::default(),
+            mismatches: meter.u64_counter("state_reconciliation_mismatches").build(),
+            diverged: meter.u64_gauge("state_diverged").build(),
+        }
+    }
+
+    pub async fn status(&self) -> ReconciliationStatus {
+        self.status.read().await.clone()
+    }
+
+    /// Compare the local state with the node once, if the DA stream is at the node head
+    pub async fn check(&self, amm_state: &AmmStateReader, sync: &SyncMonitor) {
+        let sync_status = sync.status().await;
+        if !sync_status.ready || sync_status.lag != Some(0) {
+            return;
+        }
+        let Some(state) = amm_state.snapshot().await else {
+            return;
+        };
+        let node_commitment = match self.node.get_contract(&self.contract_name).await {
+            Ok(contract) => contract.state_commitment,
+            Err(e) => {
+                tracing::warn!("⚠️ Could not fetch {} from the node for reconciliation: {}", self.contract_name, e);
+                return;
+            }
+        };
+        let local = hex::encode(Sha256::digest(state.commit().0));
+        let node = hex::encode(Sha256::digest(node_commitment));
+
+        let mut status = self.status.write().await;
+        status.checked_height = sync_status.processed_height;
+        if local == node {
+            if status.diverged {
+                tracing::info!("✅ Indexed {} state agrees with the node again", self.contract_name);
+            }
+            status.consecutive_mismatches = 0;
+            status.diverged = false;
+        } else {
+            self.mismatches.add(1, &[]);
+            status.consecutive_mismatches += 1;
+            if !status.diverged && status.consecutive_mismatches >= self.mismatch_threshold {
+                status.diverged = true;
+                tracing::error!(
+                    "🚨 Indexed {} state diverged from the node at height {:?}: local {} vs node {}",
+                    self.contract_name, status.checked_height, local, node
+                );
+            }
+        }
+        status.local_commitment = Some(local);
+        status.node_commitment = Some(node);
+        self.diverged.record(status.diverged as u64, &[]);
+    }
+}
+
+pub async fn run(reconciler: Arc<Reconciler>, amm_state: AmmStateReader, sync: SyncMonitor, interval: Duration) {
+    let mut tick = tokio::time::interval(interval);
+    loop {
+        tick.tick().await;
+        reconciler.check(&amm_state, &sync).await;
+    }
+}
@@ -0,0 +1,212 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sdk::TxHash;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::{broadcast, Mutex},
+};
+
+use crate::tx_status::TxStatus;
+
+/// Bodies above this size are not recorded
+const MAX_RECORDED_BODY: usize = 1_048_576;
+/// Body fields never written to the record file
+const SECRET_FIELDS: &[&str] = &[
+    "password",
+    "password_field",
+    "credential",
+    "signature",
+    "authenticator_data",
+    "client_data_json",
+    "session_token",
+    "private_input",
+];
+const REDACTED: &str = "<redacted>";
+/// Only the caller identity is kept; session tokens never are
+const RECORDED_HEADER: &str = "x-user";
+
+/// One line of the record file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEntry {
+    Request {
+        at: i64,
+        method: String,
+        /// Full path including any tenant prefix, with the query string
+        uri: String,
+        user: Option<String>,
+        body: Option<Value>,
+        status: u16,
+        response: Option<Value>,
+        /// Event streams are recorded without their content and not replayed
+        #[serde(default)]
+        stream: bool,
+    },
+    Settlement {
+        at: i64,
+        /// AMM contract of the tenant the transaction belongs to
+        contract: String,
+        tx_hash: String,
+        status: TxStatus,
+    },
+}
+
+/// Appends sanitized API traffic and settled outcomes to a JSON lines file (`--record`)
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub async fn open(path: &Path) -> Result<Arc<Self>> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open record file {:?}", path))?;
+        tracing::info!("📼 Recording API traffic to {:?}", path);
+        Ok(Arc::new(Self {
+            file: Mutex::new(file),
+        }))
+    }
+
+    pub async fn record(&self, entry: &RecordedEntry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("⚠️ Could not encode recorded entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(e) = self.file.lock().await.write_all(&line).await {
+            tracing::warn!("⚠️ Could not write record file: {}", e);
+        }
+    }
+}
+
+/// Record every settlement of a tenant until its tx store goes away
+pub async fn record_settlements(
+    recorder: Arc<Recorder>,
+    mut settled: broadcast::Receiver<(TxHash, TxStatus)>,
+    contract: String,
+) {
+    loop {
+        let (tx_hash, status) = match settled.recv().await {
+            Ok(settlement) => settlement,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("⚠️ Recorder missed {} settlements", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        recorder
+            .record(&RecordedEntry::Settlement {
+                at: chrono::Utc::now().timestamp(),
+                contract: contract.clone(),
+                tx_hash: tx_hash.0,
+                status,
+            })
+            .await;
+    }
+}
+
+/// Middleware recording each request with its response; event streams pass through untouched
+pub async fn record_traffic(
+    State(recorder): State<Arc<Recorder>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_RECORDED_BODY).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+    let method = parts.method.to_string();
+    let uri = parts.uri.to_string();
+    let user = parts
+        .headers
+        .get(RECORDED_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let request_body = sanitized(&body);
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let is_stream = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    let status = response.status().as_u16();
+    let (response, response_body) = if is_stream {
+        (response, None)
+    } else {
+        let (parts, body) = response.into_parts();
+        match to_bytes(body, MAX_RECORDED_BODY).await {
+            Ok(body) => {
+                let recorded = sanitized(&body);
+                (Response::from_parts(parts, Body::from(body)), recorded)
+            }
+            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR.into_response(), None),
+        }
+    };
+
+    recorder
+        .record(&RecordedEntry::Request {
+            at: chrono::Utc::now().timestamp(),
+            method,
+            uri,
+            user,
+            body: request_body,
+            status,
+            response: response_body,
+            stream: is_stream,
+        })
+        .await;
+    response
+}
+
+/// Parse a JSON body, redacting secrets and emptying wallet blob payloads
+fn sanitized(body: &[u8]) -> Option<Value> {
+    if body.is_empty() {
+        return None;
+    }
+    let mut value = serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()));
+    redact(&mut value);
+    Some(value)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) {
+                    *field = Value::String(REDACTED.into());
+                } else if key == "wallet_blobs" {
+                    // Keep the shape so requests can be replayed against `--mock-node`
+                    if let Value::Array(blobs) = field {
+                        for blob in blobs.iter_mut().filter_map(Value::as_object_mut) {
+                            blob.insert("data".into(), Value::Array(vec![]));
+                        }
+                    }
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
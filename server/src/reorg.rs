@@ -0,0 +1,100 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use opentelemetry::metrics::Counter;
+use sdk::{Block, TxHash};
+
+use crate::amm_state::AmmSnapshot;
+
+/// What processing a block changed, to take it back if the DA stream replaces the block
+struct BlockUndo {
+    height: u64,
+    hash: String,
+    /// AMM state when the block arrived
+    amm_before: Option<Arc<AmmSnapshot>>,
+    /// Transactions the block sequenced or settled
+    txs: Vec<TxHash>,
+}
+
+/// Blocks replaced by the DA stream, to revert what was derived from them
+pub struct Reorg {
+    /// Height of the first replaced block
+    pub from_height: u64,
+    /// AMM state before the first replaced block, unset if there was none yet
+    pub amm_before: Option<Arc<AmmSnapshot>>,
+    /// Transactions of the replaced blocks, whose statuses no longer hold
+    pub txs: Vec<TxHash>,
+}
+
+/// Undo logs of the last processed blocks.
+///
+/// A block arriving at a height already processed under another hash replaces that block and
+/// every later one. Blocks older than the log are taken as replays, as nothing tells them apart
+/// from a reorg deeper than what can be undone.
+pub struct UndoLog {
+    depth: usize,
+    /// Oldest first
+    blocks: VecDeque<BlockUndo>,
+    reorgs: Counter<u64>,
+}
+
+impl UndoLog {
+    pub fn new(depth: usize) -> Self {
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        Self {
+            depth: depth.max(1),
+            blocks: VecDeque::new(),
+            reorgs: meter.u64_counter("da_reorgs").build(),
+        }
+    }
+
+    /// Record a block about to be processed, returning the blocks it replaces, if any.
+    ///
+    /// Blocks delivered again under the same hash, as on a DA replay, replace nothing.
+    pub fn process(&mut self, block: &Block, amm_before: Option<Arc<AmmSnapshot>>) -> Option<Reorg> {
+        let height = block.block_height.0;
+        let hash = block.hash.to_string();
+        if self.blocks.iter().any(|undo| undo.height == height && undo.hash == hash) {
+            return None;
+        }
+
+        if self.blocks.front().is_some_and(|oldest| height < oldest.height) {
+            // Older than the log, whether it replaces anything cannot be told
+            tracing::debug!("Block {} is older than the undo log, taken as a replay", height);
+            return None;
+        }
+        let reorg = self.blocks.iter().position(|undo| undo.height >= height).map(|first| {
+            self.reorgs.add(1, &[]);
+            let replaced: Vec<BlockUndo> = self.blocks.drain(first..).collect();
+            tracing::warn!(
+                "🔀 Block {} ({}) replaces {} processed block(s), reverting what was derived from them",
+                height,
+                hash,
+                replaced.len()
+            );
+            Reorg {
+                from_height: height,
+                amm_before: replaced.first().and_then(|undo| undo.amm_before.clone()),
+                txs: replaced.into_iter().flat_map(|undo| undo.txs).collect(),
+            }
+        });
+
+        let txs = block
+            .txs
+            .iter()
+            .map(|(tx_id, _)| tx_id.1.clone())
+            .chain(block.successful_txs.iter().cloned())
+            .chain(block.failed_txs.iter().cloned())
+            .chain(block.timed_out_txs.iter().cloned())
+            .collect();
+        self.blocks.push_back(BlockUndo {
+            height,
+            hash,
+            amm_before,
+            txs,
+        });
+        while self.blocks.len() > self.depth {
+            self.blocks.pop_front();
+        }
+        reorg
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use opentelemetry::{metrics::Counter, KeyValue};
+
+use crate::conf::RetentionPolicy;
+
+struct FileEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Applies the configured retention policies to the data directory
+#[derive(Clone)]
+pub struct Pruner {
+    data_directory: PathBuf,
+    policies: HashMap<String, RetentionPolicy>,
+    reclaimed_bytes: Counter<u64>,
+    removed_files: Counter<u64>,
+}
+
+impl Pruner {
+    pub fn new(data_directory: PathBuf, policies: HashMap<String, RetentionPolicy>) -> Result<Self> {
+        for (category, policy) in &policies {
+            if policy.path.as_os_str().is_empty()
+                || policy
+                    .path
+                    .components()
+                    .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                bail!(
+                    "Retention path of {} must be a plain sub-directory of the data directory",
+                    category
+                );
+            }
+        }
+
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        Ok(Self {
+            data_directory,
+            policies,
+            reclaimed_bytes: meter.u64_counter("data_pruned_bytes").build(),
+            removed_files: meter.u64_counter("data_pruned_files").build(),
+        })
+    }
+
+    /// Prune every category, logging and recording what was reclaimed
+    pub async fn prune(&self) {
+        let pruner = self.clone();
+        let reports = match tokio::task::spawn_blocking(move || pruner.prune_blocking()).await {
+            Ok(reports) => reports,
+            Err(e) => {
+                tracing::warn!("⚠️ Pruning task failed: {}", e);
+                return;
+            }
+        };
+
+        for (category, report) in reports {
+            match report {
+                Ok(report) if report.files > 0 => {
+                    let attributes = [KeyValue::new("category", category.clone())];
+                    self.reclaimed_bytes.add(report.bytes, &attributes);
+                    self.removed_files.add(report.files, &attributes);
+                    tracing::info!(
+                        "🧹 Pruned {} files ({} bytes) from {}",
+                        report.files, report.bytes, category
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("⚠️ Could not prune {}: {}", category, e),
+            }
+        }
+    }
+
+    fn prune_blocking(&self) -> Vec<(String, Result<PruneReport>)> {
+        self.policies
+            .iter()
+            .map(|(category, policy)| {
+                let dir = self.data_directory.join(&policy.path);
+                (category.clone(), prune_dir(&dir, policy, SystemTime::now()))
+            })
+            .collect()
+    }
+}
+
+fn prune_dir(dir: &Path, policy: &RetentionPolicy, now: SystemTime) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+    if !dir.exists() {
+        return Ok(report);
+    }
+
+    let mut files = vec![];
+    collect_files(dir, &mut files)?;
+    // Oldest first, so size pruning drops the stalest data
+    files.sort_by_key(|f| f.modified);
+
+    let max_age = policy.max_age_secs.map(Duration::from_secs);
+    let mut total: u64 = files.iter().map(|f| f.size).sum();
+
+    for file in files {
+        let expired = max_age.is_some_and(|max_age| {
+            now.duration_since(file.modified).unwrap_or_default() > max_age
+        });
+        let oversized = policy.max_size_bytes.is_some_and(|max| total > max);
+        if !expired && !oversized {
+            // Files are sorted: nothing newer is expired and the size already fits
+            break;
+        }
+
+        std::fs::remove_file(&file.path)?;
+        total -= file.size;
+        report.files += 1;
+        report.bytes += file.size;
+    }
+
+    Ok(report)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<FileEntry>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push(FileEntry {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+    }
+    Ok(())
+}
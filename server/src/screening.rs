@@ -0,0 +1,208 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use axum::http::StatusCode;
+use client_sdk::contract_indexer::AppError;
+use contract2::IdentityContract;
+use futures::future::BoxFuture;
+use sdk::ContractName;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+use crate::conf::ScreeningConf;
+use crate::state_cache::StateCache;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "decision")]
+pub enum Decision {
+    Allowed,
+    Blocked { reason: String },
+}
+
+/// Decides whether an identity may submit transactions
+pub trait Screener: Send + Sync {
+    /// Recorded in the audit log next to each decision
+    fn name(&self) -> &'static str;
+
+    fn screen<'a>(&'a self, identity: &'a str) -> BoxFuture<'a, Result<Decision>>;
+}
+
+/// Default provider: no screening
+pub struct AllowAll;
+
+impl Screener for AllowAll {
+    fn name(&self) -> &'static str {
+        "allow_all"
+    }
+
+    fn screen<'a>(&'a self, _identity: &'a str) -> BoxFuture<'a, Result<Decision>> {
+        Box::pin(async { Ok(Decision::Allowed) })
+    }
+}
+
+/// Asks an external screening API: `POST <url> {"identity"}` answering `{"allowed", "reason"}`
+pub struct HttpScreener {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct HttpScreeningResponse {
+    allowed: bool,
+    reason: Option<String>,
+}
+
+impl Screener for HttpScreener {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn screen<'a>(&'a self, identity: &'a str) -> BoxFuture<'a, Result<Decision>> {
+        Box::pin(async move {
+            let response: HttpScreeningResponse = self
+                .client
+                .post(&self.url)
+                .json(&serde_json::json!({ "identity": identity }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(match response.allowed {
+                true => Decision::Allowed,
+                false => Decision::Blocked {
+                    reason: response
+                        .reason
+                        .unwrap_or_else(|| "Blocked by the screening provider".into()),
+                },
+            })
+        })
+    }
+}
+
+/// Only lets through identities in the identity contract's allowed set
+pub struct IdentityContractScreener {
+    state: Arc<StateCache<IdentityContract>>,
+    contract_name: ContractName,
+}
+
+impl Screener for IdentityContractScreener {
+    fn name(&self) -> &'static str {
+        "identity_contract"
+    }
+
+    fn screen<'a>(&'a self, identity: &'a str) -> BoxFuture<'a, Result<Decision>> {
+        Box::pin(async move {
+            let state = self.state.get().await?;
+            if state.allows(identity) {
+                return Ok(Decision::Allowed);
+            }
+            let reason = match state.verification(identity) {
+                Some(v) => format!("Identity verified as {} on {}, not allowed", v.country_code, v.verified_at),
+                None => format!("Identity not verified with {}", self.contract_name),
+            };
+            Ok(Decision::Blocked { reason })
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    at: i64,
+    identity: &'a str,
+    provider: &'static str,
+    #[serde(flatten)]
+    decision: &'a Decision,
+}
+
+/// Screens identities before submission and keeps an audit trail of every rejection
+pub struct ScreeningGate {
+    screener: Box<dyn Screener>,
+    fail_open: bool,
+    audit: Mutex<File>,
+}
+
+impl ScreeningGate {
+    pub async fn new(
+        conf: &ScreeningConf,
+        data_directory: &Path,
+        identity_state: Arc<StateCache<IdentityContract>>,
+        identity_cn: ContractName,
+    ) -> Result<Self> {
+        let screener: Box<dyn Screener> = match conf.provider.as_str() {
+            "allow_all" => Box::new(AllowAll),
+            "http" => Box::new(HttpScreener {
+                url: conf
+                    .url
+                    .clone()
+                    .context("screening.url is required by the http provider")?,
+                client: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+            }),
+            "identity_contract" => Box::new(IdentityContractScreener {
+                state: identity_state,
+                contract_name: identity_cn,
+            }),
+            other => bail!("Unknown screening provider '{}'", other),
+        };
+
+        let path = data_directory.join("screening_audit.jsonl");
+        let audit = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to open {:?}", path))?;
+
+        Ok(Self {
+            screener,
+            fail_open: conf.fail_open,
+            audit: Mutex::new(audit),
+        })
+    }
+
+    /// Reject blocked identities; provider errors block too unless configured to fail open
+    pub async fn check(&self, identity: &str) -> Result<(), AppError> {
+        let decision = match self.screener.screen(identity).await {
+            Ok(decision) => decision,
+            Err(e) if self.fail_open => {
+                tracing::warn!("⚠️ Screening of {} failed, letting it through: {}", identity, e);
+                return Ok(());
+            }
+            Err(e) => Decision::Blocked {
+                reason: format!("Screening unavailable: {}", e),
+            },
+        };
+
+        let Decision::Blocked { reason } = &decision else {
+            return Ok(());
+        };
+        tracing::warn!("🚫 Screening blocked {}: {}", identity, reason);
+        self.audit(identity, &decision).await;
+        Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!("screening_blocked: {}", reason),
+        ))
+    }
+
+    async fn audit(&self, identity: &str, decision: &Decision) {
+        let entry = AuditEntry {
+            at: chrono::Utc::now().timestamp(),
+            identity,
+            provider: self.screener.name(),
+            decision,
+        };
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("⚠️ Could not encode screening audit entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(e) = self.audit.lock().await.write_all(&line).await {
+            tracing::warn!("⚠️ Could not write screening audit log: {}", e);
+        }
+    }
+}
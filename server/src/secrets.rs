@@ -0,0 +1,257 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use opentelemetry::{metrics::Counter, KeyValue};
+use serde::Deserialize;
+use tokio::sync::{watch, Mutex};
+use tracing::{info, warn};
+
+use crate::conf::SecretsConf;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection string of the postgres storage backend
+pub const POSTGRES_URL: &str = "postgres_url";
+/// Keys of `[encryption]`, as comma-separated `id:base64-key`, current first
+pub const ENCRYPTION_KEYS: &str = "encryption_keys";
+/// PEM certificate chain of the TLS listener, read from `tls.cert_path` when not held
+pub const TLS_CERT: &str = "tls_cert";
+/// PEM private key of the TLS listener, read from `tls.key_path` when not held
+pub const TLS_KEY: &str = "tls_key";
+/// Hex secp256k1 private key the keeper signs compactions with
+pub const KEEPER_KEY: &str = "keeper_key";
+
+/// Where secrets are read from, by name
+pub trait SecretsProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Current value of a secret, `None` when the provider does not hold it
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+}
+
+/// Environment variables named after the secret, upper-cased behind a prefix:
+/// `encryption_keys` is read from `HYLI_DEFI_ENCRYPTION_KEYS`
+pub struct EnvProvider {
+    prefix: String,
+}
+
+impl SecretsProvider for EnvProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            let var = format!("{}{}", self.prefix, name.to_uppercase());
+            match std::env::var(&var) {
+                Ok(value) => Ok(Some(value)),
+                Err(std::env::VarError::NotPresent) => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("reading {}", var)),
+            }
+        })
+    }
+}
+
+/// One file per secret in a directory, as mounted by Docker and Kubernetes secrets
+pub struct FileProvider {
+    dir: PathBuf,
+}
+
+impl SecretsProvider for FileProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            let path = self.dir.join(name);
+            match tokio::fs::read_to_string(&path).await {
+                // Files usually end with a newline their value does not hold
+                Ok(value) => Ok(Some(value.trim_end().to_string())),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("reading {:?}", path)),
+            }
+        })
+    }
+}
+
+/// One key per secret of a Vault KV v2 secret: `GET <addr>/v1/<mount>/data/<path>`
+pub struct VaultProvider {
+    url: String,
+    token: VaultToken,
+    client: reqwest::Client,
+}
+
+/// Read on every request, so a token renewed by a Vault agent is picked up
+enum VaultToken {
+    Env(String),
+    File(PathBuf),
+}
+
+#[derive(Deserialize)]
+struct VaultResponse {
+    data: VaultData,
+}
+
+#[derive(Deserialize)]
+struct VaultData {
+    data: HashMap<String, String>,
+}
+
+impl VaultProvider {
+    async fn token(&self) -> Result<String> {
+        match &self.token {
+            VaultToken::Env(var) => std::env::var(var).with_context(|| format!("{} must hold the Vault token", var)),
+            VaultToken::File(path) => Ok(tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("reading the Vault token from {:?}", path))?
+                .trim_end()
+                .to_string()),
+        }
+    }
+}
+
+impl SecretsProvider for VaultProvider {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    fn fetch<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            let response: VaultResponse = self
+                .client
+                .get(&self.url)
+                .header("X-Vault-Token", self.token().await?)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(response.data.data.get(name).cloned())
+        })
+    }
+}
+
+/// Secrets the server reads from its provider instead of its config, refreshed while it runs.
+///
+/// Each secret is fetched when first watched; its users then hear of rotations through the
+/// channel they were handed, see `on_rotation`.
+pub struct Secrets {
+    provider: Box<dyn SecretsProvider>,
+    watched: Mutex<HashMap<String, watch::Sender<String>>>,
+    rotations: Counter<u64>,
+}
+
+impl Secrets {
+    pub fn new(conf: &SecretsConf) -> Result<Arc<Self>> {
+        let provider: Box<dyn SecretsProvider> = match conf.provider.as_str() {
+            "env" => Box::new(EnvProvider {
+                prefix: conf.env_prefix.clone(),
+            }),
+            "file" => Box::new(FileProvider {
+                dir: conf.dir.clone().context("secrets.dir is required by the file provider")?,
+            }),
+            "vault" => {
+                let addr = conf
+                    .vault_addr
+                    .as_deref()
+                    .context("secrets.vault_addr is required by the vault provider")?;
+                Box::new(VaultProvider {
+                    url: format!(
+                        "{}/v1/{}/data/{}",
+                        addr.trim_end_matches('/'),
+                        conf.vault_mount,
+                        conf.vault_path
+                    ),
+                    token: match &conf.vault_token_path {
+                        Some(path) => VaultToken::File(path.clone()),
+                        None => VaultToken::Env(conf.vault_token_env.clone()),
+                    },
+                    client: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+                })
+            }
+            other => bail!("Unknown secrets provider '{}', expected env, file or vault", other),
+        };
+        info!("🔑 Reading secrets from the {} provider", provider.name());
+        Ok(Arc::new(Secrets {
+            provider,
+            watched: Default::default(),
+            rotations: opentelemetry::global::meter("hyli_defi_app")
+                .u64_counter("secret_rotations")
+                .build(),
+        }))
+    }
+
+    /// Follow a secret, `None` when the provider does not hold it
+    pub async fn watch(&self, name: &str) -> Result<Option<watch::Receiver<String>>> {
+        let mut watched = self.watched.lock().await;
+        if let Some(sender) = watched.get(name) {
+            return Ok(Some(sender.subscribe()));
+        }
+        let value = self
+            .provider
+            .fetch(name)
+            .await
+            .with_context(|| format!("reading secret {}", name))?;
+        Ok(value.map(|value| {
+            let (sender, receiver) = watch::channel(value);
+            watched.insert(name.to_string(), sender);
+            receiver
+        }))
+    }
+
+    /// Follow a secret the server cannot run without
+    pub async fn require(&self, name: &str) -> Result<watch::Receiver<String>> {
+        match self.watch(name).await? {
+            Some(receiver) => Ok(receiver),
+            None => bail!("Secret {} is not set in the {} provider", name, self.provider.name()),
+        }
+    }
+
+    /// Fetch every watched secret again and announce those that changed. A secret that failed
+    /// to load or disappeared keeps its previous value.
+    pub async fn refresh(&self) {
+        let watched = self.watched.lock().await;
+        for (name, sender) in watched.iter() {
+            match self.provider.fetch(name).await {
+                Ok(Some(value)) => {
+                    if *sender.borrow() != value {
+                        sender.send_replace(value);
+                        self.rotations.add(1, &[KeyValue::new("secret", name.clone())]);
+                        info!("🔑 Secret {} rotated", name);
+                    }
+                }
+                Ok(None) => warn!("⚠️ Secret {} is no longer set, keeping its previous value", name),
+                Err(e) => warn!("⚠️ Could not refresh secret {}: {:#}", name, e),
+            }
+        }
+    }
+
+    /// Refresh the watched secrets every `interval`, for as long as the process runs
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.refresh().await;
+        }
+    }
+}
+
+/// Rotation hook: call `apply` with each new value of a watched secret. A value `apply` fails
+/// on is logged, leaving its user with the previous one.
+pub fn on_rotation<F>(name: &str, mut receiver: watch::Receiver<String>, apply: F)
+where
+    F: Fn(String) -> BoxFuture<'static, Result<()>> + Send + 'static,
+{
+    let name = name.to_string();
+    tokio::spawn(async move {
+        while receiver.changed().await.is_ok() {
+            let value = receiver.borrow_and_update().clone();
+            if let Err(e) = apply(value).await {
+                warn!("⚠️ Could not apply rotated secret {}: {:#}", name, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::conf::SpendingConf;
+use crate::storage::{Storage, Write};
+
+/// Storage namespace of daily spend, keyed by day then identity
+const NAMESPACE: &str = "spending";
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Response header listing the soft budgets a request took the user over
+pub const BUDGET_WARNING_HEADER: &str = "x-budget-warning";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendKind {
+    /// Input of swaps, streaming swaps included
+    Swap,
+    Mint,
+}
+
+/// What an identity spent during one UTC day, by token
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailySpend {
+    pub swapped: BTreeMap<String, u128>,
+    pub minted: BTreeMap<String, u128>,
+}
+
+impl DailySpend {
+    fn amounts(&self, kind: SpendKind) -> &BTreeMap<String, u128> {
+        match kind {
+            SpendKind::Swap => &self.swapped,
+            SpendKind::Mint => &self.minted,
+        }
+    }
+}
+
+/// A soft budget the identity went over today
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetWarning {
+    pub kind: SpendKind,
+    pub token: String,
+    pub spent: u128,
+    pub budget: u128,
+}
+
+impl std::fmt::Display for BudgetWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = match self.kind {
+            SpendKind::Swap => "swapped",
+            SpendKind::Mint => "minted",
+        };
+        write!(
+            f,
+            "{} {} {} today, above the soft budget of {}",
+            verb, self.spent, self.token, self.budget
+        )
+    }
+}
+
+/// Cumulative daily spend of each authenticated identity, checked against soft budgets.
+///
+/// Only settled transactions count. Budgets never block a request: going over one adds a
+/// warning to the response so testnet users notice before they drain a wallet.
+pub struct SpendingTracker {
+    storage: Arc<dyn Storage>,
+    conf: SpendingConf,
+    /// Serializes the read-modify-write of records
+    writing: Mutex<()>,
+}
+
+impl SpendingTracker {
+    pub fn new(storage: Arc<dyn Storage>, conf: SpendingConf) -> Self {
+        Self {
+            storage,
+            conf,
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Day number of a timestamp, days starting at midnight UTC
+    pub fn day_of(timestamp: i64) -> i64 {
+        timestamp.div_euclid(SECS_PER_DAY)
+    }
+
+    pub fn today() -> i64 {
+        Self::day_of(chrono::Utc::now().timestamp())
+    }
+
+    /// Add a settled transaction's spend to today's, returning the budgets it is now over
+    pub async fn record(
+        &self,
+        identity: &str,
+        kind: SpendKind,
+        token: &str,
+        amount: u128,
+    ) -> Result<Option<BudgetWarning>> {
+        let day = Self::today();
+        let _writing = self.writing.lock().await;
+        let mut spend = self.day(identity, day).await?;
+        let amounts = match kind {
+            SpendKind::Swap => &mut spend.swapped,
+            SpendKind::Mint => &mut spend.minted,
+        };
+        let spent = amounts.entry(token.to_string()).or_default();
+        *spent = spent.saturating_add(amount);
+        let warning = self.check(kind, token, *spent);
+        self.storage
+            .apply(vec![Write::put(NAMESPACE, key(day, identity), serde_json::to_vec(&spend)?)])
+            .await
+            .context("Failed to persist spending")?;
+        Ok(warning)
+    }
+
+    /// What the identity spent on the given day
+    pub async fn day(&self, identity: &str, day: i64) -> Result<DailySpend> {
+        Ok(match self.storage.get(NAMESPACE, &key(day, identity)).await? {
+            Some(bytes) => serde_json::from_slice(&bytes).context("Could not parse stored spending")?,
+            None => DailySpend::default(),
+        })
+    }
+
+    /// The identity's spend over the last `history_days` days, most recent first, skipping
+    /// days without any
+    pub async fn history(&self, identity: &str) -> Result<Vec<(i64, DailySpend)>> {
+        let today = Self::today();
+        let mut history = vec![];
+        for day in (today - self.conf.history_days as i64 + 1..=today).rev() {
+            let spend = self.day(identity, day).await?;
+            if spend != DailySpend::default() {
+                history.push((day, spend));
+            }
+        }
+        Ok(history)
+    }
+
+    /// Soft budget of a token, falling back to the default of its kind
+    pub fn budget(&self, kind: SpendKind, token: &str) -> Option<u128> {
+        let token_budgets = self.conf.tokens.get(token);
+        match kind {
+            SpendKind::Swap => token_budgets
+                .and_then(|t| t.daily_swap_budget)
+                .or(self.conf.daily_swap_budget),
+            SpendKind::Mint => token_budgets
+                .and_then(|t| t.daily_mint_budget)
+                .or(self.conf.daily_mint_budget),
+        }
+    }
+
+    fn check(&self, kind: SpendKind, token: &str, spent: u128) -> Option<BudgetWarning> {
+        let budget = self.budget(kind, token)?;
+        (spent > budget).then(|| BudgetWarning {
+            kind,
+            token: token.to_string(),
+            spent,
+            budget,
+        })
+    }
+
+    /// Every budget the given spend is over
+    pub fn warnings(&self, spend: &DailySpend) -> Vec<BudgetWarning> {
+        [SpendKind::Swap, SpendKind::Mint]
+            .into_iter()
+            .flat_map(|kind| {
+                spend
+                    .amounts(kind)
+                    .iter()
+                    .filter_map(move |(token, spent)| self.check(kind, token, *spent))
+            })
+            .collect()
+    }
+
+    /// Drop the days older than `history_days`, returning how many records went
+    pub async fn prune(&self) -> Result<usize> {
+        let cutoff = Self::today() - self.conf.history_days as i64 + 1;
+        // Keys of the cutoff day sort after the bare day, only older ones are here
+        let expired = self
+            .storage
+            .range(NAMESPACE, &[], Some(&cutoff.max(0).to_be_bytes()))
+            .await?;
+        let count = expired.len();
+        self.storage
+            .apply(expired.into_iter().map(|(key, _)| Write::remove(NAMESPACE, key)).collect())
+            .await?;
+        Ok(count)
+    }
+}
+
+/// `<day, big endian><identity>`, so records sort by day
+fn key(day: i64, identity: &str) -> Vec<u8> {
+    let mut key = day.max(0).to_be_bytes().to_vec();
+    key.extend_from_slice(identity.as_bytes());
+    key
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use client_sdk::rest_client::IndexerApiHttpClient;
+use opentelemetry::{metrics::Counter, KeyValue};
+use sdk::{ContractName, StateCommitment};
+use tokio::sync::Mutex;
+
+use crate::sync_status::SyncMonitor;
+
+/// Decoded state of a contract as last read off the indexer, kept for the block it was read at.
+///
+/// Lookups within the same processed block share one read and decode; the next block the DA
+/// stream processes invalidates it. Nothing is kept before the first block was processed.
+pub struct StateCache<T> {
+    indexer: Arc<IndexerApiHttpClient>,
+    contract_name: ContractName,
+    sync: SyncMonitor,
+    /// Processed height the state was read at, and the state
+    entry: Mutex<Option<(u64, Arc<T>)>>,
+    hits: Counter<u64>,
+    misses: Counter<u64>,
+    labels: [KeyValue; 1],
+}
+
+impl<T> StateCache<T>
+where
+    T: TryFrom<StateCommitment>,
+    T::Error: std::fmt::Display,
+{
+    pub fn new(indexer: Arc<IndexerApiHttpClient>, contract_name: ContractName, sync: SyncMonitor) -> Self {
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        Self {
+            labels: [KeyValue::new("contract", contract_name.0.clone())],
+            indexer,
+            contract_name,
+            sync,
+            entry: Default::default(),
+            hits: meter.u64_counter("contract_state_cache_hits").build(),
+            misses: meter.u64_counter("contract_state_cache_misses").build(),
+        }
+    }
+
+    /// State as of the last processed block, read off the indexer once per block
+    pub async fn get(&self) -> Result<Arc<T>> {
+        let height = self.sync.status().await.processed_height;
+        // Held across the read so concurrent misses of a block wait for one read
+        let mut entry = self.entry.lock().await;
+        if let (Some(height), Some((cached_height, state))) = (height, entry.as_ref()) {
+            if *cached_height == height {
+                self.hits.add(1, &self.labels);
+                return Ok(state.clone());
+            }
+        }
+        self.misses.add(1, &self.labels);
+        let contract = self.indexer.get_indexer_contract(&self.contract_name).await?;
+        let state = Arc::new(
+            T::try_from(StateCommitment(contract.state_commitment))
+                .map_err(|e| anyhow!("{}", e))
+                .with_context(|| format!("Could not decode {} state", self.contract_name))?,
+        );
+        *entry = height.map(|height| (height, state.clone()));
+        Ok(state)
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Result};
+use contract1::{Contract1, StateV1, StateV2, STATE_SCHEMA_VERSION};
+use sdk::ZkContract;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Canonical JSON dump of the contract1 state, for inspection, fixtures and migration rehearsals
+#[derive(Serialize, Deserialize)]
+pub struct StateDump<S = Contract1> {
+    pub schema_version: u32,
+    /// Whether the state carries the rounding reserve of the `rounding-audit` layout
+    pub rounding_audit: bool,
+    /// Whether the commitment is the root of the `segmented-state` layout
+    #[serde(default)]
+    pub segmented_state: bool,
+    pub contract_name: String,
+    /// Latest block the state had seen
+    pub block_height: u64,
+    /// SHA-256 of the state commitment, checked on import
+    pub commitment: String,
+    pub state: S,
+}
+
+/// State of a dump, encoded as the commitment of its schema covered it
+pub trait SchemaEncoding {
+    fn encode(&self, schema_version: u32) -> Result<Vec<u8>>;
+}
+
+impl SchemaEncoding for StateV1 {
+    fn encode(&self, _schema_version: u32) -> Result<Vec<u8>> {
+        Ok(borsh::to_vec(self)?)
+    }
+}
+
+impl SchemaEncoding for StateV2 {
+    fn encode(&self, _schema_version: u32) -> Result<Vec<u8>> {
+        Ok(borsh::to_vec(self)?)
+    }
+}
+
+impl SchemaEncoding for Contract1 {
+    fn encode(&self, schema_version: u32) -> Result<Vec<u8>> {
+        Ok(self.encode_as(schema_version))
+    }
+}
+
+#[derive(Deserialize)]
+struct DumpHeader {
+    schema_version: u32,
+}
+
+impl StateDump {
+    /// Read a dump of the current schema, or of an older one migrated to it
+    pub fn parse(dump: serde_json::Value) -> Result<Self> {
+        let header: DumpHeader = serde_json::from_value(dump.clone()).context("Invalid state dump")?;
+        match header.schema_version {
+            1 => serde_json::from_value::<StateDump<StateV1>>(dump).context("Invalid schema 1 state dump")?.migrate(),
+            2 => serde_json::from_value::<StateDump<StateV2>>(dump).context("Invalid schema 2 state dump")?.migrate(),
+            // Later schemas only added fields, which decode to their defaults
+            version @ 3..STATE_SCHEMA_VERSION => serde_json::from_value::<StateDump>(dump)
+                .with_context(|| format!("Invalid schema {} state dump", version))?
+                .migrate(),
+            _ => serde_json::from_value(dump).context("Invalid state dump"),
+        }
+    }
+
+    pub fn new(contract_name: &str, state: Contract1) -> Self {
+        StateDump {
+            schema_version: STATE_SCHEMA_VERSION,
+            rounding_audit: cfg!(feature = "rounding-audit"),
+            segmented_state: cfg!(feature = "segmented-state"),
+            contract_name: contract_name.to_string(),
+            block_height: state.block_height(),
+            commitment: commitment_digest(&state),
+            state,
+        }
+    }
+
+    /// The state of a dump this build can load, unchanged since it was exported
+    pub fn into_state(self) -> Result<Contract1> {
+        if self.schema_version != STATE_SCHEMA_VERSION {
+            bail!(
+                "Dump has schema version {}, this build reads version {}",
+                self.schema_version,
+                STATE_SCHEMA_VERSION
+            );
+        }
+        if self.rounding_audit != cfg!(feature = "rounding-audit") {
+            bail!("Dump and build disagree on the rounding-audit state layout");
+        }
+        if self.segmented_state != cfg!(feature = "segmented-state") {
+            bail!("Dump and build disagree on the segmented-state commitment");
+        }
+        let commitment = commitment_digest(&self.state);
+        if commitment != self.commitment {
+            bail!("State commits to {}, the dump records {}", commitment, self.commitment);
+        }
+        Ok(self.state)
+    }
+}
+
+impl<S> StateDump<S>
+where
+    S: SchemaEncoding,
+    Contract1: TryFrom<S>,
+    <Contract1 as TryFrom<S>>::Error: std::fmt::Display,
+{
+    /// The dump of an older schema checked against its commitment and moved to the current schema
+    fn migrate(self) -> Result<StateDump> {
+        if cfg!(feature = "segmented-state") || self.segmented_state {
+            bail!(
+                "Schema {} dumps of a segmented state cannot be checked, migrate them on a build without it",
+                self.schema_version
+            );
+        }
+        if self.rounding_audit != cfg!(feature = "rounding-audit") {
+            bail!("Dump and build disagree on the rounding-audit state layout");
+        }
+        let encoded = self.state.encode(self.schema_version)?;
+        // Up to schema 5 the commitment was the encoded state itself, then its hash
+        let commitment = match self.schema_version {
+            ..=5 => hex::encode(Sha256::digest(encoded)),
+            _ => hex::encode(Sha256::digest(Sha256::digest(encoded))),
+        };
+        if commitment != self.commitment {
+            bail!("State commits to {}, the dump records {}", commitment, self.commitment);
+        }
+        let state = Contract1::try_from(self.state).map_err(|e| anyhow::anyhow!("Could not migrate the state: {}", e))?;
+        Ok(StateDump::new(&self.contract_name, state))
+    }
+}
+
+pub fn commitment_digest(state: &Contract1) -> String {
+    hex::encode(Sha256::digest(state.commit().0))
+}
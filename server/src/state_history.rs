@@ -0,0 +1,338 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use contract1::{Contract1, LiquidityPool};
+use serde::{Deserialize, Serialize};
+
+use crate::conf::StateHistoryConf;
+
+/// user -> token or pair key -> amount, without zero amounts
+type Holdings = BTreeMap<String, BTreeMap<String, u128>>;
+
+/// Pools, balances and liquidity positions of the AMM as of a block
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoricalState {
+    pub pools: BTreeMap<String, LiquidityPool>,
+    /// user -> token -> amount, without zero balances
+    pub balances: Holdings,
+    /// user -> pair key -> liquidity, empty in checkpoints written before positions were kept
+    #[serde(default)]
+    pub liquidity: Holdings,
+}
+
+/// A user's balances and liquidity positions after a block, valued in a quote token
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioPoint {
+    pub block: u64,
+    pub balances: BTreeMap<String, u128>,
+    /// Pair key -> the position and what it withdraws at the pool's reserves then
+    pub positions: BTreeMap<String, PositionHoldings>,
+    /// Everything above in the quote token, leaving out tokens without a pool against it
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionHoldings {
+    pub liquidity: u128,
+    pub token_a: String,
+    pub amount_a: u128,
+    pub token_b: String,
+    pub amount_b: u128,
+}
+
+impl HistoricalState {
+    fn of(state: &Contract1) -> Self {
+        let mut balances = Holdings::new();
+        for (user, token, amount) in state.user_balances().filter(|(_, _, amount)| *amount > 0) {
+            balances.entry(user.to_string()).or_default().insert(token.to_string(), amount);
+        }
+        let mut liquidity = Holdings::new();
+        for (user, pair, amount) in state.liquidity_balances().filter(|(_, _, amount)| *amount > 0) {
+            liquidity.entry(user.to_string()).or_default().insert(pair.to_string(), amount);
+        }
+        Self {
+            pools: state.pools().iter().map(|(pair, pool)| (pair.clone(), pool.clone())).collect(),
+            balances,
+            liquidity,
+        }
+    }
+
+    fn apply(&mut self, delta: Delta) {
+        for (pair, pool) in delta.pools {
+            match pool {
+                Some(pool) => self.pools.insert(pair, pool),
+                None => self.pools.remove(&pair),
+            };
+        }
+        apply_holdings(&mut self.balances, delta.balances);
+        apply_holdings(&mut self.liquidity, delta.liquidity);
+    }
+
+    /// Holdings of `user` valued in `quote`, adding the tokens that could not be priced to
+    /// `unpriced`
+    pub fn portfolio(
+        &self,
+        user: &str,
+        block: u64,
+        quote: &str,
+        unpriced: &mut BTreeSet<String>,
+    ) -> PortfolioPoint {
+        let balances = self.balances.get(user).cloned().unwrap_or_default();
+        let positions: BTreeMap<String, PositionHoldings> = self
+            .liquidity
+            .get(user)
+            .into_iter()
+            .flatten()
+            .filter_map(|(pair, liquidity)| {
+                let pool = self.pools.get(pair).filter(|pool| pool.total_liquidity > 0)?;
+                let position = PositionHoldings {
+                    liquidity: *liquidity,
+                    token_a: pool.token_a.clone(),
+                    amount_a: liquidity * pool.reserve_a / pool.total_liquidity,
+                    token_b: pool.token_b.clone(),
+                    amount_b: liquidity * pool.reserve_b / pool.total_liquidity,
+                };
+                Some((pair.clone(), position))
+            })
+            .collect();
+
+        let held = balances
+            .iter()
+            .map(|(token, amount)| (token, *amount))
+            .chain(positions.values().flat_map(|position| {
+                [
+                    (&position.token_a, position.amount_a),
+                    (&position.token_b, position.amount_b),
+                ]
+            }));
+        let mut value = 0.0;
+        for (token, amount) in held {
+            match self.price(token, quote, block) {
+                Some(price) => value += amount as f64 * price,
+                None => {
+                    unpriced.insert(token.clone());
+                }
+            }
+        }
+        PortfolioPoint { block, balances, positions, value }
+    }
+
+    /// Spot price of `token` in `quote` in a pool of the two, 1 for the quote itself
+    fn price(&self, token: &str, quote: &str, block: u64) -> Option<f64> {
+        if token == quote {
+            return Some(1.0);
+        }
+        let pool = self.pools.values().find(|pool| {
+            let pairs = (pool.token_a == token && pool.token_b == quote)
+                || (pool.token_a == quote && pool.token_b == token);
+            pairs && pool.reserve_a > 0 && pool.reserve_b > 0
+        })?;
+        // Spot price of a weighted pool: (reserve_b / weight_b) / (reserve_a / weight_a)
+        let (weight_a, weight_b) = pool.weights(block);
+        let price_a = (pool.reserve_b * weight_a) as f64 / (pool.reserve_a * weight_b) as f64;
+        Some(if pool.token_a == token { price_a } else { 1.0 / price_a })
+    }
+}
+
+/// Set the amounts a delta changed, dropping zeroed ones
+fn apply_holdings(holdings: &mut Holdings, changes: Holdings) {
+    for (user, amounts) in changes {
+        let held = holdings.entry(user.clone()).or_default();
+        for (key, amount) in amounts {
+            match amount {
+                0 => held.remove(&key),
+                _ => held.insert(key, amount),
+            };
+        }
+        if held.is_empty() {
+            holdings.remove(&user);
+        }
+    }
+}
+
+/// What changed in a block: new pool values, `None` for removed pools, and new balances and
+/// liquidity, zero for emptied ones
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Delta {
+    pools: BTreeMap<String, Option<LiquidityPool>>,
+    balances: Holdings,
+    #[serde(default)]
+    liquidity: Holdings,
+}
+
+impl Delta {
+    fn between(previous: &HistoricalState, state: &HistoricalState) -> Self {
+        let mut delta = Delta::default();
+        for (pair, pool) in &state.pools {
+            if previous.pools.get(pair).is_none_or(|before| !same_pool(before, pool)) {
+                delta.pools.insert(pair.clone(), Some(pool.clone()));
+            }
+        }
+        for pair in previous.pools.keys().filter(|pair| !state.pools.contains_key(*pair)) {
+            delta.pools.insert(pair.clone(), None);
+        }
+        delta.balances = holdings_changes(&previous.balances, &state.balances);
+        delta.liquidity = holdings_changes(&previous.liquidity, &state.liquidity);
+        delta
+    }
+
+    /// Fold a later change of the same block into this one
+    fn merge(&mut self, later: Delta) {
+        self.pools.extend(later.pools);
+        for (user, tokens) in later.balances {
+            self.balances.entry(user).or_default().extend(tokens);
+        }
+        for (user, pairs) in later.liquidity {
+            self.liquidity.entry(user).or_default().extend(pairs);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pools.is_empty() && self.balances.is_empty() && self.liquidity.is_empty()
+    }
+}
+
+/// Amounts that differ between two holdings, zero for those gone
+fn holdings_changes(previous: &Holdings, state: &Holdings) -> Holdings {
+    let mut changes = Holdings::new();
+    let empty = BTreeMap::new();
+    for (user, amounts) in state {
+        let before = previous.get(user).unwrap_or(&empty);
+        for (key, amount) in amounts {
+            if before.get(key) != Some(amount) {
+                changes.entry(user.clone()).or_default().insert(key.clone(), *amount);
+            }
+        }
+    }
+    for (user, amounts) in previous {
+        let after = state.get(user).unwrap_or(&empty);
+        for key in amounts.keys().filter(|key| !after.contains_key(*key)) {
+            changes.entry(user.clone()).or_default().insert(key.clone(), 0);
+        }
+    }
+    changes
+}
+
+fn same_pool(a: &LiquidityPool, b: &LiquidityPool) -> bool {
+    borsh::to_vec(a).ok() == borsh::to_vec(b).ok()
+}
+
+/// Pools, balances and liquidity positions at past block heights, persisted with sled in the data directory.
+///
+/// A full checkpoint is kept every `checkpoint_interval_blocks`, and each block with settled
+/// changes in between keeps only what it changed. The state at a height is its latest checkpoint
+/// with the deltas of the blocks since applied on top. Blocks older than `retain_blocks` are
+/// dropped, down to the checkpoint they still need.
+pub struct StateHistory {
+    conf: StateHistoryConf,
+    checkpoints: sled::Tree,
+    deltas: sled::Tree,
+}
+
+impl StateHistory {
+    pub fn open(data_directory: &Path, conf: StateHistoryConf) -> Result<Self> {
+        let path = data_directory.join("state_history");
+        let db = sled::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+
+        Ok(Self {
+            conf,
+            checkpoints: db.open_tree("checkpoints")?,
+            deltas: db.open_tree("deltas")?,
+        })
+    }
+
+    /// Highest block recorded
+    pub fn latest_height(&self) -> Result<Option<u64>> {
+        let checkpoint = self.checkpoints.last()?.map(|(key, _)| height(&key));
+        let delta = self.deltas.last()?.map(|(key, _)| height(&key));
+        Ok(checkpoint.max(delta))
+    }
+
+    /// Record a settled state, `previous` being the one it was settled on.
+    ///
+    /// States of blocks older than the latest recorded one are replays of blocks already
+    /// recorded, and skipped.
+    pub fn record(&self, previous: Option<&Contract1>, state: &Contract1) -> Result<()> {
+        let block = state.block_height();
+        let latest = self.latest_height()?;
+        if latest.is_some_and(|latest| block < latest) {
+            return Ok(());
+        }
+        let last_checkpoint = self.checkpoints.last()?.map(|(key, _)| height(&key));
+        let current = HistoricalState::of(state);
+        let checkpoint_due = match (previous, last_checkpoint) {
+            (None, _) | (_, None) => true,
+            // A block checkpointed once is checkpointed again, so it never also has a delta
+            (Some(_), Some(checkpoint)) => {
+                block == checkpoint || block >= checkpoint + self.conf.checkpoint_interval_blocks.max(1)
+            }
+        };
+        if checkpoint_due {
+            self.checkpoints.insert(block.to_be_bytes(), serde_json::to_vec(&current)?)?;
+            self.prune(block)?;
+            return Ok(());
+        }
+
+        let Some(previous) = previous else {
+            return Ok(());
+        };
+        let mut delta = Delta::between(&HistoricalState::of(previous), &current);
+        if delta.is_empty() {
+            return Ok(());
+        }
+        if let Some(earlier) = self.deltas.get(block.to_be_bytes())? {
+            let mut merged: Delta = serde_json::from_slice(&earlier)?;
+            merged.merge(delta);
+            delta = merged;
+        }
+        self.deltas.insert(block.to_be_bytes(), serde_json::to_vec(&delta)?)?;
+        Ok(())
+    }
+
+    /// Pools, balances and positions after block `block`, `None` when it is older than what is retained
+    pub fn at(&self, block: u64) -> Result<Option<HistoricalState>> {
+        let Some((key, checkpoint)) = self.checkpoints.range(..=block.to_be_bytes()).next_back().transpose()? else {
+            return Ok(None);
+        };
+        let mut state: HistoricalState = serde_json::from_slice(&checkpoint)?;
+        let from = height(&key) + 1;
+        for entry in self.deltas.range(from.to_be_bytes()..=block.to_be_bytes()) {
+            state.apply(serde_json::from_slice(&entry?.1)?);
+        }
+        Ok(Some(state))
+    }
+
+    /// Forget block `from` and every later one, replaced by the DA stream
+    pub fn rollback(&self, from: u64) -> Result<()> {
+        for key in self.checkpoints.range(from.to_be_bytes()..).keys() {
+            self.checkpoints.remove(key?)?;
+        }
+        for key in self.deltas.range(from.to_be_bytes()..).keys() {
+            self.deltas.remove(key?)?;
+        }
+        Ok(())
+    }
+
+    /// Drop what states after `block - retain_blocks` no longer need
+    fn prune(&self, block: u64) -> Result<()> {
+        let cutoff = block.saturating_sub(self.conf.retain_blocks);
+        // The checkpoint the oldest retained blocks start from stays
+        let Some((oldest_needed, _)) = self.checkpoints.range(..=cutoff.to_be_bytes()).next_back().transpose()? else {
+            return Ok(());
+        };
+        for key in self.checkpoints.range(..oldest_needed.clone()).keys() {
+            self.checkpoints.remove(key?)?;
+        }
+        for key in self.deltas.range(..=oldest_needed).keys() {
+            self.deltas.remove(key?)?;
+        }
+        Ok(())
+    }
+}
+
+fn height(key: &[u8]) -> u64 {
+    u64::from_be_bytes(key.try_into().unwrap_or_default())
+}
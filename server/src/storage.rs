@@ -0,0 +1,317 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+
+use crate::conf::StorageConf;
+use crate::secrets::{self, Secrets};
+
+/// Table the Postgres backend keeps every namespace in
+const POSTGRES_TABLE: &str = "server_storage";
+
+/// Keys and values read by `Storage::range`
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A change of a batch applied by `Storage::apply`; `value` unset removes the key
+pub struct Write {
+    pub namespace: String,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl Write {
+    pub fn put(namespace: &str, key: impl Into<Vec<u8>>, value: Vec<u8>) -> Self {
+        Write {
+            namespace: namespace.to_string(),
+            key: key.into(),
+            value: Some(value),
+        }
+    }
+
+    pub fn remove(namespace: &str, key: impl Into<Vec<u8>>) -> Self {
+        Write {
+            namespace: namespace.to_string(),
+            key: key.into(),
+            value: None,
+        }
+    }
+}
+
+/// Persistence of the records the server keeps itself: tx history, submitted proofs, price
+/// history and profiles.
+///
+/// Entries live in namespaces, one per kind of record, ordered by key bytes within one so
+/// time-keyed records can be read back by range.
+pub trait Storage: Send + Sync {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Entries with keys within `[from, to]`, up to the last one when `to` is unset, in key order
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        from: &'a [u8],
+        to: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<Entries>>;
+
+    /// Apply writes of any namespaces at once: either all of them persist or none does
+    fn apply(&self, writes: Vec<Write>) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Open the backend selected by `[storage]`
+pub async fn open(conf: &StorageConf, data_directory: &Path, secrets: &Secrets) -> Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match conf.backend.as_str() {
+        "sled" => Arc::new(SledStorage::open(&data_directory.join("storage"))?),
+        "postgres" => {
+            let url = secrets.require(secrets::POSTGRES_URL).await?;
+            let initial = url.borrow().clone();
+            let storage = Arc::new(PostgresStorage::connect(&initial, &conf.scope).await?);
+            // New credentials apply to the next queries, those running finish on the old connection
+            let reconnected = storage.clone();
+            secrets::on_rotation(secrets::POSTGRES_URL, url, move |url| {
+                let storage = reconnected.clone();
+                Box::pin(async move { storage.reconnect(&url).await })
+            });
+            storage
+        }
+        other => bail!("Unknown storage backend '{}', expected sled or postgres", other),
+    };
+    tracing::info!("🗄️ Storing server records with {}", conf.backend);
+    Ok(storage)
+}
+
+/// Import a file the data directory held before its records moved to storage, then rename it
+/// with an `.imported` suffix so it is imported once
+pub async fn import_legacy(
+    storage: &dyn Storage,
+    path: &Path,
+    writes: impl FnOnce(&[u8]) -> Result<Vec<Write>>,
+) -> Result<()> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+    };
+    let writes = writes(&bytes).with_context(|| format!("parsing {:?}", path))?;
+    let count = writes.len();
+    storage.apply(writes).await?;
+    tokio::fs::rename(path, imported(path))
+        .await
+        .with_context(|| format!("renaming {:?}", path))?;
+    tracing::info!("🗄️ Imported {} records of {:?} into storage", count, path);
+    Ok(())
+}
+
+/// Where a legacy file or directory is moved once imported
+pub fn imported(path: &Path) -> std::path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".imported");
+    path.with_file_name(name)
+}
+
+/// Embedded backend: one sled tree in the data directory, keys prefixed by their namespace
+pub struct SledStorage {
+    tree: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        Ok(Self {
+            tree: db.open_tree("records")?,
+        })
+    }
+}
+
+/// `<namespace>\0<key>`, so a namespace's keys sort together and in their own order
+fn prefixed(namespace: &str, key: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(namespace.len() + 1 + key.len());
+    prefixed.extend_from_slice(namespace.as_bytes());
+    prefixed.push(0);
+    prefixed.extend_from_slice(key);
+    prefixed
+}
+
+impl Storage for SledStorage {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { Ok(self.tree.get(prefixed(namespace, key))?.map(|value| value.to_vec())) })
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        from: &'a [u8],
+        to: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<Entries>> {
+        Box::pin(async move {
+            let start = prefixed(namespace, from);
+            let entries = match to {
+                Some(to) => self.tree.range(start..=prefixed(namespace, to)),
+                None => {
+                    // Past every key of the namespace: its name followed by the next separator
+                    let mut end = namespace.as_bytes().to_vec();
+                    end.push(1);
+                    self.tree.range(start..end)
+                }
+            };
+            let skip = namespace.len() + 1;
+            entries
+                .map(|entry| {
+                    let (key, value) = entry?;
+                    Ok((key[skip..].to_vec(), value.to_vec()))
+                })
+                .collect()
+        })
+    }
+
+    fn apply(&self, writes: Vec<Write>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut batch = sled::Batch::default();
+            for write in writes {
+                let key = prefixed(&write.namespace, &write.key);
+                match write.value {
+                    Some(value) => batch.insert(key, value),
+                    None => batch.remove(key),
+                }
+            }
+            self.tree.apply_batch(batch)?;
+            Ok(())
+        })
+    }
+}
+
+/// Postgres backend: one table keyed by scope, namespace and key, shared by every server using
+/// the same database
+pub struct PostgresStorage {
+    /// Locked for transactions, which need the client exclusively
+    client: Mutex<tokio_postgres::Client>,
+    /// Deployment the rows belong to
+    scope: String,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str, scope: &str) -> Result<Self> {
+        let client = Self::client(url).await?;
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {POSTGRES_TABLE} (
+                    scope TEXT NOT NULL,
+                    namespace TEXT NOT NULL,
+                    key BYTEA NOT NULL,
+                    value BYTEA NOT NULL,
+                    PRIMARY KEY (scope, namespace, key)
+                )"
+            ))
+            .await
+            .context("Failed to create the storage table")?;
+        Ok(Self {
+            client: Mutex::new(client),
+            scope: scope.to_string(),
+        })
+    }
+
+    /// Swap the connection for one opened with another connection string, as when credentials rotate
+    pub async fn reconnect(&self, url: &str) -> Result<()> {
+        let client = Self::client(url).await?;
+        *self.client.lock().await = client;
+        tracing::info!("🗄️ Reconnected to Postgres with rotated credentials");
+        Ok(())
+    }
+
+    async fn client(url: &str) -> Result<tokio_postgres::Client> {
+        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls)
+            .await
+            .context("Failed to connect to Postgres")?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!("❌ Postgres connection closed: {}", e);
+            }
+        });
+        Ok(client)
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let client = self.client.lock().await;
+            let row = client
+                .query_opt(
+                    &format!("SELECT value FROM {POSTGRES_TABLE} WHERE scope = $1 AND namespace = $2 AND key = $3"),
+                    &[&self.scope, &namespace, &key],
+                )
+                .await?;
+            Ok(row.map(|row| row.get(0)))
+        })
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        from: &'a [u8],
+        to: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<Entries>> {
+        Box::pin(async move {
+            let client = self.client.lock().await;
+            let rows = match to {
+                Some(to) => {
+                    client
+                        .query(
+                            &format!(
+                                "SELECT key, value FROM {POSTGRES_TABLE}
+                                 WHERE scope = $1 AND namespace = $2 AND key >= $3 AND key <= $4 ORDER BY key"
+                            ),
+                            &[&self.scope, &namespace, &from, &to],
+                        )
+                        .await?
+                }
+                None => {
+                    client
+                        .query(
+                            &format!(
+                                "SELECT key, value FROM {POSTGRES_TABLE}
+                                 WHERE scope = $1 AND namespace = $2 AND key >= $3 ORDER BY key"
+                            ),
+                            &[&self.scope, &namespace, &from],
+                        )
+                        .await?
+                }
+            };
+            Ok(rows.into_iter().map(|row| (row.get(0), row.get(1))).collect())
+        })
+    }
+
+    fn apply(&self, writes: Vec<Write>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut client = self.client.lock().await;
+            let transaction = client.transaction().await?;
+            let upsert = transaction
+                .prepare(&format!(
+                    "INSERT INTO {POSTGRES_TABLE} (scope, namespace, key, value) VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (scope, namespace, key) DO UPDATE SET value = EXCLUDED.value"
+                ))
+                .await?;
+            let delete = transaction
+                .prepare(&format!(
+                    "DELETE FROM {POSTGRES_TABLE} WHERE scope = $1 AND namespace = $2 AND key = $3"
+                ))
+                .await?;
+            for write in &writes {
+                match &write.value {
+                    Some(value) => {
+                        transaction
+                            .execute(&upsert, &[&self.scope, &write.namespace, &write.key, value])
+                            .await?
+                    }
+                    None => {
+                        transaction
+                            .execute(&delete, &[&self.scope, &write.namespace, &write.key])
+                            .await?
+                    }
+                };
+            }
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+}
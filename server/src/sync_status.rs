@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use client_sdk::rest_client::{NodeApiClient, NodeApiHttpClient};
+use opentelemetry::metrics::Gauge;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+/// Span of recent progress the catch-up rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+/// Blocks between two progress lines while catching up
+const PROGRESS_LOG_BLOCKS: u64 = 1_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    /// Last block height processed from the DA stream
+    pub processed_height: Option<u64>,
+    /// Latest block height reported by the node
+    pub node_head: Option<u64>,
+    pub lag: Option<u64>,
+    pub max_lag_blocks: u64,
+    /// Whether the DA stream is currently within `max_lag_blocks` of head
+    pub in_sync: bool,
+    /// Set once the initial catch-up completed; stateful reads are refused until then
+    pub ready: bool,
+    /// Blocks processed per second over the last 30 seconds
+    pub blocks_per_sec: Option<f64>,
+    /// Time left to reach the node head at that rate, while lagging
+    pub eta_secs: Option<u64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    processed_height: Option<u64>,
+    node_head: Option<u64>,
+    ready: bool,
+    lagging: bool,
+    /// (when, processed height) over the last `RATE_WINDOW`, oldest first
+    progress: VecDeque<(Instant, u64)>,
+    /// Processed height of the last progress line
+    logged_height: u64,
+}
+
+/// Tracks how far the DA listener is behind the node head.
+///
+/// The processed height is fed by `NodeStateEvent::NewBlock`, the head is polled
+/// from the node. Crossing `max_lag_blocks` in either direction is logged once.
+#[derive(Clone)]
+pub struct SyncMonitor {
+    node: Arc<NodeApiHttpClient>,
+    max_lag_blocks: u64,
+    inner: Arc<RwLock<Inner>>,
+    lag_gauge: Gauge<u64>,
+    rate_gauge: Gauge<f64>,
+}
+
+impl SyncMonitor {
+    pub fn new(node: Arc<NodeApiHttpClient>, max_lag_blocks: u64) -> Self {
+        let meter = opentelemetry::global::meter("hyli_defi_app");
+        Self {
+            node,
+            max_lag_blocks,
+            inner: Default::default(),
+            lag_gauge: meter.u64_gauge("da_lag_blocks").build(),
+            rate_gauge: meter.f64_gauge("da_blocks_per_sec").build(),
+        }
+    }
+
+    pub async fn block_processed(&self, height: u64) {
+        let mut inner = self.inner.write().await;
+        inner.processed_height = Some(height);
+        if inner.node_head.is_none_or(|head| head < height) {
+            inner.node_head = Some(height);
+        }
+        let now = Instant::now();
+        inner.progress.push_back((now, height));
+        while inner.progress.front().is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW) {
+            inner.progress.pop_front();
+        }
+        self.evaluate(&mut inner);
+    }
+
+    /// Refresh the node head; failures keep the last known head
+    pub async fn poll_head(&self) {
+        #[cfg(feature = "chaos")]
+        if let Err(e) = crate::chaos::node_fault("get_block_height") {
+            tracing::warn!("⚠️ Could not fetch node block height: {}", e);
+            return;
+        }
+        match self.node.get_block_height().await {
+            Ok(head) => {
+                let mut inner = self.inner.write().await;
+                inner.node_head = Some(head.0);
+                self.evaluate(&mut inner);
+            }
+            Err(e) => tracing::warn!("⚠️ Could not fetch node block height: {}", e),
+        }
+    }
+
+    pub async fn status(&self) -> SyncStatus {
+        let inner = self.inner.read().await;
+        let lag = Self::lag(&inner);
+        let in_sync = lag.is_some_and(|lag| lag <= self.max_lag_blocks);
+        let blocks_per_sec = Self::rate(&inner);
+        SyncStatus {
+            processed_height: inner.processed_height,
+            node_head: inner.node_head,
+            lag,
+            max_lag_blocks: self.max_lag_blocks,
+            in_sync,
+            ready: inner.ready,
+            blocks_per_sec,
+            eta_secs: Self::eta(lag.filter(|_| !in_sync), blocks_per_sec),
+        }
+    }
+
+    /// Whether blocks are better applied in batches, the DA stream being far behind the head
+    pub async fn catching_up(&self) -> bool {
+        let inner = self.inner.read().await;
+        Self::lag(&inner).is_some_and(|lag| lag > self.max_lag_blocks)
+    }
+
+    fn rate(inner: &Inner) -> Option<f64> {
+        let (first_at, first) = inner.progress.front()?;
+        let (last_at, last) = inner.progress.back()?;
+        let secs = last_at.duration_since(*first_at).as_secs_f64();
+        (secs > 0.0).then(|| last.saturating_sub(*first) as f64 / secs)
+    }
+
+    fn eta(lag: Option<u64>, blocks_per_sec: Option<f64>) -> Option<u64> {
+        let rate = blocks_per_sec.filter(|rate| *rate > 0.0)?;
+        Some((lag? as f64 / rate).ceil() as u64)
+    }
+
+    pub async fn is_ready(&self) -> bool {
+        self.inner.read().await.ready
+    }
+
+    fn lag(inner: &Inner) -> Option<u64> {
+        Some(inner.node_head?.saturating_sub(inner.processed_height?))
+    }
+
+    fn evaluate(&self, inner: &mut Inner) {
+        let Some(lag) = Self::lag(inner) else {
+            return;
+        };
+        let lagging = lag > self.max_lag_blocks;
+        let blocks_per_sec = Self::rate(inner);
+        self.lag_gauge.record(lag, &[]);
+        if let Some(rate) = blocks_per_sec {
+            self.rate_gauge.record(rate, &[]);
+        }
+        let height = inner.processed_height.unwrap_or(0);
+        if lagging && height >= inner.logged_height + PROGRESS_LOG_BLOCKS {
+            inner.logged_height = height;
+            let eta = Self::eta(Some(lag), blocks_per_sec);
+            tracing::info!(
+                "⏳ Catching up: block {} of {:?}, {:.1} blocks/s, ETA {:?}s",
+                height,
+                inner.node_head,
+                blocks_per_sec.unwrap_or(0.0),
+                eta
+            );
+        }
+
+        if !inner.ready && !lagging {
+            inner.ready = true;
+            tracing::info!("✅ DA stream caught up with node head ({} blocks behind)", lag);
+        } else if inner.ready && lagging && !inner.lagging {
+            tracing::warn!(
+                "🚨 DA stream is {} blocks behind node head (threshold {})",
+                lag, self.max_lag_blocks
+            );
+        } else if inner.ready && !lagging && inner.lagging {
+            tracing::info!("✅ DA stream back within {} blocks of node head", self.max_lag_blocks);
+        }
+        inner.lagging = lagging;
+    }
+}
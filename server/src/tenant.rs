@@ -0,0 +1,169 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use contract1::{
+    oracle::OracleGuard, AdminCouncil, Bridge, Contract1, Faucet, IdentityPolicy, InsuranceFund, ProtocolFee,
+};
+use sdk::ContractName;
+
+use crate::conf::Conf;
+
+/// Identity contract name, until the Noir contract is deployed
+const IDENTITY_CN: &str = "zkpassport_identity";
+/// User the keeper submits its transactions as
+const KEEPER_USER: &str = "keeper";
+
+/// Identity of the keeper of a contract1 deployment
+pub fn keeper_identity(contract1_cn: &ContractName) -> String {
+    format!("{}@{}", KEEPER_USER, contract1_cn.0)
+}
+
+/// A deployment served by this process, with its own contracts, data and routes
+pub struct Tenant {
+    pub id: Option<String>,
+    pub contract1_cn: ContractName,
+    pub identity_cn: ContractName,
+    /// Config with data directory and prover settings scoped to this tenant
+    pub config: Arc<Conf>,
+}
+
+impl Tenant {
+    /// Routes of a tenant are nested under `/t/<id>`; the default tenant is unprefixed
+    pub fn route_prefix(&self) -> Option<String> {
+        self.id.as_ref().map(|id| format!("/t/{}", id))
+    }
+
+    pub fn label(&self) -> &str {
+        self.id.as_deref().unwrap_or("default")
+    }
+
+    /// Contract1 state to register, and to prove from
+    pub fn initial_state(&self) -> Result<Contract1> {
+        let mut state = Contract1::default()
+            .with_min_swap_output(self.config.min_swap_output)
+            .with_ordering_policy(self.config.ordering_policy)
+            .with_state_limits(self.config.state_limits.clone())
+            .with_faucet(Faucet::new(
+                self.config.faucet.cooldown_blocks,
+                self.config.faucet.lifetime_caps.clone(),
+            ));
+        if let Some(public_key) = &self.config.keeper.public_key {
+            let public_key = hex::decode(public_key)
+                .ok()
+                .and_then(|bytes| secp256k1::PublicKey::from_slice(&bytes).ok())
+                .ok_or_else(|| anyhow!("Invalid keeper config: public_key is not a secp256k1 key"))?;
+            state = state.with_keeper(keeper_identity(&self.contract1_cn), public_key.serialize().to_vec());
+        }
+        let policy = &self.config.identity_policy;
+        if policy.enabled {
+            state = state.with_identity_policy(IdentityPolicy {
+                identity_contract: self.identity_cn.clone(),
+                unverified_max_swap_per_block: policy.unverified_max_swap_per_block,
+                verified_max_swap_per_block: policy.verified_max_swap_per_block,
+                verified_lp_boost_bps: policy.verified_lp_boost_bps,
+            });
+        }
+        let insurance = &self.config.insurance;
+        if insurance.enabled {
+            let fund = InsuranceFund::new(
+                insurance.admin.clone(),
+                insurance.swap_fee_bps,
+                insurance.fund_share_bps,
+            )
+            .map_err(|e| anyhow!("Invalid insurance config: {}", e))?;
+            state = state.with_insurance(fund);
+        }
+        let protocol_fee = &self.config.protocol_fee;
+        if protocol_fee.enabled {
+            let protocol_fee = ProtocolFee::new(protocol_fee.admin.clone(), protocol_fee.share_bps)
+                .map_err(|e| anyhow!("Invalid protocol fee config: {}", e))?;
+            state = state.with_protocol_fee(protocol_fee);
+        }
+        let council = &self.config.admin_council;
+        if council.enabled {
+            let council = AdminCouncil::new(council.admins.clone(), council.threshold, council.mint_cap)
+                .map_err(|e| anyhow!("Invalid admin council config: {}", e))?;
+            state = state.with_admin_council(council);
+        }
+        let oracle = &self.config.oracle_guard;
+        if oracle.enabled {
+            let guard = OracleGuard::new(
+                oracle.oracle_contract.clone().into(),
+                oracle.min_amount_in,
+                oracle.max_deviation_bps,
+            )
+            .map_err(|e| anyhow!("Invalid oracle guard config: {}", e))?;
+            state = state.with_oracle_guard(guard);
+        }
+        for (token, decimals) in &self.config.token_decimals {
+            state = state.with_token_decimals(token.clone(), *decimals);
+        }
+        if let Some(distribution) = &self.config.fee_distribution {
+            distribution
+                .validate()
+                .map_err(|e| anyhow!("Invalid fee distribution config: {}", e))?;
+            state = state.with_fee_distribution(distribution.clone());
+        }
+        if let Some(swap_fee_bps) = self.config.pool_swap_fee_bps {
+            if swap_fee_bps > 10_000 {
+                bail!("Invalid pool_swap_fee_bps config: basis points must be at most 10000");
+            }
+            state = state.with_pool_swap_fee(swap_fee_bps);
+        }
+        if self.config.bridge.enabled {
+            state = state.with_bridge(Bridge::new(
+                self.config.bridge.operator.clone(),
+                self.config.bridge.withdrawal_limit,
+            ));
+        }
+        for (token, token_contract) in &self.config.external_tokens {
+            state = state.with_external_token(token.clone(), token_contract.clone().into());
+        }
+        Ok(state)
+    }
+}
+
+/// Resolve the configured tenants, falling back to a single unprefixed deployment
+pub fn resolve(config: &Arc<Conf>, contract1_cn: &str) -> Result<Vec<Tenant>> {
+    if config.tenants.is_empty() {
+        return Ok(vec![Tenant {
+            id: None,
+            contract1_cn: contract1_cn.into(),
+            identity_cn: IDENTITY_CN.into(),
+            config: config.clone(),
+        }]);
+    }
+
+    let mut seen = HashSet::new();
+    config
+        .tenants
+        .iter()
+        .map(|tenant| {
+            if tenant.id.is_empty()
+                || !tenant.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!("Invalid tenant id '{}'", tenant.id);
+            }
+            if tenant.contract_prefix.is_empty() {
+                bail!("Tenant '{}' needs a contract_prefix", tenant.id);
+            }
+            if !seen.insert(tenant.id.clone()) {
+                bail!("Duplicate tenant id '{}'", tenant.id);
+            }
+
+            let mut scoped = (**config).clone();
+            scoped.data_directory = config.data_directory.join("tenants").join(&tenant.id);
+            scoped.storage.scope = tenant.id.clone();
+            scoped.buffer_blocks = tenant.buffer_blocks.unwrap_or(config.buffer_blocks);
+            scoped.max_txs_per_proof = tenant.max_txs_per_proof.unwrap_or(config.max_txs_per_proof);
+            scoped.tenants = vec![];
+
+            Ok(Tenant {
+                id: Some(tenant.id.clone()),
+                contract1_cn: format!("{}_{}", tenant.contract_prefix, contract1_cn).into(),
+                identity_cn: format!("{}_{}", tenant.contract_prefix, IDENTITY_CN).into(),
+                config: Arc::new(scoped),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{watch, RwLock},
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use crate::conf::TlsConf;
+use crate::secrets::{self, Secrets};
+use crate::versioning;
+
+/// Marks requests whose TLS connection presented a client certificate the configured CA verified
+#[derive(Clone, Copy, Debug)]
+pub struct ClientCertificate;
+
+/// Certificate chain and key of the listener: the `tls_cert` and `tls_key` secrets when the
+/// provider holds them, the files of `[tls]` otherwise
+struct Identity {
+    conf: TlsConf,
+    cert: Option<watch::Receiver<String>>,
+    key: Option<watch::Receiver<String>>,
+}
+
+impl Identity {
+    fn certs(&self) -> Result<Vec<CertificateDer<'static>>> {
+        match &self.cert {
+            Some(cert) => CertificateDer::pem_slice_iter(cert.borrow().as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .context("reading TLS certificates from secret tls_cert"),
+            None => CertificateDer::pem_file_iter(&self.conf.cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .with_context(|| format!("reading TLS certificates from {}", self.conf.cert_path.display())),
+        }
+    }
+
+    fn key(&self) -> Result<PrivateKeyDer<'static>> {
+        match &self.key {
+            Some(key) => {
+                PrivateKeyDer::from_pem_slice(key.borrow().as_bytes()).context("reading TLS key from secret tls_key")
+            }
+            None => PrivateKeyDer::from_pem_file(&self.conf.key_path)
+                .with_context(|| format!("reading TLS key from {}", self.conf.key_path.display())),
+        }
+    }
+}
+
+fn server_config(identity: &Identity) -> Result<ServerConfig> {
+    let conf = &identity.conf;
+    let certs = identity.certs()?;
+    let key = identity.key()?;
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match &conf.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("reading client CA from {}", path.display()))?
+            {
+                roots.add(cert?)?;
+            }
+            // Other routes stay open to clients without a certificate
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    // Refuses a key that does not match the certificate, so while both rotate the previous pair
+    // keeps serving until the second one arrives
+    let mut config = builder.with_single_cert(certs, key).context("loading TLS certificate")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// HTTPS listener, bound with its certificates loaded so misconfiguration fails at startup.
+///
+/// Certificates read from secrets are swapped when they rotate; established connections keep
+/// the ones they were accepted with.
+pub struct TlsListener {
+    listener: TcpListener,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl TlsListener {
+    pub async fn bind(conf: &TlsConf, secrets: &Secrets) -> Result<Self> {
+        let identity = Arc::new(Identity {
+            conf: conf.clone(),
+            cert: secrets.watch(secrets::TLS_CERT).await?,
+            key: secrets.watch(secrets::TLS_KEY).await?,
+        });
+        let acceptor = Arc::new(RwLock::new(TlsAcceptor::from(Arc::new(server_config(&identity)?))));
+        for (name, secret) in [(secrets::TLS_CERT, &identity.cert), (secrets::TLS_KEY, &identity.key)] {
+            let Some(secret) = secret else { continue };
+            let (identity, acceptor) = (identity.clone(), acceptor.clone());
+            secrets::on_rotation(name, secret.clone(), move |_| {
+                let (identity, acceptor) = (identity.clone(), acceptor.clone());
+                Box::pin(async move {
+                    let config = server_config(&identity)?;
+                    *acceptor.write().await = TlsAcceptor::from(Arc::new(config));
+                    info!("🔒 Serving new TLS connections with the rotated certificate");
+                    Ok(())
+                })
+            });
+        }
+        let listener = TcpListener::bind(("0.0.0.0", conf.port))
+            .await
+            .with_context(|| format!("binding TLS port {}", conf.port))?;
+        info!("🔒 Serving the API over TLS on port {}", conf.port);
+        Ok(TlsListener { listener, acceptor })
+    }
+
+    /// Serve `router` until the process exits
+    pub async fn serve(self, router: Router) {
+        let TlsListener { listener, acceptor } = self;
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("⚠️ Could not accept TLS connection: {}", e);
+                    continue;
+                }
+            };
+            let acceptor = acceptor.read().await.clone();
+            let router = router.clone();
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("⚠️ TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                // The verifier rejects the handshake on an invalid certificate, so any left is verified
+                let verified = stream.get_ref().1.peer_certificates().is_some_and(|certs| !certs.is_empty());
+                let service = hyper::service::service_fn(move |mut request: Request<hyper::body::Incoming>| {
+                    if verified {
+                        request.extensions_mut().insert(ClientCertificate);
+                    }
+                    router.clone().oneshot(request)
+                });
+                if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                    .await
+                {
+                    warn!("⚠️ TLS connection with {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// Admin routes only served over TLS connections with a verified client certificate
+pub struct AdminGate {
+    routes: HashSet<String>,
+}
+
+impl AdminGate {
+    /// The gate of `conf`, when client certificates are configured
+    pub fn new(conf: &TlsConf) -> Option<Self> {
+        (conf.enabled && conf.client_ca_path.is_some()).then(|| AdminGate {
+            routes: conf.admin_routes.iter().cloned().collect(),
+        })
+    }
+
+    fn guards(&self, path: &str) -> bool {
+        let path = versioning::legacy_path(path);
+        self.routes
+            .iter()
+            .any(|route| path == *route || path.strip_prefix(route.as_str()).is_some_and(|rest| rest.starts_with('/')))
+    }
+}
+
+/// Middleware refusing admin routes to requests without a verified client certificate
+pub async fn require_client_certificate(State(gate): State<Arc<AdminGate>>, request: Request, next: Next) -> Response {
+    if gate.guards(request.uri().path()) && request.extensions().get::<ClientCertificate>().is_none() {
+        return (StatusCode::FORBIDDEN, "Admin routes need a TLS client certificate").into_response();
+    }
+    next.run(request).await
+}
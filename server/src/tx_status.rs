@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use hyle_modules::bus::BusMessage;
+use sdk::TxHash;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, RwLock};
+
+use crate::storage::{self, Storage, Write};
+
+/// Number of transactions kept in memory before the oldest are evicted
+const MAX_TRACKED_TXS: usize = 10_000;
+/// Number of recent proving durations averaged into the ETA
+const ETA_SAMPLES: usize = 20;
+/// Storage namespace of the history, keyed by tx hash
+const NAMESPACE: &str = "tx_history";
+/// How often the history is written to storage
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum TxStatus {
+    Pending,
+    Success,
+    Failed { error: String },
+}
+
+/// Intermediate steps between submission and settlement
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "stage")]
+pub enum TxProgress {
+    Submitted,
+    /// Included in a block by the node
+    Sequenced { block_height: u64 },
+    /// Executed by the AutoProver, whose proof is now being generated
+    ProvingStarted { eta_secs: Option<u64> },
+    /// Proof submitted and the transaction settled on chain
+    Settled { success: bool },
+    /// The block holding the transaction was replaced by the DA stream, from `block_height` on.
+    /// The transaction is pending again, and goes through its stages anew once sequenced
+    Reorged { block_height: u64 },
+}
+
+impl TxProgress {
+    fn same_stage(&self, other: &TxProgress) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxProgressEntry {
+    /// Position in the transaction's progress history
+    pub seq: usize,
+    #[serde(flatten)]
+    pub progress: TxProgress,
+    pub at: i64,
+}
+
+/// Progress of an API-submitted transaction, published on the bus
+#[derive(Debug, Clone, Serialize)]
+pub struct TxProgressEvent {
+    pub tx_hash: TxHash,
+    pub identity: String,
+    #[serde(flatten)]
+    pub entry: TxProgressEntry,
+}
+
+impl BusMessage for TxProgressEvent {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub tx_hash: String,
+    pub identity: String,
+    #[serde(flatten)]
+    pub status: TxStatus,
+    pub submitted_at: i64,
+    /// Transaction this one was an automatic retry of
+    pub retry_of: Option<String>,
+    /// Automatic retry submitted after this one failed
+    pub retried_by: Option<String>,
+    pub progress: Vec<TxProgressEntry>,
+    /// zkVM cycles of the contract1 actions, measured by `cycle-accounting` builds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycles: Option<ActionCycles>,
+}
+
+/// Cycles the contract1 guest spends executing a transaction
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ActionCycles {
+    /// Name of the contract1 action, `+`-joined when the transaction carries several
+    pub action: String,
+    /// Cycles executed by the guest
+    pub user_cycles: u64,
+    /// Cycles proven, with every segment padded to a power of two
+    pub total_cycles: u64,
+    pub segments: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    records: HashMap<String, TxRecord>,
+    order: VecDeque<String>,
+    /// Recent durations between proving start and settlement, in seconds
+    proving_durations: VecDeque<i64>,
+    /// Records changed since the last flush
+    dirty: HashSet<String>,
+    /// Records evicted since the last flush
+    evicted: HashSet<String>,
+}
+
+/// Tracks the lifecycle of transactions submitted through the API,
+/// periodically flushed to storage
+pub struct TxStatusStore {
+    storage: Arc<dyn Storage>,
+    inner: RwLock<Inner>,
+    settled: broadcast::Sender<(TxHash, TxStatus)>,
+    progress: broadcast::Sender<TxProgressEvent>,
+}
+
+impl TxStatusStore {
+    /// Load the stored history, importing the `tx_history.json` of the data directory once
+    pub async fn load(storage: Arc<dyn Storage>, data_directory: &Path) -> Result<Self> {
+        storage::import_legacy(&*storage, &data_directory.join("tx_history.json"), |bytes| {
+            let records: Vec<TxRecord> = serde_json::from_slice(bytes)?;
+            records
+                .iter()
+                .map(|r| Ok(Write::put(NAMESPACE, r.tx_hash.clone(), serde_json::to_vec(r)?)))
+                .collect()
+        })
+        .await?;
+        let mut records: Vec<TxRecord> = storage
+            .range(NAMESPACE, &[], None)
+            .await?
+            .into_iter()
+            .filter_map(|(_, bytes)| {
+                serde_json::from_slice(&bytes)
+                    .inspect_err(|e| tracing::warn!("⚠️ Could not parse a stored tx record: {}", e))
+                    .ok()
+            })
+            .collect();
+        records.sort_by_key(|r| r.submitted_at);
+
+        let inner = Inner {
+            order: records.iter().map(|r| r.tx_hash.clone()).collect(),
+            records: records.into_iter().map(|r| (r.tx_hash.clone(), r)).collect(),
+            ..Default::default()
+        };
+
+        Ok(Self {
+            storage,
+            inner: RwLock::new(inner),
+            settled: broadcast::channel(1024).0,
+            progress: broadcast::channel(1024).0,
+        })
+    }
+
+    /// Persist the records changed since the last flush, and forget the evicted ones
+    pub async fn flush(&self) -> Result<()> {
+        let mut inner = self.inner.write().await;
+        if inner.dirty.is_empty() && inner.evicted.is_empty() {
+            return Ok(());
+        }
+        let mut writes = vec![];
+        for tx_hash in &inner.dirty {
+            if let Some(record) = inner.records.get(tx_hash) {
+                writes.push(Write::put(NAMESPACE, tx_hash.clone(), serde_json::to_vec(record)?));
+            }
+        }
+        writes.extend(inner.evicted.iter().map(|tx_hash| Write::remove(NAMESPACE, tx_hash.clone())));
+
+        // Applied at once, so a crash never leaves the history half written
+        self.storage
+            .apply(writes)
+            .await
+            .context("Failed to persist tx history")?;
+        inner.dirty.clear();
+        inner.evicted.clear();
+        Ok(())
+    }
+
+    /// Stream of settlements; subscribe before submitting to not miss a fast outcome
+    pub fn subscribe(&self) -> broadcast::Receiver<(TxHash, TxStatus)> {
+        self.settled.subscribe()
+    }
+
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<TxProgressEvent> {
+        self.progress.subscribe()
+    }
+
+    /// Record a freshly submitted transaction, optionally linking it to the tx it retries
+    pub async fn submitted(&self, tx_hash: &TxHash, identity: &str, retry_of: Option<&TxHash>) {
+        self.track(tx_hash, identity, retry_of).await;
+        self.advance(tx_hash, TxProgress::Submitted).await;
+    }
+
+    async fn track(&self, tx_hash: &TxHash, identity: &str, retry_of: Option<&TxHash>) {
+        let mut inner = self.inner.write().await;
+
+        if let Some(previous) = retry_of.and_then(|h| inner.records.get_mut(&h.0)) {
+            previous.retried_by = Some(tx_hash.0.clone());
+            let previous = previous.tx_hash.clone();
+            inner.dirty.insert(previous);
+        }
+        let record = TxRecord {
+            tx_hash: tx_hash.0.clone(),
+            identity: identity.to_string(),
+            status: TxStatus::Pending,
+            submitted_at: chrono::Utc::now().timestamp(),
+            retry_of: retry_of.map(|h| h.0.clone()),
+            retried_by: None,
+            progress: vec![],
+            cycles: None,
+        };
+        if inner.records.insert(tx_hash.0.clone(), record).is_none() {
+            inner.order.push_back(tx_hash.0.clone());
+        }
+        inner.evicted.remove(&tx_hash.0);
+        inner.dirty.insert(tx_hash.0.clone());
+
+        while inner.order.len() > MAX_TRACKED_TXS {
+            if let Some(evicted) = inner.order.pop_front() {
+                inner.records.remove(&evicted);
+                inner.dirty.remove(&evicted);
+                inner.evicted.insert(evicted);
+            }
+        }
+    }
+
+    /// Settle a transaction; only transactions submitted through the API are kept
+    pub async fn resolve(&self, tx_hash: &TxHash, status: TxStatus) {
+        let mut inner = self.inner.write().await;
+        if let Some(record) = inner.records.get_mut(&tx_hash.0) {
+            record.status = status.clone();
+            inner.dirty.insert(tx_hash.0.clone());
+        }
+        drop(inner);
+        // No subscriber simply means nobody is waiting
+        let _ = self.settled.send((tx_hash.clone(), status));
+    }
+
+    /// Put a transaction of a replaced block back to pending, and broadcast it.
+    ///
+    /// Untracked transactions are ignored.
+    pub async fn reorged(&self, tx_hash: &TxHash, block_height: u64) {
+        {
+            let mut inner = self.inner.write().await;
+            let Some(record) = inner.records.get_mut(&tx_hash.0) else {
+                return;
+            };
+            record.status = TxStatus::Pending;
+            inner.dirty.insert(tx_hash.0.clone());
+        }
+        self.advance(tx_hash, TxProgress::Reorged { block_height }).await;
+    }
+
+    /// Append a progress stage to a tracked transaction and broadcast it.
+    ///
+    /// Untracked transactions and stages already reached since the last reorg are ignored.
+    pub async fn advance(&self, tx_hash: &TxHash, progress: TxProgress) {
+        let now = chrono::Utc::now().timestamp();
+        let mut inner = self.inner.write().await;
+        let Some(record) = inner.records.get_mut(&tx_hash.0) else {
+            return;
+        };
+        // Stages count again after a reorg, but a reorg itself is always recorded
+        let since_reorg = record
+            .progress
+            .iter()
+            .rposition(|e| matches!(e.progress, TxProgress::Reorged { .. }))
+            .map_or(0, |i| i + 1);
+        let current = &record.progress[since_reorg..];
+        let reorg = matches!(progress, TxProgress::Reorged { .. });
+        if !reorg && current.iter().any(|e| e.progress.same_stage(&progress)) {
+            return;
+        }
+
+        let proving_since = current.iter().find_map(|e| match e.progress {
+            TxProgress::ProvingStarted { .. } => Some(e.at),
+            _ => None,
+        });
+        let entry = TxProgressEntry {
+            seq: record.progress.len(),
+            progress,
+            at: now,
+        };
+        record.progress.push(entry.clone());
+        let identity = record.identity.clone();
+        inner.dirty.insert(tx_hash.0.clone());
+
+        if let (TxProgress::Settled { .. }, Some(since)) = (&entry.progress, proving_since) {
+            inner.proving_durations.push_back(now - since);
+            if inner.proving_durations.len() > ETA_SAMPLES {
+                inner.proving_durations.pop_front();
+            }
+        }
+
+        // No subscriber simply means nobody is watching
+        let _ = self.progress.send(TxProgressEvent {
+            tx_hash: tx_hash.clone(),
+            identity,
+            entry,
+        });
+    }
+
+    /// Attach the cycles measured for a tracked transaction
+    #[cfg(feature = "cycle-accounting")]
+    pub async fn record_cycles(&self, tx_hash: &TxHash, cycles: ActionCycles) {
+        let mut inner = self.inner.write().await;
+        if let Some(record) = inner.records.get_mut(&tx_hash.0) {
+            record.cycles = Some(cycles);
+            inner.dirty.insert(tx_hash.0.clone());
+        }
+    }
+
+    /// Average time recent transactions spent between proving start and settlement
+    pub async fn proving_eta(&self) -> Option<u64> {
+        let inner = self.inner.read().await;
+        if inner.proving_durations.is_empty() {
+            return None;
+        }
+        let total: i64 = inner.proving_durations.iter().sum();
+        Some((total / inner.proving_durations.len() as i64).max(0) as u64)
+    }
+
+    pub async fn get(&self, tx_hash: &str) -> Option<TxRecord> {
+        self.inner.read().await.records.get(tx_hash).cloned()
+    }
+
+    /// Full retry chain containing the given transaction, oldest first
+    pub async fn chain(&self, tx_hash: &str) -> Vec<TxRecord> {
+        let inner = self.inner.read().await;
+        let Some(mut current) = inner.records.get(tx_hash) else {
+            return vec![];
+        };
+
+        while let Some(parent) = current.retry_of.as_ref().and_then(|h| inner.records.get(h)) {
+            current = parent;
+        }
+
+        let mut chain = vec![current.clone()];
+        while let Some(next) = current.retried_by.as_ref().and_then(|h| inner.records.get(h)) {
+            chain.push(next.clone());
+            current = next;
+        }
+        chain
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// Prefix of the current API version
+pub const API_V1: &str = "/api/v1";
+/// Prefix of the unversioned routes kept for existing clients
+pub const LEGACY_API: &str = "/api";
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Headers announcing the retirement of the unversioned routes
+pub struct LegacyApi {
+    sunset: HeaderValue,
+}
+
+impl LegacyApi {
+    /// `sunset` is the HTTP-date after which the unversioned routes may be removed
+    pub fn new(sunset: &str) -> Result<Self> {
+        let sunset = HeaderValue::from_str(sunset).context("legacy_api_sunset is not a valid header value")?;
+        Ok(LegacyApi { sunset })
+    }
+}
+
+/// Path of a route as registered on the unversioned API, so per-route configuration
+/// written against `/api/...` keeps applying to `/api/v1/...`
+pub fn legacy_path(path: &str) -> String {
+    match path.strip_prefix(API_V1) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{LEGACY_API}{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+/// Middleware marking unversioned responses as deprecated, pointing at their `/api/v1` successor
+pub async fn deprecate(State(legacy): State<Arc<LegacyApi>>, request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+    headers.insert(SUNSET, legacy.sunset.clone());
+    let successor = path.replacen(&format!("{LEGACY_API}/"), &format!("{API_V1}/"), 1);
+    if let Ok(link) = HeaderValue::from_str(&format!("<{successor}>; rel=\"successor-version\"")) {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
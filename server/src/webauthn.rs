@@ -0,0 +1,650 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value as CborValue;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::encryption::{self, Keyring};
+
+/// File of the data directory registered credentials are kept in
+pub const WEBAUTHN_CREDENTIALS: &str = "webauthn_credentials.json";
+
+/// How long an issued challenge stays valid
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+/// How long a session obtained through a passkey assertion stays valid
+const SESSION_TTL: Duration = Duration::from_secs(12 * 3600);
+/// COSE algorithm identifier for ES256 (ECDSA P-256 + SHA-256)
+const COSE_ALG_ES256: i64 = -7;
+
+/// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// WebAuthn (passkey) login provider.
+///
+/// Issues registration/login challenges, verifies attestations and assertions
+/// server-side, and maps each registered credential to a `<username>@webauthn`
+/// Hyli identity.
+pub struct WebAuthnProvider {
+    rp_id: String,
+    rp_origin: String,
+    credentials_path: PathBuf,
+    /// Seals the credentials file when encryption is enabled
+    keyring: Option<Arc<Keyring>>,
+    challenges: Mutex<HashMap<String, PendingChallenge>>,
+    credentials: Mutex<HashMap<String, StoredCredential>>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+pub struct WebAuthnProviderCtx {
+    pub rp_id: String,
+    pub rp_origin: String,
+    pub data_directory: PathBuf,
+    pub keyring: Option<Arc<Keyring>>,
+    /// Read a credentials file still in clear although encryption is enabled
+    pub accept_plaintext: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CeremonyKind {
+    Registration,
+    Authentication,
+}
+
+#[derive(Debug, Clone)]
+struct PendingChallenge {
+    username: String,
+    kind: CeremonyKind,
+    issued_at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredCredential {
+    pub credential_id: String,
+    pub username: String,
+    pub identity: String,
+    /// SEC1 uncompressed P-256 public key
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    identity: String,
+    created_at: Instant,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<PubKeyCredParam>,
+    pub timeout: u64,
+    pub attestation: String,
+}
+
+#[derive(Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct PubKeyCredParam {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub alg: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<AllowedCredential>,
+    pub timeout: u64,
+    pub user_verification: String,
+}
+
+#[derive(Serialize)]
+pub struct AllowedCredential {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+/// Attestation response produced by `navigator.credentials.create()`, base64url encoded
+#[derive(Deserialize)]
+pub struct RegistrationResponse {
+    pub username: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// Assertion response produced by `navigator.credentials.get()`, base64url encoded
+#[derive(Deserialize)]
+pub struct AuthenticationResponse {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+impl WebAuthnProvider {
+    /// Load the registered credentials, failing when a sealed file does not pass its integrity check
+    pub fn new(ctx: WebAuthnProviderCtx) -> Result<Self> {
+        let credentials_path = ctx.data_directory.join(WEBAUTHN_CREDENTIALS);
+        let credentials =
+            match encryption::read_sealed_file(&credentials_path, ctx.keyring.as_deref(), ctx.accept_plaintext)? {
+                Some(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                    tracing::warn!("⚠️ Could not parse stored WebAuthn credentials: {}", e);
+                    HashMap::new()
+                }),
+                None => HashMap::new(),
+            };
+
+        Ok(Self {
+            rp_id: ctx.rp_id,
+            rp_origin: ctx.rp_origin,
+            credentials_path,
+            keyring: ctx.keyring,
+            challenges: Mutex::new(HashMap::new()),
+            credentials: Mutex::new(credentials),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Hyli identity bound to a passkey user
+    pub fn identity_for(username: &str) -> String {
+        format!("{}@webauthn", username)
+    }
+
+    /// Start a registration ceremony for a new passkey.
+    ///
+    /// A username that already has a passkey only gets another one through a session of that user.
+    pub async fn start_registration(&self, username: &str, session_token: Option<&str>) -> Result<RegistrationOptions> {
+        validate_username(username)?;
+        let credentials = self.credentials.lock().await;
+        self.check_may_register(&credentials, username, session_token).await?;
+        drop(credentials);
+        let challenge = self.issue_challenge(username, CeremonyKind::Registration).await;
+
+        Ok(RegistrationOptions {
+            challenge,
+            rp: RelyingParty {
+                id: self.rp_id.clone(),
+                name: "Hyli DeFi".to_string(),
+            },
+            user: UserEntity {
+                id: URL_SAFE_NO_PAD.encode(Sha256::digest(username.as_bytes())),
+                name: username.to_string(),
+                display_name: username.to_string(),
+            },
+            pub_key_cred_params: vec![PubKeyCredParam {
+                kind: "public-key".to_string(),
+                alg: COSE_ALG_ES256,
+            }],
+            timeout: CHALLENGE_TTL.as_millis() as u64,
+            attestation: "none".to_string(),
+        })
+    }
+
+    /// Verify an attestation and store the new credential, returning the mapped identity
+    pub async fn finish_registration(&self, response: RegistrationResponse, session_token: Option<&str>) -> Result<String> {
+        let client_data_json = decode_b64(&response.client_data_json, "clientDataJSON")?;
+        let client_data = self.check_client_data(&client_data_json, "webauthn.create")?;
+        self.consume_challenge(&client_data.challenge, &response.username, CeremonyKind::Registration)
+            .await?;
+
+        let attestation_object = decode_b64(&response.attestation_object, "attestationObject")?;
+        let auth_data = extract_auth_data(&attestation_object)?;
+        let parsed = self.parse_authenticator_data(&auth_data)?;
+        if parsed.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+            bail!("Attestation does not contain credential data");
+        }
+        let (credential_id, public_key) = parse_attested_credential(&auth_data[37..])?;
+
+        let credential = StoredCredential {
+            credential_id: URL_SAFE_NO_PAD.encode(&credential_id),
+            username: response.username.clone(),
+            identity: Self::identity_for(&response.username),
+            public_key,
+            sign_count: parsed.sign_count,
+        };
+
+        let mut credentials = self.credentials.lock().await;
+        // Checked again, the username may have been registered since the ceremony started
+        self.check_may_register(&credentials, &response.username, session_token).await?;
+        if credentials.contains_key(&credential.credential_id) {
+            bail!("Credential already registered");
+        }
+        let identity = credential.identity.clone();
+        credentials.insert(credential.credential_id.clone(), credential);
+        self.persist_credentials(&credentials)?;
+
+        tracing::info!("🔑 Registered passkey for {}", identity);
+        Ok(identity)
+    }
+
+    /// Start a login ceremony for an already registered user
+    pub async fn start_authentication(&self, username: &str) -> Result<AuthenticationOptions> {
+        let allow_credentials: Vec<AllowedCredential> = self
+            .credentials
+            .lock()
+            .await
+            .values()
+            .filter(|c| c.username == username)
+            .map(|c| AllowedCredential {
+                kind: "public-key".to_string(),
+                id: c.credential_id.clone(),
+            })
+            .collect();
+        if allow_credentials.is_empty() {
+            bail!("No passkey registered for {}", username);
+        }
+
+        let challenge = self.issue_challenge(username, CeremonyKind::Authentication).await;
+        Ok(AuthenticationOptions {
+            challenge,
+            rp_id: self.rp_id.clone(),
+            allow_credentials,
+            timeout: CHALLENGE_TTL.as_millis() as u64,
+            user_verification: "preferred".to_string(),
+        })
+    }
+
+    /// Verify an assertion and open a session, returning `(identity, session_token)`
+    pub async fn finish_authentication(&self, response: AuthenticationResponse) -> Result<(String, String)> {
+        let client_data_json = decode_b64(&response.client_data_json, "clientDataJSON")?;
+        let client_data = self.check_client_data(&client_data_json, "webauthn.get")?;
+
+        let mut credentials = self.credentials.lock().await;
+        let credential = credentials
+            .get_mut(&response.credential_id)
+            .ok_or_else(|| anyhow!("Unknown credential"))?;
+        self.consume_challenge(&client_data.challenge, &credential.username, CeremonyKind::Authentication)
+            .await?;
+
+        let auth_data = decode_b64(&response.authenticator_data, "authenticatorData")?;
+        let parsed = self.parse_authenticator_data(&auth_data)?;
+
+        // Signature is over authenticatorData || SHA-256(clientDataJSON)
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        let signature = Signature::from_der(&decode_b64(&response.signature, "signature")?)
+            .map_err(|e| anyhow!("Malformed signature: {}", e))?;
+        let key = VerifyingKey::from_sec1_bytes(&credential.public_key)
+            .map_err(|e| anyhow!("Stored public key is invalid: {}", e))?;
+        key.verify(&signed, &signature)
+            .map_err(|_| anyhow!("Assertion signature verification failed"))?;
+
+        // Authenticators without a counter always report 0
+        if parsed.sign_count != 0 || credential.sign_count != 0 {
+            if parsed.sign_count <= credential.sign_count {
+                bail!("Signature counter did not increase - possible cloned authenticator");
+            }
+            credential.sign_count = parsed.sign_count;
+        }
+        let identity = credential.identity.clone();
+        self.persist_credentials(&credentials)?;
+        drop(credentials);
+
+        let token = self.open_session(&identity).await;
+        tracing::info!("✅ Passkey login for {}", identity);
+        Ok((identity, token))
+    }
+
+    /// Issue a session token for an identity that just proved it holds a passkey
+    pub(crate) async fn open_session(&self, identity: &str) -> String {
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, s| s.created_at.elapsed() < SESSION_TTL);
+        sessions.insert(
+            token.clone(),
+            Session {
+                identity: identity.to_string(),
+                created_at: Instant::now(),
+            },
+        );
+        token
+    }
+
+    /// Resolve a session token to the identity it was issued for
+    pub async fn session_identity(&self, token: &str) -> Option<String> {
+        self.sessions
+            .lock()
+            .await
+            .get(token)
+            .filter(|s| s.created_at.elapsed() < SESSION_TTL)
+            .map(|s| s.identity.clone())
+    }
+
+    /// Refuse to add a passkey to a registered username outside a session of that user
+    async fn check_may_register(
+        &self,
+        credentials: &HashMap<String, StoredCredential>,
+        username: &str,
+        session_token: Option<&str>,
+    ) -> Result<()> {
+        if !credentials.values().any(|c| c.username == username) {
+            return Ok(());
+        }
+        let session_identity = match session_token {
+            Some(token) => self.session_identity(token).await,
+            None => None,
+        };
+        if session_identity.as_deref() != Some(Self::identity_for(username).as_str()) {
+            bail!("{} is already registered, log in with one of its passkeys to add another", username);
+        }
+        Ok(())
+    }
+
+    async fn issue_challenge(&self, username: &str, kind: CeremonyKind) -> String {
+        let challenge = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let mut challenges = self.challenges.lock().await;
+        challenges.retain(|_, c| c.issued_at.elapsed() < CHALLENGE_TTL);
+        challenges.insert(
+            challenge.clone(),
+            PendingChallenge {
+                username: username.to_string(),
+                kind,
+                issued_at: Instant::now(),
+            },
+        );
+        challenge
+    }
+
+    async fn consume_challenge(&self, challenge: &str, username: &str, kind: CeremonyKind) -> Result<()> {
+        let pending = self
+            .challenges
+            .lock()
+            .await
+            .remove(challenge)
+            .ok_or_else(|| anyhow!("Unknown or already used challenge"))?;
+        if pending.issued_at.elapsed() >= CHALLENGE_TTL {
+            bail!("Challenge expired");
+        }
+        if pending.kind != kind || pending.username != username {
+            bail!("Challenge was issued for a different ceremony");
+        }
+        Ok(())
+    }
+
+    fn check_client_data(&self, client_data_json: &[u8], expected_type: &str) -> Result<ClientData> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).context("Malformed clientDataJSON")?;
+        if client_data.kind != expected_type {
+            bail!("Unexpected client data type {}", client_data.kind);
+        }
+        if client_data.origin != self.rp_origin {
+            bail!("Unexpected origin {}", client_data.origin);
+        }
+        Ok(client_data)
+    }
+
+    fn parse_authenticator_data(&self, auth_data: &[u8]) -> Result<AuthenticatorData> {
+        if auth_data.len() < 37 {
+            bail!("Authenticator data too short");
+        }
+        if auth_data[..32] != Sha256::digest(self.rp_id.as_bytes())[..] {
+            bail!("Relying party id hash mismatch");
+        }
+        let flags = auth_data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            bail!("User presence flag not set");
+        }
+        let sign_count = u32::from_be_bytes([auth_data[33], auth_data[34], auth_data[35], auth_data[36]]);
+        Ok(AuthenticatorData { flags, sign_count })
+    }
+
+    fn persist_credentials(&self, credentials: &HashMap<String, StoredCredential>) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(credentials)?;
+        encryption::write_sealed_file(&self.credentials_path, self.keyring.as_deref(), bytes)
+            .with_context(|| format!("Failed to persist credentials to {:?}", self.credentials_path))
+    }
+}
+
+struct AuthenticatorData {
+    flags: u8,
+    sign_count: u32,
+}
+
+fn validate_username(username: &str) -> Result<()> {
+    if username.is_empty() || username.len() > 64 {
+        bail!("Username must be between 1 and 64 characters");
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        bail!("Username may only contain letters, digits, '_' and '-'");
+    }
+    Ok(())
+}
+
+fn decode_b64(value: &str, field: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .with_context(|| format!("{} is not valid base64url", field))
+}
+
+/// Pull `authData` out of the CBOR attestation object
+fn extract_auth_data(attestation_object: &[u8]) -> Result<Vec<u8>> {
+    let value: CborValue =
+        ciborium::de::from_reader(attestation_object).context("Malformed attestation object")?;
+    let map = value.as_map().ok_or_else(|| anyhow!("Attestation object is not a map"))?;
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some("authData"))
+        .and_then(|(_, v)| v.as_bytes().cloned())
+        .ok_or_else(|| anyhow!("Attestation object has no authData"))
+}
+
+/// Parse attested credential data: aaguid(16) | idLen(2) | credentialId | COSE key
+fn parse_attested_credential(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if data.len() < 18 {
+        bail!("Attested credential data too short");
+    }
+    let id_len = u16::from_be_bytes([data[16], data[17]]) as usize;
+    let id_end = 18 + id_len;
+    if data.len() < id_end {
+        bail!("Credential id truncated");
+    }
+    let credential_id = data[18..id_end].to_vec();
+
+    let cose_key: CborValue =
+        ciborium::de::from_reader(&data[id_end..]).context("Malformed COSE public key")?;
+    let map = cose_key.as_map().ok_or_else(|| anyhow!("COSE key is not a map"))?;
+    let field = |label: i64| {
+        map.iter()
+            .find(|(k, _)| k.as_integer().map(i128::from) == Some(label as i128))
+            .map(|(_, v)| v)
+    };
+
+    let alg = field(3)
+        .and_then(|v| v.as_integer())
+        .map(i128::from)
+        .ok_or_else(|| anyhow!("COSE key has no algorithm"))?;
+    if alg != COSE_ALG_ES256 as i128 {
+        bail!("Unsupported COSE algorithm {}", alg);
+    }
+    let x = field(-2).and_then(|v| v.as_bytes()).ok_or_else(|| anyhow!("COSE key has no x"))?;
+    let y = field(-3).and_then(|v| v.as_bytes()).ok_or_else(|| anyhow!("COSE key has no y"))?;
+    if x.len() != 32 || y.len() != 32 {
+        bail!("Invalid P-256 coordinates");
+    }
+
+    let mut public_key = Vec::with_capacity(65);
+    public_key.push(0x04);
+    public_key.extend_from_slice(x);
+    public_key.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&public_key).map_err(|e| anyhow!("Invalid public key: {}", e))?;
+
+    Ok((credential_id, public_key))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    const RP_ID: &str = "localhost";
+    const RP_ORIGIN: &str = "http://localhost:3000";
+
+    pub(crate) fn provider() -> WebAuthnProvider {
+        let data_directory = std::env::temp_dir().join(format!("webauthn-{}", hex::encode(rand::random::<[u8; 8]>())));
+        std::fs::create_dir_all(&data_directory).unwrap();
+        WebAuthnProvider::new(WebAuthnProviderCtx {
+            rp_id: RP_ID.to_string(),
+            rp_origin: RP_ORIGIN.to_string(),
+            data_directory,
+            keyring: None,
+            accept_plaintext: false,
+        })
+        .unwrap()
+    }
+
+    fn client_data(kind: &str, challenge: &str) -> String {
+        let json = serde_json::json!({ "type": kind, "challenge": challenge, "origin": RP_ORIGIN });
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&json).unwrap())
+    }
+
+    /// Attestation object of a `none` attestation for an ES256 passkey
+    fn attestation_object(credential_id: &[u8], key: &SigningKey) -> String {
+        let point = key.verifying_key().to_encoded_point(false);
+        let int = |i: i64| CborValue::Integer(i.into());
+        let cose_key = CborValue::Map(vec![
+            (int(1), int(2)),
+            (int(3), int(COSE_ALG_ES256)),
+            (int(-1), int(1)),
+            (int(-2), CborValue::Bytes(point.x().unwrap().to_vec())),
+            (int(-3), CborValue::Bytes(point.y().unwrap().to_vec())),
+        ]);
+        let mut auth_data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+        auth_data.push(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA);
+        auth_data.extend_from_slice(&0u32.to_be_bytes());
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(credential_id);
+        ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let object = CborValue::Map(vec![
+            (CborValue::Text("fmt".to_string()), CborValue::Text("none".to_string())),
+            (CborValue::Text("attStmt".to_string()), CborValue::Map(vec![])),
+            (CborValue::Text("authData".to_string()), CborValue::Bytes(auth_data)),
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&object, &mut bytes).unwrap();
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    async fn register(
+        provider: &WebAuthnProvider,
+        username: &str,
+        credential_id: &[u8],
+        key: &SigningKey,
+        session_token: Option<&str>,
+    ) -> Result<String> {
+        let options = provider.start_registration(username, session_token).await?;
+        let response = RegistrationResponse {
+            username: username.to_string(),
+            client_data_json: client_data("webauthn.create", &options.challenge),
+            attestation_object: attestation_object(credential_id, key),
+        };
+        provider.finish_registration(response, session_token).await
+    }
+
+    async fn login(provider: &WebAuthnProvider, username: &str, credential_id: &[u8], key: &SigningKey) -> String {
+        let options = provider.start_authentication(username).await.unwrap();
+        let client_data_json = client_data("webauthn.get", &options.challenge);
+        let mut auth_data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+        auth_data.push(FLAG_USER_PRESENT);
+        auth_data.extend_from_slice(&0u32.to_be_bytes());
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(URL_SAFE_NO_PAD.decode(&client_data_json).unwrap()));
+        let signature: Signature = key.sign(&signed);
+        let response = AuthenticationResponse {
+            credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+            client_data_json,
+            authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+            signature: URL_SAFE_NO_PAD.encode(signature.to_der()),
+        };
+        provider.finish_authentication(response).await.unwrap().1
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_first_registration_maps_the_username_to_its_identity() {
+        let provider = provider();
+        let identity = register(&provider, "alice", b"alice-1", &key(1), None).await.unwrap();
+        assert_eq!(identity, "alice@webauthn");
+
+        let token = login(&provider, "alice", b"alice-1", &key(1)).await;
+        assert_eq!(provider.session_identity(&token).await.as_deref(), Some("alice@webauthn"));
+    }
+
+    #[tokio::test]
+    async fn test_registering_a_taken_username_is_refused() {
+        let provider = provider();
+        register(&provider, "alice", b"alice-1", &key(1), None).await.unwrap();
+
+        // Without a session, and with a session of another user
+        assert!(register(&provider, "alice", b"mallory-1", &key(2), None).await.is_err());
+        register(&provider, "mallory", b"mallory-2", &key(2), None).await.unwrap();
+        let mallory = login(&provider, "mallory", b"mallory-2", &key(2)).await;
+        assert!(register(&provider, "alice", b"mallory-1", &key(2), Some(&mallory)).await.is_err());
+        assert!(register(&provider, "alice", b"mallory-1", &key(2), Some("not-a-session")).await.is_err());
+
+        let options = provider.start_authentication("alice").await.unwrap();
+        assert_eq!(options.allow_credentials.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_a_username_taken_during_the_ceremony_is_refused() {
+        let provider = provider();
+        let options = provider.start_registration("alice", None).await.unwrap();
+        register(&provider, "alice", b"alice-1", &key(1), None).await.unwrap();
+
+        let response = RegistrationResponse {
+            username: "alice".to_string(),
+            client_data_json: client_data("webauthn.create", &options.challenge),
+            attestation_object: attestation_object(b"mallory-1", &key(2)),
+        };
+        assert!(provider.finish_registration(response, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_a_logged_in_user_adds_a_passkey() {
+        let provider = provider();
+        register(&provider, "alice", b"alice-1", &key(1), None).await.unwrap();
+        let session = login(&provider, "alice", b"alice-1", &key(1)).await;
+
+        let identity = register(&provider, "alice", b"alice-2", &key(3), Some(&session)).await.unwrap();
+        assert_eq!(identity, "alice@webauthn");
+        let token = login(&provider, "alice", b"alice-2", &key(3)).await;
+        assert_eq!(provider.session_identity(&token).await.as_deref(), Some("alice@webauthn"));
+    }
+}